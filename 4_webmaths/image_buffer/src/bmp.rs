@@ -0,0 +1,305 @@
+use packed_struct::prelude::*;
+use vector_math::{*};
+use std::fs::File;
+use std::io;
+use std::io::{BufWriter, Read, Write};
+use crate::{Color, ImageBuffer};
+
+#[derive(PackedStruct)]
+#[packed_struct(endian="lsb")]
+pub struct BMPHeader {
+    file_type: [u8;2], // "BM"
+	file_size: u32,
+	reserved_1: u16, // 0
+	reserved_2: u16, // 0
+	pixel_offset: u32, // 54, 122 with alpha
+	header_size: u32, // 40, 108 with alpha
+	x_size: u32,
+	y_size: u32, // negative for top-down rows
+	planes: u16, // 1
+	bpp: u16, // 24, 32 with alpha
+	compression: u32, // 0, 3 (bit fields) with alpha
+	image_size: u32,
+	x_ppm: u32, // 0
+	y_ppm: u32, // 0
+	used_colors: u32, // 0
+	important_colors: u32, // 0
+}
+
+// The part of a version 4 header that comes after the usual 40 bytes, up to the color space end points
+// and gamma, which are unused for sRGB and written as zeros
+#[derive(PackedStruct)]
+#[packed_struct(endian="lsb")]
+struct BMPV4Masks {
+    red_mask: u32,
+    green_mask: u32,
+    blue_mask: u32,
+    alpha_mask: u32,
+    color_space: u32, // "sRGB"
+}
+
+const FILE_HEADER_SIZE: usize = 14;
+const INFO_HEADER_SIZE: usize = 40;
+const V4_HEADER_SIZE: usize = 108;
+const BI_RGB: u32 = 0;
+const BI_BITFIELDS: u32 = 3;
+const BI_ALPHABITFIELDS: u32 = 6;
+const LCS_SRGB: u32 = 0x73524742;
+
+#[derive(Clone, Copy, Debug, Default)]
+pub struct BmpOptions {
+    // Store rows from the top down (as a negative height), instead of the usual bottom-up order
+    pub top_down: bool,
+}
+
+fn to_u8(value: Scalar) -> u8 {
+    return (value.clamp(0.0, 1.0) * 255.0 + 0.5) as u8;
+}
+
+fn invalid_data(text: &str) -> io::Error {
+    return io::Error::new(io::ErrorKind::InvalidData, text);
+}
+
+fn le_u32(bytes: &[u8], pos: usize) -> u32 {
+    return u32::from_le_bytes([bytes[pos], bytes[pos + 1], bytes[pos + 2], bytes[pos + 3]]);
+}
+
+// Bytes per row, which are padded to a multiple of four
+fn row_size(width: usize, bpp: usize) -> usize {
+    return (width * bpp).div_ceil(32) * 4;
+}
+
+// Reads one channel out of a pixel with a bit mask, scaled to [0, 1]
+fn mask_channel(pixel: u32, mask: u32) -> Scalar {
+    if mask == 0 {
+        return 0.0;
+    }
+    let shift = mask.trailing_zeros();
+    return ((pixel & mask) >> shift) as Scalar / (mask >> shift) as Scalar;
+}
+
+impl<'buffer> ImageBuffer<'buffer> {
+    // Writes the image as an uncompressed bmp, clamping values to [0, 1]. With alpha (one value per pixel,
+    // in the same order as the pixels) the bmp has 32 bits per pixel with an alpha mask, otherwise 24.
+    pub fn write_bmp_to<W: Write>(&self, out: &mut W, alpha: Option<&[Scalar]>, options: &BmpOptions) -> io::Result<()> {
+        if let Some(alpha) = alpha {
+            if alpha.len() != self.width * self.height {
+                let text = format!("{} alpha values for {} pixels", alpha.len(), self.width * self.height);
+                return Err(io::Error::new(io::ErrorKind::InvalidInput, text));
+            }
+        }
+        let (bpp, header_size, compression) = match alpha {
+            Some(_) => (32, V4_HEADER_SIZE, BI_BITFIELDS),
+            None => (24, INFO_HEADER_SIZE, BI_RGB),
+        };
+        let row_size = row_size(self.width, bpp);
+        let pixel_offset = FILE_HEADER_SIZE + header_size;
+        let file_size = pixel_offset + row_size * self.height;
+        if self.width > i32::MAX as usize || self.height > i32::MAX as usize || file_size > u32::MAX as usize {
+            let text = format!("can't write a {}x{} bmp", self.width, self.height);
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, text));
+        }
+
+        let header = BMPHeader{
+            file_type: [66, 77],
+            file_size: file_size as u32,
+            reserved_1: 0,
+            reserved_2: 0,
+            pixel_offset: pixel_offset as u32,
+            header_size: header_size as u32,
+            x_size: self.width as u32,
+            y_size: if options.top_down { (self.height as i32).wrapping_neg() as u32 } else { self.height as u32 },
+            planes: 1,
+            bpp: bpp as u16,
+            compression: compression,
+            image_size: (row_size * self.height) as u32,
+            x_ppm: 0,
+            y_ppm: 0,
+            used_colors: 0,
+            important_colors: 0,
+        };
+        out.write_all(&header.pack().expect("Header packing error"))?;
+        if alpha.is_some() {
+            let masks = BMPV4Masks {
+                red_mask: 0x00ff0000,
+                green_mask: 0x0000ff00,
+                blue_mask: 0x000000ff,
+                alpha_mask: 0xff000000,
+                color_space: LCS_SRGB,
+            };
+            out.write_all(&masks.pack().expect("Header packing error"))?;
+            out.write_all(&[0; V4_HEADER_SIZE - INFO_HEADER_SIZE - 20])?;
+        }
+
+        let mut row = Vec::with_capacity(row_size);
+        for row_idx in 0..self.height {
+            let y = if options.top_down { row_idx } else { self.height - row_idx - 1 };
+            row.clear();
+            for x in 0..self.width {
+                let pixel = self.pixel(x, y);
+                row.extend_from_slice(&[to_u8(pixel.b()), to_u8(pixel.g()), to_u8(pixel.r())]);
+                if let Some(alpha) = alpha {
+                    row.push(to_u8(alpha[self.pixel_index(x, y)]));
+                }
+            }
+            row.resize(row_size, 0);
+            out.write_all(&row)?;
+        }
+        return out.flush();
+    }
+
+    pub fn write_bmp_with(&self, path: &str, alpha: Option<&[Scalar]>, options: &BmpOptions) -> io::Result<()> {
+        let mut out = BufWriter::new(File::create(path)?);
+        return self.write_bmp_to(&mut out, alpha, options);
+    }
+
+    pub fn write_bmp(&self, path: &str) {
+        self.write_bmp_with(path, None, &BmpOptions::default()).expect("Write error");
+    }
+
+    // Reads an uncompressed 8 (palette), 24 or 32 bit bmp into data_buf, which gets resized to fit. Alpha,
+    // if asked for, comes from the alpha mask or, for plain 32 bit files, from the fourth byte unless it is
+    // zero everywhere. Otherwise it is 1.
+    pub fn read_bmp_from<R: Read>(input: &mut R, data_buf: &'buffer mut Vec<Color>, alpha_buf: Option<&mut Vec<Scalar>>) -> io::Result<ImageBuffer<'buffer>> {
+        let mut bytes = Vec::new();
+        input.read_to_end(&mut bytes)?;
+        if bytes.len() < FILE_HEADER_SIZE + INFO_HEADER_SIZE || &bytes[0..2] != b"BM" {
+            return Err(invalid_data("not a bmp file"));
+        }
+        let mut header_bytes = [0u8; 54];
+        header_bytes.copy_from_slice(&bytes[..54]);
+        let header = BMPHeader::unpack(&header_bytes).map_err(|_| invalid_data("bad bmp header"))?;
+        let header_size = header.header_size as usize;
+        if header_size < INFO_HEADER_SIZE {
+            return Err(invalid_data(&format!("unsupported bmp header size {}", header_size)));
+        }
+        let width = header.x_size as i32;
+        let height = header.y_size as i32;
+        if width <= 0 || height == 0 || header.planes != 1 {
+            return Err(invalid_data(&format!("bad bmp size {}x{}", width, height)));
+        }
+        let (width, height, top_down) = (width as usize, height.unsigned_abs() as usize, height < 0);
+
+        // Where the channels are in a 32 bit pixel: from the header for bit fields (the masks follow a
+        // 40 byte header, and are part of larger ones), otherwise BGRA
+        let masks_pos = FILE_HEADER_SIZE + INFO_HEADER_SIZE;
+        let mask_count = match header.compression {
+            BI_RGB => 0,
+            BI_BITFIELDS => 3,
+            BI_ALPHABITFIELDS => 4,
+            compression => return Err(invalid_data(&format!("unsupported bmp compression {}", compression))),
+        };
+        let mut masks = [0x00ff0000, 0x0000ff00, 0x000000ff, 0xff000000];
+        if mask_count > 0 {
+            if header.bpp != 32 || bytes.len() < masks_pos + mask_count * 4 {
+                return Err(invalid_data("bad bmp bit fields"));
+            }
+            for (mask_idx, mask) in masks.iter_mut().enumerate() {
+                let in_header = header_size >= INFO_HEADER_SIZE + 4 * (mask_idx + 1);
+                *mask = if mask_idx < mask_count || in_header { le_u32(&bytes, masks_pos + mask_idx * 4) } else { 0 };
+            }
+        }
+
+        let bpp = header.bpp as usize;
+        let palette = match bpp {
+            8 => {
+                let palette_pos = FILE_HEADER_SIZE + header_size;
+                let color_count = if header.used_colors == 0 { 256 } else { (header.used_colors as usize).min(256) };
+                let palette_bytes = bytes.get(palette_pos..palette_pos + color_count * 4).ok_or_else(|| invalid_data("bmp palette cut off"))?;
+                palette_bytes.chunks_exact(4).map(|entry| {
+                    return Color::new(entry[2] as Scalar / 255.0, entry[1] as Scalar / 255.0, entry[0] as Scalar / 255.0);
+                }).collect::<Vec<Color>>()
+            },
+            24 | 32 => Vec::new(),
+            _ => return Err(invalid_data(&format!("unsupported bmp bit depth {}", bpp))),
+        };
+
+        let row_size = row_size(width, bpp);
+        let pixel_offset = header.pixel_offset as usize;
+        let pixel_bytes = row_size.checked_mul(height)
+            .and_then(|size| bytes.get(pixel_offset..pixel_offset.checked_add(size)?))
+            .ok_or_else(|| invalid_data("bmp pixel data cut off"))?;
+
+        data_buf.clear();
+        data_buf.resize(width * height, Color::new(0.0, 0.0, 0.0));
+        let mut alpha = vec![1.0; width * height];
+        for (row_idx, row) in pixel_bytes.chunks_exact(row_size).enumerate() {
+            let y = if top_down { row_idx } else { height - row_idx - 1 };
+            for x in 0..width {
+                let pixel_idx = y * width + x;
+                data_buf[pixel_idx] = match bpp {
+                    8 => *palette.get(row[x] as usize).ok_or_else(|| invalid_data("bmp palette index out of range"))?,
+                    24 => Color::new(row[x * 3 + 2] as Scalar / 255.0, row[x * 3 + 1] as Scalar / 255.0, row[x * 3] as Scalar / 255.0),
+                    _ => {
+                        let pixel = le_u32(row, x * 4);
+                        alpha[pixel_idx] = mask_channel(pixel, masks[3]);
+                        Color::new(mask_channel(pixel, masks[0]), mask_channel(pixel, masks[1]), mask_channel(pixel, masks[2]))
+                    }
+                };
+            }
+        }
+        if let Some(alpha_buf) = alpha_buf {
+            // Plain 32 bit files often leave the fourth byte at zero, which means opaque and not invisible
+            let unused_alpha = bpp != 32 || masks[3] == 0 || (header.compression == BI_RGB && alpha.iter().all(|&value| value == 0.0));
+            if unused_alpha {
+                alpha.fill(1.0);
+            }
+            *alpha_buf = alpha;
+        }
+        return Ok(ImageBuffer::new(width, height, data_buf));
+    }
+
+    pub fn read_bmp(path: &str, data_buf: &'buffer mut Vec<Color>, alpha_buf: Option<&mut Vec<Scalar>>) -> io::Result<ImageBuffer<'buffer>> {
+        let mut input = File::open(path)?;
+        return ImageBuffer::read_bmp_from(&mut input, data_buf, alpha_buf);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use vector_math::{*};
+    use crate::{Color, ImageBuffer};
+    use super::{BmpOptions, le_u32, to_u8};
+
+    #[test]
+    fn round_trips() {
+        for width in [1, 2, 3, 5, 7] {
+            let height = 3;
+            let mut data = ImageBuffer::alloc_data_buf(width, height);
+            let mut image = ImageBuffer::new(width, height, &mut data);
+            for y in 0..height {
+                for x in 0..width {
+                    // Goes a bit outside [0, 1] to check the clamping
+                    let b = ((x * 7 + y * 3) % 11) as Scalar / 10.0 * 1.2 - 0.1;
+                    image.set_pixel(x, y, Color::new(x as Scalar / width as Scalar, y as Scalar / height as Scalar, b));
+                }
+            }
+            let alpha: Vec<Scalar> = (0..width * height).map(|idx| (idx % 5) as Scalar / 4.0).collect();
+
+            for alpha in [None, Some(&alpha[..])] {
+                for top_down in [false, true] {
+                    let mut out = Vec::new();
+                    image.write_bmp_to(&mut out, alpha, &BmpOptions { top_down: top_down }).unwrap();
+                    // 24 bit rows are padded to four bytes, 32 bit ones never need it
+                    let (header_size, row_size) = if alpha.is_some() { (122, width * 4) } else { (54, (width * 3).div_ceil(4) * 4) };
+                    assert_eq!(out.len(), header_size + row_size * height);
+                    assert_eq!(le_u32(&out, 2) as usize, out.len());
+                    assert_eq!(le_u32(&out, 22) as i32, if top_down { -(height as i32) } else { height as i32 });
+
+                    let mut data_buf = Vec::new();
+                    let mut alpha_buf = Vec::new();
+                    let back = ImageBuffer::read_bmp_from(&mut &out[..], &mut data_buf, Some(&mut alpha_buf)).unwrap();
+                    assert_eq!((back.width, back.height), (width, height));
+                    for y in 0..height {
+                        for x in 0..width {
+                            let (a, b) = (image.pixel(x, y), back.pixel(x, y));
+                            assert_eq!([to_u8(b.r()), to_u8(b.g()), to_u8(b.b())], [to_u8(a.r()), to_u8(a.g()), to_u8(a.b())]);
+                            let expected_alpha = alpha.map_or(255, |alpha| to_u8(alpha[y * width + x]));
+                            assert_eq!(to_u8(alpha_buf[y * width + x]), expected_alpha);
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
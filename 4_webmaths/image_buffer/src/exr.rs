@@ -0,0 +1,464 @@
+use vector_math::{*};
+use std::fs::File;
+use std::io;
+use std::io::{BufWriter, Read, Write};
+use crate::{Color, Image, ImageBuffer};
+use crate::zlib::{zlib_compress, zlib_decompress};
+
+const MAGIC: [u8; 4] = [0x76, 0x2f, 0x31, 0x01];
+const VERSION: u32 = 2;
+const TILED_FLAG: u32 = 0x200;
+const NON_IMAGE_FLAG: u32 = 0x800;
+const MULTI_PART_FLAG: u32 = 0x1000;
+
+// Compression types, and how many scanlines go into one chunk
+const NO_COMPRESSION: u8 = 0;
+const ZIPS_COMPRESSION: u8 = 2;
+const ZIP_COMPRESSION: u8 = 3;
+const ZIP_LINES: usize = 16;
+const ZIP_LEVEL: u32 = 6;
+
+// Pixel types in the channel list
+const UINT: i32 = 0;
+const HALF: i32 = 1;
+const FLOAT: i32 = 2;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub enum ExrPixelType {
+    // 16 bit floats, plenty for color and half the size
+    #[default]
+    Half,
+    Float,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub enum ExrCompression {
+    None,
+    // zlib on blocks of 16 scanlines, lossless
+    #[default]
+    Zip,
+}
+
+#[derive(Clone, Copy, Debug, Default)]
+pub struct ExrOptions {
+    pub pixel_type: ExrPixelType,
+    pub compression: ExrCompression,
+}
+
+fn invalid_data(text: &str) -> io::Error {
+    return io::Error::new(io::ErrorKind::InvalidData, text);
+}
+
+// Shifts right, rounding to the nearest value and to even on ties
+fn round_shift(value: u32, shift: u32) -> u32 {
+    let half = 1 << (shift - 1);
+    let rest = value & ((1 << shift) - 1);
+    let result = value >> shift;
+    if rest > half || (rest == half && result & 1 == 1) {
+        return result + 1;
+    }
+    return result;
+}
+
+fn to_half(value: f32) -> u16 {
+    let bits = value.to_bits();
+    let sign = ((bits >> 16) & 0x8000) as u16;
+    let exponent = ((bits >> 23) & 0xff) as i32;
+    let mantissa = bits & 0x7fffff;
+    if exponent == 0xff {
+        // Infinity, or NaN with a mantissa bit set
+        return sign | 0x7c00 | if mantissa != 0 { 0x200 } else { 0 };
+    }
+    let half_exponent = exponent - 127 + 15;
+    if half_exponent >= 0x1f {
+        return sign | 0x7c00;
+    }
+    if half_exponent <= 0 {
+        // Subnormal, with the implicit leading bit made explicit
+        if half_exponent < -10 {
+            return sign;
+        }
+        return sign | round_shift(mantissa | 0x800000, (14 - half_exponent) as u32) as u16;
+    }
+    // Rounding can carry into the exponent, up to infinity, which is what it should do
+    return sign | round_shift((half_exponent as u32) << 23 | mantissa, 13) as u16;
+}
+
+fn from_half(half: u16) -> f32 {
+    let sign = ((half & 0x8000) as u32) << 16;
+    let exponent = ((half >> 10) & 0x1f) as u32;
+    let mantissa = (half & 0x3ff) as u32;
+    return match exponent {
+        0 => {
+            let value = mantissa as f32 / (1 << 24) as f32;
+            if sign != 0 { -value } else { value }
+        },
+        0x1f => f32::from_bits(sign | 0x7f800000 | mantissa << 13),
+        _ => f32::from_bits(sign | (exponent + 127 - 15) << 23 | mantissa << 13),
+    };
+}
+
+// Before zlib, ZIP compression splits the bytes into even and odd ones, then stores differences between
+// neighbours, which both make float data compress better
+fn zip_predict(raw: &[u8]) -> Vec<u8> {
+    let half = raw.len().div_ceil(2);
+    let mut split = vec![0u8; raw.len()];
+    for (idx, &byte) in raw.iter().enumerate() {
+        split[if idx % 2 == 0 { idx / 2 } else { half + idx / 2 }] = byte;
+    }
+    let mut previous = split.first().copied().unwrap_or(0);
+    for byte in split.iter_mut().skip(1) {
+        let value = *byte;
+        *byte = value.wrapping_sub(previous).wrapping_add(128);
+        previous = value;
+    }
+    return split;
+}
+
+fn zip_unpredict(mut split: Vec<u8>) -> Vec<u8> {
+    for idx in 1..split.len() {
+        split[idx] = split[idx - 1].wrapping_add(split[idx]).wrapping_sub(128);
+    }
+    let half = split.len().div_ceil(2);
+    return (0..split.len()).map(|idx| split[if idx % 2 == 0 { idx / 2 } else { half + idx / 2 }]).collect();
+}
+
+fn write_attribute(header: &mut Vec<u8>, name: &str, type_name: &str, value: &[u8]) {
+    for text in [name, type_name] {
+        header.extend_from_slice(text.as_bytes());
+        header.push(0);
+    }
+    header.extend_from_slice(&(value.len() as i32).to_le_bytes());
+    header.extend_from_slice(value);
+}
+
+fn box2i(width: usize, height: usize) -> Vec<u8> {
+    return [0, 0, width as i32 - 1, height as i32 - 1].iter().flat_map(|value| value.to_le_bytes()).collect();
+}
+
+struct Channel {
+    name: String,
+    pixel_type: i32,
+}
+
+impl Channel {
+    fn sample_size(&self) -> usize {
+        return if self.pixel_type == HALF { 2 } else { 4 };
+    }
+
+    fn sample(&self, bytes: &[u8]) -> Scalar {
+        return match self.pixel_type {
+            HALF => from_half(u16::from_le_bytes([bytes[0], bytes[1]])),
+            FLOAT => f32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]),
+            _ => u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]) as Scalar,
+        };
+    }
+}
+
+fn le_i32(bytes: &[u8], pos: usize) -> io::Result<i32> {
+    let value = pos.checked_add(4).and_then(|end| bytes.get(pos..end)).ok_or_else(|| invalid_data("exr file cut off"))?;
+    return Ok(i32::from_le_bytes([value[0], value[1], value[2], value[3]]));
+}
+
+// Null terminated string
+fn read_name<'a>(bytes: &'a [u8], pos: &mut usize) -> io::Result<&'a str> {
+    let length = bytes[(*pos).min(bytes.len())..].iter().position(|&byte| byte == 0).ok_or_else(|| invalid_data("exr header cut off"))?;
+    let name = std::str::from_utf8(&bytes[*pos..*pos + length]).map_err(|_| invalid_data("bad exr name"))?;
+    *pos += length + 1;
+    return Ok(name);
+}
+
+fn read_channels(value: &[u8]) -> io::Result<Vec<Channel>> {
+    let mut channels = Vec::new();
+    let mut pos = 0;
+    loop {
+        let name = read_name(value, &mut pos)?;
+        if name.is_empty() {
+            return Ok(channels);
+        }
+        let pixel_type = le_i32(value, pos)?;
+        if ![UINT, HALF, FLOAT].contains(&pixel_type) {
+            return Err(invalid_data(&format!("bad exr pixel type {}", pixel_type)));
+        }
+        if le_i32(value, pos + 8)? != 1 || le_i32(value, pos + 12)? != 1 {
+            return Err(invalid_data(&format!("unsupported exr subsampling on channel {}", name)));
+        }
+        channels.push(Channel { name: name.to_string(), pixel_type: pixel_type });
+        pos += 16;
+    }
+}
+
+// Decodes a single part scanline exr into its width, height and pixels (top row first), from the R, G and B
+// channels, or Y for grayscale ones. Other channels are skipped.
+pub(crate) fn decode_exr(bytes: &[u8]) -> io::Result<(usize, usize, Vec<Color>)> {
+    if bytes.len() < 8 || bytes[0..4] != MAGIC {
+        return Err(invalid_data("not an exr file"));
+    }
+    let version = le_i32(bytes, 4)? as u32;
+    if version & 0xff != VERSION {
+        return Err(invalid_data(&format!("unsupported exr version {}", version & 0xff)));
+    }
+    if version & (TILED_FLAG | NON_IMAGE_FLAG | MULTI_PART_FLAG) != 0 {
+        return Err(invalid_data("only single part scanline exr files are supported"));
+    }
+
+    let mut pos = 8;
+    let mut channels = None;
+    let mut compression = None;
+    let mut data_window = None;
+    loop {
+        let name = read_name(bytes, &mut pos)?;
+        if name.is_empty() {
+            break;
+        }
+        read_name(bytes, &mut pos)?;
+        let size = le_i32(bytes, pos)?;
+        let value = bytes.get(pos + 4..pos + 4 + size.max(0) as usize).ok_or_else(|| invalid_data("exr header cut off"))?;
+        pos += 4 + value.len();
+        match name {
+            "channels" => channels = Some(read_channels(value)?),
+            "compression" => compression = value.first().copied(),
+            "dataWindow" => data_window = Some([le_i32(value, 0)?, le_i32(value, 4)?, le_i32(value, 8)?, le_i32(value, 12)?]),
+            _ => {},
+        }
+    }
+    let (channels, compression, data_window) = match (channels, compression, data_window) {
+        (Some(channels), Some(compression), Some(data_window)) => (channels, compression, data_window),
+        _ => return Err(invalid_data("exr header is missing channels, compression or dataWindow")),
+    };
+    let lines_per_chunk = match compression {
+        NO_COMPRESSION | ZIPS_COMPRESSION => 1,
+        ZIP_COMPRESSION => ZIP_LINES,
+        _ => return Err(invalid_data(&format!("unsupported exr compression {}", compression))),
+    };
+    let [x_min, y_min, x_max, y_max] = data_window.map(|value| value as i64);
+    let (width, height) = ((x_max - x_min + 1) as usize, (y_max - y_min + 1) as usize);
+    // zlib doesn't compress better than about 1000:1, which bounds the size from the file size
+    if x_max < x_min || y_max < y_min || width.checked_mul(height).is_none_or(|count| count > bytes.len() * 1100) {
+        return Err(invalid_data("bad exr data window"));
+    }
+
+    // Where each of R, G and B come from
+    let find = |name: &str| channels.iter().position(|channel| channel.name == name);
+    let sources = ["R", "G", "B"].map(|name| find(name).or_else(|| find("Y")));
+    if sources.iter().any(|source| source.is_none()) {
+        return Err(invalid_data("exr file has no R, G and B or Y channels"));
+    }
+    let mut channel_offsets = Vec::new();
+    let mut line_size = 0;
+    for channel in channels.iter() {
+        channel_offsets.push(line_size);
+        line_size += channel.sample_size() * width;
+    }
+
+    let mut data = vec![Color::new(0.0, 0.0, 0.0); width * height];
+    let chunk_count = height.div_ceil(lines_per_chunk);
+    for chunk_idx in 0..chunk_count {
+        let offset_bytes = bytes.get(pos + chunk_idx * 8..pos + chunk_idx * 8 + 8).ok_or_else(|| invalid_data("exr offsets cut off"))?;
+        let offset = u64::from_le_bytes(offset_bytes.try_into().unwrap()) as usize;
+        let first_y = le_i32(bytes, offset)? as i64;
+        let size = le_i32(bytes, offset.saturating_add(4))?.max(0) as usize;
+        let chunk = offset.checked_add(8 + size).and_then(|end| bytes.get(offset + 8..end)).ok_or_else(|| invalid_data("exr chunk cut off"))?;
+        if first_y < y_min || first_y > y_max || !((first_y - y_min) as usize).is_multiple_of(lines_per_chunk) {
+            return Err(invalid_data(&format!("bad exr chunk y {}", first_y)));
+        }
+        let first_line = (first_y - y_min) as usize;
+        let lines = lines_per_chunk.min(height - first_line);
+
+        // Chunks that didn't get any smaller are stored as they are
+        let raw_size = lines * line_size;
+        let raw = if size < raw_size && compression != NO_COMPRESSION {
+            zip_unpredict(zlib_decompress(chunk).map_err(|error| invalid_data(&format!("bad exr chunk: {}", error)))?)
+        } else {
+            chunk.to_vec()
+        };
+        if raw.len() != raw_size {
+            return Err(invalid_data("exr chunk has the wrong size"));
+        }
+        for (line_idx, line) in raw.chunks_exact(line_size).enumerate() {
+            let y = first_line + line_idx;
+            for x in 0..width {
+                let [r, g, b] = sources.map(|source| {
+                    let channel_idx = source.unwrap();
+                    let channel = &channels[channel_idx];
+                    let sample_pos = channel_offsets[channel_idx] + x * channel.sample_size();
+                    return channel.sample(&line[sample_pos..]);
+                });
+                data[y * width + x] = Color::new(r, g, b);
+            }
+        }
+    }
+    return Ok((width, height, data));
+}
+
+impl<'buffer> ImageBuffer<'buffer> {
+    // Writes the image as a scanline OpenEXR file with B, G and R channels, without any tonemapping
+    pub fn write_exr_to<W: Write>(&self, out: &mut W, options: &ExrOptions) -> io::Result<()> {
+        if self.width == 0 || self.height == 0 || self.width > i32::MAX as usize || self.height > i32::MAX as usize {
+            let text = format!("can't write a {}x{} exr", self.width, self.height);
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, text));
+        }
+        let (pixel_type, sample_size) = match options.pixel_type {
+            ExrPixelType::Half => (HALF, 2),
+            ExrPixelType::Float => (FLOAT, 4),
+        };
+        let (compression, lines_per_chunk) = match options.compression {
+            ExrCompression::None => (NO_COMPRESSION, 1),
+            ExrCompression::Zip => (ZIP_COMPRESSION, ZIP_LINES),
+        };
+
+        // Channels have to be in alphabetical order
+        let mut channel_list = Vec::new();
+        for name in ["B", "G", "R"] {
+            channel_list.extend_from_slice(name.as_bytes());
+            channel_list.push(0);
+            channel_list.extend_from_slice(&pixel_type.to_le_bytes());
+            channel_list.extend_from_slice(&[0, 0, 0, 0]);
+            channel_list.extend_from_slice(&1i32.to_le_bytes());
+            channel_list.extend_from_slice(&1i32.to_le_bytes());
+        }
+        channel_list.push(0);
+
+        let mut header = Vec::new();
+        header.extend_from_slice(&MAGIC);
+        header.extend_from_slice(&VERSION.to_le_bytes());
+        write_attribute(&mut header, "channels", "chlist", &channel_list);
+        write_attribute(&mut header, "compression", "compression", &[compression]);
+        write_attribute(&mut header, "dataWindow", "box2i", &box2i(self.width, self.height));
+        write_attribute(&mut header, "displayWindow", "box2i", &box2i(self.width, self.height));
+        write_attribute(&mut header, "lineOrder", "lineOrder", &[0]);
+        write_attribute(&mut header, "pixelAspectRatio", "float", &1.0f32.to_le_bytes());
+        write_attribute(&mut header, "screenWindowCenter", "v2f", &[0; 8]);
+        write_attribute(&mut header, "screenWindowWidth", "float", &1.0f32.to_le_bytes());
+        header.push(0);
+
+        // Each chunk is its first y, its size and its lines, with the samples of each channel one after another
+        let mut chunks = Vec::new();
+        for first_y in (0..self.height).step_by(lines_per_chunk) {
+            let lines = lines_per_chunk.min(self.height - first_y);
+            let mut raw = Vec::with_capacity(lines * self.width * 3 * sample_size);
+            for y in first_y..first_y + lines {
+                for channel in [Color::b, Color::g, Color::r] {
+                    for x in 0..self.width {
+                        let value = channel(&self.pixel(x, y));
+                        match options.pixel_type {
+                            ExrPixelType::Half => raw.extend_from_slice(&to_half(value).to_le_bytes()),
+                            ExrPixelType::Float => raw.extend_from_slice(&value.to_le_bytes()),
+                        }
+                    }
+                }
+            }
+            if compression == ZIP_COMPRESSION {
+                let compressed = zlib_compress(&zip_predict(&raw), ZIP_LEVEL);
+                if compressed.len() < raw.len() {
+                    raw = compressed;
+                }
+            }
+            chunks.push((first_y, raw));
+        }
+
+        out.write_all(&header)?;
+        let mut offset = (header.len() + chunks.len() * 8) as u64;
+        for (_, data) in chunks.iter() {
+            out.write_all(&offset.to_le_bytes())?;
+            offset += 8 + data.len() as u64;
+        }
+        for (first_y, data) in chunks.iter() {
+            out.write_all(&(*first_y as i32).to_le_bytes())?;
+            out.write_all(&(data.len() as i32).to_le_bytes())?;
+            out.write_all(data)?;
+        }
+        return out.flush();
+    }
+
+    pub fn write_exr(&self, path: &str, options: &ExrOptions) -> io::Result<()> {
+        let mut out = BufWriter::new(File::create(path)?);
+        return self.write_exr_to(&mut out, options);
+    }
+
+    // Reads a single part scanline exr without compression or with ZIP compression into data_buf
+    pub fn read_exr_from<R: Read>(input: &mut R, data_buf: &'buffer mut Vec<Color>) -> io::Result<ImageBuffer<'buffer>> {
+        let mut bytes = Vec::new();
+        input.read_to_end(&mut bytes)?;
+        let (width, height, data) = decode_exr(&bytes)?;
+        *data_buf = data;
+        return Ok(ImageBuffer::new(width, height, data_buf));
+    }
+
+    pub fn read_exr(path: &str, data_buf: &'buffer mut Vec<Color>) -> io::Result<ImageBuffer<'buffer>> {
+        let mut input = File::open(path)?;
+        return ImageBuffer::read_exr_from(&mut input, data_buf);
+    }
+}
+
+impl Image {
+    // Reads a single part scanline exr without compression or with ZIP compression
+    pub fn read_exr_from<R: Read>(input: &mut R) -> io::Result<Image> {
+        let mut bytes = Vec::new();
+        input.read_to_end(&mut bytes)?;
+        let (width, height, data) = decode_exr(&bytes)?;
+        return Ok(Image::new(width, height, data));
+    }
+
+    pub fn read_exr(path: &str) -> io::Result<Image> {
+        let mut input = File::open(path)?;
+        return Image::read_exr_from(&mut input);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use vector_math::{*};
+    use crate::{Color, ImageBuffer};
+    use super::{ExrCompression, ExrOptions, ExrPixelType, from_half, to_half};
+
+    #[test]
+    fn half_conversion() {
+        assert_eq!(to_half(1.0), 0x3c00);
+        assert_eq!(to_half(-2.0), 0xc000);
+        assert_eq!(to_half(65504.0), 0x7bff);
+        // Rounds to infinity past the largest half, and to the smallest subnormal from half of it up
+        assert_eq!(to_half(65520.0), 0x7c00);
+        assert_eq!(to_half(5.97e-8), 0x0001);
+        assert_eq!(to_half(2.9e-8), 0x0000);
+        assert!(from_half(to_half(Scalar::NAN)).is_nan());
+        assert_eq!(from_half(0x0001), 5.9604645e-8);
+        assert_eq!(from_half(0x3555), 0.33325195);
+    }
+
+    fn round_trip(width: usize, height: usize, options: &ExrOptions) {
+        let mut data: Vec<Color> = (0..width * height).map(|idx| match idx % 4 {
+            0 => Color::new(0.0, 0.0, 0.0),
+            1 => Color::new(idx as Scalar * 0.37, 1.0 / 3.0, 1000.5),
+            _ => Color::new(0.25, 0.5, 2.0),
+        }).collect();
+        let image = ImageBuffer::new(width, height, &mut data);
+        let mut out = Vec::new();
+        image.write_exr_to(&mut out, options).unwrap();
+        let mut data_buf = Vec::new();
+        let back = ImageBuffer::read_exr_from(&mut &out[..], &mut data_buf).unwrap();
+        assert_eq!((back.width, back.height), (width, height));
+        for y in 0..height {
+            for x in 0..width {
+                let (a, b) = (image.pixel(x, y), back.pixel(x, y));
+                for (a, b) in [(a.r(), b.r()), (a.g(), b.g()), (a.b(), b.b())] {
+                    let expected = if options.pixel_type == ExrPixelType::Half { from_half(to_half(a)) } else { a };
+                    assert_eq!(b, expected, "{}x{} {:?} at {}, {}", width, height, options, x, y);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn round_trips() {
+        for pixel_type in [ExrPixelType::Half, ExrPixelType::Float] {
+            for compression in [ExrCompression::None, ExrCompression::Zip] {
+                let options = ExrOptions { pixel_type: pixel_type, compression: compression };
+                // Zip chunks hold 16 scanlines, so the last one is short for most of these heights
+                for (width, height) in [(1, 1), (3, 2), (7, 15), (5, 16), (9, 17), (33, 40)] {
+                    round_trip(width, height, &options);
+                }
+            }
+        }
+    }
+}
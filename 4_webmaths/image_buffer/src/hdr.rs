@@ -0,0 +1,347 @@
+use vector_math::{*};
+use std::fs::File;
+use std::io;
+use std::io::{BufWriter, Read, Write};
+use crate::{Color, Image, ImageBuffer};
+
+// Largest value RGBE can hold, with an exponent byte of 255
+const MAX_RGBE: Scalar = 1.69e38;
+
+// Scanlines can only be run-length encoded if their width fits in the 15 bit length after the marker
+const MIN_RLE_WIDTH: usize = 8;
+const MAX_RLE_WIDTH: usize = 0x7fff;
+const MIN_RUN: usize = 4;
+
+fn invalid_data(text: &str) -> io::Error {
+    return io::Error::new(io::ErrorKind::InvalidData, text);
+}
+
+// 2^exponent, for exponents that stay within normal floats
+fn pow2(exponent: i32) -> Scalar {
+    return Scalar::from_bits(((exponent + 127) as u32) << 23);
+}
+
+// Shared exponent encoding. Negative values and NaNs become 0, and the channels are rounded, except that the
+// largest one is kept below 256.
+fn to_rgbe(color: Color) -> [u8; 4] {
+    // max turns NaNs into 0, which clamp alone would keep
+    let clean = |value: Scalar| value.max(0.0).clamp(0.0, MAX_RGBE);
+    let (r, g, b) = (clean(color.r()), clean(color.g()), clean(color.b()));
+    let max = r.max(g).max(b);
+    if max < 1e-32 {
+        return [0; 4];
+    }
+    // The exponent that puts max in [0.5, 1), as frexp would
+    let exponent = ((max.to_bits() >> 23) & 0xff) as i32 - 126;
+    let scale = pow2(8 - exponent);
+    let channel = |value: Scalar| (value * scale + 0.5).min(255.0) as u8;
+    return [channel(r), channel(g), channel(b), (exponent + 128) as u8];
+}
+
+fn from_rgbe(rgbe: &[u8]) -> Color {
+    if rgbe[3] == 0 {
+        return Color::new(0.0, 0.0, 0.0);
+    }
+    // Small exponents go below the normal range, so this takes a detour through f64
+    let scale = (2.0f64).powi(rgbe[3] as i32 - 136);
+    let channel = |value: u8| (value as f64 * scale) as Scalar;
+    return Color::new(channel(rgbe[0]), channel(rgbe[1]), channel(rgbe[2]));
+}
+
+// Run-length encodes one channel of a scanline: a byte above 128 is a run of (byte - 128) copies of the next
+// byte, anything else is that many literal bytes
+fn encode_channel(data: &[u8], out: &mut Vec<u8>) {
+    let mut pos = 0;
+    while pos < data.len() {
+        // Find the next run that is worth encoding
+        let mut run_start = pos;
+        let mut run_length = 0;
+        while run_start < data.len() {
+            run_length = 1;
+            while run_start + run_length < data.len() && run_length < 127 && data[run_start + run_length] == data[run_start] {
+                run_length += 1;
+            }
+            if run_length >= MIN_RUN {
+                break;
+            }
+            run_start += run_length;
+        }
+        while pos < run_start {
+            let count = (run_start - pos).min(128);
+            out.push(count as u8);
+            out.extend_from_slice(&data[pos..pos + count]);
+            pos += count;
+        }
+        if run_start < data.len() {
+            out.push((128 + run_length) as u8);
+            out.push(data[run_start]);
+            pos = run_start + run_length;
+        }
+    }
+}
+
+fn decode_channel(bytes: &[u8], pos: &mut usize, channel: &mut [u8]) -> io::Result<()> {
+    let cut_off = || invalid_data("hdr scanline cut off");
+    let mut x = 0;
+    while x < channel.len() {
+        let count = *bytes.get(*pos).ok_or_else(cut_off)? as usize;
+        *pos += 1;
+        let (length, literal) = if count > 128 { (count - 128, false) } else { (count, true) };
+        if length == 0 || x + length > channel.len() {
+            return Err(invalid_data("bad hdr scanline run"));
+        }
+        if literal {
+            channel[x..x + length].copy_from_slice(bytes.get(*pos..*pos + length).ok_or_else(cut_off)?);
+            *pos += length;
+        } else {
+            channel[x..x + length].fill(*bytes.get(*pos).ok_or_else(cut_off)?);
+            *pos += 1;
+        }
+        x += length;
+    }
+    return Ok(());
+}
+
+// Reads a scanline of RGBE pixels, either run-length encoded per channel (starting with 2, 2 and the width)
+// or flat, where flat pixels of 1, 1, 1, n repeat the previous pixel, as in the original format
+fn decode_scanline(bytes: &[u8], pos: &mut usize, scanline: &mut [u8]) -> io::Result<()> {
+    let width = scanline.len() / 4;
+    let start = bytes.get(*pos..*pos + 4).ok_or_else(|| invalid_data("hdr scanline cut off"))?;
+    if (MIN_RLE_WIDTH..=MAX_RLE_WIDTH).contains(&width) && start[0] == 2 && start[1] == 2 && start[2] & 0x80 == 0 {
+        if ((start[2] as usize) << 8 | start[3] as usize) != width {
+            return Err(invalid_data("hdr scanline width mismatch"));
+        }
+        *pos += 4;
+        let mut channel = vec![0u8; width];
+        for channel_idx in 0..4 {
+            decode_channel(bytes, pos, &mut channel)?;
+            for (x, &value) in channel.iter().enumerate() {
+                scanline[x * 4 + channel_idx] = value;
+            }
+        }
+        return Ok(());
+    }
+
+    let mut x = 0;
+    let mut shift = 0;
+    while x < width {
+        let pixel = bytes.get(*pos..*pos + 4).ok_or_else(|| invalid_data("hdr scanline cut off"))?;
+        *pos += 4;
+        if pixel[0..3] == [1, 1, 1] {
+            // Each repeat in a row of them holds the next 8 bits of the count, which can't go on forever
+            if shift >= usize::BITS - 8 {
+                return Err(invalid_data("bad hdr scanline run"));
+            }
+            let count = (pixel[3] as usize) << shift;
+            if x == 0 || x + count > width {
+                return Err(invalid_data("bad hdr scanline run"));
+            }
+            for _ in 0..count {
+                scanline.copy_within((x - 1) * 4..x * 4, x * 4);
+                x += 1;
+            }
+            shift += 8;
+        } else {
+            scanline[x * 4..x * 4 + 4].copy_from_slice(pixel);
+            x += 1;
+            shift = 0;
+        }
+    }
+    return Ok(());
+}
+
+// Decodes a whole file into its width, height and pixels (top row first)
+pub(crate) fn decode_hdr(bytes: &[u8]) -> io::Result<(usize, usize, Vec<Color>)> {
+    if !bytes.starts_with(b"#?") {
+        return Err(invalid_data("not a radiance hdr file"));
+    }
+    // Header lines up to an empty one, then the resolution line
+    let mut pos = 0;
+    let next_line = |pos: &mut usize| -> io::Result<String> {
+        let length = bytes[*pos..].iter().position(|&byte| byte == b'\n').ok_or_else(|| invalid_data("hdr header cut off"))?;
+        let line = String::from_utf8_lossy(&bytes[*pos..*pos + length]).trim_end_matches('\r').to_string();
+        *pos += length + 1;
+        return Ok(line);
+    };
+    next_line(&mut pos)?;
+    let mut exposure = 1.0;
+    loop {
+        let line = next_line(&mut pos)?;
+        if line.is_empty() {
+            break;
+        }
+        if let Some(format) = line.strip_prefix("FORMAT=") {
+            if format.trim() != "32-bit_rle_rgbe" {
+                return Err(invalid_data(&format!("unsupported hdr format {}", format.trim())));
+            }
+        } else if let Some(value) = line.strip_prefix("EXPOSURE=") {
+            exposure *= value.trim().parse::<Scalar>().ok().filter(|&value| value > 0.0).ok_or_else(|| invalid_data("bad hdr exposure"))?;
+        }
+    }
+    let resolution = next_line(&mut pos)?;
+    let tokens: Vec<&str> = resolution.split_ascii_whitespace().collect();
+    let (top_down, height, width) = match tokens[..] {
+        [y_axis, height, "+X", width] if y_axis == "-Y" || y_axis == "+Y" => {
+            let parse = |text: &str| text.parse::<usize>().map_err(|_| invalid_data(&format!("bad hdr resolution {}", resolution)));
+            (y_axis == "-Y", parse(height)?, parse(width)?)
+        },
+        _ => return Err(invalid_data(&format!("unsupported hdr resolution {}", resolution))),
+    };
+    // Run-length encoding gets at most 127 pixels out of two bytes per channel, which bounds the size from
+    // the file size, short of old style runs, which nothing writes anymore
+    if height > (bytes.len() - pos) / 4 || width.checked_mul(height).is_none_or(|count| count > bytes.len() * 16) {
+        return Err(invalid_data(&format!("hdr pixel data too short for {}", resolution)));
+    }
+
+    let mut data = vec![Color::new(0.0, 0.0, 0.0); width * height];
+    let mut scanline = vec![0u8; width * 4];
+    for row_idx in 0..height {
+        decode_scanline(bytes, &mut pos, &mut scanline)?;
+        let y = if top_down { row_idx } else { height - row_idx - 1 };
+        for (x, rgbe) in scanline.chunks_exact(4).enumerate() {
+            // Exposure is a factor that was already applied to the values
+            data[y * width + x] = from_rgbe(rgbe) / exposure;
+        }
+    }
+    return Ok((width, height, data));
+}
+
+impl<'buffer> ImageBuffer<'buffer> {
+    // Writes the image as a Radiance hdr (RGBE) file, without any tonemapping. Scanlines are run-length
+    // encoded when they are 8 to 32767 pixels wide, which is what the format allows.
+    pub fn write_hdr_to<W: Write>(&self, out: &mut W) -> io::Result<()> {
+        out.write_all(b"#?RADIANCE\nFORMAT=32-bit_rle_rgbe\n\n")?;
+        out.write_all(format!("-Y {} +X {}\n", self.height, self.width).as_bytes())?;
+        let use_rle = (MIN_RLE_WIDTH..=MAX_RLE_WIDTH).contains(&self.width);
+        let mut channel = Vec::with_capacity(self.width);
+        let mut encoded = Vec::new();
+        for y in 0..self.height {
+            let scanline: Vec<[u8; 4]> = (0..self.width).map(|x| to_rgbe(self.pixel(x, y))).collect();
+            encoded.clear();
+            if use_rle {
+                encoded.extend_from_slice(&[2, 2, (self.width >> 8) as u8, self.width as u8]);
+                for channel_idx in 0..4 {
+                    channel.clear();
+                    channel.extend(scanline.iter().map(|rgbe| rgbe[channel_idx]));
+                    encode_channel(&channel, &mut encoded);
+                }
+            } else {
+                encoded.extend(scanline.iter().flatten());
+            }
+            out.write_all(&encoded)?;
+        }
+        return out.flush();
+    }
+
+    pub fn write_hdr(&self, path: &str) -> io::Result<()> {
+        let mut out = BufWriter::new(File::create(path)?);
+        return self.write_hdr_to(&mut out);
+    }
+
+    // Reads a Radiance hdr file with RGBE pixels, with run-length encoded or flat scanlines, into data_buf.
+    // Values are divided by the EXPOSURE of the header, if there is one.
+    pub fn read_hdr_from<R: Read>(input: &mut R, data_buf: &'buffer mut Vec<Color>) -> io::Result<ImageBuffer<'buffer>> {
+        let mut bytes = Vec::new();
+        input.read_to_end(&mut bytes)?;
+        let (width, height, data) = decode_hdr(&bytes)?;
+        *data_buf = data;
+        return Ok(ImageBuffer::new(width, height, data_buf));
+    }
+
+    pub fn read_hdr(path: &str, data_buf: &'buffer mut Vec<Color>) -> io::Result<ImageBuffer<'buffer>> {
+        let mut input = File::open(path)?;
+        return ImageBuffer::read_hdr_from(&mut input, data_buf);
+    }
+}
+
+impl Image {
+    // Reads a Radiance hdr file, with run-length encoded or flat scanlines
+    pub fn read_hdr_from<R: Read>(input: &mut R) -> io::Result<Image> {
+        let mut bytes = Vec::new();
+        input.read_to_end(&mut bytes)?;
+        let (width, height, data) = decode_hdr(&bytes)?;
+        return Ok(Image::new(width, height, data));
+    }
+
+    pub fn read_hdr(path: &str) -> io::Result<Image> {
+        let mut input = File::open(path)?;
+        return Image::read_hdr_from(&mut input);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use vector_math::{*};
+    use crate::{Color, ImageBuffer};
+
+    // Mix of runs, zeros and values of very different sizes
+    fn test_data(width: usize, height: usize) -> Vec<Color> {
+        return (0..width * height).map(|idx| match idx % 5 {
+            0 => Color::new(0.0, 0.0, 0.0),
+            1 | 2 => Color::new(0.2, 0.2, 0.2),
+            3 => Color::new(idx as Scalar * 10.0, 0.001, 0.5),
+            _ => Color::new(1.0, 0.5, idx as Scalar / 7.0),
+        }).collect();
+    }
+
+    fn round_trip(width: usize, height: usize) {
+        let mut data = test_data(width, height);
+        let image = ImageBuffer::new(width, height, &mut data);
+        let mut out = Vec::new();
+        image.write_hdr_to(&mut out).unwrap();
+        let mut data_buf = Vec::new();
+        let back = ImageBuffer::read_hdr_from(&mut &out[..], &mut data_buf).unwrap();
+        assert_eq!((back.width, back.height), (width, height));
+        for y in 0..height {
+            for x in 0..width {
+                // Channels share the exponent of the largest one, which gets 8 bits of precision
+                let (a, b) = (image.pixel(x, y), back.pixel(x, y));
+                let tolerance = a.r().max(a.g()).max(a.b()) / 128.0;
+                for (a, b) in [(a.r(), b.r()), (a.g(), b.g()), (a.b(), b.b())] {
+                    assert!((a - b).abs() <= tolerance, "{}x{} at {}, {}: {} became {}", width, height, x, y, a, b);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn round_trips_flat_scanlines() {
+        for width in [1, 2, 7] {
+            round_trip(width, 3);
+        }
+        round_trip(32768, 2);
+    }
+
+    #[test]
+    fn round_trips_rle_scanlines() {
+        for width in [8, 9, 130, 32767] {
+            round_trip(width, 3);
+        }
+    }
+
+    #[test]
+    fn reads_flat_runs() {
+        // Bottom up, with a pixel repeated twice and an exposure to divide by
+        let mut file = b"#?RGBE\nEXPOSURE=2\n\n+Y 2 +X 3\n".to_vec();
+        file.extend_from_slice(&[128, 64, 32, 129, 1, 1, 1, 2]);
+        file.extend_from_slice(&[0, 0, 0, 0, 128, 128, 128, 130, 0, 0, 0, 0]);
+        let mut data_buf = Vec::new();
+        let image = ImageBuffer::read_hdr_from(&mut &file[..], &mut data_buf).unwrap();
+        let repeated = image.pixel(2, 1);
+        assert_eq!([repeated.r(), repeated.g(), repeated.b()], [0.5, 0.25, 0.125]);
+        assert_eq!(image.pixel(1, 0).r(), 1.0);
+        assert_eq!(image.pixel(0, 0).r(), 0.0);
+    }
+
+    #[test]
+    fn long_chains_of_repeats_are_rejected() {
+        // Repeats of zero pixels keep shifting the count further
+        let mut file = b"#?RADIANCE\n\n-Y 1 +X 3\n".to_vec();
+        file.extend_from_slice(&[128, 128, 128, 129]);
+        for _ in 0..12 {
+            file.extend_from_slice(&[1, 1, 1, 0]);
+        }
+        let mut data_buf = Vec::new();
+        assert!(ImageBuffer::read_hdr_from(&mut &file[..], &mut data_buf).is_err());
+    }
+}
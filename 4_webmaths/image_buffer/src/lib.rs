@@ -1,9 +1,19 @@
-use packed_struct::prelude::*;
 use vector_math::{*};
 
 use std::vec;
 use std::fs::File;
-use std::io::prelude::*;
+use std::io;
+use std::io::Read;
+
+mod zlib;
+mod png;
+mod bmp;
+mod hdr;
+mod netpbm;
+mod exr;
+pub use png::{PngBitDepth, PngFilter, PngOptions};
+pub use bmp::{BMPHeader, BmpOptions};
+pub use exr::{ExrCompression, ExrOptions, ExrPixelType};
 
 pub type Color = Vec3;
 
@@ -14,25 +24,61 @@ pub struct ImageBuffer<'buffer> {
     data: &'buffer mut [Color]
 }
 
-#[derive(PackedStruct)]
-#[packed_struct(endian="lsb")]
-pub struct BMPHeader {
-    file_type: [u8;2], // "BM"
-	file_size: u32,
-	reserved_1: u16, // 0
-	reserved_2: u16, // 0
-	pixel_offset: u32, // 54
-	header_size: u32, // 40
-	x_size: u32,
-	y_size: u32,
-	planes: u16, // 1
-	bpp: u16, // 24
-	compression: u32, // 0
-	image_size: u32, // 0
-	x_ppm: u32, // 0
-	y_ppm: u32, // 0
-	used_colors: u32, // 0
-	important_colors: u32, // 0
+// An image that owns its pixels, for files that get loaded, like environment maps or reference images. It
+// can be lent out as an ImageBuffer for anything that works on those.
+pub struct Image {
+    pub width: usize,
+    pub height: usize,
+    pub data: Vec<Color>,
+}
+
+impl Image {
+    pub fn new(width: usize, height: usize, data: Vec<Color>) -> Image {
+        assert_eq!(data.len(), width * height, "Image data doesn't match its size");
+        return Image {
+            width: width,
+            height: height,
+            data: data,
+        };
+    }
+
+    #[inline]
+    pub fn pixel(&self, x: usize, y: usize) -> Color {
+        return self.data[y * self.width + x];
+    }
+
+    pub fn buffer(&mut self) -> ImageBuffer<'_> {
+        return ImageBuffer {
+            width: self.width,
+            height: self.height,
+            first_line: 0,
+            data: &mut self.data[..]
+        };
+    }
+
+    // Reads an hdr, pfm, ppm, pgm, exr or bmp file, going by the first bytes rather than the file name
+    pub fn read_from<R: Read>(input: &mut R) -> io::Result<Image> {
+        let mut bytes = Vec::new();
+        input.read_to_end(&mut bytes)?;
+        let (width, height, data) = match bytes.get(0..2).unwrap_or(&[]) {
+            b"#?" => hdr::decode_hdr(&bytes)?,
+            b"PF" | b"Pf" => netpbm::decode_pfm(&bytes)?,
+            b"P2" | b"P3" | b"P5" | b"P6" => netpbm::decode_pnm(&bytes)?,
+            b"v/" => exr::decode_exr(&bytes)?,
+            b"BM" => {
+                let mut data = Vec::new();
+                let buffer = ImageBuffer::read_bmp_from(&mut &bytes[..], &mut data, None)?;
+                (buffer.width, buffer.height, data)
+            },
+            _ => return Err(io::Error::new(io::ErrorKind::InvalidData, "unknown image format")),
+        };
+        return Ok(Image::new(width, height, data));
+    }
+
+    pub fn read(path: &str) -> io::Result<Image> {
+        let mut input = File::open(path)?;
+        return Image::read_from(&mut input);
+    }
 }
 
 impl<'buffer> ImageBuffer<'buffer> {
@@ -115,50 +161,36 @@ impl<'buffer> ImageBuffer<'buffer> {
         }
         return pixel_stream;
     }
+}
 
-    pub fn write_bmp(&self, path: &str) {
-        let header = BMPHeader{
-            file_type: [66, 77],
-            file_size: (self.width * self.height * 3 + 54) as u32,
-            reserved_1: 0,
-            reserved_2: 0,
-            pixel_offset: 54,
-            header_size: 40,
-            x_size: self.width as u32,
-            y_size: self.height as u32,
-            planes: 1,
-            bpp: 24,
-            compression: 0,
-            image_size: 0,
-            x_ppm: 0,
-            y_ppm: 0,
-            used_colors: 0,
-            important_colors: 0,
-        };
-        let header_packed = header.pack().expect("Header packing error");
-        let mut f = File::create(path).expect("Could not open file");
-        f.write_all(&header_packed).expect("Write error");
-
-        let mut line_pos = 0;
-        let line_max = self.width * 3;
-
-        let mut pixel_stream = Vec::new();
-        for y in 0..self.height {
-            for x in 0..self.width {
-                let pixel = self.data[(self.height - y - 1) * self.width + x];
-                pixel_stream.push((pixel.b() * 255.0) as u8);
-                pixel_stream.push((pixel.g() * 255.0) as u8);
-                pixel_stream.push((pixel.r() * 255.0) as u8);
-
-                line_pos += 3;
-                if line_pos == line_max {
-                    while line_pos < line_max + line_max % 4 {
-                        pixel_stream.push(0 as u8);
-                    }
-                    line_pos = 0;
+#[cfg(test)]
+mod tests {
+    use crate::{BmpOptions, Color, ExrOptions, Image, ImageBuffer};
+
+    #[test]
+    fn read_from_goes_by_the_first_bytes() {
+        // Values every format keeps exactly, bmp included
+        let mut data = vec![Color::new(1.0, 0.0, 0.0), Color::new(0.0, 1.0, 0.0), Color::new(0.0, 0.0, 1.0), Color::new(1.0, 1.0, 1.0)];
+        let image = ImageBuffer::new(2, 2, &mut data);
+        let mut files: Vec<Vec<u8>> = vec![Vec::new(); 4];
+        image.write_hdr_to(&mut files[0]).unwrap();
+        image.write_pfm_to(&mut files[1]).unwrap();
+        image.write_exr_to(&mut files[2], &ExrOptions::default()).unwrap();
+        image.write_bmp_to(&mut files[3], None, &BmpOptions::default()).unwrap();
+        files.push(b"P3 2 2 1 1 0 0 0 1 0 0 0 1 1 1 1".to_vec());
+        files.push(b"P6 2 2 255\n\xff\0\0\0\xff\0\0\0\xff\xff\xff\xff".to_vec());
+
+        for file in files.iter() {
+            let read = Image::read_from(&mut &file[..]).unwrap();
+            assert_eq!((read.width, read.height), (2, 2));
+            for y in 0..2 {
+                for x in 0..2 {
+                    let (a, b) = (image.pixel(x, y), read.pixel(x, y));
+                    assert_eq!([a.r(), a.g(), a.b()], [b.r(), b.g(), b.b()]);
                 }
             }
         }
-        f.write_all(&pixel_stream).expect("Write error");
+        assert!(Image::read_from(&mut &b"GIF89a"[..]).is_err());
+        assert!(Image::read_from(&mut &b""[..]).is_err());
     }
 }
@@ -0,0 +1,256 @@
+use vector_math::{*};
+use std::fs::File;
+use std::io;
+use std::io::{BufWriter, Read, Write};
+use crate::{Color, Image, ImageBuffer};
+
+fn invalid_data(text: &str) -> io::Error {
+    return io::Error::new(io::ErrorKind::InvalidData, text);
+}
+
+// Next whitespace separated header field, leaving pos on the whitespace after it. Comments go from # to the
+// end of the line.
+fn header_field<'a>(bytes: &'a [u8], pos: &mut usize) -> io::Result<&'a str> {
+    loop {
+        match bytes.get(*pos) {
+            Some(byte) if byte.is_ascii_whitespace() => *pos += 1,
+            Some(b'#') => {
+                while bytes.get(*pos).is_some_and(|&byte| byte != b'\n') {
+                    *pos += 1;
+                }
+            },
+            _ => break,
+        }
+    }
+    let start = *pos;
+    while bytes.get(*pos).is_some_and(|byte| !byte.is_ascii_whitespace()) {
+        *pos += 1;
+    }
+    if start == *pos {
+        return Err(invalid_data("netpbm header cut off"));
+    }
+    return std::str::from_utf8(&bytes[start..*pos]).map_err(|_| invalid_data("bad netpbm header"));
+}
+
+fn header_size(bytes: &[u8], pos: &mut usize) -> io::Result<usize> {
+    let field = header_field(bytes, pos)?;
+    return field.parse::<usize>().map_err(|_| invalid_data(&format!("bad netpbm size {}", field)));
+}
+
+// Decodes a pfm (PF for color, Pf for grayscale) into its width, height and pixels (top row first). The sign
+// of the scale gives the byte order, its magnitude is ignored, as most programs do.
+pub(crate) fn decode_pfm(bytes: &[u8]) -> io::Result<(usize, usize, Vec<Color>)> {
+    let mut pos = 0;
+    let channels = match header_field(bytes, &mut pos)? {
+        "PF" => 3,
+        "Pf" => 1,
+        _ => return Err(invalid_data("not a pfm file")),
+    };
+    let width = header_size(bytes, &mut pos)?;
+    let height = header_size(bytes, &mut pos)?;
+    let scale = header_field(bytes, &mut pos)?;
+    let little_endian = scale.parse::<Scalar>().map_err(|_| invalid_data(&format!("bad pfm scale {}", scale)))? < 0.0;
+    // Exactly one whitespace character before the data
+    pos += 1;
+    let data_size = width.checked_mul(height).and_then(|count| count.checked_mul(channels * 4));
+    let samples = data_size.and_then(|size| bytes.get(pos..pos.checked_add(size)?)).ok_or_else(|| invalid_data("pfm data cut off"))?;
+
+    let values: Vec<Scalar> = samples.chunks_exact(4).map(|sample| {
+        let sample = [sample[0], sample[1], sample[2], sample[3]];
+        return if little_endian { Scalar::from_le_bytes(sample) } else { Scalar::from_be_bytes(sample) };
+    }).collect();
+    let mut data = vec![Color::new(0.0, 0.0, 0.0); width * height];
+    for (pixel_idx, pixel) in values.chunks_exact(channels).enumerate() {
+        // Rows go from the bottom up
+        let (row_idx, x) = (pixel_idx / width, pixel_idx % width);
+        let y = height - row_idx - 1;
+        data[y * width + x] = if channels == 3 { Color::new(pixel[0], pixel[1], pixel[2]) } else { Color::new(pixel[0], pixel[0], pixel[0]) };
+    }
+    return Ok((width, height, data));
+}
+
+// Decodes a ppm (P3 plain or P6 raw) or pgm (P2 plain or P5 raw) into its width, height and pixels (top row
+// first). Samples are scaled to [0, 1] by the maximum value, as they are, without undoing any gamma.
+pub(crate) fn decode_pnm(bytes: &[u8]) -> io::Result<(usize, usize, Vec<Color>)> {
+    let mut pos = 0;
+    let (channels, raw) = match header_field(bytes, &mut pos)? {
+        "P2" => (1, false),
+        "P3" => (3, false),
+        "P5" => (1, true),
+        "P6" => (3, true),
+        _ => return Err(invalid_data("not a ppm or pgm file")),
+    };
+    let width = header_size(bytes, &mut pos)?;
+    let height = header_size(bytes, &mut pos)?;
+    let max_value = header_size(bytes, &mut pos)?;
+    if max_value == 0 || max_value > 65535 {
+        return Err(invalid_data(&format!("bad netpbm maximum value {}", max_value)));
+    }
+    let count = width.checked_mul(height).and_then(|count| count.checked_mul(channels)).ok_or_else(|| invalid_data("bad netpbm size"))?;
+
+    let samples: Vec<usize> = if raw {
+        // Exactly one whitespace character before the data, then one or two (big-endian) bytes per sample
+        pos += 1;
+        let sample_size = if max_value > 255 { 2 } else { 1 };
+        let data_size = count.checked_mul(sample_size);
+        let data = data_size.and_then(|size| bytes.get(pos..pos.checked_add(size)?)).ok_or_else(|| invalid_data("netpbm data cut off"))?;
+        if sample_size == 2 {
+            data.chunks_exact(2).map(|sample| (sample[0] as usize) << 8 | sample[1] as usize).collect()
+        } else {
+            data.iter().map(|&sample| sample as usize).collect()
+        }
+    } else {
+        let mut samples = Vec::with_capacity(count.min(bytes.len()));
+        for _ in 0..count {
+            let field = header_field(bytes, &mut pos).map_err(|_| invalid_data("netpbm data cut off"))?;
+            samples.push(field.parse::<usize>().map_err(|_| invalid_data(&format!("bad netpbm sample {}", field)))?);
+        }
+        samples
+    };
+    if samples.iter().any(|&sample| sample > max_value) {
+        return Err(invalid_data("netpbm sample above the maximum value"));
+    }
+
+    let data = samples.chunks_exact(channels).map(|pixel| {
+        let value = |idx: usize| pixel[idx] as Scalar / max_value as Scalar;
+        return if channels == 3 { Color::new(value(0), value(1), value(2)) } else { Color::new(value(0), value(0), value(0)) };
+    }).collect();
+    return Ok((width, height, data));
+}
+
+impl<'buffer> ImageBuffer<'buffer> {
+    // Writes the image as a color pfm, with little-endian floats and without any tonemapping
+    pub fn write_pfm_to<W: Write>(&self, out: &mut W) -> io::Result<()> {
+        out.write_all(format!("PF\n{} {}\n-1.0\n", self.width, self.height).as_bytes())?;
+        let mut row = Vec::with_capacity(self.width * 12);
+        for y in (0..self.height).rev() {
+            row.clear();
+            for x in 0..self.width {
+                let pixel = self.pixel(x, y);
+                for value in [pixel.r(), pixel.g(), pixel.b()] {
+                    row.extend_from_slice(&value.to_le_bytes());
+                }
+            }
+            out.write_all(&row)?;
+        }
+        return out.flush();
+    }
+
+    pub fn write_pfm(&self, path: &str) -> io::Result<()> {
+        let mut out = BufWriter::new(File::create(path)?);
+        return self.write_pfm_to(&mut out);
+    }
+
+    // Reads a color or grayscale pfm into data_buf
+    pub fn read_pfm_from<R: Read>(input: &mut R, data_buf: &'buffer mut Vec<Color>) -> io::Result<ImageBuffer<'buffer>> {
+        let mut bytes = Vec::new();
+        input.read_to_end(&mut bytes)?;
+        let (width, height, data) = decode_pfm(&bytes)?;
+        *data_buf = data;
+        return Ok(ImageBuffer::new(width, height, data_buf));
+    }
+
+    pub fn read_pfm(path: &str, data_buf: &'buffer mut Vec<Color>) -> io::Result<ImageBuffer<'buffer>> {
+        let mut input = File::open(path)?;
+        return ImageBuffer::read_pfm_from(&mut input, data_buf);
+    }
+}
+
+impl Image {
+    // Reads a color or grayscale pfm
+    pub fn read_pfm_from<R: Read>(input: &mut R) -> io::Result<Image> {
+        let mut bytes = Vec::new();
+        input.read_to_end(&mut bytes)?;
+        let (width, height, data) = decode_pfm(&bytes)?;
+        return Ok(Image::new(width, height, data));
+    }
+
+    pub fn read_pfm(path: &str) -> io::Result<Image> {
+        let mut input = File::open(path)?;
+        return Image::read_pfm_from(&mut input);
+    }
+
+    // Reads a plain or raw ppm or pgm, with 8 or 16 bit samples scaled to [0, 1]
+    pub fn read_pnm_from<R: Read>(input: &mut R) -> io::Result<Image> {
+        let mut bytes = Vec::new();
+        input.read_to_end(&mut bytes)?;
+        let (width, height, data) = decode_pnm(&bytes)?;
+        return Ok(Image::new(width, height, data));
+    }
+
+    pub fn read_pnm(path: &str) -> io::Result<Image> {
+        let mut input = File::open(path)?;
+        return Image::read_pnm_from(&mut input);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use vector_math::{*};
+    use crate::{Color, Image, ImageBuffer};
+
+    fn pixels(image: &Image) -> Vec<[Scalar; 3]> {
+        return image.data.iter().map(|c| [c.r(), c.g(), c.b()]).collect();
+    }
+
+    #[test]
+    fn reads_plain_pgm_and_ppm() {
+        let pgm = b"P2\n# made by hand\n3 2 # width and height\n15\n0 5 15\n10 15 0\n";
+        let image = Image::read_pnm_from(&mut &pgm[..]).unwrap();
+        assert_eq!((image.width, image.height), (3, 2));
+        let gray = |value: Scalar| [value / 15.0; 3];
+        assert_eq!(pixels(&image), [gray(0.0), gray(5.0), gray(15.0), gray(10.0), gray(15.0), gray(0.0)]);
+
+        // A maximum value above 255 doesn't change anything for plain files
+        let ppm = b"P3 2 1\n#comment\n1000\n1000 0 500\n250 750 1\n";
+        let image = Image::read_pnm_from(&mut &ppm[..]).unwrap();
+        assert_eq!(pixels(&image), [[1.0, 0.0, 0.5], [0.25, 0.75, 0.001]]);
+    }
+
+    #[test]
+    fn reads_raw_pgm_and_ppm() {
+        let mut pgm = b"P5\n# comment\n2 2\n255\n".to_vec();
+        pgm.extend_from_slice(&[0, 51, 255, 102]);
+        let image = Image::read_pnm_from(&mut &pgm[..]).unwrap();
+        assert_eq!((image.width, image.height), (2, 2));
+        assert_eq!(pixels(&image), [[0.0; 3], [0.2; 3], [1.0; 3], [0.4; 3]]);
+
+        // 16 bit samples are big-endian
+        let mut ppm = b"P6 2 1 65535\n".to_vec();
+        for sample in [65535u16, 0, 32768, 256, 1, 13107] {
+            ppm.extend_from_slice(&sample.to_be_bytes());
+        }
+        let image = Image::read_pnm_from(&mut &ppm[..]).unwrap();
+        let expected = [[65535.0, 0.0, 32768.0], [256.0, 1.0, 13107.0]].map(|pixel: [Scalar; 3]| pixel.map(|value| value / 65535.0));
+        assert_eq!(pixels(&image), expected);
+    }
+
+    #[test]
+    fn rejects_samples_above_the_maximum() {
+        let pgm = b"P2 2 1 100 50 101\n";
+        assert!(Image::read_pnm_from(&mut &pgm[..]).is_err());
+        let mut ppm = b"P6 1 1 200\n".to_vec();
+        ppm.extend_from_slice(&[0, 201, 0]);
+        assert!(Image::read_pnm_from(&mut &ppm[..]).is_err());
+        ppm[b"P6 1 1 200\n".len() + 1] = 200;
+        assert!(Image::read_pnm_from(&mut &ppm[..]).is_ok());
+    }
+
+    #[test]
+    fn pfm_round_trips_exactly() {
+        let (width, height) = (5, 3);
+        let mut data: Vec<Color> = (0..width * height).map(|idx| Color::new(idx as f32 * 0.1, -1e30, 1e-40)).collect();
+        let image = ImageBuffer::new(width, height, &mut data);
+        let mut out = Vec::new();
+        image.write_pfm_to(&mut out).unwrap();
+        let mut data_buf = Vec::new();
+        let back = ImageBuffer::read_pfm_from(&mut &out[..], &mut data_buf).unwrap();
+        assert_eq!((back.width, back.height), (width, height));
+        for y in 0..height {
+            for x in 0..width {
+                let (a, b) = (image.pixel(x, y), back.pixel(x, y));
+                assert_eq!([a.r(), a.g(), a.b()], [b.r(), b.g(), b.b()]);
+            }
+        }
+    }
+}
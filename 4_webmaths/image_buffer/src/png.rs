@@ -0,0 +1,358 @@
+use vector_math::{*};
+use std::fs::File;
+use std::io;
+use std::io::{BufWriter, Write};
+use crate::ImageBuffer;
+use crate::zlib::zlib_compress;
+
+const SIGNATURE: [u8; 8] = [137, 80, 78, 71, 13, 10, 26, 10];
+const IDAT_SIZE: usize = 65536;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub enum PngBitDepth {
+    #[default]
+    Eight,
+    Sixteen,
+}
+
+// Per-row filter. Adaptive tries all of them on every row and keeps the one with the smallest sum of
+// absolute (signed) bytes, which is the usual heuristic and tends to compress best.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub enum PngFilter {
+    None,
+    Sub,
+    Up,
+    Average,
+    Paeth,
+    #[default]
+    Adaptive,
+}
+
+#[derive(Clone, Debug)]
+pub struct PngOptions {
+    pub bit_depth: PngBitDepth,
+
+    // zlib level, from 0 (no compression) to 9 (smallest file)
+    pub compression: u32,
+
+    pub filter: PngFilter,
+
+    // Writes a gAMA chunk with the gamma the values are encoded with, e.g. 1/2.2
+    pub gamma: Option<Scalar>,
+
+    // Writes an sRGB chunk (perceptual rendering intent), saying the values are sRGB encoded
+    pub srgb: bool,
+
+    // tEXt chunks, as keyword and text. Both have to be latin-1, and keywords 1 to 79 characters long.
+    pub text: Vec<(String, String)>,
+}
+
+impl Default for PngOptions {
+    fn default() -> PngOptions {
+        return PngOptions {
+            bit_depth: PngBitDepth::Eight,
+            compression: 6,
+            filter: PngFilter::Adaptive,
+            gamma: None,
+            srgb: false,
+            text: Vec::new(),
+        };
+    }
+}
+
+const fn crc32_table() -> [u32; 256] {
+    let mut table = [0u32; 256];
+    let mut idx = 0;
+    while idx < 256 {
+        let mut value = idx as u32;
+        let mut bit = 0;
+        while bit < 8 {
+            value = if value & 1 != 0 { 0xedb88320 ^ (value >> 1) } else { value >> 1 };
+            bit += 1;
+        }
+        table[idx] = value;
+        idx += 1;
+    }
+    return table;
+}
+
+const CRC32_TABLE: [u32; 256] = crc32_table();
+
+fn crc32(parts: &[&[u8]]) -> u32 {
+    let mut crc = 0xffffffffu32;
+    for part in parts {
+        for &byte in part.iter() {
+            crc = CRC32_TABLE[((crc ^ byte as u32) & 0xff) as usize] ^ (crc >> 8);
+        }
+    }
+    return crc ^ 0xffffffff;
+}
+
+fn write_chunk<W: Write>(out: &mut W, chunk_type: &[u8; 4], data: &[u8]) -> io::Result<()> {
+    out.write_all(&(data.len() as u32).to_be_bytes())?;
+    out.write_all(chunk_type)?;
+    out.write_all(data)?;
+    return out.write_all(&crc32(&[chunk_type, data]).to_be_bytes());
+}
+
+fn invalid_input(text: &str) -> io::Error {
+    return io::Error::new(io::ErrorKind::InvalidInput, text);
+}
+
+// Latin-1 bytes of a string, failing for characters outside of it
+fn latin1(text: &str) -> io::Result<Vec<u8>> {
+    return text.chars().map(|c| {
+        return u8::try_from(c as u32).map_err(|_| invalid_input(&format!("png text is not latin-1: {}", text)));
+    }).collect();
+}
+
+fn text_chunk(keyword: &str, text: &str) -> io::Result<Vec<u8>> {
+    let keyword_ok = (1..80).contains(&keyword.chars().count())
+        && keyword.chars().all(|c| (' '..='~').contains(&c) || ('\u{a1}'..='\u{ff}').contains(&c))
+        && !keyword.starts_with(' ') && !keyword.ends_with(' ') && !keyword.contains("  ");
+    if !keyword_ok {
+        return Err(invalid_input(&format!("bad png text keyword: {}", keyword)));
+    }
+    if text.contains('\0') {
+        return Err(invalid_input("png text contains a null character"));
+    }
+    let mut data = latin1(keyword)?;
+    data.push(0);
+    data.extend(latin1(text)?);
+    return Ok(data);
+}
+
+fn paeth(left: u8, up: u8, up_left: u8) -> u8 {
+    let estimate = left as i16 + up as i16 - up_left as i16;
+    let (distance_left, distance_up, distance_up_left) = ((estimate - left as i16).abs(), (estimate - up as i16).abs(), (estimate - up_left as i16).abs());
+    if distance_left <= distance_up && distance_left <= distance_up_left {
+        return left;
+    }
+    if distance_up <= distance_up_left {
+        return up;
+    }
+    return up_left;
+}
+
+// Filters a row into out, given the row above it (all zeros for the first one) and the bytes per pixel
+fn filter_row(filter: PngFilter, row: &[u8], previous: &[u8], bpp: usize, out: &mut Vec<u8>) {
+    out.clear();
+    for idx in 0..row.len() {
+        let left = if idx >= bpp { row[idx - bpp] } else { 0 };
+        let up_left = if idx >= bpp { previous[idx - bpp] } else { 0 };
+        let predicted = match filter {
+            PngFilter::Sub => left,
+            PngFilter::Up => previous[idx],
+            PngFilter::Average => ((left as u16 + previous[idx] as u16) / 2) as u8,
+            PngFilter::Paeth => paeth(left, previous[idx], up_left),
+            _ => 0,
+        };
+        out.push(row[idx].wrapping_sub(predicted));
+    }
+}
+
+fn filter_type(filter: PngFilter) -> u8 {
+    return match filter {
+        PngFilter::Sub => 1,
+        PngFilter::Up => 2,
+        PngFilter::Average => 3,
+        PngFilter::Paeth => 4,
+        _ => 0,
+    };
+}
+
+// Filters all rows, each prefixed with its filter type
+fn filter_rows(pixels: &[u8], stride: usize, bpp: usize, filter: PngFilter) -> Vec<u8> {
+    let candidates = match filter {
+        PngFilter::Adaptive => vec![PngFilter::None, PngFilter::Sub, PngFilter::Up, PngFilter::Average, PngFilter::Paeth],
+        _ => vec![filter],
+    };
+    let mut filtered = Vec::with_capacity(pixels.len() + pixels.len() / stride.max(1));
+    let zero_row = vec![0u8; stride];
+    let mut candidate_row = Vec::with_capacity(stride);
+    let mut best_row = Vec::with_capacity(stride);
+    for (row_idx, row) in pixels.chunks_exact(stride).enumerate() {
+        let previous = if row_idx == 0 { &zero_row[..] } else { &pixels[(row_idx - 1) * stride..row_idx * stride] };
+        let mut best: Option<(u64, PngFilter)> = None;
+        for &candidate in candidates.iter() {
+            filter_row(candidate, row, previous, bpp, &mut candidate_row);
+            let cost = if candidates.len() > 1 { candidate_row.iter().map(|&byte| (byte as i8).unsigned_abs() as u64).sum() } else { 0 };
+            if best.is_none_or(|(best_cost, _)| cost < best_cost) {
+                best = Some((cost, candidate));
+                std::mem::swap(&mut best_row, &mut candidate_row);
+            }
+        }
+        filtered.push(filter_type(best.unwrap().1));
+        filtered.extend_from_slice(&best_row);
+    }
+    return filtered;
+}
+
+impl<'buffer> ImageBuffer<'buffer> {
+    // Writes the image as png, clamping values to [0, 1] without any tonemapping or gamma. With alpha
+    // (one value per pixel, in the same order as the pixels) the png is RGBA, otherwise RGB.
+    pub fn write_png_to<W: Write>(&self, out: &mut W, alpha: Option<&[Scalar]>, options: &PngOptions) -> io::Result<()> {
+        if let Some(alpha) = alpha {
+            if alpha.len() != self.width * self.height {
+                return Err(invalid_input(&format!("{} alpha values for {} pixels", alpha.len(), self.width * self.height)));
+            }
+        }
+        if self.width == 0 || self.height == 0 || self.width > i32::MAX as usize || self.height > i32::MAX as usize {
+            return Err(invalid_input(&format!("can't write a {}x{} png", self.width, self.height)));
+        }
+        let text_chunks = options.text.iter().map(|(keyword, text)| text_chunk(keyword, text)).collect::<io::Result<Vec<_>>>()?;
+
+        // Interleaved big-endian samples
+        let channels = if alpha.is_some() { 4 } else { 3 };
+        let (sample_bytes, max_value) = match options.bit_depth {
+            PngBitDepth::Eight => (1, 255.0),
+            PngBitDepth::Sixteen => (2, 65535.0),
+        };
+        let mut pixels = Vec::with_capacity(self.width * self.height * channels * sample_bytes);
+        for pixel_idx in 0..self.width * self.height {
+            let pixel = self.data[pixel_idx];
+            let samples = [pixel.r(), pixel.g(), pixel.b(), alpha.map_or(1.0, |alpha| alpha[pixel_idx])];
+            for &sample in samples[..channels].iter() {
+                let value = (sample.clamp(0.0, 1.0) * max_value + 0.5) as u16;
+                if sample_bytes == 2 {
+                    pixels.push((value >> 8) as u8);
+                }
+                pixels.push(value as u8);
+            }
+        }
+        let bpp = channels * sample_bytes;
+        let compressed = zlib_compress(&filter_rows(&pixels, self.width * bpp, bpp, options.filter), options.compression);
+
+        out.write_all(&SIGNATURE)?;
+        let mut header = Vec::with_capacity(13);
+        header.extend_from_slice(&(self.width as u32).to_be_bytes());
+        header.extend_from_slice(&(self.height as u32).to_be_bytes());
+        header.push(8 * sample_bytes as u8);
+        header.push(if alpha.is_some() { 6 } else { 2 });
+        header.extend_from_slice(&[0, 0, 0]);
+        write_chunk(out, b"IHDR", &header)?;
+        if let Some(gamma) = options.gamma {
+            write_chunk(out, b"gAMA", &((gamma * 100000.0).round() as u32).to_be_bytes())?;
+        }
+        if options.srgb {
+            write_chunk(out, b"sRGB", &[0])?;
+        }
+        for text in text_chunks.iter() {
+            write_chunk(out, b"tEXt", text)?;
+        }
+        for data in compressed.chunks(IDAT_SIZE) {
+            write_chunk(out, b"IDAT", data)?;
+        }
+        write_chunk(out, b"IEND", &[])?;
+        return out.flush();
+    }
+
+    pub fn write_png(&self, path: &str, alpha: Option<&[Scalar]>, options: &PngOptions) -> io::Result<()> {
+        let mut out = BufWriter::new(File::create(path)?);
+        return self.write_png_to(&mut out, alpha, options);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use vector_math::{*};
+    use crate::{Color, ImageBuffer};
+    use crate::zlib::zlib_decompress;
+    use super::{PngBitDepth, PngFilter, PngOptions, SIGNATURE, crc32, paeth};
+
+    fn be_u32(bytes: &[u8]) -> u32 {
+        return u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]);
+    }
+
+    // The chunks of a png, checking their crcs
+    fn chunks(png: &[u8]) -> Vec<([u8; 4], Vec<u8>)> {
+        assert_eq!(png[..8], SIGNATURE);
+        let mut chunks = Vec::new();
+        let mut pos = 8;
+        while pos < png.len() {
+            let length = be_u32(&png[pos..]) as usize;
+            let chunk_type: [u8; 4] = png[pos + 4..pos + 8].try_into().unwrap();
+            let data = &png[pos + 8..pos + 8 + length];
+            assert_eq!(be_u32(&png[pos + 8 + length..]), crc32(&[&chunk_type, data]));
+            chunks.push((chunk_type, data.to_vec()));
+            pos += 12 + length;
+        }
+        return chunks;
+    }
+
+    // Undoes the filters of all rows
+    fn unfilter(filtered: &[u8], stride: usize, bpp: usize) -> Vec<u8> {
+        let mut pixels: Vec<u8> = Vec::with_capacity(filtered.len());
+        for (row_idx, row) in filtered.chunks_exact(stride + 1).enumerate() {
+            let start = pixels.len();
+            for idx in 0..stride {
+                let left = if idx >= bpp { pixels[start + idx - bpp] } else { 0 };
+                let up = if row_idx > 0 { pixels[start + idx - stride] } else { 0 };
+                let up_left = if row_idx > 0 && idx >= bpp { pixels[start + idx - stride - bpp] } else { 0 };
+                let predicted = match row[0] {
+                    0 => 0,
+                    1 => left,
+                    2 => up,
+                    3 => ((left as u16 + up as u16) / 2) as u8,
+                    4 => paeth(left, up, up_left),
+                    filter => panic!("filter type {}", filter),
+                };
+                pixels.push(row[idx + 1].wrapping_add(predicted));
+            }
+        }
+        return pixels;
+    }
+
+    #[test]
+    fn crc32_known_value() {
+        assert_eq!(crc32(&[b"123456789"]), 0xcbf43926);
+        assert_eq!(crc32(&[b"1234", b"56789"]), 0xcbf43926);
+    }
+
+    #[test]
+    fn samples_survive_filtering_and_compression() {
+        let (width, height) = (7, 5);
+        let mut data: Vec<Color> = (0..width * height).map(|idx| {
+            let (x, y) = ((idx % width) as Scalar, (idx / width) as Scalar);
+            return Color::new(x / 6.0, y / 4.0, ((idx * 37) % 101) as Scalar / 100.0 * 1.2 - 0.1);
+        }).collect();
+        let image = ImageBuffer::new(width, height, &mut data);
+        let alpha: Vec<Scalar> = (0..width * height).map(|idx| (idx % 7) as Scalar / 6.0).collect();
+
+        for bit_depth in [PngBitDepth::Eight, PngBitDepth::Sixteen] {
+            for alpha in [None, Some(&alpha[..])] {
+                for filter in [PngFilter::None, PngFilter::Sub, PngFilter::Up, PngFilter::Average, PngFilter::Paeth, PngFilter::Adaptive] {
+                    let options = PngOptions { bit_depth: bit_depth, filter: filter, ..PngOptions::default() };
+                    let mut out = Vec::new();
+                    image.write_png_to(&mut out, alpha, &options).unwrap();
+                    let chunks = chunks(&out);
+
+                    let channels = if alpha.is_some() { 4 } else { 3 };
+                    let (sample_bytes, max_value) = match bit_depth {
+                        PngBitDepth::Eight => (1, 255.0),
+                        PngBitDepth::Sixteen => (2, 65535.0),
+                    };
+                    let header = &chunks[0].1;
+                    assert_eq!(&chunks[0].0, b"IHDR");
+                    assert_eq!((be_u32(header), be_u32(&header[4..])), (width as u32, height as u32));
+                    assert_eq!((header[8], header[9]), (8 * sample_bytes as u8, if alpha.is_some() { 6 } else { 2 }));
+                    assert_eq!(&chunks.last().unwrap().0, b"IEND");
+
+                    let compressed: Vec<u8> = chunks.iter().filter(|(chunk_type, _)| chunk_type == b"IDAT").flat_map(|(_, data)| data.clone()).collect();
+                    let bpp = channels * sample_bytes;
+                    let pixels = unfilter(&zlib_decompress(&compressed).unwrap(), width * bpp, bpp);
+                    assert_eq!(pixels.len(), width * height * bpp);
+                    for (pixel_idx, pixel) in pixels.chunks_exact(bpp).enumerate() {
+                        let color = image.data[pixel_idx];
+                        let expected = [color.r(), color.g(), color.b(), alpha.map_or(1.0, |alpha| alpha[pixel_idx])];
+                        for channel in 0..channels {
+                            let sample = &pixel[channel * sample_bytes..(channel + 1) * sample_bytes];
+                            let value = if sample_bytes == 2 { u16::from_be_bytes([sample[0], sample[1]]) } else { sample[0] as u16 };
+                            assert_eq!(value, (expected[channel].clamp(0.0, 1.0) * max_value + 0.5) as u16);
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
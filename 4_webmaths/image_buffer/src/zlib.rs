@@ -0,0 +1,709 @@
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
+
+// zlib (deflate) compression, as used by png and exr. Levels go from 0 (stored, no compression) to 9 (slowest,
+// smallest). Matches are found with hash chains, searched further and lazily at higher levels, and every block
+// gets its own huffman codes, or the fixed ones or is stored if that comes out smaller.
+
+const WINDOW_SIZE: usize = 32768;
+const HASH_BITS: usize = 15;
+const MIN_MATCH: usize = 3;
+const MAX_MATCH: usize = 258;
+const BLOCK_SYMBOLS: usize = 65536;
+const END_OF_BLOCK: usize = 256;
+
+// Base values and extra bits of the length (257..285) and distance (0..29) codes
+const LENGTH_BASE: [u16; 29] = [
+    3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31, 35, 43, 51, 59, 67, 83, 99, 115, 131, 163, 195, 227, 258
+];
+const LENGTH_EXTRA: [u8; 29] = [0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0];
+const DISTANCE_BASE: [u16; 30] = [
+    1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193, 257, 385, 513, 769, 1025, 1537, 2049, 3073,
+    4097, 6145, 8193, 12289, 16385, 24577
+];
+const DISTANCE_EXTRA: [u8; 30] = [0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6, 7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13, 13];
+
+// Order in which code length code lengths are stored
+const CODE_LENGTH_ORDER: [usize; 19] = [16, 17, 18, 0, 8, 7, 9, 6, 10, 5, 11, 4, 12, 3, 13, 2, 14, 1, 15];
+
+// How hard each level looks for matches: hash chain steps, a length that is good enough to stop at,
+// and whether to check if the next position has a better match before taking one
+struct LevelParams {
+    max_chain: usize,
+    nice_length: usize,
+    lazy: bool,
+}
+
+fn level_params(level: u32) -> LevelParams {
+    let (max_chain, nice_length, lazy) = match level {
+        1 => (4, 8, false),
+        2 => (8, 16, false),
+        3 => (16, 32, false),
+        4 => (16, 32, true),
+        5 => (32, 64, true),
+        6 => (128, 128, true),
+        7 => (256, 258, true),
+        8 => (1024, 258, true),
+        _ => (4096, 258, true),
+    };
+    return LevelParams { max_chain: max_chain, nice_length: nice_length, lazy: lazy };
+}
+
+pub fn adler32(bytes: &[u8]) -> u32 {
+    let (mut a, mut b) = (1u32, 0u32);
+    // 5552 bytes is the most that can be summed before b could overflow
+    for chunk in bytes.chunks(5552) {
+        for &byte in chunk {
+            a += byte as u32;
+            b += a;
+        }
+        a %= 65521;
+        b %= 65521;
+    }
+    return (b << 16) | a;
+}
+
+// Writes bits starting from the least significant one, like deflate wants
+struct BitWriter {
+    bytes: Vec<u8>,
+    bit_buffer: u64,
+    bit_count: u32,
+}
+
+impl BitWriter {
+    fn write(&mut self, value: u32, bits: u32) {
+        self.bit_buffer |= (value as u64) << self.bit_count;
+        self.bit_count += bits;
+        while self.bit_count >= 8 {
+            self.bytes.push(self.bit_buffer as u8);
+            self.bit_buffer >>= 8;
+            self.bit_count -= 8;
+        }
+    }
+
+    fn align(&mut self) {
+        if self.bit_count > 0 {
+            self.write(0, 8 - self.bit_count);
+        }
+    }
+}
+
+// A literal byte, or a match of length bytes starting distance bytes back
+#[derive(Clone, Copy)]
+enum Symbol {
+    Literal(u8),
+    Match(u16, u16),
+}
+
+fn length_code(length: usize) -> usize {
+    return LENGTH_BASE.iter().rposition(|&base| base as usize <= length).unwrap();
+}
+
+fn distance_code(distance: usize) -> usize {
+    return DISTANCE_BASE.iter().rposition(|&base| base as usize <= distance).unwrap();
+}
+
+// Hash chains over the last WINDOW_SIZE positions
+struct MatchFinder<'a> {
+    data: &'a [u8],
+    head: Vec<u32>,
+    prev: Vec<u32>,
+}
+
+impl<'a> MatchFinder<'a> {
+    fn new(data: &'a [u8]) -> MatchFinder<'a> {
+        return MatchFinder {
+            data: data,
+            head: vec![u32::MAX; 1 << HASH_BITS],
+            prev: vec![u32::MAX; WINDOW_SIZE],
+        };
+    }
+
+    fn hash(&self, pos: usize) -> usize {
+        let value = (self.data[pos] as u32) << 16 | (self.data[pos + 1] as u32) << 8 | self.data[pos + 2] as u32;
+        return (value.wrapping_mul(2654435761) >> (32 - HASH_BITS)) as usize;
+    }
+
+    fn insert(&mut self, pos: usize) {
+        if pos + MIN_MATCH <= self.data.len() {
+            let hash = self.hash(pos);
+            self.prev[pos % WINDOW_SIZE] = self.head[hash];
+            self.head[hash] = pos as u32;
+        }
+    }
+
+    // Longest earlier match for the bytes at pos, as (length, distance)
+    fn longest_match(&self, pos: usize, params: &LevelParams) -> Option<(usize, usize)> {
+        if pos + MIN_MATCH > self.data.len() {
+            return None;
+        }
+        let max_length = MAX_MATCH.min(self.data.len() - pos);
+        let mut best: Option<(usize, usize)> = None;
+        let mut candidate = self.head[self.hash(pos)];
+        for _ in 0..params.max_chain {
+            if candidate == u32::MAX || pos - candidate as usize > WINDOW_SIZE {
+                break;
+            }
+            let start = candidate as usize;
+            let best_length = best.map_or(MIN_MATCH - 1, |(length, _)| length);
+            // Cheap check of the byte that would make this match longer than the best so far
+            if self.data[start + best_length] == self.data[pos + best_length] {
+                let length = self.data[start..start + max_length].iter().zip(self.data[pos..pos + max_length].iter()).take_while(|(a, b)| a == b).count();
+                if length > best_length {
+                    best = Some((length, pos - start));
+                    if length >= params.nice_length.min(max_length) {
+                        break;
+                    }
+                }
+            }
+            let next = self.prev[start % WINDOW_SIZE];
+            if next == u32::MAX || next as usize >= start {
+                break;
+            }
+            candidate = next;
+        }
+        return best;
+    }
+}
+
+// Turns the data into literals and matches
+fn find_symbols(data: &[u8], level: u32) -> Vec<Symbol> {
+    let params = level_params(level);
+    let mut finder = MatchFinder::new(data);
+    let mut symbols = Vec::with_capacity(data.len() / 2);
+    let mut pos = 0;
+    while pos < data.len() {
+        let mut found = finder.longest_match(pos, &params);
+        finder.insert(pos);
+        if let (Some((length, _)), true) = (found, params.lazy) {
+            // A longer match one byte later beats this one plus a literal
+            if length < params.nice_length {
+                if let Some((next_length, _)) = finder.longest_match(pos + 1, &params) {
+                    if next_length > length {
+                        found = None;
+                    }
+                }
+            }
+        }
+        match found {
+            Some((length, distance)) => {
+                symbols.push(Symbol::Match(length as u16, distance as u16));
+                for match_pos in pos + 1..pos + length {
+                    finder.insert(match_pos);
+                }
+                pos += length;
+            },
+            None => {
+                symbols.push(Symbol::Literal(data[pos]));
+                pos += 1;
+            }
+        }
+    }
+    return symbols;
+}
+
+// Huffman code lengths for the given symbol frequencies, no longer than max_bits. Frequencies get halved
+// until the lengths fit, which is slightly worse than optimal but simple.
+fn code_lengths(frequencies: &[u32], max_bits: u8) -> Vec<u8> {
+    let mut frequencies = frequencies.to_vec();
+    // Decoders want complete codes, so there have to be at least two symbols
+    let mut used = frequencies.iter().filter(|&&frequency| frequency > 0).count();
+    for frequency in frequencies.iter_mut() {
+        if used >= 2 {
+            break;
+        }
+        if *frequency == 0 {
+            *frequency = 1;
+            used += 1;
+        }
+    }
+
+    loop {
+        // Nodes are the symbols, then the merged nodes, each with the index of their parent
+        let mut parents = vec![usize::MAX; frequencies.len()];
+        let mut heap: BinaryHeap<Reverse<(u64, usize)>> = frequencies.iter().enumerate()
+            .filter(|(_, &frequency)| frequency > 0)
+            .map(|(symbol, &frequency)| Reverse((frequency as u64, symbol)))
+            .collect();
+        while heap.len() > 1 {
+            let Reverse((frequency_a, node_a)) = heap.pop().unwrap();
+            let Reverse((frequency_b, node_b)) = heap.pop().unwrap();
+            parents.push(usize::MAX);
+            let merged = parents.len() - 1;
+            parents[node_a] = merged;
+            parents[node_b] = merged;
+            heap.push(Reverse((frequency_a + frequency_b, merged)));
+        }
+
+        let mut lengths = vec![0u8; frequencies.len()];
+        let mut too_long = false;
+        for symbol in 0..frequencies.len() {
+            if frequencies[symbol] == 0 {
+                continue;
+            }
+            let mut depth = 0;
+            let mut node = symbol;
+            while parents[node] != usize::MAX {
+                node = parents[node];
+                depth += 1;
+            }
+            too_long |= depth > max_bits as usize;
+            lengths[symbol] = depth as u8;
+        }
+        if !too_long {
+            return lengths;
+        }
+        for frequency in frequencies.iter_mut().filter(|frequency| **frequency > 0) {
+            *frequency = frequency.div_ceil(2);
+        }
+    }
+}
+
+// Canonical codes for code lengths, bit reversed so they can be written least significant bit first
+fn canonical_codes(lengths: &[u8]) -> Vec<u32> {
+    let mut length_counts = [0u32; 16];
+    for &length in lengths {
+        length_counts[length as usize] += 1;
+    }
+    length_counts[0] = 0;
+    let mut next_code = [0u32; 16];
+    let mut code = 0;
+    for bits in 1..16 {
+        code = (code + length_counts[bits - 1]) << 1;
+        next_code[bits] = code;
+    }
+    return lengths.iter().map(|&length| {
+        if length == 0 {
+            return 0;
+        }
+        let code = next_code[length as usize];
+        next_code[length as usize] += 1;
+        return code.reverse_bits() >> (32 - length as u32);
+    }).collect();
+}
+
+// Run length encodes the literal/length and distance code lengths into code length symbols, with the
+// extra bits value for the repeat codes 16, 17 and 18
+fn run_length_encode(lengths: &[u8]) -> Vec<(u8, u8)> {
+    let mut encoded = Vec::new();
+    let mut pos = 0;
+    while pos < lengths.len() {
+        let length = lengths[pos];
+        let run = lengths[pos..].iter().take_while(|&&other| other == length).count();
+        if length == 0 && run >= 11 {
+            let count = run.min(138);
+            encoded.push((18, (count - 11) as u8));
+            pos += count;
+        } else if length == 0 && run >= 3 {
+            encoded.push((17, (run - 3) as u8));
+            pos += run;
+        } else if length != 0 && run >= 4 {
+            encoded.push((length, 0));
+            let count = (run - 1).min(6);
+            encoded.push((16, (count - 3) as u8));
+            pos += 1 + count;
+        } else {
+            encoded.push((length, 0));
+            pos += 1;
+        }
+    }
+    return encoded;
+}
+
+fn write_stored_block(out: &mut BitWriter, data: &[u8], last: bool) {
+    out.write(last as u32, 1);
+    out.write(0, 2);
+    out.align();
+    out.write(data.len() as u32, 16);
+    out.write(!(data.len() as u16) as u32, 16);
+    out.bytes.extend_from_slice(data);
+}
+
+fn write_stored_blocks(out: &mut BitWriter, data: &[u8], last: bool) {
+    if data.is_empty() {
+        write_stored_block(out, data, last);
+    }
+    let chunk_count = data.len().div_ceil(65535);
+    for (chunk_idx, chunk) in data.chunks(65535).enumerate() {
+        write_stored_block(out, chunk, last && chunk_idx == chunk_count - 1);
+    }
+}
+
+// Size in bits of the symbols of a block with the given code lengths, end of block included
+fn symbol_bits(literal_frequencies: &[u32], distance_frequencies: &[u32], literal_lengths: &[u8], distance_lengths: &[u8]) -> usize {
+    let mut bits = 0;
+    for symbol in 0..286 {
+        bits += literal_frequencies[symbol] as usize * literal_lengths[symbol] as usize;
+        if symbol >= 257 {
+            bits += literal_frequencies[symbol] as usize * LENGTH_EXTRA[symbol - 257] as usize;
+        }
+    }
+    for symbol in 0..30 {
+        bits += distance_frequencies[symbol] as usize * (distance_lengths[symbol] + DISTANCE_EXTRA[symbol]) as usize;
+    }
+    return bits;
+}
+
+fn write_symbols(out: &mut BitWriter, symbols: &[Symbol], literal_lengths: &[u8], distance_lengths: &[u8]) {
+    let literal_codes = canonical_codes(literal_lengths);
+    let distance_codes = canonical_codes(distance_lengths);
+    for symbol in symbols {
+        match *symbol {
+            Symbol::Literal(byte) => out.write(literal_codes[byte as usize], literal_lengths[byte as usize] as u32),
+            Symbol::Match(length, distance) => {
+                let (length, distance) = (length as usize, distance as usize);
+                let code = length_code(length);
+                out.write(literal_codes[257 + code], literal_lengths[257 + code] as u32);
+                out.write((length - LENGTH_BASE[code] as usize) as u32, LENGTH_EXTRA[code] as u32);
+                let code = distance_code(distance);
+                out.write(distance_codes[code], distance_lengths[code] as u32);
+                out.write((distance - DISTANCE_BASE[code] as usize) as u32, DISTANCE_EXTRA[code] as u32);
+            }
+        }
+    }
+    out.write(literal_codes[END_OF_BLOCK], literal_lengths[END_OF_BLOCK] as u32);
+}
+
+// Writes a block with its own huffman codes, the fixed ones or stored, whichever is smallest. data is what
+// the symbols encode.
+fn write_block(out: &mut BitWriter, symbols: &[Symbol], data: &[u8], last: bool) {
+    let mut literal_frequencies = [0u32; 286];
+    let mut distance_frequencies = [0u32; 30];
+    for symbol in symbols {
+        match *symbol {
+            Symbol::Literal(byte) => literal_frequencies[byte as usize] += 1,
+            Symbol::Match(length, distance) => {
+                literal_frequencies[257 + length_code(length as usize)] += 1;
+                distance_frequencies[distance_code(distance as usize)] += 1;
+            }
+        }
+    }
+    literal_frequencies[END_OF_BLOCK] = 1;
+
+    let literal_lengths = code_lengths(&literal_frequencies, 15);
+    let distance_lengths = code_lengths(&distance_frequencies, 15);
+    let literal_count = 257.max(literal_lengths.iter().rposition(|&length| length > 0).unwrap() + 1);
+    let distance_count = 1.max(distance_lengths.iter().rposition(|&length| length > 0).unwrap() + 1);
+    let mut all_lengths = literal_lengths[..literal_count].to_vec();
+    all_lengths.extend_from_slice(&distance_lengths[..distance_count]);
+    let encoded_lengths = run_length_encode(&all_lengths);
+
+    let mut code_length_frequencies = [0u32; 19];
+    for &(symbol, _) in encoded_lengths.iter() {
+        code_length_frequencies[symbol as usize] += 1;
+    }
+    let code_length_lengths = code_lengths(&code_length_frequencies, 7);
+    let code_length_count = 4.max(CODE_LENGTH_ORDER.iter().rposition(|&symbol| code_length_lengths[symbol] > 0).unwrap() + 1);
+
+    // The fixed codes: literals 0..143 and lengths 280..287 get 8 bits, literals 144..255 9 bits,
+    // end of block and lengths 257..279 7 bits, and all distances 5 bits
+    let fixed_literal_lengths: Vec<u8> = (0..288).map(|symbol| match symbol {
+        0..=143 => 8,
+        144..=255 => 9,
+        256..=279 => 7,
+        _ => 8,
+    }).collect();
+    let fixed_distance_lengths = [5u8; 30];
+
+    // Compare sizes in bits before writing anything
+    let repeat_extra_bits = |symbol: u8| -> u32 { match symbol { 16 => 2, 17 => 3, 18 => 7, _ => 0 } };
+    let mut huffman_bits = 3 + 5 + 5 + 4 + 3 * code_length_count;
+    huffman_bits += encoded_lengths.iter().map(|&(symbol, _)| code_length_lengths[symbol as usize] as usize + repeat_extra_bits(symbol) as usize).sum::<usize>();
+    huffman_bits += symbol_bits(&literal_frequencies, &distance_frequencies, &literal_lengths, &distance_lengths);
+    let fixed_bits = 3 + symbol_bits(&literal_frequencies, &distance_frequencies, &fixed_literal_lengths, &fixed_distance_lengths);
+    let stored_bits = (data.len().div_ceil(65535).max(1) * 5 + data.len()) * 8 + 7;
+    if stored_bits <= huffman_bits.min(fixed_bits) {
+        write_stored_blocks(out, data, last);
+        return;
+    }
+    if fixed_bits <= huffman_bits {
+        out.write(last as u32, 1);
+        out.write(1, 2);
+        write_symbols(out, symbols, &fixed_literal_lengths, &fixed_distance_lengths);
+        return;
+    }
+
+    out.write(last as u32, 1);
+    out.write(2, 2);
+    out.write((literal_count - 257) as u32, 5);
+    out.write((distance_count - 1) as u32, 5);
+    out.write((code_length_count - 4) as u32, 4);
+    for &symbol in CODE_LENGTH_ORDER[..code_length_count].iter() {
+        out.write(code_length_lengths[symbol] as u32, 3);
+    }
+    let code_length_codes = canonical_codes(&code_length_lengths);
+    for &(symbol, extra) in encoded_lengths.iter() {
+        out.write(code_length_codes[symbol as usize], code_length_lengths[symbol as usize] as u32);
+        out.write(extra as u32, repeat_extra_bits(symbol));
+    }
+    write_symbols(out, symbols, &literal_lengths, &distance_lengths);
+}
+
+// Raw deflate stream
+pub fn deflate(data: &[u8], level: u32) -> Vec<u8> {
+    let mut out = BitWriter { bytes: Vec::with_capacity(data.len() / 2 + 64), bit_buffer: 0, bit_count: 0 };
+    if level == 0 {
+        write_stored_blocks(&mut out, data, true);
+        return out.bytes;
+    }
+
+    let symbols = find_symbols(data, level);
+    let block_count = symbols.len().div_ceil(BLOCK_SYMBOLS).max(1);
+    let mut data_pos = 0;
+    for block_idx in 0..block_count {
+        let block = &symbols[(block_idx * BLOCK_SYMBOLS).min(symbols.len())..((block_idx + 1) * BLOCK_SYMBOLS).min(symbols.len())];
+        let block_bytes: usize = block.iter().map(|symbol| match *symbol {
+            Symbol::Literal(_) => 1,
+            Symbol::Match(length, _) => length as usize,
+        }).sum();
+        write_block(&mut out, block, &data[data_pos..data_pos + block_bytes], block_idx == block_count - 1);
+        data_pos += block_bytes;
+    }
+    out.align();
+    return out.bytes;
+}
+
+// deflate with the zlib header and checksum around it
+pub fn zlib_compress(data: &[u8], level: u32) -> Vec<u8> {
+    let level = level.min(9);
+    let level_flags = match level {
+        0 | 1 => 0,
+        2..=5 => 1,
+        6 => 2,
+        _ => 3,
+    };
+    // Deflate with a 32k window, with the check bits making the header a multiple of 31
+    let header = 0x7800 | level_flags << 6;
+    let header = header + (31 - header % 31) % 31;
+    let mut out = vec![(header >> 8) as u8, header as u8];
+    out.extend(deflate(data, level));
+    out.extend_from_slice(&adler32(data).to_be_bytes());
+    return out;
+}
+
+// Reads bits starting from the least significant one
+struct BitReader<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+    bit_buffer: u64,
+    bit_count: u32,
+}
+
+impl<'a> BitReader<'a> {
+    fn bits(&mut self, count: u32) -> Result<u32, String> {
+        while self.bit_count < count {
+            let byte = *self.bytes.get(self.pos).ok_or_else(|| "deflate data cut off".to_string())?;
+            self.bit_buffer |= (byte as u64) << self.bit_count;
+            self.pos += 1;
+            self.bit_count += 8;
+        }
+        let value = (self.bit_buffer & ((1u64 << count) - 1)) as u32;
+        self.bit_buffer >>= count;
+        self.bit_count -= count;
+        return Ok(value);
+    }
+
+    // Drops the rest of the current byte
+    fn align(&mut self) {
+        self.bit_buffer >>= self.bit_count % 8;
+        self.bit_count -= self.bit_count % 8;
+    }
+}
+
+// A canonical huffman code for decoding: how many codes there are of each length, and the symbols in code order
+struct Decoder {
+    counts: [u16; 16],
+    symbols: Vec<u16>,
+}
+
+impl Decoder {
+    fn new(lengths: &[u8]) -> Result<Decoder, String> {
+        let mut counts = [0u16; 16];
+        for &length in lengths {
+            counts[length as usize] += 1;
+        }
+        counts[0] = 0;
+        // More codes than fit is an error, fewer (an incomplete code) is allowed
+        let mut left = 1i32;
+        for &count in counts[1..].iter() {
+            left = left * 2 - count as i32;
+            if left < 0 {
+                return Err("bad huffman code".to_string());
+            }
+        }
+        let mut offsets = [0u16; 16];
+        for bits in 1..15 {
+            offsets[bits + 1] = offsets[bits] + counts[bits];
+        }
+        let mut symbols = vec![0u16; lengths.len()];
+        for (symbol, &length) in lengths.iter().enumerate() {
+            if length != 0 {
+                symbols[offsets[length as usize] as usize] = symbol as u16;
+                offsets[length as usize] += 1;
+            }
+        }
+        return Ok(Decoder { counts: counts, symbols: symbols });
+    }
+
+    // Walks the code one bit at a time, since canonical codes of each length are consecutive
+    fn decode(&self, input: &mut BitReader) -> Result<usize, String> {
+        let (mut code, mut first, mut index) = (0i32, 0i32, 0i32);
+        for bits in 1..16 {
+            code |= input.bits(1)? as i32;
+            let count = self.counts[bits] as i32;
+            if code - first < count {
+                return Ok(self.symbols[(index + code - first) as usize] as usize);
+            }
+            index += count;
+            first = (first + count) << 1;
+            code <<= 1;
+        }
+        return Err("bad huffman code".to_string());
+    }
+}
+
+fn fixed_decoders() -> (Decoder, Decoder) {
+    let literal_lengths: Vec<u8> = (0..288).map(|symbol| match symbol {
+        0..=143 => 8,
+        144..=255 => 9,
+        256..=279 => 7,
+        _ => 8,
+    }).collect();
+    return (Decoder::new(&literal_lengths).unwrap(), Decoder::new(&[5; 30]).unwrap());
+}
+
+fn dynamic_decoders(input: &mut BitReader) -> Result<(Decoder, Decoder), String> {
+    let literal_count = input.bits(5)? as usize + 257;
+    let distance_count = input.bits(5)? as usize + 1;
+    let code_length_count = input.bits(4)? as usize + 4;
+    let mut code_length_lengths = [0u8; 19];
+    for &symbol in CODE_LENGTH_ORDER[..code_length_count].iter() {
+        code_length_lengths[symbol] = input.bits(3)? as u8;
+    }
+    let code_length_decoder = Decoder::new(&code_length_lengths)?;
+
+    let mut lengths = Vec::with_capacity(literal_count + distance_count);
+    while lengths.len() < literal_count + distance_count {
+        let (value, repeat) = match code_length_decoder.decode(input)? {
+            16 => (*lengths.last().ok_or_else(|| "repeat without a length".to_string())?, 3 + input.bits(2)?),
+            17 => (0, 3 + input.bits(3)?),
+            18 => (0, 11 + input.bits(7)?),
+            length => (length as u8, 1),
+        };
+        lengths.extend(std::iter::repeat_n(value, repeat as usize));
+    }
+    if lengths.len() > literal_count + distance_count || lengths[END_OF_BLOCK] == 0 {
+        return Err("bad code lengths".to_string());
+    }
+    return Ok((Decoder::new(&lengths[..literal_count])?, Decoder::new(&lengths[literal_count..])?));
+}
+
+// Raw deflate stream, returning the data and where the stream ended
+pub fn inflate(data: &[u8]) -> Result<(Vec<u8>, usize), String> {
+    let mut input = BitReader { bytes: data, pos: 0, bit_buffer: 0, bit_count: 0 };
+    let mut out = Vec::new();
+    loop {
+        let last = input.bits(1)? == 1;
+        let (literals, distances) = match input.bits(2)? {
+            0 => {
+                input.align();
+                let length = input.bits(16)?;
+                if length != !input.bits(16)? & 0xffff {
+                    return Err("bad stored block length".to_string());
+                }
+                for _ in 0..length {
+                    out.push(input.bits(8)? as u8);
+                }
+                if last {
+                    break;
+                }
+                continue;
+            },
+            1 => fixed_decoders(),
+            2 => dynamic_decoders(&mut input)?,
+            _ => return Err("bad block type".to_string()),
+        };
+        loop {
+            let symbol = literals.decode(&mut input)?;
+            if symbol < 256 {
+                out.push(symbol as u8);
+                continue;
+            }
+            if symbol == END_OF_BLOCK {
+                break;
+            }
+            let code = symbol - 257;
+            if code >= LENGTH_BASE.len() {
+                return Err("bad length code".to_string());
+            }
+            let length = LENGTH_BASE[code] as usize + input.bits(LENGTH_EXTRA[code] as u32)? as usize;
+            let code = distances.decode(&mut input)?;
+            if code >= DISTANCE_BASE.len() {
+                return Err("bad distance code".to_string());
+            }
+            let distance = DISTANCE_BASE[code] as usize + input.bits(DISTANCE_EXTRA[code] as u32)? as usize;
+            if distance > out.len() {
+                return Err("distance too far back".to_string());
+            }
+            let start = out.len() - distance;
+            for idx in 0..length {
+                out.push(out[start + idx]);
+            }
+        }
+        if last {
+            break;
+        }
+    }
+    input.align();
+    return Ok((out, input.pos - (input.bit_count / 8) as usize));
+}
+
+pub fn zlib_decompress(data: &[u8]) -> Result<Vec<u8>, String> {
+    if data.len() < 6 || data[0] & 0x0f != 8 || !((data[0] as u32) << 8 | data[1] as u32).is_multiple_of(31) || data[1] & 0x20 != 0 {
+        return Err("bad zlib header".to_string());
+    }
+    let (out, end) = inflate(&data[2..])?;
+    let checksum = data.get(2 + end..2 + end + 4).ok_or_else(|| "zlib checksum cut off".to_string())?;
+    if u32::from_be_bytes([checksum[0], checksum[1], checksum[2], checksum[3]]) != adler32(&out) {
+        return Err("zlib checksum mismatch".to_string());
+    }
+    return Ok(out);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{adler32, zlib_compress, zlib_decompress};
+
+    // xorshift, so the random input is the same on every run
+    fn random_bytes(count: usize) -> Vec<u8> {
+        let mut state = 0x2545f4914f6cdd1du64;
+        return (0..count).map(|_| {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            return (state >> 32) as u8;
+        }).collect();
+    }
+
+    #[test]
+    fn adler32_known_values() {
+        assert_eq!(adler32(b""), 1);
+        assert_eq!(adler32(b"Wikipedia"), 0x11e60398);
+        // Long enough to need the modulo more than once
+        assert_eq!(adler32(&[0xff; 100000]), 0x149a302c);
+    }
+
+    #[test]
+    fn round_trips_at_every_level() {
+        let repetitive: Vec<u8> = b"abcabcabd".iter().cycle().take(70000).copied().collect();
+        let inputs = [Vec::new(), b"hello, hello world".to_vec(), repetitive, random_bytes(100 * 1024)];
+        for level in 0..=9 {
+            for input in inputs.iter() {
+                let compressed = zlib_compress(input, level);
+                assert_eq!(&zlib_decompress(&compressed).unwrap(), input, "level {} with {} bytes", level, input.len());
+            }
+        }
+        // Compression has to actually happen for the repetitive input
+        assert!(zlib_compress(&inputs[2], 6).len() < inputs[2].len() / 50);
+    }
+}
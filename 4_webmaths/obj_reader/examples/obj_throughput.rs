@@ -0,0 +1,52 @@
+// Throughput benchmark for the different ways of loading an obj file.
+// Run with: cargo run --release --example obj_throughput [grid size] [threads]
+use obj_reader::{*};
+use std::fmt::Write;
+use std::io::BufReader;
+use std::time::Instant;
+
+// A size x size vertex grid with normals and texture coordinates, as quads
+fn generate_grid(size: usize) -> String {
+    let mut obj = String::new();
+    for y in 0..size {
+        for x in 0..size {
+            let height = ((x as f32 * 0.1).sin() * (y as f32 * 0.1).cos()) * 0.5;
+            writeln!(obj, "v {:.6} {:.6} {:.6}", x as f32 / size as f32, height, y as f32 / size as f32).unwrap();
+            writeln!(obj, "vt {:.6} {:.6}", x as f32 / size as f32, y as f32 / size as f32).unwrap();
+        }
+    }
+    writeln!(obj, "vn 0.0 1.0 0.0").unwrap();
+    for y in 0..size - 1 {
+        for x in 0..size - 1 {
+            let a = y * size + x + 1;
+            writeln!(obj, "f {0}/{0}/1 {1}/{1}/1 {2}/{2}/1 {3}/{3}/1", a, a + size, a + size + 1, a + 1).unwrap();
+        }
+    }
+    return obj;
+}
+
+fn report(name: &str, bytes: usize, triangles: usize, start: Instant) {
+    let seconds = start.elapsed().as_secs_f64();
+    println!("{:>12}: {:8.1} ms, {:8.1} MB/s, {} triangles", name, seconds * 1000.0, bytes as f64 / seconds / 1e6, triangles);
+}
+
+fn main() {
+    let args: Vec<String> = std::env::args().collect();
+    let size = args.get(1).map(|arg| arg.parse().expect("Bad grid size")).unwrap_or(1000);
+    let threads = args.get(2).map(|arg| arg.parse().expect("Bad thread count")).unwrap_or(8);
+    let obj = generate_grid(size);
+    println!("{} x {} grid, {:.1} MB", size, size, obj.len() as f64 / 1e6);
+
+    let start = Instant::now();
+    let mesh = parse_obj(&obj).expect("Parse error");
+    report("sequential", obj.len(), mesh.triangles.len(), start);
+
+    let start = Instant::now();
+    let mesh = parse_obj_with(&obj, &ObjOptions { threads: threads, ..Default::default() }).expect("Parse error");
+    report(&format!("{} threads", threads), obj.len(), mesh.triangles.len(), start);
+
+    let start = Instant::now();
+    let mut triangles = 0;
+    stream_obj(BufReader::new(obj.as_bytes()), &ObjOptions { normals: NormalMode::Flat, ..Default::default() }, |_| triangles += 1).expect("Parse error");
+    report("streaming", obj.len(), triangles, start);
+}
@@ -0,0 +1,669 @@
+use vector_math::{*};
+use std::collections::HashMap;
+use std::fs;
+use std::fs::File;
+use std::io;
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+use crate::{Material, Mesh, NormalMode, ObjError, ObjErrorKind, ObjOptions, Point, Polygon, Polyline, SubMesh, TriData, load_materials, read_obj_with};
+
+// Binary mesh cache, little-endian throughout:
+//   header: magic, version, attribute flags, the options the mesh was read with, and the counts of vertices,
+//           triangles, polygons, lines, points, sub-meshes, materials and material libraries (u32 each)
+//   vertices: all positions, then all normals, then texture coordinates and colors if the flags say so (f32 x 3)
+//   triangles: all vertex indices (u32 x 3), then all materials (u32, u32::MAX for none), then all
+//              flags (u8, whether the triangle has texture coordinates and colors)
+//   polygons: corner count, triangle range, flags, then the vertex index of every corner
+//   lines: vertex count, flags, material, sub-mesh, then the index of every vertex (with a zero normal)
+//   points: vertex index (with a zero normal), flags, material, sub-mesh
+//   sub-meshes, material names and material libraries, with strings as a u32 length and utf-8
+//   checksum: adler-32 of everything before it
+// Materials are stored by name only and get loaded from their mtl files like read_obj does, and warnings
+// aren't stored at all.
+const MAGIC: &[u8; 4] = b"OBJC";
+const VERSION: u32 = 2;
+const HEADER_SIZE: usize = 48;
+
+const HAS_TEXCOORDS: u8 = 1;
+const HAS_COLORS: u8 = 2;
+const NO_MATERIAL: u32 = u32::MAX;
+
+// Adler-32, which is a good deal faster than crc32 and catches truncated and damaged files just as well
+fn adler32(bytes: &[u8]) -> u32 {
+    let (mut a, mut b) = (1u32, 0u32);
+    // 5552 bytes is the most that can be summed before b could overflow
+    for chunk in bytes.chunks(5552) {
+        for &byte in chunk {
+            a += byte as u32;
+            b += a;
+        }
+        a %= 65521;
+        b %= 65521;
+    }
+    return (b << 16) | a;
+}
+
+// The options that change what parsing gives, so a cache written with other ones doesn't get used
+fn options_key(options: &ObjOptions) -> u32 {
+    let normals = match options.normals {
+        NormalMode::Flat => 0,
+        NormalMode::SmoothArea => 1,
+        NormalMode::SmoothAngle => 2,
+    };
+    return normals | (options.lenient as u32) << 2;
+}
+
+fn attribute_flags(t: bool, c: bool) -> u8 {
+    return if t { HAS_TEXCOORDS } else { 0 } | if c { HAS_COLORS } else { 0 };
+}
+
+fn material_id(material: Option<usize>) -> u32 {
+    return material.map(|material| material as u32).unwrap_or(NO_MATERIAL);
+}
+
+// Shared vertices of all triangles, polygons, lines and points, merged where everything about them is identical
+struct VertexTable {
+    known_vertices: HashMap<[u32; 12], u32>,
+    positions: Vec<Vec3>,
+    normals: Vec<Vec3>,
+    texcoords: Vec<Vec3>,
+    colors: Vec<Vec3>,
+}
+
+impl VertexTable {
+    fn index(&mut self, p: Vec3, n: Vec3, t: Option<Vec3>, c: Option<Vec3>) -> u32 {
+        let zero = Vec3::new(0.0, 0.0, 0.0);
+        let (t, c) = (t.unwrap_or(zero), c.unwrap_or(zero));
+        let mut key = [0; 12];
+        for (idx, v) in [p, n, t, c].iter().enumerate() {
+            key[idx * 3] = v.x().to_bits();
+            key[idx * 3 + 1] = v.y().to_bits();
+            key[idx * 3 + 2] = v.z().to_bits();
+        }
+        if let Some(&idx) = self.known_vertices.get(&key) {
+            return idx;
+        }
+        self.positions.push(p);
+        self.normals.push(n);
+        self.texcoords.push(t);
+        self.colors.push(c);
+        self.known_vertices.insert(key, self.positions.len() as u32 - 1);
+        return self.positions.len() as u32 - 1;
+    }
+}
+
+struct CacheWriter {
+    bytes: Vec<u8>,
+}
+
+impl CacheWriter {
+    fn u32(&mut self, value: u32) {
+        self.bytes.extend_from_slice(&value.to_le_bytes());
+    }
+
+    fn usize(&mut self, value: usize) {
+        self.u32(value as u32);
+    }
+
+    fn vec3s(&mut self, values: &[Vec3]) {
+        for v in values {
+            for value in [v.x(), v.y(), v.z()] {
+                self.bytes.extend_from_slice(&(value as f32).to_le_bytes());
+            }
+        }
+    }
+
+    fn string(&mut self, value: &str) {
+        self.usize(value.len());
+        self.bytes.extend_from_slice(value.as_bytes());
+    }
+
+    fn list<T>(&mut self, values: &[T], mut write: impl FnMut(&mut CacheWriter, &T)) {
+        self.usize(values.len());
+        for value in values {
+            write(self, value);
+        }
+    }
+}
+
+// Writes a mesh cache, to be read back with parse_mesh_cache. options should be the ones the mesh was
+// read with, read_obj_cached only uses caches that match.
+pub fn write_mesh_cache_to<W: Write>(out: &mut W, mesh: &Mesh, options: &ObjOptions) -> io::Result<()> {
+    let mut vertices = VertexTable {
+        known_vertices: HashMap::new(),
+        positions: Vec::new(),
+        normals: Vec::new(),
+        texcoords: Vec::new(),
+        colors: Vec::new(),
+    };
+    let triangle_indices: Vec<[u32; 3]> = mesh.triangles.iter().map(|tri| {
+        return [0, 1, 2].map(|corner| vertices.index(tri.p[corner], tri.n[corner], tri.t.map(|t| t[corner]), tri.c.map(|c| c[corner])));
+    }).collect();
+    let polygon_indices: Vec<Vec<u32>> = mesh.polygons.iter().map(|polygon| {
+        return (0..polygon.p.len()).map(|corner| vertices.index(
+            polygon.p[corner],
+            polygon.n[corner],
+            polygon.t.as_ref().map(|t| t[corner]),
+            polygon.c.as_ref().map(|c| c[corner]),
+        )).collect();
+    }).collect();
+    let zero = Vec3::new(0.0, 0.0, 0.0);
+    let polyline_indices: Vec<Vec<u32>> = mesh.polylines.iter().map(|polyline| {
+        return (0..polyline.p.len()).map(|idx| vertices.index(
+            polyline.p[idx],
+            zero,
+            polyline.t.as_ref().map(|t| t[idx]),
+            polyline.c.as_ref().map(|c| c[idx]),
+        )).collect();
+    }).collect();
+    let point_indices: Vec<u32> = mesh.points.iter().map(|point| vertices.index(point.p, zero, None, point.c)).collect();
+    let has_texcoords = mesh.triangles.iter().any(|tri| tri.t.is_some())
+        || mesh.polygons.iter().any(|polygon| polygon.t.is_some())
+        || mesh.polylines.iter().any(|polyline| polyline.t.is_some());
+    let has_colors = mesh.triangles.iter().any(|tri| tri.c.is_some())
+        || mesh.polygons.iter().any(|polygon| polygon.c.is_some())
+        || mesh.polylines.iter().any(|polyline| polyline.c.is_some())
+        || mesh.points.iter().any(|point| point.c.is_some());
+
+    let mut writer = CacheWriter { bytes: Vec::new() };
+    writer.bytes.extend_from_slice(MAGIC);
+    writer.u32(VERSION);
+    writer.u32(attribute_flags(has_texcoords, has_colors) as u32);
+    writer.u32(options_key(options));
+    let counts = [
+        vertices.positions.len(), mesh.triangles.len(), mesh.polygons.len(), mesh.polylines.len(), mesh.points.len(),
+        mesh.sub_meshes.len(), mesh.materials.len(), mesh.material_libs.len()
+    ];
+    for count in counts {
+        writer.usize(count);
+    }
+
+    writer.vec3s(&vertices.positions);
+    writer.vec3s(&vertices.normals);
+    if has_texcoords {
+        writer.vec3s(&vertices.texcoords);
+    }
+    if has_colors {
+        writer.vec3s(&vertices.colors);
+    }
+
+    for indices in triangle_indices.iter() {
+        for &idx in indices {
+            writer.u32(idx);
+        }
+    }
+    for tri in mesh.triangles.iter() {
+        writer.u32(material_id(tri.material));
+    }
+    for tri in mesh.triangles.iter() {
+        writer.bytes.push(attribute_flags(tri.t.is_some(), tri.c.is_some()));
+    }
+
+    for (polygon, indices) in mesh.polygons.iter().zip(polygon_indices.iter()) {
+        writer.usize(indices.len());
+        writer.usize(polygon.triangles.start);
+        writer.usize(polygon.triangles.end);
+        writer.bytes.push(attribute_flags(polygon.t.is_some(), polygon.c.is_some()));
+        for &idx in indices {
+            writer.u32(idx);
+        }
+    }
+
+    for (polyline, indices) in mesh.polylines.iter().zip(polyline_indices.iter()) {
+        writer.usize(indices.len());
+        writer.bytes.push(attribute_flags(polyline.t.is_some(), polyline.c.is_some()));
+        writer.u32(material_id(polyline.material));
+        writer.usize(polyline.sub_mesh);
+        for &idx in indices {
+            writer.u32(idx);
+        }
+    }
+    for (point, &idx) in mesh.points.iter().zip(point_indices.iter()) {
+        writer.u32(idx);
+        writer.bytes.push(attribute_flags(false, point.c.is_some()));
+        writer.u32(material_id(point.material));
+        writer.usize(point.sub_mesh);
+    }
+
+    for sub_mesh in mesh.sub_meshes.iter() {
+        writer.string(&sub_mesh.name);
+        writer.string(&sub_mesh.object);
+        writer.list(&sub_mesh.groups, |writer, group| writer.string(group));
+        writer.usize(sub_mesh.triangles.start);
+        writer.usize(sub_mesh.triangles.end);
+        writer.list(&sub_mesh.materials, |writer, &material| writer.usize(material));
+        writer.list(&sub_mesh.smoothing_groups, |writer, &group| writer.u32(group));
+    }
+    for material in mesh.materials.iter() {
+        writer.string(&material.name);
+    }
+    for library in mesh.material_libs.iter() {
+        writer.string(library);
+    }
+
+    let checksum = adler32(&writer.bytes);
+    writer.u32(checksum);
+    return out.write_all(&writer.bytes);
+}
+
+pub fn write_mesh_cache(path: &str, mesh: &Mesh, options: &ObjOptions) -> io::Result<()> {
+    let mut out = BufWriter::new(File::create(path)?);
+    write_mesh_cache_to(&mut out, mesh, options)?;
+    return out.flush();
+}
+
+fn cache_error(text: &str, kind: ObjErrorKind) -> ObjError {
+    return ObjError { line: 0, column: 0, text: text.to_string(), kind: kind };
+}
+
+// The idx-th little-endian u32 in bytes
+fn le_u32(bytes: &[u8], idx: usize) -> u32 {
+    return u32::from_le_bytes([bytes[idx * 4], bytes[idx * 4 + 1], bytes[idx * 4 + 2], bytes[idx * 4 + 3]]);
+}
+
+// Reads values in order, failing at the end of the data instead of panicking
+struct CacheReader<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> CacheReader<'a> {
+    fn take(&mut self, count: usize) -> Result<&'a [u8], ObjError> {
+        if count > self.bytes.len() - self.pos {
+            return Err(cache_error(&format!("{} bytes at {}", count, self.pos), ObjErrorKind::UnexpectedEnd));
+        }
+        self.pos += count;
+        return Ok(&self.bytes[self.pos - count..self.pos]);
+    }
+
+    fn u8(&mut self) -> Result<u8, ObjError> {
+        return Ok(self.take(1)?[0]);
+    }
+
+    fn u32(&mut self) -> Result<u32, ObjError> {
+        return Ok(le_u32(self.take(4)?, 0));
+    }
+
+    fn usize(&mut self) -> Result<usize, ObjError> {
+        return Ok(self.u32()? as usize);
+    }
+
+    // A material index below count, or none
+    fn material(&mut self, count: usize) -> Result<Option<usize>, ObjError> {
+        return material(self.u32()?, count);
+    }
+
+    // An index that has to be below count
+    fn index(&mut self, count: usize) -> Result<usize, ObjError> {
+        let idx = self.usize()?;
+        if idx >= count {
+            return Err(cache_error(&format!("index {} of {}", idx, count), ObjErrorKind::IndexOutOfRange));
+        }
+        return Ok(idx);
+    }
+
+    // count indices that all have to be below limit
+    fn indices(&mut self, count: usize, limit: usize) -> Result<Vec<usize>, ObjError> {
+        let bytes = self.take(count.saturating_mul(4))?;
+        let indices: Vec<usize> = (0..count).map(|idx| le_u32(bytes, idx) as usize).collect();
+        if let Some(&idx) = indices.iter().find(|&&idx| idx >= limit) {
+            return Err(cache_error(&format!("index {} of {}", idx, limit), ObjErrorKind::IndexOutOfRange));
+        }
+        return Ok(indices);
+    }
+
+    // A range of triangles, which may be empty but has to be within count
+    fn range(&mut self, count: usize) -> Result<std::ops::Range<usize>, ObjError> {
+        let (start, end) = (self.usize()?, self.usize()?);
+        if start > end || end > count {
+            return Err(cache_error(&format!("range {}..{} of {}", start, end, count), ObjErrorKind::IndexOutOfRange));
+        }
+        return Ok(start..end);
+    }
+
+    fn vec3s(&mut self, count: usize) -> Result<Vec<Vec3>, ObjError> {
+        let bytes = self.take(count.saturating_mul(12))?;
+        return Ok(bytes.chunks_exact(12).map(|v| {
+            let value = |idx: usize| f32::from_le_bytes([v[idx * 4], v[idx * 4 + 1], v[idx * 4 + 2], v[idx * 4 + 3]]) as Scalar;
+            return Vec3::new(value(0), value(1), value(2));
+        }).collect());
+    }
+
+    fn string(&mut self) -> Result<String, ObjError> {
+        let len = self.usize()?;
+        let bytes = self.take(len)?;
+        return String::from_utf8(bytes.to_vec()).map_err(|_| cache_error("string", ObjErrorKind::BadHeader));
+    }
+
+    fn list<T>(&mut self, mut read: impl FnMut(&mut CacheReader<'a>) -> Result<T, ObjError>) -> Result<Vec<T>, ObjError> {
+        let count = self.usize()?;
+        return (0..count).map(|_| read(self)).collect();
+    }
+}
+
+fn material(id: u32, count: usize) -> Result<Option<usize>, ObjError> {
+    return match id {
+        NO_MATERIAL => Ok(None),
+        material if (material as usize) < count => Ok(Some(material as usize)),
+        material => Err(cache_error(&format!("material {} of {}", material, count), ObjErrorKind::IndexOutOfRange)),
+    };
+}
+
+// Texture coordinates or colors, if the flags of a triangle or polygon say it has them
+fn attribute(values: &Option<Vec<Vec3>>, flags: u8, flag: u8) -> Option<&Vec<Vec3>> {
+    return values.as_ref().filter(|_| flags & flag != 0);
+}
+
+// Checks magic, version and checksum, returning the attribute flags, options key and the reader positioned
+// after the header
+fn read_header(contents: &[u8]) -> Result<(u8, u32, CacheReader<'_>), ObjError> {
+    if contents.len() < HEADER_SIZE + 4 {
+        return Err(cache_error(&format!("{} bytes", contents.len()), ObjErrorKind::UnexpectedEnd));
+    }
+    let data = &contents[..contents.len() - 4];
+    let mut reader = CacheReader { bytes: data, pos: 0 };
+    if reader.take(4)? != MAGIC {
+        return Err(cache_error("not a mesh cache", ObjErrorKind::BadHeader));
+    }
+    let version = reader.u32()?;
+    if version != VERSION {
+        return Err(cache_error(&format!("version {}", version), ObjErrorKind::BadHeader));
+    }
+    let checksum = CacheReader { bytes: contents, pos: data.len() }.u32()?;
+    if checksum != adler32(data) {
+        return Err(cache_error("checksum mismatch", ObjErrorKind::BadHeader));
+    }
+    let attributes = reader.u32()? as u8;
+    let options = reader.u32()?;
+    return Ok((attributes, options, reader));
+}
+
+// Reads a mesh cache from memory, e.g. from include_bytes!. Materials only have their names, like after
+// parse_obj.
+pub fn parse_mesh_cache(contents: &[u8]) -> Result<Mesh, ObjError> {
+    let (attributes, _, mut reader) = read_header(contents)?;
+    let mut counts = [0; 8];
+    for count in counts.iter_mut() {
+        *count = reader.usize()?;
+    }
+    let [vertex_count, triangle_count, polygon_count, polyline_count, point_count, sub_mesh_count, material_count, library_count] = counts;
+
+    let positions = reader.vec3s(vertex_count)?;
+    let normals = reader.vec3s(vertex_count)?;
+    let texcoords = if attributes & HAS_TEXCOORDS != 0 { Some(reader.vec3s(vertex_count)?) } else { None };
+    let colors = if attributes & HAS_COLORS != 0 { Some(reader.vec3s(vertex_count)?) } else { None };
+
+    let indices = reader.indices(triangle_count.saturating_mul(3), vertex_count)?;
+    let material_bytes = reader.take(triangle_count.saturating_mul(4))?;
+    let flag_bytes = reader.take(triangle_count)?;
+    let mut triangles = Vec::with_capacity(triangle_count);
+    for tri_idx in 0..triangle_count {
+        let indices = [indices[tri_idx * 3], indices[tri_idx * 3 + 1], indices[tri_idx * 3 + 2]];
+        let material = material(le_u32(material_bytes, tri_idx), material_count)?;
+        let flags = flag_bytes[tri_idx];
+        triangles.push(TriData {
+            p: indices.map(|idx| positions[idx]),
+            n: indices.map(|idx| normals[idx]),
+            t: attribute(&texcoords, flags, HAS_TEXCOORDS).map(|t| indices.map(|idx| t[idx])),
+            c: attribute(&colors, flags, HAS_COLORS).map(|c| indices.map(|idx| c[idx])),
+            material: material,
+        });
+    }
+
+    let mut polygons = Vec::with_capacity(polygon_count.min(contents.len()));
+    for _ in 0..polygon_count {
+        let corner_count = reader.usize()?;
+        let triangle_range = reader.range(triangle_count)?;
+        let flags = reader.u8()?;
+        let indices = reader.indices(corner_count, vertex_count)?;
+        polygons.push(Polygon {
+            p: indices.iter().map(|&idx| positions[idx]).collect(),
+            n: indices.iter().map(|&idx| normals[idx]).collect(),
+            t: attribute(&texcoords, flags, HAS_TEXCOORDS).map(|t| indices.iter().map(|&idx| t[idx]).collect()),
+            c: attribute(&colors, flags, HAS_COLORS).map(|c| indices.iter().map(|&idx| c[idx]).collect()),
+            triangles: triangle_range,
+        });
+    }
+
+    let mut polylines = Vec::with_capacity(polyline_count.min(contents.len()));
+    for _ in 0..polyline_count {
+        let line_length = reader.usize()?;
+        let flags = reader.u8()?;
+        let material = reader.material(material_count)?;
+        let sub_mesh = reader.index(sub_mesh_count)?;
+        let indices = reader.indices(line_length, vertex_count)?;
+        polylines.push(Polyline {
+            p: indices.iter().map(|&idx| positions[idx]).collect(),
+            t: attribute(&texcoords, flags, HAS_TEXCOORDS).map(|t| indices.iter().map(|&idx| t[idx]).collect()),
+            c: attribute(&colors, flags, HAS_COLORS).map(|c| indices.iter().map(|&idx| c[idx]).collect()),
+            material: material,
+            sub_mesh: sub_mesh,
+        });
+    }
+
+    let mut points = Vec::with_capacity(point_count.min(contents.len()));
+    for _ in 0..point_count {
+        let idx = reader.index(vertex_count)?;
+        let flags = reader.u8()?;
+        points.push(Point {
+            p: positions[idx],
+            c: attribute(&colors, flags, HAS_COLORS).map(|c| c[idx]),
+            material: reader.material(material_count)?,
+            sub_mesh: reader.index(sub_mesh_count)?,
+        });
+    }
+
+    let mut sub_meshes = Vec::with_capacity(sub_mesh_count.min(contents.len()));
+    for _ in 0..sub_mesh_count {
+        sub_meshes.push(SubMesh {
+            name: reader.string()?,
+            object: reader.string()?,
+            groups: reader.list(|reader| reader.string())?,
+            triangles: reader.range(triangle_count)?,
+            materials: reader.list(|reader| reader.index(material_count))?,
+            smoothing_groups: reader.list(|reader| reader.u32())?,
+        });
+    }
+    let materials = (0..material_count).map(|_| reader.string().map(|name| Material::new(&name))).collect::<Result<Vec<_>, ObjError>>()?;
+    let material_libs = (0..library_count).map(|_| reader.string()).collect::<Result<Vec<_>, ObjError>>()?;
+    if reader.pos != reader.bytes.len() {
+        return Err(cache_error(&format!("{} trailing bytes", reader.bytes.len() - reader.pos), ObjErrorKind::BadHeader));
+    }
+
+    return Ok(Mesh {
+        triangles: triangles,
+        polygons: polygons,
+        polylines: polylines,
+        points: points,
+        sub_meshes: sub_meshes,
+        materials: materials,
+        material_libs: material_libs,
+        warnings: Vec::new(),
+    });
+}
+
+// Reads a mesh cache file, loading its materials relative to it
+pub fn read_mesh_cache(path: &str) -> Result<Mesh, ObjError> {
+    let contents = fs::read(path).map_err(|error| cache_error(&format!("{}: {}", path, error), ObjErrorKind::FileRead))?;
+    let mut mesh = parse_mesh_cache(&contents)?;
+    load_materials(&mut mesh, Path::new(path).parent().unwrap_or(Path::new("")), &ObjOptions::default());
+    return Ok(mesh);
+}
+
+// Where read_obj_cached keeps the cache for an obj file: next to it, with ".cache" added
+pub fn mesh_cache_path(obj_path: &str) -> PathBuf {
+    return PathBuf::from(format!("{}.cache", obj_path));
+}
+
+fn modified(path: &Path) -> Option<std::time::SystemTime> {
+    return fs::metadata(path).and_then(|metadata| metadata.modified()).ok();
+}
+
+// Like read_obj_with, but goes through a cache next to the obj file: if there is one that is at least as
+// new as the obj file and was written with the same options it gets read instead, otherwise the obj file
+// is parsed and the cache (re)written. A cache that fails to read or write is quietly ignored.
+pub fn read_obj_cached(path: &str, options: &ObjOptions) -> Result<Mesh, ObjError> {
+    let cache_path = mesh_cache_path(path);
+    let cache_is_current = match (modified(Path::new(path)), modified(&cache_path)) {
+        (Some(obj_time), Some(cache_time)) => cache_time >= obj_time,
+        (None, Some(_)) => true,
+        _ => false,
+    };
+    if cache_is_current {
+        if let Ok(contents) = fs::read(&cache_path) {
+            let matches_options = read_header(&contents).map(|(_, key, _)| key == options_key(options)).unwrap_or(false);
+            if let (true, Ok(mut mesh)) = (matches_options, parse_mesh_cache(&contents)) {
+                load_materials(&mut mesh, Path::new(path).parent().unwrap_or(Path::new("")), options);
+                return Ok(mesh);
+            }
+        }
+    }
+
+    let mesh = read_obj_with(path, options)?;
+    if let Some(cache_path) = cache_path.to_str() {
+        let _ = write_mesh_cache(cache_path, &mesh, options);
+    }
+    return Ok(mesh);
+}
+
+#[cfg(test)]
+mod tests {
+    use vector_math::{*};
+    use std::fs;
+    use std::path::PathBuf;
+    use std::time::{Duration, SystemTime};
+    use crate::{Mesh, NormalMode, ObjErrorKind, ObjOptions, parse_obj};
+    use super::{mesh_cache_path, parse_mesh_cache, read_obj_cached, write_mesh_cache, write_mesh_cache_to};
+
+    fn xyz(v: Vec3) -> [Scalar; 3] {
+        return [v.x(), v.y(), v.z()];
+    }
+
+    fn xyzs(values: &[Vec3]) -> Vec<[Scalar; 3]> {
+        return values.iter().map(|&v| xyz(v)).collect();
+    }
+
+    // A quad and a triangle in two groups with two materials, a colored polyline with texture coordinates
+    // and two points
+    const SCENE: &str = "mtllib scene.mtl\n\
+        v 0 0 0 1 0 0\nv 1 0 0 0 1 0\nv 1 1 0 0 0 1\nv 0 1 0 1 1 1\nv 0 0 1 0.5 0.5 0.5\n\
+        vt 0 0\nvt 1 0\nvt 1 1\nvt 0 1\nvn 0 0 1\n\
+        o thing\ng front\nusemtl red\nf 1/1/1 2/2/1 3/3/1 4/4/1\n\
+        g back\nusemtl blue\nf 1 4 5\nl 1/1 2/2 3/3\nusemtl\np 4 5\n";
+
+    fn cache_bytes(mesh: &Mesh, options: &ObjOptions) -> Vec<u8> {
+        let mut out = Vec::new();
+        write_mesh_cache_to(&mut out, mesh, options).unwrap();
+        return out;
+    }
+
+    #[test]
+    fn round_trips_through_parse_mesh_cache() {
+        let mesh = parse_obj(SCENE).unwrap();
+        assert_eq!((mesh.polygons.len(), mesh.polylines.len(), mesh.points.len(), mesh.sub_meshes.len()), (2, 1, 2, 2));
+        let back = parse_mesh_cache(&cache_bytes(&mesh, &ObjOptions::default())).unwrap();
+
+        assert_eq!(back.triangles.len(), mesh.triangles.len());
+        for (a, b) in mesh.triangles.iter().zip(&back.triangles) {
+            assert_eq!(a.p.map(xyz), b.p.map(xyz));
+            assert_eq!(a.n.map(xyz), b.n.map(xyz));
+            assert_eq!(a.t.map(|t| t.map(xyz)), b.t.map(|t| t.map(xyz)));
+            assert_eq!(a.c.map(|c| c.map(xyz)), b.c.map(|c| c.map(xyz)));
+            assert_eq!(a.material, b.material);
+        }
+
+        assert_eq!(back.polygons.len(), mesh.polygons.len());
+        for (a, b) in mesh.polygons.iter().zip(&back.polygons) {
+            assert_eq!(xyzs(&a.p), xyzs(&b.p));
+            assert_eq!(xyzs(&a.n), xyzs(&b.n));
+            assert_eq!(a.t.as_deref().map(xyzs), b.t.as_deref().map(xyzs));
+            assert_eq!(a.c.as_deref().map(xyzs), b.c.as_deref().map(xyzs));
+            assert_eq!(a.triangles, b.triangles);
+        }
+
+        assert_eq!(back.polylines.len(), 1);
+        let (a, b) = (&mesh.polylines[0], &back.polylines[0]);
+        assert_eq!(xyzs(&a.p), xyzs(&b.p));
+        assert_eq!(a.t.as_deref().map(xyzs), b.t.as_deref().map(xyzs));
+        assert_eq!(a.c.as_deref().map(xyzs), b.c.as_deref().map(xyzs));
+        assert_eq!((a.material, a.sub_mesh), (b.material, b.sub_mesh));
+
+        let points = |mesh: &Mesh| mesh.points.iter().map(|point| (xyz(point.p), point.c.map(xyz), point.material, point.sub_mesh)).collect::<Vec<_>>();
+        assert_eq!(points(&back), points(&mesh));
+
+        assert_eq!(back.sub_meshes.len(), mesh.sub_meshes.len());
+        for (a, b) in mesh.sub_meshes.iter().zip(&back.sub_meshes) {
+            assert_eq!((&a.name, &a.object, &a.groups, &a.triangles), (&b.name, &b.object, &b.groups, &b.triangles));
+            assert_eq!((&a.materials, &a.smoothing_groups), (&b.materials, &b.smoothing_groups));
+        }
+        let names = |mesh: &Mesh| mesh.materials.iter().map(|material| material.name.clone()).collect::<Vec<String>>();
+        assert_eq!(names(&back), ["red", "blue"]);
+        assert_eq!(back.material_libs, ["scene.mtl"]);
+    }
+
+    #[test]
+    fn rejects_damaged_caches() {
+        let bytes = cache_bytes(&parse_obj(SCENE).unwrap(), &ObjOptions::default());
+        let kind = |bytes: &[u8]| parse_mesh_cache(bytes).err().map(|error| error.kind);
+
+        // Any flipped byte past the header fails the checksum
+        for idx in [60, bytes.len() / 2, bytes.len() - 10] {
+            let mut flipped = bytes.clone();
+            flipped[idx] ^= 0x10;
+            assert_eq!(kind(&flipped), Some(ObjErrorKind::BadHeader));
+        }
+
+        let mut magic = bytes.clone();
+        magic[0] = b'X';
+        assert_eq!(kind(&magic), Some(ObjErrorKind::BadHeader));
+        let mut version = bytes.clone();
+        version[4] += 1;
+        assert_eq!(kind(&version), Some(ObjErrorKind::BadHeader));
+
+        // A truncated file loses its checksum, or is too short to even have a header
+        assert_eq!(kind(&bytes[..bytes.len() - 1]), Some(ObjErrorKind::BadHeader));
+        assert_eq!(kind(&bytes[..20]), Some(ObjErrorKind::UnexpectedEnd));
+    }
+
+    fn set_modified(path: &PathBuf, time: SystemTime) {
+        fs::File::options().write(true).open(path).unwrap().set_modified(time).unwrap();
+    }
+
+    #[test]
+    fn read_obj_cached_uses_current_caches_only() {
+        let dir = std::env::temp_dir().join(format!("obj_reader_cache_test_{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let obj_path = dir.join("scene.obj");
+        fs::write(&obj_path, SCENE).unwrap();
+        let path = obj_path.to_str().unwrap();
+        let cache_path = mesh_cache_path(path);
+        let options = ObjOptions::default();
+
+        // The first read writes the cache
+        assert_eq!(read_obj_cached(path, &options).unwrap().triangles.len(), 3);
+        assert!(cache_path.exists());
+
+        // A newer cache with the same options gets read instead of the obj file, which this one shows
+        // by having a single triangle
+        let stand_in = parse_obj("v 0 0 0\nv 1 0 0\nv 0 1 0\nf 1 2 3\n").unwrap();
+        let now = SystemTime::now();
+        write_mesh_cache(cache_path.to_str().unwrap(), &stand_in, &options).unwrap();
+        set_modified(&obj_path, now - Duration::from_secs(60));
+        set_modified(&cache_path, now);
+        assert_eq!(read_obj_cached(path, &options).unwrap().triangles.len(), 1);
+
+        // Other options mean parsing again, and replacing the cache
+        let flat = ObjOptions { normals: NormalMode::Flat, ..options };
+        assert_eq!(read_obj_cached(path, &flat).unwrap().triangles.len(), 3);
+        assert_eq!(read_obj_cached(path, &flat).unwrap().triangles.len(), 3);
+
+        // As does an obj file that is newer than its cache
+        write_mesh_cache(cache_path.to_str().unwrap(), &stand_in, &options).unwrap();
+        set_modified(&cache_path, now - Duration::from_secs(60));
+        set_modified(&obj_path, now);
+        assert_eq!(read_obj_cached(path, &options).unwrap().triangles.len(), 3);
+        assert_eq!(parse_mesh_cache(&fs::read(&cache_path).unwrap()).unwrap().triangles.len(), 3);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,519 @@
+use vector_math::{*};
+use std::fs;
+use std::path::{Path, PathBuf};
+use serde_json::Value;
+use crate::{Material, Mesh, ObjError, ObjErrorKind, ObjOptions};
+use crate::parse::{Corner, Face, ObjState, Statement, build_mesh};
+
+const GLB_MAGIC: &[u8; 4] = b"glTF";
+const GLB_CHUNK_JSON: u32 = 0x4E4F534A;
+const GLB_CHUNK_BIN: u32 = 0x004E4942;
+
+const MODE_TRIANGLES: u64 = 4;
+const MODE_TRIANGLE_STRIP: u64 = 5;
+const MODE_TRIANGLE_FAN: u64 = 6;
+
+fn gltf_error(text: &str, kind: ObjErrorKind) -> ObjError {
+    return ObjError {
+        line: 0,
+        column: 0,
+        text: text.to_string(),
+        kind: kind,
+    };
+}
+
+fn read_u32(bytes: &[u8], offset: usize) -> Result<u32, ObjError> {
+    let value = bytes.get(offset..offset + 4).ok_or_else(|| gltf_error("glb header", ObjErrorKind::UnexpectedEnd))?;
+    return Ok(u32::from_le_bytes([value[0], value[1], value[2], value[3]]));
+}
+
+fn decode_base64(text: &str) -> Result<Vec<u8>, ObjError> {
+    let mut out = Vec::with_capacity(text.len() * 3 / 4);
+    let mut bits: u32 = 0;
+    let mut bit_count = 0;
+    for c in text.bytes() {
+        let value = match c {
+            b'A'..=b'Z' => c - b'A',
+            b'a'..=b'z' => c - b'a' + 26,
+            b'0'..=b'9' => c - b'0' + 52,
+            b'+' | b'-' => 62,
+            b'/' | b'_' => 63,
+            b'=' => break,
+            b'\r' | b'\n' | b' ' => continue,
+            _ => return Err(gltf_error("base64 data", ObjErrorKind::BadNumber)),
+        };
+        bits = (bits << 6) | value as u32;
+        bit_count += 6;
+        if bit_count >= 8 {
+            bit_count -= 8;
+            out.push((bits >> bit_count) as u8);
+        }
+    }
+    return Ok(out);
+}
+
+// Small helpers for reading the json, with glTF defaults for missing values
+fn get_usize(value: &Value, key: &str) -> Option<usize> {
+    return value.get(key).and_then(|v| v.as_u64()).map(|v| v as usize);
+}
+
+fn get_scalar(value: &Value, key: &str, default: Scalar) -> Scalar {
+    return value.get(key).and_then(|v| v.as_f64()).map(|v| v as Scalar).unwrap_or(default);
+}
+
+fn get_scalars(value: &Value, key: &str) -> Option<Vec<Scalar>> {
+    return value.get(key).and_then(|v| v.as_array()).map(|values| values.iter().map(|v| v.as_f64().unwrap_or(0.0) as Scalar).collect());
+}
+
+fn get_array<'a>(value: &'a Value, key: &str) -> &'a [Value] {
+    return value.get(key).and_then(|v| v.as_array()).map(|values| values.as_slice()).unwrap_or(&[]);
+}
+
+fn identity() -> Mat4x4 {
+    return Mat4x4::new(
+        1.0, 0.0, 0.0, 0.0,
+        0.0, 1.0, 0.0, 0.0,
+        0.0, 0.0, 1.0, 0.0,
+        0.0, 0.0, 0.0, 1.0,
+    );
+}
+
+// Local transform of a node, either a column-major matrix or translation * rotation * scale
+fn node_transform(node: &Value) -> Mat4x4 {
+    if let Some(m) = get_scalars(node, "matrix").filter(|m| m.len() == 16) {
+        return Mat4x4::new(
+            m[0], m[4], m[8], m[12],
+            m[1], m[5], m[9], m[13],
+            m[2], m[6], m[10], m[14],
+            m[3], m[7], m[11], m[15],
+        );
+    }
+    let t = get_scalars(node, "translation").filter(|t| t.len() == 3).unwrap_or(vec![0.0, 0.0, 0.0]);
+    let r = get_scalars(node, "rotation").filter(|r| r.len() == 4).unwrap_or(vec![0.0, 0.0, 0.0, 1.0]);
+    let s = get_scalars(node, "scale").filter(|s| s.len() == 3).unwrap_or(vec![1.0, 1.0, 1.0]);
+    let (x, y, z, w) = (r[0], r[1], r[2], r[3]);
+    return Mat4x4::new(
+        (1.0 - 2.0 * (y * y + z * z)) * s[0], 2.0 * (x * y - z * w) * s[1], 2.0 * (x * z + y * w) * s[2], t[0],
+        2.0 * (x * y + z * w) * s[0], (1.0 - 2.0 * (x * x + z * z)) * s[1], 2.0 * (y * z - x * w) * s[2], t[1],
+        2.0 * (x * z - y * w) * s[0], 2.0 * (y * z + x * w) * s[1], (1.0 - 2.0 * (x * x + y * y)) * s[2], t[2],
+        0.0, 0.0, 0.0, 1.0,
+    );
+}
+
+// The parsed json plus the contents of all buffers
+struct GltfFile {
+    json: Value,
+    buffers: Vec<Vec<u8>>,
+    base_dir: PathBuf,
+}
+
+impl GltfFile {
+    fn new(contents: &[u8], base_dir: &Path) -> Result<GltfFile, ObjError> {
+        // glb: 12 byte header, then a json chunk and optionally a binary chunk
+        let mut json_bytes = contents;
+        let mut bin_chunk = None;
+        if contents.starts_with(GLB_MAGIC) {
+            let total_length = (read_u32(contents, 8)? as usize).min(contents.len());
+            let mut offset = 12;
+            while offset + 8 <= total_length {
+                let chunk_length = read_u32(contents, offset)? as usize;
+                let chunk_type = read_u32(contents, offset + 4)?;
+                let chunk = contents.get(offset + 8..offset + 8 + chunk_length)
+                    .ok_or_else(|| gltf_error("glb chunk", ObjErrorKind::UnexpectedEnd))?;
+                match chunk_type {
+                    GLB_CHUNK_JSON => json_bytes = chunk,
+                    GLB_CHUNK_BIN if bin_chunk.is_none() => bin_chunk = Some(chunk),
+                    _ => {},
+                }
+                offset += 8 + chunk_length;
+            }
+        }
+        let json: Value = serde_json::from_slice(json_bytes).map_err(|error| ObjError {
+            line: error.line(),
+            column: error.column(),
+            text: error.to_string(),
+            kind: ObjErrorKind::BadHeader,
+        })?;
+
+        let mut buffers = Vec::new();
+        for buffer in get_array(&json, "buffers") {
+            let data = match buffer.get("uri").and_then(|uri| uri.as_str()) {
+                Some(uri) if uri.starts_with("data:") => {
+                    let (_, data) = uri.split_once(";base64,").ok_or_else(|| gltf_error(uri, ObjErrorKind::UnsupportedDirective))?;
+                    decode_base64(data)?
+                },
+                Some(uri) => {
+                    let path = base_dir.join(uri);
+                    fs::read(&path).map_err(|error| gltf_error(&format!("{}: {}", path.display(), error), ObjErrorKind::FileRead))?
+                },
+                None => bin_chunk.ok_or_else(|| gltf_error("buffer without uri", ObjErrorKind::MissingIndex))?.to_vec(),
+            };
+            if data.len() < get_usize(buffer, "byteLength").unwrap_or(0) {
+                return Err(gltf_error(&format!("buffer {}", buffers.len()), ObjErrorKind::UnexpectedEnd));
+            }
+            buffers.push(data);
+        }
+        return Ok(GltfFile {
+            json: json,
+            buffers: buffers,
+            base_dir: base_dir.to_path_buf(),
+        });
+    }
+
+    fn item(&self, kind: &str, idx: usize) -> Result<&Value, ObjError> {
+        return get_array(&self.json, kind).get(idx).ok_or_else(|| gltf_error(&format!("{} {}", kind, idx), ObjErrorKind::IndexOutOfRange));
+    }
+
+    // Reads an accessor as a flat list of numbers, with the number of components per element
+    fn accessor(&self, idx: usize) -> Result<(Vec<f64>, usize), ObjError> {
+        let accessor = self.item("accessors", idx)?;
+        let components = match accessor.get("type").and_then(|t| t.as_str()) {
+            Some("SCALAR") => 1,
+            Some("VEC2") => 2,
+            Some("VEC3") => 3,
+            Some("VEC4") => 4,
+            Some("MAT4") => 16,
+            _ => return Err(gltf_error(&format!("accessor {} type", idx), ObjErrorKind::UnsupportedDirective)),
+        };
+        if accessor.get("sparse").is_some() {
+            return Err(gltf_error(&format!("sparse accessor {}", idx), ObjErrorKind::UnsupportedDirective));
+        }
+        let component_type = get_usize(accessor, "componentType").unwrap_or(0);
+        let (component_size, normalize_by) = match component_type {
+            5120 => (1, 127.0),
+            5121 => (1, 255.0),
+            5122 => (2, 32767.0),
+            5123 => (2, 65535.0),
+            5125 => (4, 1.0),
+            5126 => (4, 1.0),
+            _ => return Err(gltf_error(&format!("accessor {} component type {}", idx, component_type), ObjErrorKind::UnsupportedDirective)),
+        };
+        let normalized = accessor.get("normalized").and_then(|n| n.as_bool()).unwrap_or(false);
+        let count = get_usize(accessor, "count").unwrap_or(0);
+        let element_size = component_size * components;
+        let too_long = || gltf_error(&format!("accessor {}", idx), ObjErrorKind::UnexpectedEnd);
+
+        // Accessors without a buffer view are all zeros. Nothing in the file backs them, so they can't be
+        // larger than they would be if they were stored in the buffers.
+        let view_idx = match get_usize(accessor, "bufferView") {
+            Some(view_idx) => view_idx,
+            None => {
+                let buffer_size: usize = self.buffers.iter().map(|buffer| buffer.len()).sum();
+                if count.checked_mul(element_size).is_none_or(|size| size > buffer_size) {
+                    return Err(too_long());
+                }
+                return Ok((vec![0.0; count * components], components));
+            },
+        };
+        let view = self.item("bufferViews", view_idx)?;
+        let buffer_idx = get_usize(view, "buffer").unwrap_or(0);
+        let buffer = self.buffers.get(buffer_idx).ok_or_else(|| gltf_error(&format!("buffers {}", buffer_idx), ObjErrorKind::IndexOutOfRange))?;
+        let start = get_usize(view, "byteOffset").unwrap_or(0).checked_add(get_usize(accessor, "byteOffset").unwrap_or(0)).ok_or_else(too_long)?;
+        let stride = get_usize(view, "byteStride").unwrap_or(element_size);
+        if stride < element_size {
+            return Err(gltf_error(&format!("accessor {} stride {}", idx, stride), ObjErrorKind::BadNumber));
+        }
+        // With the stride at least the element size, this also bounds the allocation by the buffer size
+        if count > 0 {
+            let end = (count - 1).checked_mul(stride).and_then(|offset| offset.checked_add(start)).and_then(|offset| offset.checked_add(element_size));
+            if end.is_none_or(|end| end > buffer.len()) {
+                return Err(too_long());
+            }
+        }
+
+        let mut values = Vec::with_capacity(count * components);
+        for element in 0..count {
+            for component in 0..components {
+                let offset = start + element * stride + component * component_size;
+                let b = &buffer[offset..offset + component_size];
+                let value = match component_type {
+                    5120 => b[0] as i8 as f64,
+                    5121 => b[0] as f64,
+                    5122 => i16::from_le_bytes([b[0], b[1]]) as f64,
+                    5123 => u16::from_le_bytes([b[0], b[1]]) as f64,
+                    5125 => u32::from_le_bytes([b[0], b[1], b[2], b[3]]) as f64,
+                    _ => f32::from_le_bytes([b[0], b[1], b[2], b[3]]) as f64,
+                };
+                values.push(if normalized { (value / normalize_by).max(-1.0) } else { value });
+            }
+        }
+        return Ok((values, components));
+    }
+
+    fn accessor_vec3s(&self, idx: usize) -> Result<Vec<Vec3>, ObjError> {
+        let (values, components) = self.accessor(idx)?;
+        return Ok(values.chunks(components).map(|v| Vec3::new(
+            v[0] as Scalar,
+            v.get(1).copied().unwrap_or(0.0) as Scalar,
+            v.get(2).copied().unwrap_or(0.0) as Scalar,
+        )).collect());
+    }
+
+    fn texture_path(&self, texture_info: Option<&Value>) -> Option<PathBuf> {
+        let texture = self.item("textures", get_usize(texture_info?, "index")?).ok()?;
+        let image = self.item("images", get_usize(texture, "source")?).ok()?;
+        let uri = image.get("uri")?.as_str()?;
+        if uri.starts_with("data:") {
+            return None;
+        }
+        return Some(self.base_dir.join(uri));
+    }
+
+    // Metallic-roughness materials, mapped onto the mtl style material (base color as diffuse, alpha as dissolve)
+    fn material(&self, idx: usize, material: &Value) -> Material {
+        let name = material.get("name").and_then(|n| n.as_str()).map(|n| n.to_string()).unwrap_or(format!("material{}", idx));
+        let mut out = Material::new(&name);
+        let pbr = material.get("pbrMetallicRoughness").cloned().unwrap_or(Value::Null);
+        let base_color = get_scalars(&pbr, "baseColorFactor").filter(|c| c.len() == 4).unwrap_or(vec![1.0, 1.0, 1.0, 1.0]);
+        out.diffuse = Vec3::new(base_color[0], base_color[1], base_color[2]);
+        out.dissolve = base_color[3];
+        out.metallic = Some(get_scalar(&pbr, "metallicFactor", 1.0));
+        out.roughness = Some(get_scalar(&pbr, "roughnessFactor", 1.0));
+        out.diffuse_map = self.texture_path(pbr.get("baseColorTexture"));
+        out.bump_map = self.texture_path(material.get("normalTexture"));
+        out.bump_multiplier = material.get("normalTexture").map(|t| get_scalar(t, "scale", 1.0)).unwrap_or(1.0);
+
+        let extensions = material.get("extensions").cloned().unwrap_or(Value::Null);
+        let emissive = get_scalars(material, "emissiveFactor").filter(|e| e.len() == 3).unwrap_or(vec![0.0, 0.0, 0.0]);
+        let strength = extensions.get("KHR_materials_emissive_strength").map(|e| get_scalar(e, "emissiveStrength", 1.0)).unwrap_or(1.0);
+        out.emission = Vec3::new(emissive[0], emissive[1], emissive[2]) * strength;
+        out.ior = extensions.get("KHR_materials_ior").map(|e| get_scalar(e, "ior", 1.5)).unwrap_or(1.5);
+        out.clearcoat = extensions.get("KHR_materials_clearcoat").map(|e| get_scalar(e, "clearcoatFactor", 0.0));
+        return out;
+    }
+}
+
+// Vertex indices of the triangles of a primitive
+fn primitive_triangles(mode: u64, indices: &[usize]) -> Vec<[usize; 3]> {
+    return match mode {
+        MODE_TRIANGLE_STRIP => (2..indices.len()).map(|idx| {
+            if idx % 2 == 0 {
+                return [indices[idx - 2], indices[idx - 1], indices[idx]];
+            }
+            return [indices[idx - 1], indices[idx - 2], indices[idx]];
+        }).collect(),
+        MODE_TRIANGLE_FAN => (2..indices.len()).map(|idx| [indices[0], indices[idx - 1], indices[idx]]).collect(),
+        _ => indices.chunks_exact(3).map(|tri| [tri[0], tri[1], tri[2]]).collect(),
+    };
+}
+
+// Adds all triangle primitives of a mesh, transformed to world space, as one sub-mesh
+fn add_mesh(gltf: &GltfFile, state: &mut ObjState, faces: &mut Vec<Face>, warnings: &mut Vec<ObjError>, mesh_idx: usize, node_name: &str, transform: Mat4x4) -> Result<(), ObjError> {
+    let mesh = gltf.item("meshes", mesh_idx)?;
+    let mesh_name = mesh.get("name").and_then(|n| n.as_str()).map(|n| n.to_string()).unwrap_or(format!("mesh{}", mesh_idx));
+    state.apply(0, Statement::Object(node_name.to_string()))?;
+    state.apply(0, Statement::Group(vec![mesh_name]))?;
+
+    // Normals go through the inverse transpose, and mirroring transforms flip the winding back
+    let normal_transform = transform.inverse().t();
+    let mirrored = transform.determinant() < 0.0;
+    for primitive in get_array(mesh, "primitives") {
+        let mode = primitive.get("mode").and_then(|m| m.as_u64()).unwrap_or(MODE_TRIANGLES);
+        if mode != MODE_TRIANGLES && mode != MODE_TRIANGLE_STRIP && mode != MODE_TRIANGLE_FAN {
+            warnings.push(gltf_error(&format!("primitive mode {} in mesh {}", mode, mesh_idx), ObjErrorKind::UnsupportedDirective));
+            continue;
+        }
+        let attributes = primitive.get("attributes").cloned().unwrap_or(Value::Null);
+        let position_idx = get_usize(&attributes, "POSITION").ok_or_else(|| gltf_error(&format!("positions of mesh {}", mesh_idx), ObjErrorKind::MissingIndex))?;
+        let positions = gltf.accessor_vec3s(position_idx)?;
+        let normals = get_usize(&attributes, "NORMAL").map(|idx| gltf.accessor_vec3s(idx)).transpose()?;
+        let texcoords = get_usize(&attributes, "TEXCOORD_0").map(|idx| gltf.accessor_vec3s(idx)).transpose()?;
+        let colors = get_usize(&attributes, "COLOR_0").map(|idx| gltf.accessor_vec3s(idx)).transpose()?;
+        let indices: Vec<usize> = match get_usize(primitive, "indices") {
+            Some(idx) => gltf.accessor(idx)?.0.iter().map(|&i| i as usize).collect(),
+            None => (0..positions.len()).collect(),
+        };
+        if let Some(&bad_index) = indices.iter().find(|&&i| i >= positions.len()) {
+            return Err(gltf_error(&format!("index {} in mesh {}", bad_index, mesh_idx), ObjErrorKind::IndexOutOfRange));
+        }
+        // Corners use the same index for all attributes, so normals and texture coordinates have to be as long
+        for (name, attribute) in [("normals", &normals), ("texture coordinates", &texcoords)] {
+            if attribute.as_ref().is_some_and(|values| values.len() < positions.len()) {
+                return Err(gltf_error(&format!("{} of mesh {}", name, mesh_idx), ObjErrorKind::IndexOutOfRange));
+            }
+        }
+
+        let first_vertex = state.vertices.len();
+        let first_normal = state.normals.len();
+        let first_texcoord = state.texcoords.len();
+        for (idx, &p) in positions.iter().enumerate() {
+            let p = transform | Vec4::new(p.x(), p.y(), p.z(), 1.0);
+            state.vertices.push(Vec3::new(p.x(), p.y(), p.z()) / p.w());
+            state.colors.push(colors.as_ref().and_then(|c| c.get(idx).copied()));
+        }
+        if let Some(normals) = &normals {
+            for &n in normals {
+                let n = normal_transform | Vec4::new(n.x(), n.y(), n.z(), 0.0);
+                let n = Vec3::new(n.x(), n.y(), n.z());
+                state.normals.push(if n.length() > 0.0 { n.normalized() } else { n });
+            }
+        }
+        if let Some(texcoords) = &texcoords {
+            // glTF has v pointing down, obj up
+            state.texcoords.extend(texcoords.iter().map(|t| Vec3::new(t.x(), 1.0 - t.y(), 0.0)));
+        }
+
+        state.material = get_usize(primitive, "material").filter(|&idx| idx < state.materials.len());
+        for mut tri in primitive_triangles(mode, &indices) {
+            if mirrored {
+                tri.swap(1, 2);
+            }
+            let corners = tri.iter().map(|&idx| Corner {
+                p: first_vertex + idx,
+                t: texcoords.as_ref().map(|_| first_texcoord + idx),
+                n: normals.as_ref().map(|_| first_normal + idx),
+            }).collect();
+            faces.push(state.face(corners));
+        }
+    }
+    return Ok(());
+}
+
+// Reads a glTF 2.0 file (json or glb) into the same mesh as parse_obj would give. The node hierarchy of the
+// default scene is flattened, with every node that has a mesh becoming a sub-mesh (node name as object,
+// mesh name as group). External buffers and textures are relative to base_dir. Primitives without normals
+// get smooth ones.
+pub fn parse_gltf_with(contents: &[u8], base_dir: &Path, options: &ObjOptions) -> Result<Mesh, ObjError> {
+    let gltf = GltfFile::new(contents, base_dir)?;
+    let mut state = ObjState::new();
+    state.smoothing_group = 1;
+    for (idx, material) in get_array(&gltf.json, "materials").iter().enumerate() {
+        state.materials.push(gltf.material(idx, material));
+    }
+
+    // Scene roots, or all nodes that are nobody's children if there is no scene
+    let roots: Vec<usize> = match get_usize(&gltf.json, "scene").or(if get_array(&gltf.json, "scenes").is_empty() { None } else { Some(0) }) {
+        Some(scene_idx) => get_array(gltf.item("scenes", scene_idx)?, "nodes").iter().filter_map(|n| n.as_u64()).map(|n| n as usize).collect(),
+        None => {
+            let nodes = get_array(&gltf.json, "nodes");
+            let children: Vec<usize> = nodes.iter().flat_map(|node| get_array(node, "children").iter().filter_map(|c| c.as_u64()).map(|c| c as usize)).collect();
+            (0..nodes.len()).filter(|idx| !children.contains(idx)).collect()
+        },
+    };
+
+    let mut faces = Vec::new();
+    let mut warnings = Vec::new();
+    let mut stack: Vec<(usize, Mat4x4, usize)> = roots.iter().map(|&idx| (idx, identity(), 0)).collect();
+    let node_count = get_array(&gltf.json, "nodes").len();
+    while let Some((node_idx, parent_transform, depth)) = stack.pop() {
+        // Hierarchies are trees, so a node deeper than there are nodes means a cycle
+        if depth > node_count {
+            return Err(gltf_error(&format!("cycle at node {}", node_idx), ObjErrorKind::BadHeader));
+        }
+        let node = gltf.item("nodes", node_idx)?;
+        let transform = parent_transform | node_transform(node);
+        if let Some(mesh_idx) = get_usize(node, "mesh") {
+            let node_name = node.get("name").and_then(|n| n.as_str()).map(|n| n.to_string()).unwrap_or(format!("node{}", node_idx));
+            let result = add_mesh(&gltf, &mut state, &mut faces, &mut warnings, mesh_idx, &node_name, transform);
+            match result {
+                Ok(()) => {},
+                Err(error) if options.lenient => warnings.push(error),
+                Err(error) => return Err(error),
+            }
+        }
+        for child in get_array(node, "children").iter().rev().filter_map(|c| c.as_u64()) {
+            stack.push((child as usize, transform, depth + 1));
+        }
+    }
+    return Ok(build_mesh(state, faces, warnings, options));
+}
+
+pub fn parse_gltf(contents: &[u8], base_dir: &Path) -> Result<Mesh, ObjError> {
+    return parse_gltf_with(contents, base_dir, &ObjOptions::default());
+}
+
+pub fn read_gltf_with(path: &str, options: &ObjOptions) -> Result<Mesh, ObjError> {
+    let contents = fs::read(path).map_err(|error| gltf_error(&format!("{}: {}", path, error), ObjErrorKind::FileRead))?;
+    return parse_gltf_with(&contents, Path::new(path).parent().unwrap_or(Path::new("")), options);
+}
+
+pub fn read_gltf(path: &str) -> Result<Mesh, ObjError> {
+    return read_gltf_with(path, &ObjOptions::default());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_file(name: &str) -> String {
+        return format!("{}/test_data/gltf/{}", env!("CARGO_MANIFEST_DIR"), name);
+    }
+
+    fn components(v: Vec3) -> [Scalar; 3] {
+        return [v.x(), v.y(), v.z()];
+    }
+
+    fn check_quad(mesh: &Mesh) {
+        assert_eq!(mesh.triangles.len(), 2);
+        assert_eq!(mesh.sub_meshes.len(), 1);
+        assert_eq!(mesh.sub_meshes[0].object, "quad_node");
+        assert_eq!(mesh.sub_meshes[0].groups, vec!["quad".to_string()]);
+
+        // Scaled by 2 by the node, then moved by 1 in x by its parent. Triangles come out with their corners
+        // reversed, as they do for obj files.
+        let tri = &mesh.triangles[0];
+        assert_eq!(tri.p.map(components), [[3.0, 2.0, 0.0], [3.0, 0.0, 0.0], [1.0, 0.0, 0.0]]);
+        assert_eq!(tri.n.map(components), [[0.0, 0.0, 1.0]; 3]);
+        assert_eq!(tri.t.unwrap().map(components), [[1.0, 1.0, 0.0], [1.0, 0.0, 0.0], [0.0, 0.0, 0.0]]);
+        assert_eq!(tri.material, Some(0));
+
+        let material = &mesh.materials[0];
+        assert_eq!(material.name, "red");
+        assert_eq!(components(material.diffuse), [1.0, 0.0, 0.0]);
+        assert_eq!(material.dissolve, 0.5);
+        assert_eq!(material.metallic, Some(0.25));
+        assert_eq!(material.roughness, Some(0.75));
+        assert_eq!(components(material.emission), [0.1, 0.2, 0.3]);
+    }
+
+    #[test]
+    fn reads_external_buffer() {
+        check_quad(&read_gltf(&test_file("quad.gltf")).unwrap());
+    }
+
+    #[test]
+    fn reads_embedded_buffer() {
+        check_quad(&read_gltf(&test_file("quad_embedded.gltf")).unwrap());
+    }
+
+    #[test]
+    fn reads_glb() {
+        check_quad(&read_gltf(&test_file("quad.glb")).unwrap());
+    }
+
+    // The embedded file with its json changed by edit
+    fn parse_edited<F: FnOnce(&mut Value)>(edit: F) -> Result<Mesh, ObjError> {
+        let mut json: Value = serde_json::from_slice(&fs::read(test_file("quad_embedded.gltf")).unwrap()).unwrap();
+        edit(&mut json);
+        return parse_gltf(&serde_json::to_vec(&json).unwrap(), Path::new(""));
+    }
+
+    #[test]
+    fn short_attributes_are_out_of_range() {
+        let error = parse_edited(|json| json["accessors"][1]["count"] = 3.into()).err().unwrap();
+        assert_eq!(error.kind, ObjErrorKind::IndexOutOfRange);
+        let error = parse_edited(|json| json["accessors"][2]["count"] = 2.into()).err().unwrap();
+        assert_eq!(error.kind, ObjErrorKind::IndexOutOfRange);
+    }
+
+    #[test]
+    fn huge_counts_are_rejected() {
+        for count in [u64::MAX, u64::MAX / 12, 1 << 40] {
+            let error = parse_edited(|json| json["accessors"][0]["count"] = count.into()).err().unwrap();
+            assert_eq!(error.kind, ObjErrorKind::UnexpectedEnd);
+        }
+        let error = parse_edited(|json| {
+            json["accessors"][0]["count"] = (1u64 << 40).into();
+            json["accessors"][0].as_object_mut().unwrap().remove("bufferView");
+        }).err().unwrap();
+        assert_eq!(error.kind, ObjErrorKind::UnexpectedEnd);
+        let error = parse_edited(|json| json["accessors"][0]["byteOffset"] = u64::MAX.into()).err().unwrap();
+        assert_eq!(error.kind, ObjErrorKind::UnexpectedEnd);
+    }
+
+    #[test]
+    fn stride_below_element_size_is_rejected() {
+        let error = parse_edited(|json| json["bufferViews"][0]["byteStride"] = 0.into()).err().unwrap();
+        assert_eq!(error.kind, ObjErrorKind::BadNumber);
+    }
+}
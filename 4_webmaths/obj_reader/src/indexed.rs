@@ -0,0 +1,134 @@
+use vector_math::{*};
+use std::collections::HashMap;
+use crate::{Mesh, TriData};
+
+// Mesh with shared vertices: every vertex has a position and normal (and texture coordinate and color, if
+// the mesh has those) at the same index, and each triangle is three indices into them.
+#[derive(Clone)]
+pub struct IndexedMesh {
+    pub positions: Vec<Vec3>,
+    pub normals: Vec<Vec3>,
+    pub texcoords: Option<Vec<Vec3>>,
+    pub colors: Option<Vec<Vec3>>,
+    pub indices: Vec<[u32; 3]>,
+    pub triangle_materials: Vec<Option<usize>>,
+}
+
+// Bit patterns of everything that makes up a vertex, for exact deduplication. Missing texture coordinates
+// and colors count as the zero and white that from_triangles stores for them.
+fn vertex_key(tri: &TriData, corner: usize) -> [u32; 12] {
+    let t = tri.t.map(|t| t[corner]).unwrap_or(Vec3::new(0.0, 0.0, 0.0));
+    let c = tri.c.map(|c| c[corner]).unwrap_or(Vec3::new(1.0, 1.0, 1.0));
+    let mut key = [0; 12];
+    for (idx, v) in [tri.p[corner], tri.n[corner], t, c].iter().enumerate() {
+        key[idx * 3] = v.x().to_bits();
+        key[idx * 3 + 1] = v.y().to_bits();
+        key[idx * 3 + 2] = v.z().to_bits();
+    }
+    return key;
+}
+
+impl IndexedMesh {
+    // Builds an indexed mesh from a triangle list. With weld set, identical vertices get merged, otherwise
+    // every triangle gets its own three vertices. Texture coordinates and colors are kept if any triangle
+    // has them, with triangles that don't getting zero texture coordinates and white.
+    pub fn from_triangles(triangles: &[TriData], weld: bool) -> IndexedMesh {
+        let has_texcoords = triangles.iter().any(|tri| tri.t.is_some());
+        let has_colors = triangles.iter().any(|tri| tri.c.is_some());
+        let mut mesh = IndexedMesh {
+            positions: Vec::new(),
+            normals: Vec::new(),
+            texcoords: if has_texcoords { Some(Vec::new()) } else { None },
+            colors: if has_colors { Some(Vec::new()) } else { None },
+            indices: Vec::with_capacity(triangles.len()),
+            triangle_materials: Vec::with_capacity(triangles.len()),
+        };
+
+        let mut known_vertices: HashMap<[u32; 12], u32> = HashMap::new();
+        for tri in triangles {
+            let mut tri_indices = [0; 3];
+            for corner in 0..3 {
+                let key = vertex_key(tri, corner);
+                if weld {
+                    if let Some(&idx) = known_vertices.get(&key) {
+                        tri_indices[corner] = idx;
+                        continue;
+                    }
+                }
+                let idx = mesh.positions.len() as u32;
+                mesh.positions.push(tri.p[corner]);
+                mesh.normals.push(tri.n[corner]);
+                if let Some(texcoords) = mesh.texcoords.as_mut() {
+                    texcoords.push(tri.t.map(|t| t[corner]).unwrap_or(Vec3::new(0.0, 0.0, 0.0)));
+                }
+                if let Some(colors) = mesh.colors.as_mut() {
+                    colors.push(tri.c.map(|c| c[corner]).unwrap_or(Vec3::new(1.0, 1.0, 1.0)));
+                }
+                if weld {
+                    known_vertices.insert(key, idx);
+                }
+                tri_indices[corner] = idx;
+            }
+            mesh.indices.push(tri_indices);
+            mesh.triangle_materials.push(tri.material);
+        }
+        return mesh;
+    }
+
+    pub fn vertex_count(&self) -> usize {
+        return self.positions.len();
+    }
+
+    pub fn triangle_count(&self) -> usize {
+        return self.indices.len();
+    }
+
+    // Expands back into a flat triangle list
+    pub fn to_triangles(&self) -> Vec<TriData> {
+        return self.indices.iter().zip(self.triangle_materials.iter()).map(|(tri_indices, material)| {
+            let [a, b, c] = tri_indices.map(|idx| idx as usize);
+            return TriData {
+                p: [self.positions[a], self.positions[b], self.positions[c]],
+                n: [self.normals[a], self.normals[b], self.normals[c]],
+                t: self.texcoords.as_ref().map(|t| [t[a], t[b], t[c]]),
+                c: self.colors.as_ref().map(|col| [col[a], col[b], col[c]]),
+                material: *material,
+            };
+        }).collect();
+    }
+}
+
+impl Mesh {
+    pub fn to_indexed(&self, weld: bool) -> IndexedMesh {
+        return IndexedMesh::from_triangles(&self.triangles, weld);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use vector_math::{*};
+    use crate::TriData;
+    use super::IndexedMesh;
+
+    #[test]
+    fn missing_colors_weld_as_white() {
+        let p = [Vec3::new(0.0, 0.0, 0.0), Vec3::new(1.0, 0.0, 0.0), Vec3::new(0.0, 1.0, 0.0)];
+        let n = [Vec3::new(0.0, 0.0, 1.0); 3];
+        let black = Vec3::new(0.0, 0.0, 0.0);
+        let white = Vec3::new(1.0, 1.0, 1.0);
+        let triangles = [
+            TriData { p: p, n: n, t: None, c: Some([black; 3]), material: None },
+            TriData { p: p, n: n, t: None, c: None, material: None },
+            TriData { p: p, n: n, t: None, c: Some([white; 3]), material: None },
+        ];
+        let mesh = IndexedMesh::from_triangles(&triangles, true);
+        // The uncolored triangle shares the white one's vertices, not the black one's
+        assert_eq!(mesh.vertex_count(), 6);
+        assert_eq!(mesh.indices[1], mesh.indices[2]);
+        let colors = mesh.colors.as_ref().unwrap();
+        for &idx in &mesh.indices[1] {
+            let c = colors[idx as usize];
+            assert_eq!([c.r(), c.g(), c.b()], [1.0, 1.0, 1.0]);
+        }
+    }
+}
@@ -1,62 +1,223 @@
 use vector_math::{*};
 use std::fs;
+use std::fmt;
+use std::error::Error;
+use std::ops::Range;
+use std::path::Path;
 
+mod triangulate;
+mod normals;
+mod mtl;
+mod indexed;
+mod parse;
+mod stream;
+mod writer;
+mod ply;
+mod stl;
+mod gltf;
+mod process;
+mod simplify;
+mod subdivide;
+mod shapes;
+mod validate;
+mod cache;
+pub use triangulate::{triangulate, polygon_normal};
+pub use normals::NormalMode;
+pub use mtl::{Material, MaterialLibrary, parse_mtl, read_mtl, load_materials};
+pub use indexed::IndexedMesh;
+pub use stream::{ObjStream, stream_obj};
+pub use writer::{ObjWriteOptions, write_obj, write_obj_indexed, write_obj_triangles, write_obj_to, write_mtl, write_mtl_to};
+pub use ply::{PlyFormat, parse_ply, parse_ply_with, read_ply, read_ply_with, write_ply, write_ply_indexed, write_ply_to};
+pub use stl::{StlFormat, StlNormals, parse_stl, parse_stl_with, read_stl, read_stl_with, write_stl, write_stl_to};
+pub use gltf::{parse_gltf, parse_gltf_with, read_gltf, read_gltf_with};
+pub use process::{Aabb, BoundingSphere};
+pub use simplify::SimplifyOptions;
+pub use subdivide::{BoundaryRule, SubdivisionOptions};
+pub use shapes::{icosphere, uv_sphere, plane, cylinder, cone, torus, rounded_box, cube};
+pub use validate::{MeshIssue, MeshIssueKind, ValidationOptions, ValidationReport};
+pub use cache::{write_mesh_cache, write_mesh_cache_to, parse_mesh_cache, read_mesh_cache, mesh_cache_path, read_obj_cached};
+use parse::{ObjState, obj_lines, parse_statement, build_mesh};
+use stream::parse_obj_parallel;
+
+// Per-vertex positions and normals, plus texture coordinates (u, v, w, with w = 0 for 2D coordinates) and
+// colors (from the "v x y z r g b" extension) if every vertex of the triangle has them. The material is an
+// index into Mesh::materials, if there was a usemtl before the face.
 #[derive(Clone, Copy)]
 pub struct TriData {
     pub p: [Vec3; 3],
     pub n: [Vec3; 3],
+    pub t: Option<[Vec3; 3]>,
+    pub c: Option<[Vec3; 3]>,
+    pub material: Option<usize>,
+}
+
+// A face as it was in the obj file, before triangulation
+#[derive(Clone)]
+pub struct Polygon {
+    pub p: Vec<Vec3>,
+    pub n: Vec<Vec3>,
+    pub t: Option<Vec<Vec3>>,
+    pub c: Option<Vec<Vec3>>,
+    pub triangles: Range<usize>,
+}
+
+// A line ("l") element: a polyline through two or more vertices. Texture coordinates and colors are there if
+// every vertex has them, the material and sub-mesh are the ones the line was in.
+#[derive(Clone)]
+pub struct Polyline {
+    pub p: Vec<Vec3>,
+    pub t: Option<Vec<Vec3>>,
+    pub c: Option<Vec<Vec3>>,
+    pub material: Option<usize>,
+    pub sub_mesh: usize,
+}
+
+// A single vertex of a points ("p") element
+#[derive(Clone, Copy)]
+pub struct Point {
+    pub p: Vec3,
+    pub c: Option<Vec3>,
+    pub material: Option<usize>,
+    pub sub_mesh: usize,
+}
+
+// A named part of a mesh: all faces, lines and points that were in the same object ("o") and group(s) ("g").
+// Its name is the group name(s), or the object name for faces that aren't in a group.
+#[derive(Clone)]
+pub struct SubMesh {
+    pub name: String,
+    pub object: String,
+    pub groups: Vec<String>,
+    pub triangles: Range<usize>,
+    pub materials: Vec<usize>,
+    pub smoothing_groups: Vec<u32>,
+}
+
+// Triangles read from an obj file, the polygons they came from, lines, points, and everything that was skipped
+// when reading leniently.
+// Triangles are ordered by sub-mesh, so each sub-mesh is one contiguous range.
+// Materials are in order of first use, and only have their names filled in until their mtllib files are loaded.
+pub struct Mesh {
+    pub triangles: Vec<TriData>,
+    pub polygons: Vec<Polygon>,
+    pub polylines: Vec<Polyline>,
+    pub points: Vec<Point>,
+    pub sub_meshes: Vec<SubMesh>,
+    pub materials: Vec<Material>,
+    pub material_libs: Vec<String>,
+    pub warnings: Vec<ObjError>,
+}
+
+impl Mesh {
+    pub fn sub_mesh(&self, name: &str) -> Option<&SubMesh> {
+        return self.sub_meshes.iter().find(|sub_mesh| sub_mesh.name == name);
+    }
+
+    // All sub-meshes of an object, in order
+    pub fn object_sub_meshes<'a>(&'a self, object: &'a str) -> impl Iterator<Item = &'a SubMesh> {
+        return self.sub_meshes.iter().filter(move |sub_mesh| sub_mesh.object == object);
+    }
+
+    pub fn sub_mesh_triangles(&self, name: &str) -> Option<&[TriData]> {
+        return self.sub_mesh(name).map(|sub_mesh| &self.triangles[sub_mesh.triangles.clone()]);
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ObjErrorKind {
+    BadNumber,
+    MissingIndex,
+    IndexOutOfRange,
+    UnsupportedDirective,
+    MissingMaterial,
+    FileRead,
+    BadHeader,
+    UnexpectedEnd,
+    UnsupportedOption,
+}
+
+// Line and column are 1-based (0 for errors not tied to a line), text is the offending token (or the whole line,
+// if a token is missing)
+#[derive(Clone, Debug)]
+pub struct ObjError {
+    pub line: usize,
+    pub column: usize,
+    pub text: String,
+    pub kind: ObjErrorKind,
+}
+
+impl fmt::Display for ObjError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let description = match self.kind {
+            ObjErrorKind::BadNumber => "bad number",
+            ObjErrorKind::MissingIndex => "missing index",
+            ObjErrorKind::IndexOutOfRange => "index out of range",
+            ObjErrorKind::UnsupportedDirective => "unsupported directive",
+            ObjErrorKind::MissingMaterial => "missing material",
+            ObjErrorKind::FileRead => "file read error",
+            ObjErrorKind::BadHeader => "bad header",
+            ObjErrorKind::UnexpectedEnd => "unexpected end of file",
+            ObjErrorKind::UnsupportedOption => "unsupported option",
+        };
+        if self.line == 0 {
+            return write!(f, "Obj error: {} ('{}')", description, self.text);
+        }
+        return write!(f, "Obj parse error at line {}, column {}: {} ('{}')", self.line, self.column, description, self.text);
+    }
+}
+
+impl Error for ObjError {}
+
+#[derive(Clone, Copy, Default)]
+pub struct ObjOptions {
+    // Skip lines that fail to parse and record them in Mesh::warnings, instead of failing
+    pub lenient: bool,
+
+    // How to make up normals for faces that don't have any
+    pub normals: NormalMode,
+
+    // Parse on this many threads (0 or 1 means parse on the calling thread)
+    pub threads: usize,
 }
 
 // Opinionated obj reader
 // t/l note: opinionated means i implement only a subset and make various assumptions that may not hold in reality
-pub fn parse_obj(contents: &str) -> Vec<TriData> {
-    let mut vertices: Vec<Vec3> = Vec::new();
-    let mut normals: Vec<Vec3> = Vec::new(); 
-    let mut triangles: Vec<TriData> = Vec::new();
-
-    for line in contents.lines() {
-        let mut tokens = line.split(' ');
-        let line_type = tokens.next();
-        if !line_type.is_none() {
-            let line_type = line_type.unwrap();
-            if line_type.eq("v") {
-                vertices.push(Vec3::new(
-                    tokens.next().expect("Obj parse error").parse::<Scalar>().unwrap(),
-                    tokens.next().expect("Obj parse error").parse::<Scalar>().unwrap(),
-                    tokens.next().expect("Obj parse error").parse::<Scalar>().unwrap(),
-                ));
-            }
-
-            if line_type.eq("vn") {
-                normals.push(Vec3::new(
-                    tokens.next().expect("Obj parse error").parse::<Scalar>().unwrap(),
-                    tokens.next().expect("Obj parse error").parse::<Scalar>().unwrap(),
-                    tokens.next().expect("Obj parse error").parse::<Scalar>().unwrap(),
-                ));
-            }
-
-            if line_type.eq("f") {
-                let mut new_tri_data = TriData {
-                    p: [Vec3::new(0.0, 0.0, 0.0), Vec3::new(0.0, 0.0, 0.0), Vec3::new(0.0, 0.0, 0.0)],
-                    n: [Vec3::new(0.0, 0.0, 0.0), Vec3::new(0.0, 0.0, 0.0), Vec3::new(0.0, 0.0, 0.0)],
-                };
-                for idx in 0..3 {
-                    let vertex_token = tokens.next().expect("Obj parse error x");
-                    let mut vertex_info = vertex_token.split("/");
-                    let vertex_idx = vertex_info.next().expect("Obj parse error y").parse::<usize>().unwrap() - 1;
-                    vertex_info.next();
-                    let normal_idx = vertex_info.next().expect("Obj parse error z").parse::<usize>().unwrap() - 1;
-                    new_tri_data.p[2 - idx] = vertices[vertex_idx];
-                    new_tri_data.n[2 - idx] = normals[normal_idx];
-                    triangles.push(new_tri_data);
-                }
-            }
+pub fn parse_obj_with(contents: &str, options: &ObjOptions) -> Result<Mesh, ObjError> {
+    if options.threads > 1 {
+        return parse_obj_parallel(contents, options);
+    }
+
+    let mut state = ObjState::new();
+    let mut faces = Vec::new();
+    let mut warnings = Vec::new();
+    for (line_num, line) in obj_lines(contents) {
+        let result = parse_statement(line_num, &line).and_then(|statement| state.apply(line_num, statement));
+        match result {
+            Ok(Some(face)) => faces.push(face),
+            Ok(None) => {},
+            Err(error) if options.lenient => warnings.push(error),
+            Err(error) => return Err(error),
         }
     }
-    return triangles;
+    return Ok(build_mesh(state, faces, warnings, options));
 }
 
-pub fn read_obj(path: &str) -> Vec<TriData> {
-    let contents = fs::read_to_string(path).expect("File read error");
-    return parse_obj(&contents);
-}
\ No newline at end of file
+pub fn parse_obj(contents: &str) -> Result<Mesh, ObjError> {
+    return parse_obj_with(contents, &ObjOptions::default());
+}
+
+pub fn read_obj_with(path: &str, options: &ObjOptions) -> Result<Mesh, ObjError> {
+    let contents = fs::read_to_string(path).map_err(|error| ObjError {
+        line: 0,
+        column: 0,
+        text: error.to_string(),
+        kind: ObjErrorKind::FileRead,
+    })?;
+    let mut mesh = parse_obj_with(&contents, options)?;
+    load_materials(&mut mesh, Path::new(path).parent().unwrap_or(Path::new("")), options);
+    return Ok(mesh);
+}
+
+pub fn read_obj(path: &str) -> Result<Mesh, ObjError> {
+    return read_obj_with(path, &ObjOptions::default());
+}
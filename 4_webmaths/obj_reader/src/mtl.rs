@@ -0,0 +1,269 @@
+use vector_math::{*};
+use std::fs;
+use std::path::{Path, PathBuf};
+use crate::{Mesh, ObjError, ObjErrorKind, ObjOptions};
+use crate::parse::{LineTokens, strip_comment};
+
+// A material from an mtl file. Texture paths are relative to the mtl file when parsed, and get resolved
+// against its directory when loaded through read_obj / load_materials.
+#[derive(Clone)]
+pub struct Material {
+    pub name: String,
+    pub diffuse: Vec3, // Kd
+    pub specular: Vec3, // Ks
+    pub emission: Vec3, // Ke
+    pub specular_exponent: Scalar, // Ns
+    pub ior: Scalar, // Ni
+    pub dissolve: Scalar, // d, or 1 - Tr
+    pub illum: u32,
+    pub diffuse_map: Option<PathBuf>, // map_Kd
+    pub bump_map: Option<PathBuf>, // map_Bump / bump
+    pub bump_multiplier: Scalar, // -bm option of the bump map
+    pub roughness: Option<Scalar>, // Pr
+    pub metallic: Option<Scalar>, // Pm
+    pub clearcoat: Option<Scalar>, // Pc
+}
+
+impl Material {
+    pub fn new(name: &str) -> Material {
+        return Material {
+            name: name.to_string(),
+            diffuse: Vec3::new(0.8, 0.8, 0.8),
+            specular: Vec3::new(0.0, 0.0, 0.0),
+            emission: Vec3::new(0.0, 0.0, 0.0),
+            specular_exponent: 0.0,
+            ior: 1.0,
+            dissolve: 1.0,
+            illum: 2,
+            diffuse_map: None,
+            bump_map: None,
+            bump_multiplier: 1.0,
+            roughness: None,
+            metallic: None,
+            clearcoat: None,
+        };
+    }
+}
+
+// Materials read from an mtl file, plus everything that was skipped when reading leniently
+pub struct MaterialLibrary {
+    pub materials: Vec<Material>,
+    pub warnings: Vec<ObjError>,
+}
+
+// Statements that are valid but carry nothing we use
+const IGNORED_STATEMENTS: [&str; 19] = [
+    "Ka", "Tf", "map_Ka", "map_Ks", "map_Ns", "map_d", "map_Ke", "map_Pr", "map_Pm", "map_Ps", "disp", "decal",
+    "refl", "sharpness", "Ps", "Pcr", "aniso", "anisor", "norm"
+];
+
+// Parses a color, where a single value means gray
+fn parse_color(tokens: &mut LineTokens) -> Result<Vec3, ObjError> {
+    let values = tokens.rest_scalars()?;
+    return match values.len() {
+        1 => Ok(Vec3::new(values[0], values[0], values[0])),
+        3 => Ok(Vec3::new(values[0], values[1], values[2])),
+        _ => Err(tokens.missing(ObjErrorKind::BadNumber)),
+    };
+}
+
+// Parses a texture map statement, skipping over options ("-o 0.5 0.5", "-clamp on", ...) and returning the
+// file name and the bump multiplier, if given
+fn parse_map(tokens: &mut LineTokens) -> Result<(PathBuf, Option<Scalar>), ObjError> {
+    let mut bump_multiplier = None;
+    let mut file_name: Vec<&str> = Vec::new();
+    while let Some((column, token)) = tokens.next() {
+        if !file_name.is_empty() || !token.starts_with('-') {
+            file_name.push(token);
+            continue;
+        }
+        match token {
+            "-bm" => bump_multiplier = Some(tokens.next_scalar()?),
+            "-blendu" | "-blendv" | "-boost" | "-cc" | "-clamp" | "-imfchan" | "-texres" | "-type" => {
+                tokens.next().ok_or_else(|| tokens.missing(ObjErrorKind::BadNumber))?;
+            },
+            "-mm" => {
+                tokens.next_scalar()?;
+                tokens.next_scalar()?;
+            },
+            "-o" | "-s" | "-t" => {
+                tokens.next_scalar()?;
+                let mut lookahead = tokens.clone();
+                for _ in 0..2 {
+                    match lookahead.next() {
+                        Some((_, value)) if value.parse::<Scalar>().is_ok() => { tokens.next(); },
+                        _ => break,
+                    }
+                }
+            },
+            _ => return Err(tokens.error(column, token, ObjErrorKind::UnsupportedDirective)),
+        }
+    }
+    if file_name.is_empty() {
+        return Err(tokens.missing(ObjErrorKind::MissingIndex));
+    }
+    return Ok((PathBuf::from(file_name.join(" ")), bump_multiplier));
+}
+
+pub fn parse_mtl(contents: &str, options: &ObjOptions) -> Result<MaterialLibrary, ObjError> {
+    let mut library = MaterialLibrary {
+        materials: Vec::new(),
+        warnings: Vec::new(),
+    };
+
+    for (line_idx, line) in contents.lines().enumerate() {
+        let mut tokens = LineTokens::new(line_idx + 1, strip_comment(line));
+        let (column, statement) = match tokens.next() {
+            Some(token) => token,
+            None => continue,
+        };
+        if statement == "newmtl" {
+            library.materials.push(Material::new(tokens.rest()));
+            continue;
+        }
+
+        let result = match library.materials.last_mut() {
+            None => Err(tokens.error(column, statement, ObjErrorKind::MissingMaterial)),
+            Some(material) => match statement {
+                "Kd" => parse_color(&mut tokens).map(|c| material.diffuse = c),
+                "Ks" => parse_color(&mut tokens).map(|c| material.specular = c),
+                "Ke" => parse_color(&mut tokens).map(|c| material.emission = c),
+                "Ns" => tokens.next_scalar().map(|v| material.specular_exponent = v),
+                "Ni" => tokens.next_scalar().map(|v| material.ior = v),
+                "d" => tokens.next_scalar().map(|v| material.dissolve = v),
+                "Tr" => tokens.next_scalar().map(|v| material.dissolve = 1.0 - v),
+                "illum" => tokens.next_scalar().map(|v| material.illum = v as u32),
+                "Pr" => tokens.next_scalar().map(|v| material.roughness = Some(v)),
+                "Pm" => tokens.next_scalar().map(|v| material.metallic = Some(v)),
+                "Pc" => tokens.next_scalar().map(|v| material.clearcoat = Some(v)),
+                "map_Kd" => parse_map(&mut tokens).map(|(path, _)| material.diffuse_map = Some(path)),
+                "map_Bump" | "map_bump" | "bump" => parse_map(&mut tokens).map(|(path, multiplier)| {
+                    material.bump_map = Some(path);
+                    material.bump_multiplier = multiplier.unwrap_or(1.0);
+                }),
+                _ if IGNORED_STATEMENTS.contains(&statement) => Ok(()),
+                _ => Err(tokens.error(column, statement, ObjErrorKind::UnsupportedDirective)),
+            },
+        };
+        if let Err(error) = result {
+            if !options.lenient {
+                return Err(error);
+            }
+            library.warnings.push(error);
+        }
+    }
+    return Ok(library);
+}
+
+pub fn read_mtl(path: &Path, options: &ObjOptions) -> Result<MaterialLibrary, ObjError> {
+    let contents = fs::read_to_string(path).map_err(|error| ObjError {
+        line: 0,
+        column: 0,
+        text: format!("{}: {}", path.display(), error),
+        kind: ObjErrorKind::FileRead,
+    })?;
+    let mut library = parse_mtl(&contents, options)?;
+    let mtl_dir = path.parent().unwrap_or(Path::new(""));
+    for material in library.materials.iter_mut() {
+        material.diffuse_map = material.diffuse_map.as_ref().map(|map| mtl_dir.join(map));
+        material.bump_map = material.bump_map.as_ref().map(|map| mtl_dir.join(map));
+    }
+    return Ok(library);
+}
+
+// Loads the mtllib files of a mesh (relative to obj_dir) and fills in the materials it uses.
+// Missing libraries or materials only produce warnings, since the geometry is fine without them.
+pub fn load_materials(mesh: &mut Mesh, obj_dir: &Path, options: &ObjOptions) {
+    let mut found = vec![false; mesh.materials.len()];
+    for library_name in mesh.material_libs.clone() {
+        match read_mtl(&obj_dir.join(&library_name), options) {
+            Ok(library) => {
+                mesh.warnings.extend(library.warnings);
+                for loaded in library.materials {
+                    for (material_idx, material) in mesh.materials.iter_mut().enumerate() {
+                        if material.name == loaded.name {
+                            *material = loaded.clone();
+                            found[material_idx] = true;
+                        }
+                    }
+                }
+            },
+            Err(error) => mesh.warnings.push(error),
+        }
+    }
+    for (material_idx, material) in mesh.materials.iter().enumerate() {
+        if !found[material_idx] {
+            mesh.warnings.push(ObjError {
+                line: 0,
+                column: 0,
+                text: material.name.clone(),
+                kind: ObjErrorKind::MissingMaterial,
+            });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use vector_math::{*};
+    use std::fs;
+    use std::path::PathBuf;
+    use crate::{ObjErrorKind, ObjOptions};
+    use super::{Material, parse_mtl, read_mtl};
+
+    fn parse(mtl: &str) -> Vec<Material> {
+        return parse_mtl(mtl, &ObjOptions::default()).unwrap().materials;
+    }
+
+    fn rgb(v: Vec3) -> [Scalar; 3] {
+        return [v.x(), v.y(), v.z()];
+    }
+
+    #[test]
+    fn comments_end_lines() {
+        let materials = parse("# colors\nnewmtl red # not part of the name\nKd 1 0 0 # red\nKs 0.5\t# gray\n  # indented\n");
+        assert_eq!(materials[0].name, "red");
+        assert_eq!(rgb(materials[0].diffuse), [1.0, 0.0, 0.0]);
+        assert_eq!(rgb(materials[0].specular), [0.5; 3]);
+        let error = parse_mtl("newmtl red\nNs 10#no space\n", &ObjOptions::default()).err().unwrap();
+        assert_eq!((error.line, error.kind), (2, ObjErrorKind::BadNumber));
+    }
+
+    #[test]
+    fn tr_is_the_opposite_of_d() {
+        let materials = parse("newmtl glass\nTr 0.25\nnewmtl both\nTr 0.25\nd 0.5\nnewmtl solid\n");
+        assert_eq!(materials.iter().map(|material| material.dissolve).collect::<Vec<_>>(), [0.75, 0.5, 1.0]);
+    }
+
+    #[test]
+    fn maps_skip_their_options() {
+        let mtl = "newmtl textured\n\
+            map_Kd -o 0.5 0.5 -clamp on -s 2 textures/my wood.png\n\
+            bump -bm 0.5 -imfchan l bump.png\n\
+            newmtl plain_bump\nmap_Bump normal.png\n";
+        let materials = parse(mtl);
+        assert_eq!(materials[0].diffuse_map, Some(PathBuf::from("textures/my wood.png")));
+        assert_eq!(materials[0].bump_map, Some(PathBuf::from("bump.png")));
+        assert_eq!(materials[0].bump_multiplier, 0.5);
+        assert_eq!(materials[1].bump_map, Some(PathBuf::from("normal.png")));
+        assert_eq!(materials[1].bump_multiplier, 1.0);
+
+        let error = parse_mtl("newmtl broken\nmap_Kd -o 0.5\n", &ObjOptions::default()).err().unwrap();
+        assert_eq!(error.kind, ObjErrorKind::MissingIndex);
+    }
+
+    #[test]
+    fn texture_paths_are_relative_to_the_mtl_file() {
+        let dir = std::env::temp_dir().join(format!("obj_reader_mtl_test_{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("scene.mtl");
+        fs::write(&path, "newmtl wood\nmap_Kd textures/wood.png\nbump -bm 2 ../shared/bump.png\n").unwrap();
+        let library = read_mtl(&path, &ObjOptions::default()).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+
+        let material = &library.materials[0];
+        assert_eq!(material.diffuse_map, Some(dir.join("textures/wood.png")));
+        assert_eq!(material.bump_map, Some(dir.join("../shared/bump.png")));
+        assert_eq!(material.bump_multiplier, 2.0);
+    }
+}
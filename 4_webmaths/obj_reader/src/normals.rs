@@ -0,0 +1,94 @@
+use vector_math::{*};
+use std::collections::HashMap;
+use crate::triangulate::polygon_normal;
+use crate::parse::Face;
+
+// Normal generation for faces without normals. The smooth modes average the normals of all faces
+// sharing a vertex within the same smoothing group ("s 1", "s 2", ...), faces with "s off" stay flat.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Default)]
+pub enum NormalMode {
+    Flat,
+    SmoothArea,
+    #[default]
+    SmoothAngle,
+}
+
+// Angle between the two edges at a corner
+pub fn corner_angle(prev: Vec3, this: Vec3, next: Vec3) -> Scalar {
+    let a = prev - this;
+    let b = next - this;
+    let len = a.length() * b.length();
+    if len == 0.0 {
+        return 0.0;
+    }
+    return ((a & b) / len).clamp(-1.0, 1.0).acos();
+}
+
+// Computes a normal for every corner of every face (whether it already has one or not), or nothing at all
+// if every corner already has a normal
+pub fn generate_normals(vertices: &[Vec3], faces: &[Face], mode: NormalMode) -> Vec<Vec<Vec3>> {
+    if faces.iter().all(|face| face.corners.iter().all(|corner| corner.n.is_some())) {
+        return vec![Vec::new(); faces.len()];
+    }
+
+    // Newells normal has length twice the polygon area, which is exactly the area weight we want
+    let face_normals: Vec<Vec3> = faces.iter().map(|face| {
+        let points: Vec<Vec3> = face.corners.iter().map(|corner| vertices[corner.p]).collect();
+        return polygon_normal(&points);
+    }).collect();
+
+    // Accumulate weighted face normals per vertex and smoothing group
+    let mut accumulated: HashMap<(usize, u32), Vec3> = HashMap::new();
+    if mode != NormalMode::Flat {
+        for (face, face_normal) in faces.iter().zip(face_normals.iter()) {
+            // Zero area faces have no direction to add, and would add NaNs in the angle weighted mode
+            if face.smoothing_group == 0 || face_normal.length() == 0.0 {
+                continue;
+            }
+            let count = face.corners.len();
+            for idx in 0..count {
+                let weighted = match mode {
+                    NormalMode::SmoothAngle => {
+                        let prev = vertices[face.corners[(idx + count - 1) % count].p];
+                        let this = vertices[face.corners[idx].p];
+                        let next = vertices[face.corners[(idx + 1) % count].p];
+                        face_normal.normalized() * corner_angle(prev, this, next)
+                    },
+                    _ => *face_normal,
+                };
+                let key = (face.corners[idx].p, face.smoothing_group);
+                let sum = accumulated.entry(key).or_insert(Vec3::new(0.0, 0.0, 0.0));
+                *sum += weighted;
+            }
+        }
+    }
+
+    return faces.iter().zip(face_normals.iter()).map(|(face, face_normal)| {
+        return face.corners.iter().map(|corner| {
+            let normal = accumulated.get(&(corner.p, face.smoothing_group)).copied().unwrap_or(*face_normal);
+            if normal.length() == 0.0 {
+                return normal;
+            }
+            return normal.normalized();
+        }).collect();
+    }).collect();
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{ObjOptions, parse_obj_with};
+    use super::NormalMode;
+
+    #[test]
+    fn degenerate_faces_dont_spread_nan() {
+        // The second face has all corners on a line, and shares two vertices with the first
+        let obj = "v 0 0 0\nv 1 0 0\nv 0 1 0\nv 2 0 0\ns 1\nf 1 2 3\nf 1 2 4\n";
+        for mode in [NormalMode::SmoothAngle, NormalMode::SmoothArea] {
+            let mesh = parse_obj_with(obj, &ObjOptions { normals: mode, ..ObjOptions::default() }).unwrap();
+            for n in mesh.triangles[0].n.iter() {
+                assert_eq!([n.x(), n.y(), n.z()], [0.0, 0.0, 1.0]);
+            }
+            assert!(mesh.triangles[1].n.iter().all(|n| !n.x().is_nan() && !n.y().is_nan() && !n.z().is_nan()));
+        }
+    }
+}
@@ -0,0 +1,598 @@
+use vector_math::{*};
+use std::borrow::Cow;
+use crate::{Material, Mesh, ObjError, ObjErrorKind, ObjOptions, Point, Polygon, Polyline, SubMesh, TriData};
+use crate::normals::generate_normals;
+use crate::triangulate::triangulate;
+
+// A face corner as written in the file, with 1-based (or negative, relative) indices
+#[derive(Clone, Copy)]
+pub struct RawCorner {
+    column: usize,
+    p: isize,
+    t: Option<isize>,
+    n: Option<isize>,
+}
+
+// A face corner, as 0-based indices into the position, texture coordinate and normal lists
+#[derive(Clone, Copy)]
+pub struct Corner {
+    pub p: usize,
+    pub t: Option<usize>,
+    pub n: Option<usize>,
+}
+
+pub struct Face {
+    pub corners: Vec<Corner>,
+    pub smoothing_group: u32,
+    pub material: Option<usize>,
+    pub sub_mesh: usize,
+}
+
+// A single parsed line. Parsing a line needs no context, so lines can be parsed in any order (or in parallel),
+// and then get applied to an ObjState in file order.
+pub enum Statement {
+    Vertex(Vec3, Option<Vec3>),
+    Normal(Vec3),
+    Texcoord(Vec3),
+    Parameter(Vec3),
+    Face(Vec<RawCorner>),
+    Line(Vec<RawCorner>),
+    Points(Vec<RawCorner>),
+    Smoothing(u32),
+    Object(String),
+    Group(Vec<String>),
+    UseMtl(String),
+    MtlLib(Vec<String>),
+    Nothing,
+}
+
+// Directives that are valid but carry nothing we use, so they get skipped without complaint
+const IGNORED_DIRECTIVES: [&str; 7] = [
+    "mg", "lod", "bevel", "c_interp", "d_interp", "shadow_obj", "trace_obj"
+];
+
+// Where a line ending in a backslash (and maybe some whitespace) continues onto the next one, if it does
+pub fn continuation(line: &str) -> Option<usize> {
+    let trimmed = line.trim_end();
+    return if trimmed.ends_with('\\') { Some(trimmed.len() - 1) } else { None };
+}
+
+// Lines of an obj file, numbered from 1, with continued lines joined into one. A joined line gets the
+// number of its first line, and columns count from the start of the joined line.
+pub fn obj_lines(contents: &str) -> impl Iterator<Item = (usize, Cow<'_, str>)> {
+    let mut lines = contents.lines().enumerate();
+    return std::iter::from_fn(move || {
+        let (line_idx, line) = lines.next()?;
+        let mut line = Cow::Borrowed(line);
+        while let Some(end) = continuation(&line) {
+            let joined = line.to_mut();
+            joined.truncate(end);
+            joined.push(' ');
+            match lines.next() {
+                Some((_, next_line)) => joined.push_str(next_line),
+                None => break,
+            }
+        }
+        return Some((line_idx + 1, line));
+    });
+}
+
+// Cuts off a comment, which starts with a '#' at the start of the line or after whitespace
+pub fn strip_comment(line: &str) -> &str {
+    let mut search_start = 0;
+    while let Some(idx) = line[search_start..].find('#') {
+        let idx = search_start + idx;
+        if idx == 0 || line.as_bytes()[idx - 1].is_ascii_whitespace() {
+            return &line[..idx];
+        }
+        search_start = idx + 1;
+    }
+    return line;
+}
+
+// Whitespace separated tokens of a single line, keeping track of where we are for error reporting
+#[derive(Clone)]
+pub struct LineTokens<'a> {
+    line_num: usize,
+    line: &'a str,
+    pos: usize,
+    column: usize,
+}
+
+impl<'a> LineTokens<'a> {
+    pub fn new(line_num: usize, line: &'a str) -> LineTokens<'a> {
+        return LineTokens {
+            line_num: line_num,
+            line: line,
+            pos: 0,
+            column: 1,
+        };
+    }
+
+    pub fn next(&mut self) -> Option<(usize, &'a str)> {
+        // Whitespace is ascii, so this can go byte by byte and count skipped bytes as columns
+        let bytes = self.line.as_bytes();
+        let mut start = self.pos;
+        while start < bytes.len() && bytes[start].is_ascii_whitespace() {
+            start += 1;
+        }
+        if start == bytes.len() {
+            self.pos = start;
+            return None;
+        }
+        let mut end = start;
+        while end < bytes.len() && !bytes[end].is_ascii_whitespace() {
+            end += 1;
+        }
+        let token = &self.line[start..end];
+        let column = self.column + (start - self.pos);
+        self.pos = end;
+        self.column = column + token.chars().count();
+        return Some((column, token));
+    }
+
+    // Whatever is left on the line, without surrounding whitespace
+    pub fn rest(&mut self) -> &'a str {
+        let rest = self.line[self.pos..].trim_matches(|c: char| c.is_ascii_whitespace());
+        self.column += self.line[self.pos..].chars().count();
+        self.pos = self.line.len();
+        return rest;
+    }
+
+    // Whatever is left on the line, as a list of names
+    pub fn rest_names(&mut self) -> Vec<String> {
+        let mut names = Vec::new();
+        while let Some((_, name)) = self.next() {
+            names.push(name.to_string());
+        }
+        return names;
+    }
+
+    pub fn error(&self, column: usize, text: &str, kind: ObjErrorKind) -> ObjError {
+        return ObjError {
+            line: self.line_num,
+            column: column,
+            text: text.to_string(),
+            kind: kind,
+        };
+    }
+
+    pub fn missing(&self, kind: ObjErrorKind) -> ObjError {
+        return self.error(self.line.chars().count() + 1, self.line, kind);
+    }
+
+    pub fn next_scalar(&mut self) -> Result<Scalar, ObjError> {
+        let (column, token) = self.next().ok_or_else(|| self.missing(ObjErrorKind::BadNumber))?;
+        return token.parse::<Scalar>().map_err(|_| self.error(column, token, ObjErrorKind::BadNumber));
+    }
+
+    pub fn next_vec3(&mut self) -> Result<Vec3, ObjError> {
+        return Ok(Vec3::new(self.next_scalar()?, self.next_scalar()?, self.next_scalar()?));
+    }
+
+    // Parses whatever is left on the line as numbers
+    pub fn rest_scalars(&mut self) -> Result<Vec<Scalar>, ObjError> {
+        let mut values = Vec::new();
+        while let Some((column, token)) = self.next() {
+            values.push(token.parse::<Scalar>().map_err(|_| self.error(column, token, ObjErrorKind::BadNumber))?);
+        }
+        return Ok(values);
+    }
+}
+
+// Parses the corners of a face, line or points element. Faces take any of the v, v/vt, v//vn and v/vt/vn forms,
+// lines only v and v/vt, and points only v.
+fn parse_corners(tokens: &mut LineTokens, min_corners: usize, max_parts: usize) -> Result<Vec<RawCorner>, ObjError> {
+    let mut corners = Vec::new();
+    while let Some((column, vertex_token)) = tokens.next() {
+        let vertex_info: Vec<&str> = vertex_token.split("/").collect();
+        if vertex_info.len() > max_parts {
+            return Err(tokens.error(column, vertex_token, ObjErrorKind::BadNumber));
+        }
+        if vertex_info[0].is_empty() {
+            return Err(tokens.error(column, vertex_token, ObjErrorKind::MissingIndex));
+        }
+        let mut parsed = [None; 3];
+        for (info_idx, info) in vertex_info.iter().enumerate() {
+            if !info.is_empty() {
+                let idx = info.parse::<isize>().map_err(|_| tokens.error(column, info, ObjErrorKind::BadNumber))?;
+                parsed[info_idx] = Some(idx);
+            }
+        }
+        corners.push(RawCorner {
+            column: column,
+            p: parsed[0].unwrap(),
+            t: parsed[1],
+            n: parsed[2],
+        });
+    }
+    if corners.len() < min_corners {
+        return Err(tokens.missing(ObjErrorKind::MissingIndex));
+    }
+    return Ok(corners);
+}
+
+// Parses a vertex position, with either the optional weight w or a trailing color. The weight only matters
+// for free-form geometry, which we don't read, so it just gets checked and dropped.
+fn parse_vertex(tokens: &mut LineTokens) -> Result<Statement, ObjError> {
+    let position = tokens.next_vec3()?;
+    let rest = tokens.rest_scalars()?;
+    return match rest.len() {
+        0 | 1 => Ok(Statement::Vertex(position, None)),
+        2 => Err(tokens.missing(ObjErrorKind::BadNumber)),
+        _ => Ok(Statement::Vertex(position, Some(Vec3::new(rest[0], rest[1], rest[2])))),
+    };
+}
+
+// Parses a parameter space vertex, where v defaults to 0 and the weight w to 1
+fn parse_parameter(tokens: &mut LineTokens) -> Result<Vec3, ObjError> {
+    let u = tokens.next_scalar()?;
+    let rest = tokens.rest_scalars()?;
+    return Ok(Vec3::new(u, rest.first().copied().unwrap_or(0.0), rest.get(1).copied().unwrap_or(1.0)));
+}
+
+// Parses a texture coordinate, where v and w are optional and default to 0
+fn parse_texcoord(tokens: &mut LineTokens) -> Result<Vec3, ObjError> {
+    let u = tokens.next_scalar()?;
+    let rest = tokens.rest_scalars()?;
+    return Ok(Vec3::new(u, rest.first().copied().unwrap_or(0.0), rest.get(1).copied().unwrap_or(0.0)));
+}
+
+fn parse_smoothing_group(tokens: &mut LineTokens) -> Result<u32, ObjError> {
+    let (column, token) = tokens.next().ok_or_else(|| tokens.missing(ObjErrorKind::BadNumber))?;
+    if token == "off" {
+        return Ok(0);
+    }
+    return token.parse::<u32>().map_err(|_| tokens.error(column, token, ObjErrorKind::BadNumber));
+}
+
+// Parses a single (possibly joined, see obj_lines) line
+pub fn parse_statement(line_num: usize, line: &str) -> Result<Statement, ObjError> {
+    let mut tokens = LineTokens::new(line_num, strip_comment(line));
+    let (column, line_type) = match tokens.next() {
+        Some(token) => token,
+        None => return Ok(Statement::Nothing),
+    };
+    return match line_type {
+        "v" => parse_vertex(&mut tokens),
+        "vn" => tokens.next_vec3().map(Statement::Normal),
+        "vt" => parse_texcoord(&mut tokens).map(Statement::Texcoord),
+        "vp" => parse_parameter(&mut tokens).map(Statement::Parameter),
+        "f" => parse_corners(&mut tokens, 3, 3).map(Statement::Face),
+        "l" => parse_corners(&mut tokens, 2, 2).map(Statement::Line),
+        "p" => parse_corners(&mut tokens, 1, 1).map(Statement::Points),
+        "s" => parse_smoothing_group(&mut tokens).map(Statement::Smoothing),
+        "o" => Ok(Statement::Object(tokens.rest().to_string())),
+        "g" => {
+            let mut groups = tokens.rest_names();
+            if groups.is_empty() {
+                groups.push("default".to_string());
+            }
+            Ok(Statement::Group(groups))
+        },
+        "mtllib" => Ok(Statement::MtlLib(tokens.rest_names())),
+        "usemtl" => Ok(Statement::UseMtl(tokens.rest().to_string())),
+        _ if IGNORED_DIRECTIVES.contains(&line_type) => Ok(Statement::Nothing),
+        _ => Err(tokens.error(column, line_type, ObjErrorKind::UnsupportedDirective)),
+    };
+}
+
+// Resolves a 1-based (or negative, relative to the end) obj index into a list with count elements
+fn resolve_index(line_num: usize, column: usize, idx: isize, count: usize) -> Result<usize, ObjError> {
+    let resolved = if idx < 0 { count as isize + idx } else { idx - 1 };
+    if resolved < 0 || resolved >= count as isize {
+        return Err(ObjError {
+            line: line_num,
+            column: column,
+            text: idx.to_string(),
+            kind: ObjErrorKind::IndexOutOfRange,
+        });
+    }
+    return Ok(resolved as usize);
+}
+
+// Everything a face needs from the lines before it
+pub struct ObjState {
+    pub vertices: Vec<Vec3>,
+    pub colors: Vec<Option<Vec3>>,
+    pub normals: Vec<Vec3>,
+    pub texcoords: Vec<Vec3>,
+    pub parameters: Vec<Vec3>,
+    pub polylines: Vec<Polyline>,
+    pub points: Vec<Point>,
+    pub smoothing_group: u32,
+    pub material: Option<usize>,
+    pub object: String,
+    pub groups: Vec<String>,
+    pub materials: Vec<Material>,
+    pub material_libs: Vec<String>,
+    pub sub_meshes: Vec<SubMesh>,
+    current_sub_mesh: Option<usize>,
+}
+
+impl ObjState {
+    pub fn new() -> ObjState {
+        return ObjState {
+            vertices: Vec::new(),
+            colors: Vec::new(),
+            normals: Vec::new(),
+            texcoords: Vec::new(),
+            parameters: Vec::new(),
+            polylines: Vec::new(),
+            points: Vec::new(),
+            smoothing_group: 0,
+            material: None,
+            object: String::new(),
+            groups: Vec::new(),
+            materials: Vec::new(),
+            material_libs: Vec::new(),
+            sub_meshes: Vec::new(),
+            current_sub_mesh: None,
+        };
+    }
+
+    // Looks up the sub-mesh for the current object and groups, adding it if it isn't there yet
+    fn sub_mesh_index(&mut self) -> usize {
+        if let Some(idx) = self.current_sub_mesh {
+            return idx;
+        }
+        let idx = self.find_sub_mesh();
+        self.current_sub_mesh = Some(idx);
+        return idx;
+    }
+
+    fn find_sub_mesh(&mut self) -> usize {
+        match self.sub_meshes.iter().position(|sub_mesh| sub_mesh.object == self.object && sub_mesh.groups == self.groups) {
+            Some(idx) => return idx,
+            None => {
+                self.sub_meshes.push(SubMesh {
+                    name: if self.groups.is_empty() { self.object.clone() } else { self.groups.join(" ") },
+                    object: self.object.clone(),
+                    groups: self.groups.clone(),
+                    triangles: 0..0,
+                    materials: Vec::new(),
+                    smoothing_groups: Vec::new(),
+                });
+                return self.sub_meshes.len() - 1;
+            }
+        }
+    }
+
+    // Looks up a material by name, adding it if it isn't there yet
+    fn material_index(&mut self, name: &str) -> usize {
+        match self.materials.iter().position(|material| material.name == name) {
+            Some(idx) => return idx,
+            None => {
+                self.materials.push(Material::new(name));
+                return self.materials.len() - 1;
+            }
+        }
+    }
+
+    fn resolve_corners(&self, line_num: usize, raw_corners: Vec<RawCorner>) -> Result<Vec<Corner>, ObjError> {
+        let mut corners = Vec::with_capacity(raw_corners.len());
+        for raw in raw_corners {
+            corners.push(Corner {
+                p: resolve_index(line_num, raw.column, raw.p, self.vertices.len())?,
+                t: raw.t.map(|t| resolve_index(line_num, raw.column, t, self.texcoords.len())).transpose()?,
+                n: raw.n.map(|n| resolve_index(line_num, raw.column, n, self.normals.len())).transpose()?,
+            });
+        }
+        return Ok(corners);
+    }
+
+    // Applies a statement, returning the resolved face if it was one. Lines and points get collected here.
+    pub fn apply(&mut self, line_num: usize, statement: Statement) -> Result<Option<Face>, ObjError> {
+        match statement {
+            Statement::Vertex(v, c) => {
+                self.vertices.push(v);
+                self.colors.push(c);
+            },
+            Statement::Normal(n) => self.normals.push(n),
+            Statement::Texcoord(t) => self.texcoords.push(t),
+            Statement::Parameter(u) => self.parameters.push(u),
+            Statement::Face(raw_corners) => {
+                let corners = self.resolve_corners(line_num, raw_corners)?;
+                return Ok(Some(self.face(corners)));
+            },
+            Statement::Line(raw_corners) => {
+                let corners = self.resolve_corners(line_num, raw_corners)?;
+                let sub_mesh = self.sub_mesh_index();
+                self.polylines.push(Polyline {
+                    p: corners.iter().map(|corner| self.vertices[corner.p]).collect(),
+                    t: corners.iter().map(|corner| corner.t.map(|t| self.texcoords[t])).collect(),
+                    c: corners.iter().map(|corner| self.colors[corner.p]).collect(),
+                    material: self.material,
+                    sub_mesh: sub_mesh,
+                });
+            },
+            Statement::Points(raw_corners) => {
+                let corners = self.resolve_corners(line_num, raw_corners)?;
+                let sub_mesh = self.sub_mesh_index();
+                for corner in corners {
+                    self.points.push(Point {
+                        p: self.vertices[corner.p],
+                        c: self.colors[corner.p],
+                        material: self.material,
+                        sub_mesh: sub_mesh,
+                    });
+                }
+            },
+            Statement::Smoothing(group) => self.smoothing_group = group,
+            Statement::Object(name) => {
+                self.object = name;
+                self.groups.clear();
+                self.current_sub_mesh = None;
+            },
+            Statement::Group(groups) => {
+                self.groups = groups;
+                self.current_sub_mesh = None;
+            },
+            // A usemtl without a name goes back to no material, which is what writers put out for that
+            Statement::UseMtl(name) if name.is_empty() => self.material = None,
+            Statement::UseMtl(name) => self.material = Some(self.material_index(&name)),
+            Statement::MtlLib(libraries) => self.material_libs.extend(libraries),
+            Statement::Nothing => {},
+        }
+        return Ok(None);
+    }
+
+    // A face with the current smoothing group, material and sub-mesh
+    pub fn face(&mut self, corners: Vec<Corner>) -> Face {
+        return Face {
+            corners: corners,
+            smoothing_group: self.smoothing_group,
+            material: self.material,
+            sub_mesh: self.sub_mesh_index(),
+        };
+    }
+
+    // The polygon for a face, given normals for the corners that don't have one
+    pub fn polygon(&self, face: &Face, generated_normals: Vec<Vec3>) -> Polygon {
+        return Polygon {
+            p: face.corners.iter().map(|corner| self.vertices[corner.p]).collect(),
+            n: face.corners.iter().enumerate().map(|(corner_idx, corner)| match corner.n {
+                Some(n) => self.normals[n],
+                None => generated_normals[corner_idx],
+            }).collect(),
+            t: face.corners.iter().map(|corner| corner.t.map(|t| self.texcoords[t])).collect(),
+            c: face.corners.iter().map(|corner| self.colors[corner.p]).collect(),
+            triangles: 0..0,
+        };
+    }
+}
+
+// Triangulates a polygon, flipping the winding of each triangle
+pub fn polygon_triangles(polygon: &Polygon, material: Option<usize>) -> Vec<TriData> {
+    return triangulate(&polygon.p).iter().map(|&[a, b, c]| TriData {
+        p: [polygon.p[c], polygon.p[b], polygon.p[a]],
+        n: [polygon.n[c], polygon.n[b], polygon.n[a]],
+        t: polygon.t.as_ref().map(|t| [t[c], t[b], t[a]]),
+        c: polygon.c.as_ref().map(|col| [col[c], col[b], col[a]]),
+        material: material,
+    }).collect();
+}
+
+// Fills in missing normals, then triangulates sub-mesh by sub-mesh
+pub fn build_mesh(mut state: ObjState, faces: Vec<Face>, warnings: Vec<ObjError>, options: &ObjOptions) -> Mesh {
+    let mut generated_normals = generate_normals(&state.vertices, &faces, options.normals);
+    let mut face_order: Vec<usize> = (0..faces.len()).collect();
+    face_order.sort_by_key(|&face_idx| faces[face_idx].sub_mesh);
+
+    let mut mesh = Mesh {
+        triangles: Vec::new(),
+        polygons: Vec::new(),
+        polylines: std::mem::take(&mut state.polylines),
+        points: std::mem::take(&mut state.points),
+        sub_meshes: std::mem::take(&mut state.sub_meshes),
+        materials: std::mem::take(&mut state.materials),
+        material_libs: std::mem::take(&mut state.material_libs),
+        warnings: warnings,
+    };
+    for face_idx in face_order {
+        let face = &faces[face_idx];
+        let mut polygon = state.polygon(face, std::mem::take(&mut generated_normals[face_idx]));
+        let first_triangle = mesh.triangles.len();
+        mesh.triangles.extend(polygon_triangles(&polygon, face.material));
+        polygon.triangles = first_triangle..mesh.triangles.len();
+        mesh.polygons.push(polygon);
+
+        let sub_mesh = &mut mesh.sub_meshes[face.sub_mesh];
+        if sub_mesh.triangles.is_empty() {
+            sub_mesh.triangles.start = first_triangle;
+        }
+        sub_mesh.triangles.end = mesh.triangles.len();
+        if let Some(material) = face.material {
+            if !sub_mesh.materials.contains(&material) {
+                sub_mesh.materials.push(material);
+            }
+        }
+        if !sub_mesh.smoothing_groups.contains(&face.smoothing_group) {
+            sub_mesh.smoothing_groups.push(face.smoothing_group);
+        }
+    }
+    return mesh;
+}
+
+#[cfg(test)]
+mod tests {
+    use vector_math::{*};
+    use crate::{Mesh, ObjErrorKind, parse_obj};
+    use super::{ObjState, Statement, obj_lines, parse_statement};
+
+    fn xyz(v: Vec3) -> [Scalar; 3] {
+        return [v.x(), v.y(), v.z()];
+    }
+
+    fn positions(mesh: &Mesh) -> Vec<[[Scalar; 3]; 3]> {
+        return mesh.triangles.iter().map(|tri| tri.p.map(xyz)).collect();
+    }
+
+    // Applies every line to a fresh state
+    fn apply_all(obj: &str) -> ObjState {
+        let mut state = ObjState::new();
+        for (line_num, line) in obj_lines(obj) {
+            state.apply(line_num, parse_statement(line_num, &line).unwrap()).unwrap();
+        }
+        return state;
+    }
+
+    #[test]
+    fn whitespace_and_line_endings_dont_matter() {
+        let expected = parse_obj("v 0 0 0\nv 1 0 0\nv 0 1 0\nvt 0.5 0.5\nf 1/1 2/1 3/1\n").unwrap();
+        let obj = "v\t0 0 0\r\nv  1\t\t0   0  \r\n  v 0 1 0\t\r\n\r\nvt 0.5\t0.5 \r\nf 1/1\t2/1  3/1   \r\n";
+        let mesh = parse_obj(obj).unwrap();
+        assert_eq!(positions(&mesh), positions(&expected));
+        assert_eq!(mesh.triangles[0].t.unwrap().map(xyz), [[0.5, 0.5, 0.0]; 3]);
+        // The last line without any line ending
+        assert_eq!(parse_obj("v 0 0 0\r\nv 1 0 0\r\nv 0 1 0\r\nf 1 2 3").unwrap().triangles.len(), 1);
+    }
+
+    #[test]
+    fn continued_lines_are_joined() {
+        let obj = "v 0 0 0\nv 1 0 0\nv 0 1 0\nv 1 1 0\nf 1 2 \\\n  4 \\  \n3\nf 1 2 3 \\";
+        let lines: Vec<(usize, String)> = obj_lines(obj).map(|(line_num, line)| (line_num, line.to_string())).collect();
+        assert_eq!(lines[4], (5, "f 1 2    4  3".to_string()));
+        // At the end of the file, there is nothing to join
+        assert_eq!(lines[5], (8, "f 1 2 3  ".to_string()));
+        let mesh = parse_obj(obj).unwrap();
+        assert_eq!(mesh.polygons.iter().map(|polygon| polygon.p.len()).collect::<Vec<_>>(), [4, 3]);
+
+        // Errors after a continued line still get its own number
+        let error = parse_obj("v 0 0 0\nf 1 \\\n1 1\nv x 0 0\n").err().unwrap();
+        assert_eq!((error.line, error.column, error.kind), (4, 3, ObjErrorKind::BadNumber));
+    }
+
+    #[test]
+    fn comments_end_lines() {
+        let obj = "# a triangle\nv 0 0 0 # origin\nv 1 0 0\t# x\nv 0 1 0#not a comment, no space\nusemtl red # the material\nf 1 2 3 #\n";
+        let error = parse_obj(obj).err().unwrap();
+        assert_eq!((error.line, error.kind), (4, ObjErrorKind::BadNumber));
+        let mesh = parse_obj(&obj.replace("0#not", "0 #not")).unwrap();
+        assert_eq!(mesh.triangles.len(), 1);
+        assert_eq!(mesh.materials[0].name, "red");
+    }
+
+    #[test]
+    fn lines_and_points_take_relative_indices() {
+        let obj = "v 0 0 0\nv 1 0 0\nv 2 0 0\nvt 0 0\nvt 1 0\nl -3/-2 -2/-1 3/2\np -1 -3\n";
+        let mesh = parse_obj(obj).unwrap();
+        assert_eq!(mesh.polylines[0].p.iter().map(|&p| xyz(p)).collect::<Vec<_>>(), [[0.0, 0.0, 0.0], [1.0, 0.0, 0.0], [2.0, 0.0, 0.0]]);
+        assert_eq!(mesh.polylines[0].t.as_ref().unwrap().iter().map(|&t| t.x()).collect::<Vec<_>>(), [0.0, 1.0, 1.0]);
+        assert_eq!(mesh.points.iter().map(|point| xyz(point.p)).collect::<Vec<_>>(), [[2.0, 0.0, 0.0], [0.0, 0.0, 0.0]]);
+        let error = parse_obj("v 0 0 0\nl -1 -2\n").err().unwrap();
+        assert_eq!(error.kind, ObjErrorKind::IndexOutOfRange);
+    }
+
+    #[test]
+    fn parameters_and_weights() {
+        let state = apply_all("vp 0.5\nvp 0.25 0.75\nvp 0.1 0.2 0.3\nv 1 2 3 0.5\nv 1 2 3 0.1 0.2 0.3\n");
+        assert_eq!(state.parameters.iter().map(|&p| xyz(p)).collect::<Vec<_>>(), [[0.5, 0.0, 1.0], [0.25, 0.75, 1.0], [0.1, 0.2, 0.3]]);
+        // The weight is dropped, the color kept
+        assert_eq!(state.vertices.iter().map(|&v| xyz(v)).collect::<Vec<_>>(), [[1.0, 2.0, 3.0]; 2]);
+        assert_eq!(state.colors.iter().map(|c| c.map(xyz)).collect::<Vec<_>>(), [None, Some([0.1, 0.2, 0.3])]);
+        assert!(matches!(parse_statement(1, "v 1 2 3 0.1 0.2"), Err(error) if error.kind == ObjErrorKind::BadNumber));
+        assert!(matches!(parse_statement(1, "vp"), Err(error) if error.kind == ObjErrorKind::BadNumber));
+        assert!(matches!(parse_statement(1, "  # nothing here"), Ok(Statement::Nothing)));
+    }
+}
@@ -0,0 +1,555 @@
+use vector_math::{*};
+use std::fs;
+use std::fs::File;
+use std::io;
+use std::io::{BufWriter, Write};
+use crate::{IndexedMesh, Mesh, ObjError, ObjErrorKind, ObjOptions};
+use crate::parse::{Corner, ObjState, build_mesh};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PlyFormat {
+    Ascii,
+    BinaryLittleEndian,
+    BinaryBigEndian,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum PlyType {
+    Int8,
+    UInt8,
+    Int16,
+    UInt16,
+    Int32,
+    UInt32,
+    Float32,
+    Float64,
+}
+
+impl PlyType {
+    fn from_name(name: &str) -> Option<PlyType> {
+        return match name {
+            "char" | "int8" => Some(PlyType::Int8),
+            "uchar" | "uint8" => Some(PlyType::UInt8),
+            "short" | "int16" => Some(PlyType::Int16),
+            "ushort" | "uint16" => Some(PlyType::UInt16),
+            "int" | "int32" => Some(PlyType::Int32),
+            "uint" | "uint32" => Some(PlyType::UInt32),
+            "float" | "float32" => Some(PlyType::Float32),
+            "double" | "float64" => Some(PlyType::Float64),
+            _ => None,
+        };
+    }
+
+    fn size(&self) -> usize {
+        return match self {
+            PlyType::Int8 | PlyType::UInt8 => 1,
+            PlyType::Int16 | PlyType::UInt16 => 2,
+            PlyType::Int32 | PlyType::UInt32 | PlyType::Float32 => 4,
+            PlyType::Float64 => 8,
+        };
+    }
+
+    // What an integer color channel of this type gets divided by to end up in 0..1
+    fn color_scale(&self) -> f64 {
+        return match self {
+            PlyType::Int8 | PlyType::UInt8 => 255.0,
+            PlyType::Int16 | PlyType::UInt16 => 65535.0,
+            _ => 1.0,
+        };
+    }
+}
+
+// A property, with the type of its length if it is a list
+struct PlyProperty {
+    name: String,
+    value_type: PlyType,
+    count_type: Option<PlyType>,
+}
+
+struct PlyElement {
+    name: String,
+    count: usize,
+    properties: Vec<PlyProperty>,
+}
+
+fn header_error(line_num: usize, text: &str) -> ObjError {
+    return ObjError {
+        line: line_num,
+        column: 1,
+        text: text.to_string(),
+        kind: ObjErrorKind::BadHeader,
+    };
+}
+
+// Parses the header, returning format, elements and the offset at which the body starts
+fn parse_header(contents: &[u8]) -> Result<(PlyFormat, Vec<PlyElement>, usize, usize), ObjError> {
+    let mut format = None;
+    let mut elements: Vec<PlyElement> = Vec::new();
+    let mut offset = 0;
+    let mut line_num = 0;
+    loop {
+        let line_end = contents[offset..].iter().position(|&byte| byte == b'\n').map(|pos| offset + pos)
+            .ok_or_else(|| ObjError { line: line_num + 1, column: 1, text: "end_header".to_string(), kind: ObjErrorKind::UnexpectedEnd })?;
+        let line = std::str::from_utf8(&contents[offset..line_end]).map_err(|_| header_error(line_num + 1, "not text"))?.trim_end_matches('\r');
+        offset = line_end + 1;
+        line_num += 1;
+
+        let tokens: Vec<&str> = line.split_whitespace().collect();
+        if line_num == 1 {
+            if tokens != ["ply"] {
+                return Err(header_error(line_num, line));
+            }
+            continue;
+        }
+        match tokens.first().copied() {
+            Some("format") => {
+                format = match tokens.get(1).copied() {
+                    Some("ascii") => Some(PlyFormat::Ascii),
+                    Some("binary_little_endian") => Some(PlyFormat::BinaryLittleEndian),
+                    Some("binary_big_endian") => Some(PlyFormat::BinaryBigEndian),
+                    _ => return Err(header_error(line_num, line)),
+                };
+            },
+            Some("element") => {
+                let count = tokens.get(2).and_then(|count| count.parse::<usize>().ok());
+                match (tokens.get(1), count) {
+                    (Some(name), Some(count)) => elements.push(PlyElement {
+                        name: name.to_string(),
+                        count: count,
+                        properties: Vec::new(),
+                    }),
+                    _ => return Err(header_error(line_num, line)),
+                }
+            },
+            Some("property") => {
+                let property = match tokens[1..] {
+                    ["list", count_type, value_type, name] => PlyType::from_name(count_type).zip(PlyType::from_name(value_type)).map(|(count_type, value_type)| PlyProperty {
+                        name: name.to_string(),
+                        value_type: value_type,
+                        count_type: Some(count_type),
+                    }),
+                    [value_type, name] => PlyType::from_name(value_type).map(|value_type| PlyProperty {
+                        name: name.to_string(),
+                        value_type: value_type,
+                        count_type: None,
+                    }),
+                    _ => None,
+                };
+                match (property, elements.last_mut()) {
+                    (Some(property), Some(element)) => element.properties.push(property),
+                    _ => return Err(header_error(line_num, line)),
+                }
+            },
+            Some("end_header") => break,
+            Some("comment") | Some("obj_info") | None => {},
+            Some(_) => return Err(header_error(line_num, line)),
+        }
+    }
+    let format = format.ok_or_else(|| header_error(line_num, "format"))?;
+    return Ok((format, elements, offset, line_num));
+}
+
+// Reads values from the body, whitespace separated numbers for ascii and packed values otherwise.
+// Everything is read as f64, which holds every value of every ply type exactly.
+struct PlyReader<'a> {
+    format: PlyFormat,
+    contents: &'a [u8],
+    offset: usize,
+    line_num: usize,
+    line_start: usize,
+}
+
+impl<'a> PlyReader<'a> {
+    fn error(&self, text: String, kind: ObjErrorKind) -> ObjError {
+        if self.format == PlyFormat::Ascii {
+            return ObjError {
+                line: self.line_num,
+                column: self.offset - self.line_start + 1,
+                text: text,
+                kind: kind,
+            };
+        }
+        return ObjError {
+            line: 0,
+            column: 0,
+            text: format!("byte {}: {}", self.offset, text),
+            kind: kind,
+        };
+    }
+
+    fn read(&mut self, value_type: PlyType) -> Result<f64, ObjError> {
+        if self.format == PlyFormat::Ascii {
+            return self.read_ascii();
+        }
+
+        let size = value_type.size();
+        if self.offset + size > self.contents.len() {
+            return Err(self.error("value".to_string(), ObjErrorKind::UnexpectedEnd));
+        }
+        let mut bytes = [0u8; 8];
+        bytes[..size].copy_from_slice(&self.contents[self.offset..self.offset + size]);
+        if self.format == PlyFormat::BinaryBigEndian {
+            bytes[..size].reverse();
+        }
+        self.offset += size;
+        return Ok(match value_type {
+            PlyType::Int8 => bytes[0] as i8 as f64,
+            PlyType::UInt8 => bytes[0] as f64,
+            PlyType::Int16 => i16::from_le_bytes([bytes[0], bytes[1]]) as f64,
+            PlyType::UInt16 => u16::from_le_bytes([bytes[0], bytes[1]]) as f64,
+            PlyType::Int32 => i32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]) as f64,
+            PlyType::UInt32 => u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]) as f64,
+            PlyType::Float32 => f32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]) as f64,
+            PlyType::Float64 => f64::from_le_bytes(bytes),
+        });
+    }
+
+    fn read_ascii(&mut self) -> Result<f64, ObjError> {
+        while self.offset < self.contents.len() && self.contents[self.offset].is_ascii_whitespace() {
+            if self.contents[self.offset] == b'\n' {
+                self.line_num += 1;
+                self.line_start = self.offset + 1;
+            }
+            self.offset += 1;
+        }
+        let start = self.offset;
+        while self.offset < self.contents.len() && !self.contents[self.offset].is_ascii_whitespace() {
+            self.offset += 1;
+        }
+        if start == self.offset {
+            return Err(self.error("value".to_string(), ObjErrorKind::UnexpectedEnd));
+        }
+        let token = String::from_utf8_lossy(&self.contents[start..self.offset]).to_string();
+        return token.parse::<f64>().map_err(|_| ObjError {
+            line: self.line_num,
+            column: start - self.line_start + 1,
+            text: token.clone(),
+            kind: ObjErrorKind::BadNumber,
+        });
+    }
+
+    // Reads one element, as one list of values per property (plain properties have a single value)
+    fn read_element(&mut self, element: &PlyElement) -> Result<Vec<Vec<f64>>, ObjError> {
+        let mut values = Vec::with_capacity(element.properties.len());
+        for property in &element.properties {
+            match property.count_type {
+                Some(count_type) => {
+                    let count = self.read(count_type)?;
+                    if count < 0.0 {
+                        return Err(self.error(count.to_string(), ObjErrorKind::BadNumber));
+                    }
+                    // Every value takes at least a byte, so a count from a broken file can't reserve more than that
+                    let mut list = Vec::with_capacity((count as usize).min(self.contents.len() - self.offset));
+                    for _ in 0..count as usize {
+                        list.push(self.read(property.value_type)?);
+                    }
+                    values.push(list);
+                },
+                None => values.push(vec![self.read(property.value_type)?]),
+            }
+        }
+        return Ok(values);
+    }
+}
+
+// Index of the first property with one of the given names
+fn find_property(element: &PlyElement, names: &[&str]) -> Option<usize> {
+    return element.properties.iter().position(|property| names.contains(&property.name.as_str()));
+}
+
+fn find_vec(element: &PlyElement, names: [&[&str]; 3]) -> Option<[usize; 3]> {
+    return Some([find_property(element, names[0])?, find_property(element, names[1])?, find_property(element, names[2])?]);
+}
+
+// Reads a ply file into the same mesh as parse_obj would give. Vertices can have any properties, of which
+// positions, normals, colors and texture coordinates are used. Faces are polygons, and can have per-corner
+// texture coordinates ("texcoord" lists, as meshlab writes them), which take the place of the vertices' ones.
+// Faces without normals get smooth ones.
+pub fn parse_ply_with(contents: &[u8], options: &ObjOptions) -> Result<Mesh, ObjError> {
+    let (format, elements, body_start, header_lines) = parse_header(contents)?;
+    let mut reader = PlyReader {
+        format: format,
+        contents: contents,
+        offset: body_start,
+        line_num: header_lines + 1,
+        line_start: body_start,
+    };
+
+    let vertex_count = elements.iter().find(|element| element.name == "vertex").map(|element| element.count).unwrap_or(0);
+    // Whether the vertices had normals and texture coordinates, which faces can then index. Per-corner
+    // texture coordinates of faces go after those of the vertices.
+    let (mut vertex_normals, mut vertex_texcoords) = (false, false);
+    let mut state = ObjState::new();
+    state.smoothing_group = 1;
+    let mut faces = Vec::new();
+    let mut warnings = Vec::new();
+    for element in &elements {
+        match element.name.as_str() {
+            "vertex" => {
+                let position = find_vec(element, [&["x"], &["y"], &["z"]])
+                    .ok_or_else(|| header_error(0, "vertex element without x, y, z"))?;
+                let normal = find_vec(element, [&["nx"], &["ny"], &["nz"]]);
+                let color = find_vec(element, [&["red", "r"], &["green", "g"], &["blue", "b"]]);
+                let color_scale = color.map(|color| color.map(|idx| element.properties[idx].value_type.color_scale()));
+                let u = find_property(element, &["u", "s", "texture_u", "texture_s"]);
+                let v = find_property(element, &["v", "t", "texture_v", "texture_t"]);
+                let to_vec3 = |values: &Vec<Vec<f64>>, idx: [usize; 3], scale: [f64; 3]| -> Vec3 {
+                    return Vec3::new(
+                        (values[idx[0]][0] / scale[0]) as Scalar,
+                        (values[idx[1]][0] / scale[1]) as Scalar,
+                        (values[idx[2]][0] / scale[2]) as Scalar,
+                    );
+                };
+                vertex_normals = normal.is_some() && element.count > 0;
+                vertex_texcoords = u.is_some() && v.is_some() && element.count > 0;
+                for _ in 0..element.count {
+                    let values = reader.read_element(element)?;
+                    state.vertices.push(to_vec3(&values, position, [1.0; 3]));
+                    state.colors.push(color.map(|color| to_vec3(&values, color, color_scale.unwrap())));
+                    if let Some(normal) = normal {
+                        state.normals.push(to_vec3(&values, normal, [1.0; 3]));
+                    }
+                    if let (Some(u), Some(v)) = (u, v) {
+                        state.texcoords.push(Vec3::new(values[u][0] as Scalar, values[v][0] as Scalar, 0.0));
+                    }
+                }
+            },
+            "face" => {
+                let indices = find_property(element, &["vertex_indices", "vertex_index"])
+                    .ok_or_else(|| header_error(0, "face element without vertex_indices"))?;
+                let texcoords = find_property(element, &["texcoord"]);
+                for face_idx in 0..element.count {
+                    let values = reader.read_element(element)?;
+                    let corner_indices = &values[indices];
+                    let bad_index = corner_indices.iter().find(|&&idx| idx < 0.0 || idx as usize >= vertex_count);
+                    let error = match bad_index {
+                        Some(idx) => Some((ObjErrorKind::IndexOutOfRange, idx.to_string())),
+                        None if corner_indices.len() < 3 => Some((ObjErrorKind::MissingIndex, format!("face {}", face_idx))),
+                        None => None,
+                    };
+                    if let Some((kind, text)) = error {
+                        let error = reader.error(text, kind);
+                        if !options.lenient {
+                            return Err(error);
+                        }
+                        warnings.push(error);
+                        continue;
+                    }
+
+                    let face_texcoords = texcoords.map(|idx| &values[idx]).filter(|t| t.len() == corner_indices.len() * 2);
+                    let mut corners = Vec::with_capacity(corner_indices.len());
+                    for (corner_idx, &idx) in corner_indices.iter().enumerate() {
+                        let idx = idx as usize;
+                        let t = match face_texcoords {
+                            Some(t) => {
+                                state.texcoords.push(Vec3::new(t[corner_idx * 2] as Scalar, t[corner_idx * 2 + 1] as Scalar, 0.0));
+                                Some(state.texcoords.len() - 1)
+                            },
+                            None if vertex_texcoords => Some(idx),
+                            None => None,
+                        };
+                        corners.push(Corner {
+                            p: idx,
+                            t: t,
+                            n: if vertex_normals { Some(idx) } else { None },
+                        });
+                    }
+                    faces.push(state.face(corners));
+                }
+            },
+            _ => {
+                for _ in 0..element.count {
+                    reader.read_element(element)?;
+                }
+            },
+        }
+    }
+    return Ok(build_mesh(state, faces, warnings, options));
+}
+
+pub fn parse_ply(contents: &[u8]) -> Result<Mesh, ObjError> {
+    return parse_ply_with(contents, &ObjOptions::default());
+}
+
+pub fn read_ply_with(path: &str, options: &ObjOptions) -> Result<Mesh, ObjError> {
+    let contents = fs::read(path).map_err(|error| ObjError {
+        line: 0,
+        column: 0,
+        text: format!("{}: {}", path, error),
+        kind: ObjErrorKind::FileRead,
+    })?;
+    return parse_ply_with(&contents, options);
+}
+
+pub fn read_ply(path: &str) -> Result<Mesh, ObjError> {
+    return read_ply_with(path, &ObjOptions::default());
+}
+
+// Writes an indexed mesh as ply, with normals, texture coordinates (u, v) and colors (as uchar), and
+// triangles flipped back like write_obj_to does
+pub fn write_ply_to<W: Write>(out: &mut W, mesh: &IndexedMesh, format: PlyFormat) -> io::Result<()> {
+    let format_name = match format {
+        PlyFormat::Ascii => "ascii",
+        PlyFormat::BinaryLittleEndian => "binary_little_endian",
+        PlyFormat::BinaryBigEndian => "binary_big_endian",
+    };
+    writeln!(out, "ply")?;
+    writeln!(out, "format {} 1.0", format_name)?;
+    writeln!(out, "element vertex {}", mesh.vertex_count())?;
+    for name in ["x", "y", "z", "nx", "ny", "nz"] {
+        writeln!(out, "property float {}", name)?;
+    }
+    if mesh.texcoords.is_some() {
+        writeln!(out, "property float u")?;
+        writeln!(out, "property float v")?;
+    }
+    if mesh.colors.is_some() {
+        for name in ["red", "green", "blue"] {
+            writeln!(out, "property uchar {}", name)?;
+        }
+    }
+    writeln!(out, "element face {}", mesh.triangle_count())?;
+    writeln!(out, "property list uchar uint vertex_indices")?;
+    writeln!(out, "end_header")?;
+
+    let write_float = |out: &mut W, value: Scalar| -> io::Result<()> {
+        return match format {
+            PlyFormat::Ascii => write!(out, "{} ", value),
+            PlyFormat::BinaryLittleEndian => out.write_all(&(value as f32).to_le_bytes()),
+            PlyFormat::BinaryBigEndian => out.write_all(&(value as f32).to_be_bytes()),
+        };
+    };
+    let write_uint = |out: &mut W, value: u32| -> io::Result<()> {
+        return match format {
+            PlyFormat::Ascii => write!(out, "{} ", value),
+            PlyFormat::BinaryLittleEndian => out.write_all(&value.to_le_bytes()),
+            PlyFormat::BinaryBigEndian => out.write_all(&value.to_be_bytes()),
+        };
+    };
+    let write_uchar = |out: &mut W, value: u8| -> io::Result<()> {
+        return match format {
+            PlyFormat::Ascii => write!(out, "{} ", value),
+            _ => out.write_all(&[value]),
+        };
+    };
+    let end_line = |out: &mut W| -> io::Result<()> {
+        if format == PlyFormat::Ascii {
+            writeln!(out)?;
+        }
+        return Ok(());
+    };
+
+    for idx in 0..mesh.vertex_count() {
+        let (p, n) = (mesh.positions[idx], mesh.normals[idx]);
+        for value in [p.x(), p.y(), p.z(), n.x(), n.y(), n.z()] {
+            write_float(out, value)?;
+        }
+        if let Some(texcoords) = &mesh.texcoords {
+            write_float(out, texcoords[idx].x())?;
+            write_float(out, texcoords[idx].y())?;
+        }
+        if let Some(colors) = &mesh.colors {
+            let c = colors[idx];
+            for value in [c.r(), c.g(), c.b()] {
+                write_uchar(out, (value.clamp(0.0, 1.0) * 255.0).round() as u8)?;
+            }
+        }
+        end_line(out)?;
+    }
+    for &[a, b, c] in &mesh.indices {
+        write_uchar(out, 3)?;
+        for idx in [c, b, a] {
+            write_uint(out, idx)?;
+        }
+        end_line(out)?;
+    }
+    return Ok(());
+}
+
+pub fn write_ply_indexed(path: &str, mesh: &IndexedMesh, format: PlyFormat) -> io::Result<()> {
+    let mut out = BufWriter::new(File::create(path)?);
+    write_ply_to(&mut out, mesh, format)?;
+    return out.flush();
+}
+
+// Writes a mesh with identical vertices welded, since ply has no separate indices per attribute
+pub fn write_ply(path: &str, mesh: &Mesh, format: PlyFormat) -> io::Result<()> {
+    return write_ply_indexed(path, &mesh.to_indexed(true), format);
+}
+
+#[cfg(test)]
+mod tests {
+    use vector_math::{*};
+    use crate::{ObjErrorKind, parse_obj};
+    use super::{PlyFormat, parse_ply, write_ply_to};
+
+    fn xyz(v: Vec3) -> [Scalar; 3] {
+        return [v.x(), v.y(), v.z()];
+    }
+
+    #[test]
+    fn round_trips_in_every_format() {
+        let obj = "v 0 0 0 1 0 0\nv 1 0 0 0 1 0\nv 1 1 0 0 0 1\nv 0 1 0.5 0.2 0.4 0.6\n\
+            vt 0 0\nvt 1 0\nvt 1 1\nvt 0.25 0.75\nvn 0 0 1\nvn 0 0.6 0.8\n\
+            f 1/1/1 2/2/1 3/3/1\nf 1/1/1 3/3/1 4/4/2\n";
+        let mesh = parse_obj(obj).unwrap();
+        let indexed = mesh.to_indexed(true);
+        for format in [PlyFormat::Ascii, PlyFormat::BinaryLittleEndian, PlyFormat::BinaryBigEndian] {
+            let mut out = Vec::new();
+            write_ply_to(&mut out, &indexed, format).unwrap();
+            let back = parse_ply(&out).unwrap();
+            assert_eq!(back.triangles.len(), mesh.triangles.len());
+            for (a, b) in mesh.triangles.iter().zip(back.triangles.iter()) {
+                assert_eq!(a.p.map(xyz), b.p.map(xyz));
+                assert_eq!(a.n.map(xyz), b.n.map(xyz));
+                assert_eq!(a.t.unwrap().map(xyz), b.t.unwrap().map(xyz));
+                // Colors are stored as bytes
+                for (ca, cb) in a.c.unwrap().iter().zip(b.c.unwrap().iter()) {
+                    assert!((*ca - *cb).length() < 0.5 / 255.0);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn faces_can_have_their_own_texture_coordinates() {
+        // The second face has no texture coordinates of its own, and the vertices have none either
+        let ply = "ply\nformat ascii 1.0\nelement vertex 3\nproperty float x\nproperty float y\nproperty float z\n\
+            element face 2\nproperty list uchar int vertex_indices\nproperty list uchar float texcoord\nend_header\n\
+            0 0 0\n1 0 0\n0 1 0\n\
+            3 0 1 2 6 0 0 0.5 0 0 0.5\n3 0 2 1 0\n";
+        let mesh = parse_ply(ply.as_bytes()).unwrap();
+        let mut texcoords: Vec<[Scalar; 3]> = mesh.triangles[0].t.unwrap().map(xyz).to_vec();
+        texcoords.sort_by(|a, b| a.partial_cmp(b).unwrap());
+        assert_eq!(texcoords, [[0.0, 0.0, 0.0], [0.0, 0.5, 0.0], [0.5, 0.0, 0.0]]);
+        assert!(mesh.triangles[1].t.is_none());
+
+        // With texture coordinates on the vertices as well, faces without their own use those
+        let ply = "ply\nformat ascii 1.0\nelement vertex 3\nproperty float x\nproperty float y\nproperty float z\n\
+            property float u\nproperty float v\n\
+            element face 2\nproperty list uchar int vertex_indices\nproperty list uchar float texcoord\nend_header\n\
+            0 0 0 0 0\n1 0 0 1 0\n0 1 0 0 1\n\
+            3 0 1 2 6 0 0 0.5 0 0 0.5\n3 0 2 1 0\n";
+        let mesh = parse_ply(ply.as_bytes()).unwrap();
+        let corner_texcoords = |tri: usize| {
+            let tri = &mesh.triangles[tri];
+            let mut corners: Vec<([Scalar; 3], [Scalar; 3])> = tri.p.iter().zip(tri.t.unwrap().iter()).map(|(&p, &t)| (xyz(p), xyz(t))).collect();
+            corners.sort_by(|a, b| a.partial_cmp(b).unwrap());
+            return corners;
+        };
+        assert_eq!(corner_texcoords(0), [([0.0, 0.0, 0.0], [0.0, 0.0, 0.0]), ([0.0, 1.0, 0.0], [0.0, 0.5, 0.0]), ([1.0, 0.0, 0.0], [0.5, 0.0, 0.0])]);
+        assert_eq!(corner_texcoords(1), [([0.0, 0.0, 0.0], [0.0, 0.0, 0.0]), ([0.0, 1.0, 0.0], [0.0, 1.0, 0.0]), ([1.0, 0.0, 0.0], [1.0, 0.0, 0.0])]);
+    }
+
+    #[test]
+    fn huge_list_counts_are_rejected() {
+        let mut ply = b"ply\nformat binary_little_endian 1.0\nelement vertex 3\nproperty float x\nproperty float y\nproperty float z\nelement face 1\nproperty list uint int vertex_indices\nend_header\n".to_vec();
+        for value in [0.0f32, 0.0, 0.0, 1.0, 0.0, 0.0, 0.0, 1.0, 0.0] {
+            ply.extend_from_slice(&value.to_le_bytes());
+        }
+        ply.extend_from_slice(&u32::MAX.to_le_bytes());
+        ply.extend_from_slice(&[0; 12]);
+        let error = parse_ply(&ply).err().unwrap();
+        assert!(error.kind == ObjErrorKind::UnexpectedEnd);
+    }
+}
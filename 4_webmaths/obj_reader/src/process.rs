@@ -0,0 +1,679 @@
+use vector_math::{*};
+use std::collections::HashMap;
+use crate::{IndexedMesh, Mesh, NormalMode, TriData};
+use crate::normals::corner_angle;
+use crate::triangulate::polygon_normal;
+
+// Axis aligned bounding box
+#[derive(Clone, Copy)]
+pub struct Aabb {
+    pub min: Vec3,
+    pub max: Vec3,
+}
+
+impl Aabb {
+    pub fn from_points<'a>(points: impl IntoIterator<Item = &'a Vec3>) -> Option<Aabb> {
+        let mut points = points.into_iter();
+        let first = *points.next()?;
+        let mut aabb = Aabb {
+            min: first,
+            max: first,
+        };
+        for p in points {
+            aabb.min = Vec3::new(aabb.min.x().min(p.x()), aabb.min.y().min(p.y()), aabb.min.z().min(p.z()));
+            aabb.max = Vec3::new(aabb.max.x().max(p.x()), aabb.max.y().max(p.y()), aabb.max.z().max(p.z()));
+        }
+        return Some(aabb);
+    }
+
+    pub fn from_triangles(triangles: &[TriData]) -> Option<Aabb> {
+        return Aabb::from_points(triangles.iter().flat_map(|tri| tri.p.iter()));
+    }
+
+    pub fn center(&self) -> Vec3 {
+        return (self.min + self.max) * 0.5;
+    }
+
+    pub fn size(&self) -> Vec3 {
+        return self.max - self.min;
+    }
+
+    // Scale and offset (p * scale + offset) that center the box and make its largest side 1
+    pub fn unit_box_transform(&self) -> (Scalar, Vec3) {
+        let size = self.size();
+        let extent = size.x().max(size.y()).max(size.z());
+        let scale = if extent > 0.0 { 1.0 / extent } else { 1.0 };
+        return (scale, self.center() * -scale);
+    }
+}
+
+#[derive(Clone, Copy)]
+pub struct BoundingSphere {
+    pub center: Vec3,
+    pub radius: Scalar,
+}
+
+impl BoundingSphere {
+    // Ritters algorithm: start with the sphere between two far apart points, then grow it to include
+    // every point outside. Not minimal, but within a few percent of it.
+    pub fn from_points(points: &[Vec3]) -> Option<BoundingSphere> {
+        let first = *points.first()?;
+        let farthest_from = |from: Vec3| -> Vec3 {
+            return *points.iter().max_by(|a, b| (**a - from).length().total_cmp(&(**b - from).length())).unwrap();
+        };
+        let a = farthest_from(first);
+        let b = farthest_from(a);
+        let mut sphere = BoundingSphere {
+            center: (a + b) * 0.5,
+            radius: (b - a).length() * 0.5,
+        };
+        for &p in points {
+            let dist = (p - sphere.center).length();
+            if dist > sphere.radius {
+                let new_radius = (sphere.radius + dist) * 0.5;
+                sphere.center = sphere.center + (p - sphere.center) * ((new_radius - sphere.radius) / dist);
+                sphere.radius = new_radius;
+            }
+        }
+        return Some(sphere);
+    }
+
+    pub fn from_triangles(triangles: &[TriData]) -> Option<BoundingSphere> {
+        let points: Vec<Vec3> = triangles.iter().flat_map(|tri| tri.p.iter().copied()).collect();
+        return BoundingSphere::from_points(&points);
+    }
+}
+
+// Normal of a triangle as stored in TriData (winding flipped relative to the file, see polygon_triangles), with
+// length twice its area
+pub(crate) fn triangle_normal(p: [Vec3; 3]) -> Vec3 {
+    return polygon_normal(&[p[2], p[1], p[0]]);
+}
+
+// Key for grouping vertices by position, ignoring everything else
+pub(crate) fn position_key(p: Vec3) -> [u32; 3] {
+    return [p.x().to_bits(), p.y().to_bits(), p.z().to_bits()];
+}
+
+impl Mesh {
+    pub fn aabb(&self) -> Option<Aabb> {
+        return Aabb::from_triangles(&self.triangles);
+    }
+
+    pub fn bounding_sphere(&self) -> Option<BoundingSphere> {
+        return BoundingSphere::from_triangles(&self.triangles);
+    }
+
+    // Scales, then offsets all positions. Normals stay as they are, so scale should be positive.
+    pub fn transform(&mut self, scale: Scalar, offset: Vec3) {
+        for tri in self.triangles.iter_mut() {
+            tri.p = tri.p.map(|p| p * scale + offset);
+        }
+        for polygon in self.polygons.iter_mut() {
+            for p in polygon.p.iter_mut() {
+                *p = *p * scale + offset;
+            }
+        }
+        for polyline in self.polylines.iter_mut() {
+            for p in polyline.p.iter_mut() {
+                *p = *p * scale + offset;
+            }
+        }
+        for point in self.points.iter_mut() {
+            point.p = point.p * scale + offset;
+        }
+    }
+
+    // Moves the center of the bounding box to the origin
+    pub fn recenter(&mut self) {
+        if let Some(aabb) = self.aabb() {
+            self.transform(1.0, aabb.center() * -1.0);
+        }
+    }
+
+    // Recenters and scales so that the bounding box fits a unit cube
+    pub fn normalize(&mut self) {
+        if let Some(aabb) = self.aabb() {
+            let (scale, offset) = aabb.unit_box_transform();
+            self.transform(scale, offset);
+        }
+    }
+
+    // Reverses the winding of all triangles and polygons, and flips the normals to match
+    pub fn flip_winding(&mut self) {
+        for tri in self.triangles.iter_mut() {
+            tri.p.swap(1, 2);
+            tri.n.swap(1, 2);
+            tri.n = tri.n.map(|n| n * -1.0);
+            if let Some(t) = tri.t.as_mut() {
+                t.swap(1, 2);
+            }
+            if let Some(c) = tri.c.as_mut() {
+                c.swap(1, 2);
+            }
+        }
+        for polygon in self.polygons.iter_mut() {
+            polygon.p.reverse();
+            polygon.n.reverse();
+            for n in polygon.n.iter_mut() {
+                *n *= -1.0;
+            }
+            if let Some(t) = polygon.t.as_mut() {
+                t.reverse();
+            }
+            if let Some(c) = polygon.c.as_mut() {
+                c.reverse();
+            }
+        }
+    }
+}
+
+impl IndexedMesh {
+    pub fn aabb(&self) -> Option<Aabb> {
+        return Aabb::from_points(&self.positions);
+    }
+
+    pub fn bounding_sphere(&self) -> Option<BoundingSphere> {
+        return BoundingSphere::from_points(&self.positions);
+    }
+
+    pub fn transform(&mut self, scale: Scalar, offset: Vec3) {
+        for p in self.positions.iter_mut() {
+            *p = *p * scale + offset;
+        }
+    }
+
+    pub fn recenter(&mut self) {
+        if let Some(aabb) = self.aabb() {
+            self.transform(1.0, aabb.center() * -1.0);
+        }
+    }
+
+    pub fn normalize(&mut self) {
+        if let Some(aabb) = self.aabb() {
+            let (scale, offset) = aabb.unit_box_transform();
+            self.transform(scale, offset);
+        }
+    }
+
+    pub fn flip_winding(&mut self) {
+        for tri_indices in self.indices.iter_mut() {
+            tri_indices.swap(1, 2);
+        }
+        for n in self.normals.iter_mut() {
+            *n *= -1.0;
+        }
+    }
+
+    fn triangle_positions(&self, tri_indices: [u32; 3]) -> [Vec3; 3] {
+        return tri_indices.map(|idx| self.positions[idx as usize]);
+    }
+
+    // Drops vertices no triangle uses, keeping the order of the rest
+    pub(crate) fn remove_unused_vertices(&mut self) {
+        let mut used = vec![false; self.vertex_count()];
+        for tri_indices in &self.indices {
+            for &idx in tri_indices {
+                used[idx as usize] = true;
+            }
+        }
+        let mut remap = vec![0; self.vertex_count()];
+        let mut kept = 0;
+        for idx in 0..self.vertex_count() {
+            if used[idx] {
+                remap[idx] = kept as u32;
+                self.positions[kept] = self.positions[idx];
+                self.normals[kept] = self.normals[idx];
+                if let Some(texcoords) = self.texcoords.as_mut() {
+                    texcoords[kept] = texcoords[idx];
+                }
+                if let Some(colors) = self.colors.as_mut() {
+                    colors[kept] = colors[idx];
+                }
+                kept += 1;
+            }
+        }
+        self.positions.truncate(kept);
+        self.normals.truncate(kept);
+        if let Some(texcoords) = self.texcoords.as_mut() {
+            texcoords.truncate(kept);
+        }
+        if let Some(colors) = self.colors.as_mut() {
+            colors.truncate(kept);
+        }
+        for tri_indices in self.indices.iter_mut() {
+            *tri_indices = tri_indices.map(|idx| remap[idx as usize]);
+        }
+    }
+
+    fn vertices_close(&self, a: usize, b: usize, epsilon: Scalar) -> bool {
+        let close = |x: Vec3, y: Vec3| (x - y).length() <= epsilon;
+        return close(self.positions[a], self.positions[b])
+            && close(self.normals[a], self.normals[b])
+            && self.texcoords.as_ref().map(|t| close(t[a], t[b])).unwrap_or(true)
+            && self.colors.as_ref().map(|c| close(c[a], c[b])).unwrap_or(true);
+    }
+
+    // Merges vertices whose positions, normals, texture coordinates and colors are all within epsilon of
+    // each other, keeping the first. Returns how many vertices were merged away. Triangles that collapse
+    // are kept, remove_degenerate gets rid of them.
+    pub fn weld(&mut self, epsilon: Scalar) -> usize {
+        let cell_size = epsilon.max(Scalar::MIN_POSITIVE);
+        let cell = |p: Vec3| [(p.x() / cell_size).floor() as i64, (p.y() / cell_size).floor() as i64, (p.z() / cell_size).floor() as i64];
+
+        let mut grid: HashMap<[i64; 3], Vec<usize>> = HashMap::new();
+        let mut remap: Vec<u32> = Vec::with_capacity(self.vertex_count());
+        let mut merged = 0;
+        for idx in 0..self.vertex_count() {
+            let [x, y, z] = cell(self.positions[idx]);
+            let mut found = None;
+            'search: for dx in -1..=1 {
+                for dy in -1..=1 {
+                    for dz in -1..=1 {
+                        for &other in grid.get(&[x + dx, y + dy, z + dz]).map(|v| v.as_slice()).unwrap_or(&[]) {
+                            if self.vertices_close(idx, other, epsilon) {
+                                found = Some(other);
+                                break 'search;
+                            }
+                        }
+                    }
+                }
+            }
+            match found {
+                Some(other) => {
+                    remap.push(other as u32);
+                    merged += 1;
+                },
+                None => {
+                    remap.push(idx as u32);
+                    grid.entry([x, y, z]).or_default().push(idx);
+                },
+            }
+        }
+        for tri_indices in self.indices.iter_mut() {
+            *tri_indices = tri_indices.map(|idx| remap[idx as usize]);
+        }
+        self.remove_unused_vertices();
+        return merged;
+    }
+
+    // Removes triangles that reuse a vertex or have an area of at most area_epsilon, returns how many
+    pub fn remove_degenerate(&mut self, area_epsilon: Scalar) -> usize {
+        let before = self.triangle_count();
+        let keep: Vec<bool> = self.indices.iter().map(|&[a, b, c]| {
+            return a != b && b != c && a != c && triangle_normal(self.triangle_positions([a, b, c])).length() * 0.5 > area_epsilon;
+        }).collect();
+        let mut keep_iter = keep.iter();
+        self.indices.retain(|_| *keep_iter.next().unwrap());
+        let mut keep_iter = keep.iter();
+        self.triangle_materials.retain(|_| *keep_iter.next().unwrap());
+        self.remove_unused_vertices();
+        return before - self.triangle_count();
+    }
+
+    // Replaces all normals. Flat gives every triangle its own vertices, the smooth modes average over all
+    // triangles sharing a position (there are no smoothing groups here, so hard edges get smoothed too).
+    pub fn recompute_normals(&mut self, mode: NormalMode) {
+        if mode == NormalMode::Flat {
+            let mut triangles = self.to_triangles();
+            for tri in triangles.iter_mut() {
+                let normal = triangle_normal(tri.p);
+                let normal = if normal.length() > 0.0 { normal.normalized() } else { normal };
+                tri.n = [normal; 3];
+            }
+            *self = IndexedMesh::from_triangles(&triangles, false);
+            return;
+        }
+
+        let mut accumulated: HashMap<[u32; 3], Vec3> = HashMap::new();
+        for &tri_indices in &self.indices {
+            let p = self.triangle_positions(tri_indices);
+            let face_normal = triangle_normal(p);
+            for corner in 0..3 {
+                let weighted = match mode {
+                    NormalMode::SmoothAngle if face_normal.length() > 0.0 => {
+                        face_normal.normalized() * corner_angle(p[(corner + 2) % 3], p[corner], p[(corner + 1) % 3])
+                    },
+                    _ => face_normal,
+                };
+                let sum = accumulated.entry(position_key(p[corner])).or_insert(Vec3::new(0.0, 0.0, 0.0));
+                *sum += weighted;
+            }
+        }
+        for idx in 0..self.vertex_count() {
+            let normal = accumulated.get(&position_key(self.positions[idx])).copied().unwrap_or(Vec3::new(0.0, 0.0, 0.0));
+            self.normals[idx] = if normal.length() > 0.0 { normal.normalized() } else { normal };
+        }
+    }
+
+    // Per-vertex tangents for normal mapping, as MikkTSpace computes them (and glTF expects them): xyz is
+    // the tangent, pointing along increasing u and orthogonal to the normal, and w is the handedness, such
+    // that the bitangent is w * normal.cross(tangent). Corners get grouped per vertex across triangles with
+    // the same uv orientation, each group's tangent is the angle weighted average of its triangles'
+    // tangents projected onto the corner normals. Vertices whose corners end up in groups with different
+    // tangents (mirrored uvs, for one) get split, so the returned tangents line up with the vertices after
+    // that. Returns None if the mesh has no texture coordinates.
+    pub fn tangents(&mut self) -> Option<Vec<Vec4>> {
+        let corner_tangents = self.corner_tangents()?;
+        let mut tangents: Vec<Option<Vec4>> = vec![None; self.vertex_count()];
+        // Copies of each vertex made for other tangents
+        let mut splits: HashMap<usize, Vec<usize>> = HashMap::new();
+        for (tri_idx, tri_tangents) in corner_tangents.iter().enumerate() {
+            for (corner, &tangent) in tri_tangents.iter().enumerate() {
+                let idx = self.indices[tri_idx][corner] as usize;
+                let same = |other: Option<Vec4>| other.is_some_and(|other| [other.x(), other.y(), other.z(), other.w()] == [tangent.x(), tangent.y(), tangent.z(), tangent.w()]);
+                if tangents[idx].is_none() {
+                    tangents[idx] = Some(tangent);
+                    continue;
+                }
+                if same(tangents[idx]) {
+                    continue;
+                }
+                let copies = splits.entry(idx).or_default();
+                let copy = match copies.iter().copied().find(|&copy| same(tangents[copy])) {
+                    Some(copy) => copy,
+                    None => {
+                        self.positions.push(self.positions[idx]);
+                        self.normals.push(self.normals[idx]);
+                        if let Some(texcoords) = self.texcoords.as_mut() {
+                            texcoords.push(texcoords[idx]);
+                        }
+                        if let Some(colors) = self.colors.as_mut() {
+                            colors.push(colors[idx]);
+                        }
+                        tangents.push(Some(tangent));
+                        copies.push(tangents.len() - 1);
+                        tangents.len() - 1
+                    },
+                };
+                self.indices[tri_idx][corner] = copy as u32;
+            }
+        }
+        return Some(tangents.into_iter().map(|tangent| tangent.unwrap_or(Vec4::new(0.0, 0.0, 0.0, 1.0))).collect());
+    }
+
+    // The MikkTSpace tangent of every corner, in the order of the indices
+    fn corner_tangents(&self) -> Option<Vec<[Vec4; 3]>> {
+        let texcoords = self.texcoords.as_ref()?;
+        let zero = Vec3::new(0.0, 0.0, 0.0);
+
+        // MikkTSpace works on the winding of the file, which is the reverse of the stored one (see
+        // polygon_triangles), and on vertices identified by their position, normal and texture coordinate
+        let corners: Vec<[usize; 3]> = self.indices.iter().map(|&[a, b, c]| [c as usize, b as usize, a as usize]).collect();
+        let mut vertex_ids: HashMap<[u32; 9], usize> = HashMap::new();
+        let vertex_id: Vec<usize> = (0..self.vertex_count()).map(|idx| {
+            let [p, n, t] = [position_key(self.positions[idx]), position_key(self.normals[idx]), position_key(texcoords[idx])];
+            let key = [p[0], p[1], p[2], n[0], n[1], n[2], t[0], t[1], t[2]];
+            let next_id = vertex_ids.len();
+            return *vertex_ids.entry(key).or_insert(next_id);
+        }).collect();
+
+        // Per triangle: the unit tangent direction (flipped for mirrored uvs), whether the uvs keep their
+        // orientation, and whether the uvs are degenerate, in which case the triangle joins any group
+        struct Face {
+            tangent: Vec3,
+            orient: bool,
+            any: bool,
+        }
+        let mut faces: Vec<Face> = corners.iter().map(|tri| {
+            let p = tri.map(|idx| self.positions[idx]);
+            let t = tri.map(|idx| texcoords[idx]);
+            let (d1, d2) = (p[1] - p[0], p[2] - p[0]);
+            let (t21, t31) = (t[1] - t[0], t[2] - t[0]);
+            let signed_area = t21.x() * t31.y() - t21.y() * t31.x();
+            let orient = signed_area > 0.0;
+            let tangent = d1 * t31.y() - d2 * t21.y();
+            let any = signed_area == 0.0 || tangent.length() == 0.0;
+            return Face {
+                tangent: if any { zero } else { tangent.normalized() * if orient { 1.0 } else { -1.0 } },
+                orient: orient,
+                any: any,
+            };
+        }).collect();
+
+        // Neighbors across each edge (from corner i to i + 1), matching the edge the other way around
+        let mut edges: HashMap<(usize, usize), usize> = HashMap::new();
+        for (tri_idx, tri) in corners.iter().enumerate() {
+            for corner in 0..3 {
+                edges.entry((vertex_id[tri[corner]], vertex_id[tri[(corner + 1) % 3]])).or_insert(tri_idx);
+            }
+        }
+        let neighbors: Vec<[Option<usize>; 3]> = corners.iter().enumerate().map(|(tri_idx, tri)| {
+            return [0, 1, 2].map(|corner| {
+                let key = (vertex_id[tri[(corner + 1) % 3]], vertex_id[tri[corner]]);
+                return edges.get(&key).copied().filter(|&other| other != tri_idx);
+            });
+        }).collect();
+
+        // Triangles with degenerate uvs take their orientation from a neighbor that has one
+        for tri_idx in 0..faces.len() {
+            if faces[tri_idx].any {
+                if let Some(other) = neighbors[tri_idx].iter().flatten().find(|&&other| !faces[other].any) {
+                    faces[tri_idx].orient = faces[*other].orient;
+                }
+            }
+        }
+
+        // Groups of corners around a vertex, connected over the edges at that vertex to triangles with the
+        // same orientation
+        let mut group_of = vec![[usize::MAX; 3]; corners.len()];
+        let mut groups: Vec<(Vec<(usize, usize)>, bool)> = Vec::new();
+        for tri_idx in 0..corners.len() {
+            for corner in 0..3 {
+                if group_of[tri_idx][corner] != usize::MAX {
+                    continue;
+                }
+                let vertex = vertex_id[corners[tri_idx][corner]];
+                let orient = faces[tri_idx].orient;
+                let group = groups.len();
+                let mut members = Vec::new();
+                let mut stack = vec![(tri_idx, corner)];
+                group_of[tri_idx][corner] = group;
+                while let Some((tri, corner)) = stack.pop() {
+                    members.push((tri, corner));
+                    for neighbor in [neighbors[tri][corner], neighbors[tri][(corner + 2) % 3]].into_iter().flatten() {
+                        let Some(other_corner) = (0..3).find(|&c| vertex_id[corners[neighbor][c]] == vertex) else {
+                            continue;
+                        };
+                        if group_of[neighbor][other_corner] == usize::MAX && faces[neighbor].orient == orient {
+                            group_of[neighbor][other_corner] = group;
+                            stack.push((neighbor, other_corner));
+                        }
+                    }
+                }
+                groups.push((members, orient));
+            }
+        }
+
+        // Each group's tangent, from the triangle tangents projected onto the corner normal and weighted by
+        // the corner angle in that plane
+        let group_tangents: Vec<Vec4> = groups.iter().map(|(members, orient)| {
+            let mut sum = zero;
+            for &(tri, corner) in members {
+                if faces[tri].any {
+                    continue;
+                }
+                let idx = corners[tri][corner];
+                let n = self.normals[idx];
+                let project = |v: Vec3| v - n * (n & v);
+                let tangent = project(faces[tri].tangent);
+                if tangent.length() == 0.0 {
+                    continue;
+                }
+                let this = self.positions[idx];
+                let prev = this + project(self.positions[corners[tri][(corner + 2) % 3]] - this);
+                let next = this + project(self.positions[corners[tri][(corner + 1) % 3]] - this);
+                sum += tangent.normalized() * corner_angle(prev, this, next);
+            }
+            let tangent = if sum.length() > 0.0 { sum.normalized() } else { zero };
+            return Vec4::new(tangent.x(), tangent.y(), tangent.z(), if *orient { 1.0 } else { -1.0 });
+        }).collect();
+
+        // Back to the stored corner order
+        return Some(group_of.iter().map(|groups| [2, 1, 0].map(|corner| group_tangents[groups[corner]])).collect());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use vector_math::{*};
+    use crate::{IndexedMesh, parse_obj, shapes};
+    use super::{Aabb, BoundingSphere, triangle_normal};
+
+    fn xyz(v: Vec3) -> [Scalar; 3] {
+        return [v.x(), v.y(), v.z()];
+    }
+
+    fn xyzw(v: Vec4) -> [Scalar; 4] {
+        return [v.x(), v.y(), v.z(), v.w()];
+    }
+
+    // Unit quad facing +z, with the given texture coordinates for its corners
+    fn quad(texcoords: [[Scalar; 2]; 4]) -> IndexedMesh {
+        let mut obj = String::from("v 0 0 0\nv 1 0 0\nv 1 1 0\nv 0 1 0\nvn 0 0 1\n");
+        for [u, v] in texcoords {
+            obj.push_str(&format!("vt {} {}\n", u, v));
+        }
+        obj.push_str("f 1/1/1 2/2/1 3/3/1 4/4/1\n");
+        return parse_obj(&obj).unwrap().to_indexed(true);
+    }
+
+    #[test]
+    fn aabb_and_bounding_sphere() {
+        let mesh = parse_obj("v -1 0 2\nv 3 -2 0\nv 0 4 1\nv 1 1 -5\nf 1 2 3\nf 1 3 4\n").unwrap();
+        let aabb = mesh.aabb().unwrap();
+        assert_eq!(xyz(aabb.min), [-1.0, -2.0, -5.0]);
+        assert_eq!(xyz(aabb.max), [3.0, 4.0, 2.0]);
+        assert_eq!(xyz(aabb.center()), [1.0, 1.0, -1.5]);
+        assert!(Aabb::from_triangles(&[]).is_none());
+
+        let sphere = shapes::torus(2.0, 0.5, 24, 12).bounding_sphere().unwrap();
+        assert!(sphere.radius < 2.5 * 1.1);
+        let points: Vec<Vec3> = (0..200).map(|idx| {
+            let idx = idx as Scalar;
+            return Vec3::new((idx * 0.37).sin() * idx, (idx * 1.3).cos() * 5.0, idx * 0.01 - 1.0);
+        }).collect();
+        let sphere = BoundingSphere::from_points(&points).unwrap();
+        for p in &points {
+            assert!((*p - sphere.center).length() <= sphere.radius * (1.0 + 1e-5));
+        }
+    }
+
+    #[test]
+    fn normalize_and_recenter() {
+        let obj = "v 1 1 1\nv 5 1 1\nv 1 3 2\nf 1 2 3\n";
+        let mut mesh = parse_obj(obj).unwrap();
+        mesh.recenter();
+        let aabb = mesh.aabb().unwrap();
+        assert_eq!(xyz(aabb.center()), [0.0, 0.0, 0.0]);
+        assert_eq!(xyz(aabb.size()), [4.0, 2.0, 1.0]);
+        assert_eq!(xyz(mesh.polygons[0].p[1]), [2.0, -1.0, -0.5]);
+
+        let mut mesh = parse_obj(obj).unwrap();
+        mesh.normalize();
+        let aabb = mesh.aabb().unwrap();
+        assert_eq!(xyz(aabb.center()), [0.0, 0.0, 0.0]);
+        assert_eq!(xyz(aabb.size()), [1.0, 0.5, 0.25]);
+
+        let mut indexed = parse_obj(obj).unwrap().to_indexed(true);
+        indexed.normalize();
+        assert_eq!(xyz(indexed.aabb().unwrap().size()), [1.0, 0.5, 0.25]);
+    }
+
+    #[test]
+    fn weld_merges_shared_corners() {
+        // Two triangles of a quad, each with its own vertices, and one corner off by less than epsilon
+        let obj = "v 0 0 0\nv 1 0 0\nv 1 1 0\nv 0 1 0\nv 1.0000001 1 0\nvn 0 0 1\nf 1//1 2//1 3//1\nf 1//1 5//1 4//1\n";
+        let mut mesh = IndexedMesh::from_triangles(&parse_obj(obj).unwrap().triangles, false);
+        assert_eq!(mesh.vertex_count(), 6);
+        assert_eq!(mesh.weld(1e-5), 2);
+        assert_eq!(mesh.vertex_count(), 4);
+        let shared = mesh.indices[0].iter().filter(|idx| mesh.indices[1].contains(idx)).count();
+        assert_eq!(shared, 2);
+
+        // Different normals keep vertices apart
+        let mut cube = shapes::cube(1.0, 1).to_indexed(false);
+        cube.weld(1e-5);
+        assert_eq!(cube.vertex_count(), 24);
+    }
+
+    #[test]
+    fn remove_degenerate_drops_slivers_and_repeats() {
+        let obj = "v 0 0 0\nv 1 0 0\nv 0 1 0\nv 2 0 0\nf 1 2 3\nf 1 2 4\nf 1 1 3\n";
+        let mut mesh = parse_obj(obj).unwrap().to_indexed(true);
+        assert_eq!(mesh.triangle_count(), 3);
+        assert_eq!(mesh.remove_degenerate(0.0), 2);
+        assert_eq!(mesh.triangle_count(), 1);
+        assert_eq!(mesh.triangle_materials.len(), 1);
+        assert_eq!(mesh.vertex_count(), 3);
+    }
+
+    #[test]
+    fn flip_winding_turns_triangles_and_normals() {
+        let mut mesh = parse_obj("v 0 0 0\nv 1 0 0\nv 0 1 0\nf 1 2 3\n").unwrap();
+        let before = triangle_normal(mesh.triangles[0].p).normalized();
+        assert_eq!(xyz(before), [0.0, 0.0, 1.0]);
+        mesh.flip_winding();
+        assert_eq!(xyz(triangle_normal(mesh.triangles[0].p).normalized()), [0.0, 0.0, -1.0]);
+        assert!(mesh.triangles[0].n.iter().all(|&n| xyz(n) == [0.0, 0.0, -1.0]));
+        assert_eq!(xyz(mesh.polygons[0].n[0]), [0.0, 0.0, -1.0]);
+
+        let mut indexed = parse_obj("v 0 0 0\nv 1 0 0\nv 0 1 0\nf 1 2 3\n").unwrap().to_indexed(true);
+        indexed.flip_winding();
+        let tri = indexed.to_triangles()[0];
+        assert_eq!(xyz(triangle_normal(tri.p).normalized()), [0.0, 0.0, -1.0]);
+        assert_eq!(xyz(tri.n[0]), [0.0, 0.0, -1.0]);
+    }
+
+    #[test]
+    fn tangents_follow_u() {
+        let mut mesh = quad([[0.0, 0.0], [1.0, 0.0], [1.0, 1.0], [0.0, 1.0]]);
+        let tangents = mesh.tangents().unwrap();
+        assert_eq!(tangents.len(), 4);
+        for &tangent in &tangents {
+            assert_eq!(xyzw(tangent), [1.0, 0.0, 0.0, 1.0]);
+        }
+
+        // u along +y and v along -x: normal.cross(tangent) is -x, so still right handed
+        let mut mesh = quad([[0.0, 1.0], [0.0, 0.0], [1.0, 0.0], [1.0, 1.0]]);
+        for tangent in mesh.tangents().unwrap() {
+            assert_eq!(xyzw(tangent), [0.0, 1.0, 0.0, 1.0]);
+        }
+
+        // Mirrored v flips the handedness
+        let mut mesh = quad([[0.0, 1.0], [1.0, 1.0], [1.0, 0.0], [0.0, 0.0]]);
+        for tangent in mesh.tangents().unwrap() {
+            assert_eq!(xyzw(tangent), [1.0, 0.0, 0.0, -1.0]);
+        }
+    }
+
+    #[test]
+    fn mirrored_uvs_split_vertices() {
+        // Two quads sharing the edge at x = 0, with u mirrored across it
+        let obj = "v -1 0 0\nv 0 0 0\nv 1 0 0\nv -1 1 0\nv 0 1 0\nv 1 1 0\nvn 0 0 1\n\
+            vt 1 0\nvt 0 0\nvt 1 0\nvt 1 1\nvt 0 1\nvt 1 1\n\
+            f 1/1/1 2/2/1 5/5/1 4/4/1\nf 2/2/1 3/3/1 6/6/1 5/5/1\n";
+        let mut mesh = parse_obj(obj).unwrap().to_indexed(true);
+        assert_eq!(mesh.vertex_count(), 6);
+        let tangents = mesh.tangents().unwrap();
+        // The two shared vertices get a copy each
+        assert_eq!(mesh.vertex_count(), 8);
+        assert_eq!(tangents.len(), 8);
+        for (tri_idx, tri) in mesh.to_triangles().iter().enumerate() {
+            let left = tri.p.iter().all(|p| p.x() <= 0.0);
+            for &idx in &mesh.indices[tri_idx] {
+                let expected = if left { [-1.0, 0.0, 0.0, -1.0] } else { [1.0, 0.0, 0.0, 1.0] };
+                assert_eq!(xyzw(tangents[idx as usize]), expected);
+            }
+        }
+
+        // Without the mirroring the shared vertices stay shared
+        let obj = "v -1 0 0\nv 0 0 0\nv 1 0 0\nv -1 1 0\nv 0 1 0\nv 1 1 0\nvn 0 0 1\n\
+            vt 0 0\nvt 0.5 0\nvt 1 0\nvt 0 1\nvt 0.5 1\nvt 1 1\n\
+            f 1/1/1 2/2/1 5/5/1 4/4/1\nf 2/2/1 3/3/1 6/6/1 5/5/1\n";
+        let mut mesh = parse_obj(obj).unwrap().to_indexed(true);
+        mesh.tangents().unwrap();
+        assert_eq!(mesh.vertex_count(), 6);
+        assert!(parse_obj("v 0 0 0\nv 1 0 0\nv 0 1 0\nf 1 2 3\n").unwrap().to_indexed(true).tangents().is_none());
+    }
+}
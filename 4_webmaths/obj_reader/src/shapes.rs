@@ -0,0 +1,450 @@
+use vector_math::{*};
+use std::collections::HashMap;
+use std::f32::consts::PI;
+use crate::{Mesh, ObjOptions};
+use crate::parse::{Corner, Face, ObjState, build_mesh};
+
+// Generated shapes are built the same way a parsed obj file is, so they come out as the same kind of mesh:
+// one sub-mesh named after the shape, y up, centered on the origin and wound counter-clockwise seen from
+// the outside.
+struct ShapeBuilder {
+    state: ObjState,
+    faces: Vec<Face>,
+}
+
+// Cosine and sine of t full turns. Exact where the circle closes and at half turns, so that seams and poles
+// end up bit-identical and weld.
+fn turn(t: Scalar) -> (Scalar, Scalar) {
+    let t = t % 1.0;
+    if t == 0.0 {
+        return (1.0, 0.0);
+    }
+    if t == 0.5 {
+        return (-1.0, 0.0);
+    }
+    let (sin, cos) = (t * 2.0 * PI).sin_cos();
+    return (cos, sin);
+}
+
+// Texture coordinates of a point on the unit sphere, matching uv_sphere
+fn sphere_uv(n: Vec3) -> (Scalar, Scalar) {
+    let u = n.z().atan2(n.x()) / (-2.0 * PI);
+    return (if u < 0.0 { u + 1.0 } else { u }, 0.5 + n.y().clamp(-1.0, 1.0).asin() / PI);
+}
+
+impl ShapeBuilder {
+    fn new(name: &str) -> ShapeBuilder {
+        let mut state = ObjState::new();
+        state.object = name.to_string();
+        return ShapeBuilder {
+            state: state,
+            faces: Vec::new(),
+        };
+    }
+
+    fn position(&mut self, p: Vec3, n: Vec3) -> usize {
+        // Adding zero turns -0 into 0, so points that coincide are also bit-identical
+        let zero = Vec3::new(0.0, 0.0, 0.0);
+        self.state.vertices.push(p + zero);
+        self.state.colors.push(None);
+        self.state.normals.push(n + zero);
+        return self.state.vertices.len() - 1;
+    }
+
+    fn texcoord(&mut self, u: Scalar, v: Scalar) -> usize {
+        self.state.texcoords.push(Vec3::new(u, v, 0.0));
+        return self.state.texcoords.len() - 1;
+    }
+
+    fn vertex(&mut self, p: Vec3, n: Vec3, u: Scalar, v: Scalar) -> (usize, usize) {
+        return (self.position(p, n), self.texcoord(u, v));
+    }
+
+    // Adds a face from (position, texture coordinate) index pairs, in counter-clockwise order
+    fn face(&mut self, corners: &[(usize, usize)]) {
+        let corners = corners.iter().map(|&(p, t)| Corner { p: p, t: Some(t), n: Some(p) }).collect();
+        let face = self.state.face(corners);
+        self.faces.push(face);
+    }
+
+    // Adds a (columns + 1) x (rows + 1) grid of vertices from a function of (u, v) in [0, 1] to position and
+    // normal, with (u, v) as texture coordinates, facing where the derivative in u crossed with the one in v
+    // points. The first and last row can be poles where all columns meet, which get one vertex per column
+    // (at its middle u, so the texture doesn't shear) and triangles instead of quads.
+    fn grid(&mut self, columns: usize, rows: usize, poles: [bool; 2], f: impl Fn(Scalar, Scalar) -> (Vec3, Vec3)) {
+        let is_pole = |row: usize| (row == 0 && poles[0]) || (row == rows && poles[1]);
+        let mut vertices = Vec::with_capacity(rows + 1);
+        for row in 0..=rows {
+            let v = row as Scalar / rows as Scalar;
+            let row_vertices: Vec<(usize, usize)> = if is_pole(row) {
+                (0..columns).map(|column| (column as Scalar + 0.5) / columns as Scalar).map(|u| {
+                    let (p, n) = f(u, v);
+                    return self.vertex(p, n, u, v);
+                }).collect()
+            }
+            else {
+                (0..=columns).map(|column| column as Scalar / columns as Scalar).map(|u| {
+                    let (p, n) = f(u, v);
+                    return self.vertex(p, n, u, v);
+                }).collect()
+            };
+            vertices.push(row_vertices);
+        }
+        for row in 0..rows {
+            for column in 0..columns {
+                let (bottom, top) = (&vertices[row], &vertices[row + 1]);
+                match (is_pole(row), is_pole(row + 1)) {
+                    (true, true) => {},
+                    (true, false) => self.face(&[bottom[column], top[column + 1], top[column]]),
+                    (false, true) => self.face(&[bottom[column], bottom[column + 1], top[column]]),
+                    (false, false) => self.face(&[bottom[column], bottom[column + 1], top[column + 1], top[column]]),
+                }
+            }
+        }
+    }
+
+    // A flat disc at height y facing up or down, as a fan around its center with planar texture coordinates
+    fn disc(&mut self, radius: Scalar, y: Scalar, segments: usize, up: bool) {
+        let normal = Vec3::new(0.0, if up { 1.0 } else { -1.0 }, 0.0);
+        let center = self.vertex(Vec3::new(0.0, y, 0.0), normal, 0.5, 0.5);
+        let ring: Vec<(usize, usize)> = (0..segments).map(|segment| {
+            let (cos, sin) = turn(segment as Scalar / segments as Scalar);
+            let v = if up { 0.5 + 0.5 * sin } else { 0.5 - 0.5 * sin };
+            return self.vertex(Vec3::new(radius * cos, y, -radius * sin), normal, 0.5 + 0.5 * cos, v);
+        }).collect();
+        for segment in 0..segments {
+            let (a, b) = (ring[segment], ring[(segment + 1) % segments]);
+            self.face(&if up { [center, a, b] } else { [center, b, a] });
+        }
+    }
+
+    fn build(self) -> Mesh {
+        return build_mesh(self.state, self.faces, Vec::new(), &ObjOptions::default());
+    }
+}
+
+// Sphere made by subdividing an icosahedron, so its triangles are all about the same size. Every level
+// has four times the triangles of the one before (20 at level 0), plus the pieces of those split along
+// the texture seam.
+pub fn icosphere(radius: Scalar, level: usize) -> Mesh {
+    let t = (1.0 + (5.0 as Scalar).sqrt()) / 2.0;
+    let mut points: Vec<Vec3> = [
+        (-1.0, t, 0.0), (1.0, t, 0.0), (-1.0, -t, 0.0), (1.0, -t, 0.0),
+        (0.0, -1.0, t), (0.0, 1.0, t), (0.0, -1.0, -t), (0.0, 1.0, -t),
+        (t, 0.0, -1.0), (t, 0.0, 1.0), (-t, 0.0, -1.0), (-t, 0.0, 1.0),
+    ].iter().map(|&(x, y, z)| Vec3::new(x, y, z).normalized()).collect();
+    let mut triangles: Vec<[usize; 3]> = vec![
+        [0, 11, 5], [0, 5, 1], [0, 1, 7], [0, 7, 10], [0, 10, 11],
+        [1, 5, 9], [5, 11, 4], [11, 10, 2], [10, 7, 6], [7, 1, 8],
+        [3, 9, 4], [3, 4, 2], [3, 2, 6], [3, 6, 8], [3, 8, 9],
+        [4, 9, 5], [2, 4, 11], [6, 2, 10], [8, 6, 7], [9, 8, 1],
+    ];
+
+    for _ in 0..level {
+        let mut midpoints: HashMap<(usize, usize), usize> = HashMap::new();
+        let mut midpoint = |a: usize, b: usize| -> usize {
+            return *midpoints.entry((a.min(b), a.max(b))).or_insert_with(|| {
+                points.push(((points[a] + points[b]) * 0.5).normalized());
+                return points.len() - 1;
+            });
+        };
+        triangles = triangles.iter().flat_map(|&[a, b, c]| {
+            let (ab, bc, ca) = (midpoint(a, b), midpoint(b, c), midpoint(c, a));
+            return [[a, ab, ca], [b, bc, ab], [c, ca, bc], [ab, bc, ca]];
+        }).collect();
+    }
+
+    // Triangles that cross the texture seam (the half of the xy plane towards +x) get split along it, so
+    // the texture coordinates on either side can stay within [0, 1]. The points they get split at are put
+    // back on the sphere, shared between the triangles on both sides of an edge.
+    let mut seam_points: HashMap<(usize, usize), usize> = HashMap::new();
+    let mut seam_point = |a: usize, b: usize| -> Option<usize> {
+        let (pa, pb) = (points[a], points[b]);
+        if pa.z() * pb.z() >= 0.0 {
+            return None;
+        }
+        let t = pa.z() / (pa.z() - pb.z());
+        let p = pa + (pb - pa) * t;
+        if p.x() <= 0.0 {
+            return None;
+        }
+        return Some(*seam_points.entry((a.min(b), a.max(b))).or_insert_with(|| {
+            points.push(Vec3::new(p.x(), p.y(), 0.0).normalized());
+            return points.len() - 1;
+        }));
+    };
+    let rings: Vec<Vec<usize>> = triangles.iter().map(|triangle| {
+        return (0..3).flat_map(|corner| [Some(triangle[corner]), seam_point(triangle[corner], triangle[(corner + 1) % 3])]).flatten().collect();
+    }).collect();
+    let mut split_triangles = Vec::with_capacity(rings.len());
+    for ring in rings {
+        if ring.len() == 3 {
+            split_triangles.push([ring[0], ring[1], ring[2]]);
+            continue;
+        }
+        // Points on the seam go into both halves, which are convex and get fanned
+        for side in [1.0, -1.0] {
+            let half: Vec<usize> = ring.iter().copied().filter(|&idx| points[idx].z() * side >= 0.0).collect();
+            split_triangles.extend((1..half.len() - 1).map(|idx| [half[0], half[idx], half[idx + 1]]));
+        }
+    }
+
+    let mut builder = ShapeBuilder::new("icosphere");
+    let positions: Vec<usize> = points.iter().map(|&n| builder.position(n * radius, n)).collect();
+    for triangle in split_triangles {
+        // Corners on the seam are at u = 1 for triangles on its +z side, and corners on a pole take the u of
+        // the rest of the triangle
+        let mut uvs = triangle.map(|idx| sphere_uv(points[idx]));
+        let on_pole = triangle.map(|idx| points[idx].x() == 0.0 && points[idx].z() == 0.0);
+        if triangle.iter().any(|&idx| points[idx].z() > 0.0) {
+            for corner in (0..3).filter(|&corner| points[triangle[corner]].z() == 0.0 && points[triangle[corner]].x() > 0.0) {
+                uvs[corner].0 = 1.0;
+            }
+        }
+        for corner in (0..3).filter(|&corner| on_pole[corner]) {
+            uvs[corner].0 = (0..3).filter(|&other| !on_pole[other]).map(|other| uvs[other].0).sum::<Scalar>() / 2.0;
+        }
+        let corners: Vec<(usize, usize)> = (0..3).map(|corner| {
+            return (positions[triangle[corner]], builder.texcoord(uvs[corner].0, uvs[corner].1));
+        }).collect();
+        builder.face(&corners);
+    }
+    return builder.build();
+}
+
+// Sphere with segments around the y axis and rings from pole to pole. Texture coordinates go around once
+// in u (with a seam at +x) and from the bottom to the top in v.
+pub fn uv_sphere(radius: Scalar, segments: usize, rings: usize) -> Mesh {
+    let mut builder = ShapeBuilder::new("uv_sphere");
+    builder.grid(segments.max(3), rings.max(2), [true, true], |u, v| {
+        let (cos_phi, sin_phi) = turn(u);
+        let (cos_theta, sin_theta) = turn((1.0 - v) / 2.0);
+        let n = Vec3::new(sin_theta * cos_phi, cos_theta, -sin_theta * sin_phi);
+        return (n * radius, n);
+    });
+    return builder.build();
+}
+
+// Plane in xz facing up, with segments_x by segments_z quads. v runs towards -z, so a texture reads
+// correctly from above with -z as up.
+pub fn plane(width: Scalar, depth: Scalar, segments_x: usize, segments_z: usize) -> Mesh {
+    let mut builder = ShapeBuilder::new("plane");
+    builder.grid(segments_x.max(1), segments_z.max(1), [false, false], |u, v| {
+        return (Vec3::new((u - 0.5) * width, 0.0, (0.5 - v) * depth), Vec3::new(0.0, 1.0, 0.0));
+    });
+    return builder.build();
+}
+
+// Side of a cylinder or cone around the y axis, from radius bottom at -height / 2 to radius top at
+// height / 2, with caps where the radius isn't zero
+fn frustum(name: &str, bottom: Scalar, top: Scalar, height: Scalar, segments: usize, height_segments: usize) -> Mesh {
+    let segments = segments.max(3);
+    let mut builder = ShapeBuilder::new(name);
+    builder.grid(segments, height_segments.max(1), [bottom == 0.0, top == 0.0], |u, v| {
+        let (cos, sin) = turn(u);
+        let radius = if v == 1.0 { top } else { bottom + (top - bottom) * v };
+        let p = Vec3::new(radius * cos, (v - 0.5) * height, -radius * sin);
+        return (p, Vec3::new(height * cos, bottom - top, -height * sin).normalized());
+    });
+    if bottom > 0.0 {
+        builder.disc(bottom, -0.5 * height, segments, false);
+    }
+    if top > 0.0 {
+        builder.disc(top, 0.5 * height, segments, true);
+    }
+    return builder.build();
+}
+
+// Closed cylinder around the y axis. The side is textured like uv_sphere, the caps get planar texture
+// coordinates.
+pub fn cylinder(radius: Scalar, height: Scalar, segments: usize, height_segments: usize) -> Mesh {
+    return frustum("cylinder", radius, radius, height, segments, height_segments);
+}
+
+// Cone around the y axis with its tip at the top, textured like cylinder
+pub fn cone(radius: Scalar, height: Scalar, segments: usize, height_segments: usize) -> Mesh {
+    return frustum("cone", radius, 0.0, height, segments, height_segments);
+}
+
+// Torus around the y axis, with major_segments around the ring and minor_segments around the tube.
+// u goes around the ring and v around the tube, starting on the outside.
+pub fn torus(major_radius: Scalar, minor_radius: Scalar, major_segments: usize, minor_segments: usize) -> Mesh {
+    let mut builder = ShapeBuilder::new("torus");
+    builder.grid(major_segments.max(3), minor_segments.max(3), [false, false], |u, v| {
+        let (cos_phi, sin_phi) = turn(u);
+        let (cos_psi, sin_psi) = turn(v);
+        let n = Vec3::new(cos_psi * cos_phi, sin_psi, -cos_psi * sin_phi);
+        let ring = Vec3::new(major_radius * cos_phi, 0.0, -major_radius * sin_phi);
+        return (ring + n * minor_radius, n);
+    });
+    return builder.build();
+}
+
+// Coordinates along one axis of a (rounded) box side, from -half to half. The rounded ends get segments
+// steps each of equal angle, the flat middle (if there is one) a single step.
+fn box_coordinates(half: Scalar, radius: Scalar, segments: usize) -> Vec<Scalar> {
+    let mut coordinates: Vec<Scalar> = if radius == 0.0 {
+        (0..=segments).map(|step| half * (2.0 * step as Scalar / segments as Scalar - 1.0)).collect()
+    }
+    else {
+        let flat = half - radius;
+        let rounded = |step: usize| radius * (0.25 * PI * step as Scalar / segments as Scalar).tan();
+        let mut coordinates: Vec<Scalar> = (0..=segments).rev().map(|step| -flat - rounded(step)).collect();
+        coordinates.extend((if flat > 0.0 { 0 } else { 1 }..=segments).map(|step| flat + rounded(step)));
+        coordinates
+    };
+
+    // Exact ends and symmetric down to the bit, so that the edges two sides share weld
+    let count = coordinates.len();
+    coordinates[0] = -half;
+    for idx in 0..count / 2 {
+        coordinates[count - 1 - idx] = -coordinates[idx];
+    }
+    if count % 2 == 1 {
+        coordinates[count / 2] = 0.0;
+    }
+    return coordinates;
+}
+
+// Box of the given size with its edges and corners rounded off with the given radius (up to half the
+// smallest side), segments steps per rounded edge. Every side is textured with the whole texture.
+pub fn rounded_box(size: Vec3, radius: Scalar, segments: usize) -> Mesh {
+    let half = size * 0.5;
+    let radius = radius.clamp(0.0, half.x().min(half.y()).min(half.z()));
+    let segments = segments.max(1);
+    let inner = Vec3::new(half.x() - radius, half.y() - radius, half.z() - radius);
+    let axis = |x: Scalar, y: Scalar, z: Scalar| Vec3::new(x, y, z);
+    let sides = [
+        (axis(1.0, 0.0, 0.0), axis(0.0, 0.0, -1.0), axis(0.0, 1.0, 0.0)),
+        (axis(-1.0, 0.0, 0.0), axis(0.0, 0.0, 1.0), axis(0.0, 1.0, 0.0)),
+        (axis(0.0, 1.0, 0.0), axis(1.0, 0.0, 0.0), axis(0.0, 0.0, -1.0)),
+        (axis(0.0, -1.0, 0.0), axis(1.0, 0.0, 0.0), axis(0.0, 0.0, 1.0)),
+        (axis(0.0, 0.0, 1.0), axis(1.0, 0.0, 0.0), axis(0.0, 1.0, 0.0)),
+        (axis(0.0, 0.0, -1.0), axis(-1.0, 0.0, 0.0), axis(0.0, 1.0, 0.0)),
+    ];
+
+    let mut builder = ShapeBuilder::new(if radius == 0.0 { "cube" } else { "rounded_box" });
+    for (normal, u_axis, v_axis) in sides {
+        let extent = |direction: Vec3| (direction & half).abs();
+        let u_coordinates = box_coordinates(extent(u_axis), radius, segments);
+        let v_coordinates = box_coordinates(extent(v_axis), radius, segments);
+        let (columns, rows) = (u_coordinates.len() - 1, v_coordinates.len() - 1);
+        builder.grid(columns, rows, [false, false], |u, v| {
+            // A point on the box, pushed out from the nearest point of the inner box by the radius
+            let q = normal * extent(normal)
+                + u_axis * u_coordinates[(u * columns as Scalar).round() as usize]
+                + v_axis * v_coordinates[(v * rows as Scalar).round() as usize];
+            if radius == 0.0 {
+                return (q, normal);
+            }
+            let clamped = Vec3::new(
+                q.x().clamp(-inner.x(), inner.x()),
+                q.y().clamp(-inner.y(), inner.y()),
+                q.z().clamp(-inner.z(), inner.z()),
+            );
+            let n = (q - clamped).normalized();
+            return (clamped + n * radius, n);
+        });
+    }
+    return builder.build();
+}
+
+// Cube with the given edge length, segments by segments quads per side
+pub fn cube(size: Scalar, segments: usize) -> Mesh {
+    return rounded_box(Vec3::new(size, size, size), 0.0, segments);
+}
+
+#[cfg(test)]
+mod tests {
+    use vector_math::{*};
+    use crate::{Mesh, MeshIssueKind, ValidationOptions};
+    use crate::process::triangle_normal;
+    use super::{cone, cube, cylinder, icosphere, plane, rounded_box, torus, uv_sphere};
+
+    // Goes through an indexed mesh and welds, which has to leave every triangle in place
+    fn welded(mut mesh: Mesh) -> Mesh {
+        let mut indexed = mesh.to_indexed(true);
+        indexed.weld(1e-5);
+        let triangles = indexed.to_triangles();
+        assert_eq!(triangles.len(), mesh.triangles.len());
+        mesh.triangles = triangles;
+        return mesh;
+    }
+
+    fn assert_texcoords_in_unit_square(mesh: &Mesh) {
+        for tri in mesh.triangles.iter() {
+            for t in tri.t.unwrap() {
+                assert!((0.0..=1.0).contains(&t.x()) && (0.0..=1.0).contains(&t.y()), "uv {} {}", t.x(), t.y());
+            }
+        }
+    }
+
+    // Closed, consistently wound, unit normals facing the way of their triangles, and those facing away
+    // from inside, where inside is a function of the triangle's center
+    fn assert_closed_and_outward(mesh: Mesh, expected_triangles: usize, inside: impl Fn(Vec3) -> Vec3) {
+        assert_eq!(mesh.triangles.len(), expected_triangles);
+        let mesh = welded(mesh);
+        let report = mesh.validate(&ValidationOptions::default());
+        assert!(report.is_clean(), "{}", report);
+        for tri in mesh.triangles.iter() {
+            let center = (tri.p[0] + tri.p[1] + tri.p[2]) * (1.0 / 3.0);
+            assert!((triangle_normal(tri.p) & (center - inside(center))) > 0.0);
+        }
+        assert_texcoords_in_unit_square(&mesh);
+    }
+
+    fn origin(_: Vec3) -> Vec3 {
+        return Vec3::new(0.0, 0.0, 0.0);
+    }
+
+    #[test]
+    fn icosphere_is_closed_and_on_its_radius() {
+        for level in 0..4 {
+            // The seam crosses 2^level edges, and splits the two triangles at each of them
+            let mesh = icosphere(2.0, level);
+            for tri in mesh.triangles.iter() {
+                for p in tri.p {
+                    assert!((p.length() - 2.0).abs() < 1e-5);
+                }
+            }
+            assert_closed_and_outward(mesh, 20 * 4usize.pow(level as u32) + 2usize.pow(level as u32 + 1), origin);
+        }
+    }
+
+    #[test]
+    fn spheres_cylinders_and_cones_are_closed() {
+        assert_closed_and_outward(uv_sphere(1.0, 16, 8), 2 * 16 * 7, origin);
+        assert_closed_and_outward(uv_sphere(1.0, 3, 2), 2 * 3, origin);
+        // Sides, and a fan for each cap
+        assert_closed_and_outward(cylinder(1.0, 2.0, 12, 3), 2 * 12 * 3 + 2 * 12, origin);
+        // Quads up to the last row, which meets at the tip, and the bottom cap
+        assert_closed_and_outward(cone(1.0, 2.0, 12, 3), 2 * 12 * 2 + 12 + 12, origin);
+    }
+
+    #[test]
+    fn torus_is_closed() {
+        let (major, minor) = (2.0, 0.5);
+        assert_closed_and_outward(torus(major, minor, 24, 8), 2 * 24 * 8, |p| {
+            return Vec3::new(p.x(), 0.0, p.z()).normalized() * major;
+        });
+    }
+
+    #[test]
+    fn boxes_are_closed() {
+        assert_closed_and_outward(cube(2.0, 1), 12, origin);
+        assert_closed_and_outward(cube(2.0, 3), 6 * 2 * 9, origin);
+        // Every side has segments steps for each rounded end and one across the flat middle
+        assert_closed_and_outward(rounded_box(Vec3::new(2.0, 3.0, 4.0), 0.5, 2), 6 * 2 * 5 * 5, origin);
+    }
+
+    #[test]
+    fn plane_is_open_only_at_its_rim() {
+        let mesh = welded(plane(2.0, 3.0, 4, 5));
+        assert_eq!(mesh.triangles.len(), 2 * 4 * 5);
+        let report = mesh.validate(&ValidationOptions::default());
+        assert_eq!(report.count(MeshIssueKind::BoundaryEdge), 2 * (4 + 5));
+        assert_eq!(report.issues.len(), report.count(MeshIssueKind::BoundaryEdge));
+        assert!(mesh.triangles.iter().all(|tri| triangle_normal(tri.p).y() > 0.0));
+        assert_texcoords_in_unit_square(&mesh);
+    }
+}
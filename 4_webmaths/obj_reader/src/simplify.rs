@@ -0,0 +1,481 @@
+use vector_math::{*};
+use std::cmp::Ordering;
+use std::collections::{BinaryHeap, HashMap};
+use crate::{IndexedMesh, NormalMode};
+
+#[derive(Clone, Copy)]
+pub struct SimplifyOptions {
+    // Stop once the mesh has at most this many triangles
+    pub target_triangles: usize,
+
+    // Stop before collapses that would move the surface further than this (roughly, as a distance)
+    pub max_error: Scalar,
+
+    // How to rebuild normals afterwards. Flat keeps hard edges, the smooth modes smooth over them.
+    pub normals: NormalMode,
+}
+
+impl Default for SimplifyOptions {
+    fn default() -> SimplifyOptions {
+        return SimplifyOptions {
+            target_triangles: 0,
+            max_error: Scalar::INFINITY,
+            normals: NormalMode::SmoothAngle,
+        };
+    }
+}
+
+// Boundary edges get a plane perpendicular to their face with this weight, so they keep their shape
+const BOUNDARY_WEIGHT: f64 = 100.0;
+
+// Symmetric 4x4 matrix of a sum of squared distances to planes, upper triangle only
+#[derive(Clone, Copy, Default)]
+struct Quadric([f64; 10]);
+
+impl Quadric {
+    fn from_plane(n: [f64; 3], d: f64, weight: f64) -> Quadric {
+        let [a, b, c] = n;
+        return Quadric([
+            a * a, a * b, a * c, a * d,
+                   b * b, b * c, b * d,
+                          c * c, c * d,
+                                 d * d,
+        ].map(|v| v * weight));
+    }
+
+    fn add(&mut self, other: &Quadric) {
+        for idx in 0..10 {
+            self.0[idx] += other.0[idx];
+        }
+    }
+
+    fn error(&self, p: Vec3) -> f64 {
+        let q = &self.0;
+        let (x, y, z) = (p.x() as f64, p.y() as f64, p.z() as f64);
+        return q[0] * x * x + 2.0 * q[1] * x * y + 2.0 * q[2] * x * z + 2.0 * q[3] * x
+            + q[4] * y * y + 2.0 * q[5] * y * z + 2.0 * q[6] * y
+            + q[7] * z * z + 2.0 * q[8] * z
+            + q[9];
+    }
+}
+
+fn plane_quadric(normal: Vec3, point: Vec3, weight: f64) -> Quadric {
+    let n = [normal.x() as f64, normal.y() as f64, normal.z() as f64];
+    let d = -(n[0] * point.x() as f64 + n[1] * point.y() as f64 + n[2] * point.z() as f64);
+    return Quadric::from_plane(n, d, weight);
+}
+
+// A possible collapse of vertex "from" onto vertex "to", valid as long as neither changed since
+struct Collapse {
+    cost: f64,
+    from: usize,
+    to: usize,
+    from_version: u32,
+    to_version: u32,
+}
+
+impl PartialEq for Collapse {
+    fn eq(&self, other: &Collapse) -> bool {
+        return self.cost == other.cost;
+    }
+}
+
+impl Eq for Collapse {}
+
+impl PartialOrd for Collapse {
+    fn partial_cmp(&self, other: &Collapse) -> Option<Ordering> {
+        return Some(self.cmp(other));
+    }
+}
+
+// Reversed, so the binary heap pops the cheapest collapse first
+impl Ord for Collapse {
+    fn cmp(&self, other: &Collapse) -> Ordering {
+        return other.cost.total_cmp(&self.cost);
+    }
+}
+
+// Working state of a simplification. Vertices are the mesh vertices, merged where they only differ in their
+// normal, since normals get rebuilt afterwards. Positions are groups of vertices at the same position, more
+// than one where texture coordinates or colors differ (uv seams).
+struct Simplifier {
+    points: Vec<Vec3>,
+    representatives: Vec<usize>,
+    triangles: Vec<[usize; 3]>,
+    alive: Vec<bool>,
+    live_count: usize,
+    vertex_triangles: Vec<Vec<usize>>,
+    position_of: Vec<usize>,
+    position_vertices: Vec<Vec<usize>>,
+    quadrics: Vec<Quadric>,
+    locked: Vec<bool>,
+    border: Vec<bool>,
+    versions: Vec<u32>,
+    heap: BinaryHeap<Collapse>,
+}
+
+fn face_normal(p: [Vec3; 3]) -> Vec3 {
+    return (p[1] - p[0]).cross(p[2] - p[0]);
+}
+
+fn bits(v: Vec3) -> [u32; 3] {
+    return [v.x().to_bits(), v.y().to_bits(), v.z().to_bits()];
+}
+
+impl Simplifier {
+    fn new(mesh: &IndexedMesh) -> Simplifier {
+        // Merge vertices that only differ in their normal, keeping the first as the one to copy from
+        let zero = Vec3::new(0.0, 0.0, 0.0);
+        let mut vertex_ids: HashMap<[[u32; 3]; 3], usize> = HashMap::new();
+        let mut representatives = Vec::new();
+        let vertex_of: Vec<usize> = (0..mesh.vertex_count()).map(|idx| {
+            let t = mesh.texcoords.as_ref().map_or(zero, |t| t[idx]);
+            let c = mesh.colors.as_ref().map_or(zero, |c| c[idx]);
+            return *vertex_ids.entry([bits(mesh.positions[idx]), bits(t), bits(c)]).or_insert_with(|| {
+                representatives.push(idx);
+                return representatives.len() - 1;
+            });
+        }).collect();
+        let points: Vec<Vec3> = representatives.iter().map(|&idx| mesh.positions[idx]).collect();
+
+        let mut position_ids: HashMap<[u32; 3], usize> = HashMap::new();
+        let mut position_vertices: Vec<Vec<usize>> = Vec::new();
+        let position_of: Vec<usize> = points.iter().enumerate().map(|(idx, &p)| {
+            let id = *position_ids.entry(bits(p)).or_insert_with(|| {
+                position_vertices.push(Vec::new());
+                return position_vertices.len() - 1;
+            });
+            position_vertices[id].push(idx);
+            return id;
+        }).collect();
+
+        let triangles: Vec<[usize; 3]> = mesh.indices.iter().map(|tri| tri.map(|idx| vertex_of[idx as usize])).collect();
+        let mut vertex_triangles = vec![Vec::new(); points.len()];
+        for (tri_idx, tri) in triangles.iter().enumerate() {
+            for &idx in tri {
+                vertex_triangles[idx].push(tri_idx);
+            }
+        }
+
+        let vertex_count = points.len();
+        let mut simplifier = Simplifier {
+            points: points,
+            representatives: representatives,
+            alive: vec![true; triangles.len()],
+            live_count: triangles.len(),
+            triangles: triangles,
+            vertex_triangles: vertex_triangles,
+            quadrics: vec![Quadric::default(); position_vertices.len()],
+            locked: vec![false; position_vertices.len()],
+            border: vec![false; position_vertices.len()],
+            position_of: position_of,
+            position_vertices: position_vertices,
+            versions: vec![0; vertex_count],
+            heap: BinaryHeap::new(),
+        };
+        simplifier.classify();
+        return simplifier;
+    }
+
+    // Sets up quadrics, and finds border positions (on edges with a single triangle) and locked positions
+    // (uv seams and non-manifold edges)
+    fn classify(&mut self) {
+        let mut edge_triangles: HashMap<(usize, usize), Vec<usize>> = HashMap::new();
+        for (tri_idx, tri) in self.triangles.iter().enumerate() {
+            let p = tri.map(|idx| self.points[idx]);
+            let normal = face_normal(p);
+            if normal.length() > 0.0 {
+                let quadric = plane_quadric(normal.normalized(), p[0], 1.0);
+                for &idx in tri {
+                    self.quadrics[self.position_of[idx]].add(&quadric);
+                }
+            }
+            for corner in 0..3 {
+                let (a, b) = (self.position_of[tri[corner]], self.position_of[tri[(corner + 1) % 3]]);
+                edge_triangles.entry((a.min(b), a.max(b))).or_default().push(tri_idx);
+            }
+        }
+
+        for (&(a, b), edge_tris) in &edge_triangles {
+            if edge_tris.len() == 1 {
+                self.border[a] = true;
+                self.border[b] = true;
+                let tri = self.triangles[edge_tris[0]].map(|idx| self.points[idx]);
+                let (pa, pb) = (self.points[self.position_vertices[a][0]], self.points[self.position_vertices[b][0]]);
+                let edge_normal = (pb - pa).cross(face_normal(tri));
+                if edge_normal.length() > 0.0 {
+                    let quadric = plane_quadric(edge_normal.normalized(), pa, BOUNDARY_WEIGHT);
+                    self.quadrics[a].add(&quadric);
+                    self.quadrics[b].add(&quadric);
+                }
+            }
+            else if edge_tris.len() > 2 {
+                self.locked[a] = true;
+                self.locked[b] = true;
+            }
+        }
+        // Vertices split only by normals are merged by now, so more than one vertex means a seam
+        for position in 0..self.position_vertices.len() {
+            if self.position_vertices[position].len() > 1 {
+                self.locked[position] = true;
+            }
+        }
+    }
+
+    fn live_triangles(&self, vertex: usize) -> impl Iterator<Item = usize> + '_ {
+        return self.vertex_triangles[vertex].iter().copied().filter(|&tri_idx| self.alive[tri_idx]);
+    }
+
+    // Positions connected to a position by live triangles
+    fn neighbor_positions(&self, position: usize) -> Vec<usize> {
+        let mut neighbors = Vec::new();
+        for &vertex in &self.position_vertices[position] {
+            for tri_idx in self.live_triangles(vertex) {
+                for &idx in &self.triangles[tri_idx] {
+                    let other = self.position_of[idx];
+                    if other != position && !neighbors.contains(&other) {
+                        neighbors.push(other);
+                    }
+                }
+            }
+        }
+        return neighbors;
+    }
+
+    // Number of live triangles with an edge between two positions
+    fn edge_triangle_count(&self, a: usize, b: usize) -> usize {
+        return self.position_vertices[a].iter().flat_map(|&vertex| self.live_triangles(vertex))
+            .filter(|&tri_idx| self.triangles[tri_idx].iter().any(|&idx| self.position_of[idx] == b))
+            .count();
+    }
+
+    fn allowed(&self, from: usize, to: usize) -> bool {
+        let (from_pos, to_pos) = (self.position_of[from], self.position_of[to]);
+        if from_pos == to_pos || self.locked[from_pos] {
+            return false;
+        }
+        // Border vertices may only slide along the border
+        if self.border[from_pos] {
+            return self.border[to_pos] && self.edge_triangle_count(from_pos, to_pos) == 1;
+        }
+        return true;
+    }
+
+    fn push_collapse(&mut self, from: usize, to: usize) {
+        if !self.allowed(from, to) {
+            return;
+        }
+        let mut quadric = self.quadrics[self.position_of[from]];
+        quadric.add(&self.quadrics[self.position_of[to]]);
+        self.heap.push(Collapse {
+            cost: quadric.error(self.points[to]).max(0.0),
+            from: from,
+            to: to,
+            from_version: self.versions[from],
+            to_version: self.versions[to],
+        });
+    }
+
+    fn push_vertex_collapses(&mut self, vertex: usize) {
+        let neighbors: Vec<usize> = self.live_triangles(vertex).flat_map(|tri_idx| self.triangles[tri_idx]).filter(|&idx| idx != vertex).collect();
+        for other in neighbors {
+            self.push_collapse(vertex, other);
+            self.push_collapse(other, vertex);
+        }
+    }
+
+    // Checks that a collapse keeps the mesh manifold and doesn't fold any triangle over
+    fn valid(&self, from: usize, to: usize) -> bool {
+        let (from_pos, to_pos) = (self.position_of[from], self.position_of[to]);
+        let to_neighbors = self.neighbor_positions(to_pos);
+        let shared = self.neighbor_positions(from_pos).iter().filter(|position| to_neighbors.contains(position)).count();
+        if shared != self.edge_triangle_count(from_pos, to_pos) {
+            return false;
+        }
+
+        let to_point = self.points[to];
+        for tri_idx in self.live_triangles(from) {
+            let tri = self.triangles[tri_idx];
+            if tri.iter().any(|&idx| self.position_of[idx] == to_pos) {
+                continue;
+            }
+            let old = tri.map(|idx| self.points[idx]);
+            let new = tri.map(|idx| if idx == from { to_point } else { self.points[idx] });
+            let (old_normal, new_normal) = (face_normal(old), face_normal(new));
+            if new_normal.length() == 0.0 || (old_normal & new_normal) <= 0.0 {
+                return false;
+            }
+        }
+        return true;
+    }
+
+    fn collapse(&mut self, from: usize, to: usize) {
+        let to_pos = self.position_of[to];
+        for tri_idx in std::mem::take(&mut self.vertex_triangles[from]) {
+            if !self.alive[tri_idx] {
+                continue;
+            }
+            if self.triangles[tri_idx].iter().any(|&idx| self.position_of[idx] == to_pos) {
+                self.alive[tri_idx] = false;
+                self.live_count -= 1;
+                continue;
+            }
+            for idx in self.triangles[tri_idx].iter_mut() {
+                if *idx == from {
+                    *idx = to;
+                }
+            }
+            self.vertex_triangles[to].push(tri_idx);
+        }
+        let from_quadric = self.quadrics[self.position_of[from]];
+        self.quadrics[to_pos].add(&from_quadric);
+
+        // Only costs involving the merged quadric change, everything else gets checked again when popped
+        self.versions[from] += 1;
+        self.versions[to] += 1;
+        self.push_vertex_collapses(to);
+    }
+
+    fn run(&mut self, options: &SimplifyOptions) {
+        for vertex in 0..self.points.len() {
+            self.push_vertex_collapses(vertex);
+        }
+        let max_cost = (options.max_error as f64) * (options.max_error as f64);
+        while self.live_count > options.target_triangles {
+            let collapse = match self.heap.pop() {
+                Some(collapse) => collapse,
+                None => break,
+            };
+            if collapse.from_version != self.versions[collapse.from] || collapse.to_version != self.versions[collapse.to] {
+                continue;
+            }
+            if collapse.cost > max_cost {
+                break;
+            }
+            if self.valid(collapse.from, collapse.to) {
+                self.collapse(collapse.from, collapse.to);
+            }
+        }
+    }
+}
+
+impl IndexedMesh {
+    // Garland-Heckbert quadric error simplification, collapsing edges until the target triangle count or
+    // the error limit is reached. Collapses use endpoint placement: a vertex moves onto one of its neighbors
+    // instead of to the optimal point of the quadric, so texture coordinates and colors stay exact. Vertices
+    // where texture coordinates or colors differ (uv seams) stay in place, boundary vertices only move along
+    // the boundary. Normals don't split vertices; they get rebuilt afterwards with options.normals.
+    pub fn simplify(&self, options: &SimplifyOptions) -> IndexedMesh {
+        let mut simplifier = Simplifier::new(self);
+        simplifier.run(options);
+
+        let mut out = IndexedMesh {
+            positions: Vec::new(),
+            normals: Vec::new(),
+            texcoords: self.texcoords.as_ref().map(|_| Vec::new()),
+            colors: self.colors.as_ref().map(|_| Vec::new()),
+            indices: Vec::new(),
+            triangle_materials: Vec::new(),
+        };
+        let mut remap: Vec<Option<u32>> = vec![None; simplifier.points.len()];
+        for (tri_idx, tri) in simplifier.triangles.iter().enumerate() {
+            if !simplifier.alive[tri_idx] {
+                continue;
+            }
+            let tri_indices = tri.map(|vertex| *remap[vertex].get_or_insert_with(|| {
+                let source = simplifier.representatives[vertex];
+                out.positions.push(self.positions[source]);
+                out.normals.push(self.normals[source]);
+                if let (Some(texcoords), Some(source_texcoords)) = (out.texcoords.as_mut(), self.texcoords.as_ref()) {
+                    texcoords.push(source_texcoords[source]);
+                }
+                if let (Some(colors), Some(source_colors)) = (out.colors.as_mut(), self.colors.as_ref()) {
+                    colors.push(source_colors[source]);
+                }
+                return (out.positions.len() - 1) as u32;
+            }));
+            out.indices.push(tri_indices);
+            out.triangle_materials.push(self.triangle_materials[tri_idx]);
+        }
+        out.recompute_normals(options.normals);
+        return out;
+    }
+
+    // Levels of detail, starting with the mesh itself, each with about ratio times the triangles of the
+    // one before. Every level gets simplified from the full mesh, so errors don't add up over the chain.
+    // Stops early once simplification can't get any further within max_error.
+    pub fn lod_chain(&self, levels: usize, ratio: Scalar, max_error: Scalar, normals: NormalMode) -> Vec<IndexedMesh> {
+        let mut chain = vec![self.clone()];
+        let mut target = self.triangle_count() as Scalar;
+        while chain.len() < levels {
+            target *= ratio;
+            let next = self.simplify(&SimplifyOptions {
+                target_triangles: target as usize,
+                max_error: max_error,
+                normals: normals,
+            });
+            if next.triangle_count() >= chain.last().unwrap().triangle_count() {
+                break;
+            }
+            chain.push(next);
+        }
+        return chain;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::{HashMap, HashSet};
+    use crate::{NormalMode, shapes};
+    use crate::process::position_key;
+    use super::SimplifyOptions;
+
+    #[test]
+    fn flat_normals_dont_lock_vertices() {
+        // Flat normals split every vertex by triangle, which must not count as a seam
+        let mut mesh = shapes::plane(2.0, 2.0, 8, 8).to_indexed(true);
+        mesh.recompute_normals(NormalMode::Flat);
+        let simplified = mesh.simplify(&SimplifyOptions { target_triangles: 0, max_error: 1e-3, normals: NormalMode::Flat });
+        assert!(simplified.triangle_count() <= mesh.triangle_count() / 8, "{} triangles left", simplified.triangle_count());
+        assert_eq!(simplified.normals.len(), simplified.vertex_count());
+        for n in &simplified.normals {
+            assert!((n.y() - 1.0).abs() < 1e-5);
+        }
+    }
+
+    #[test]
+    fn uv_seams_stay() {
+        let mesh = shapes::cylinder(1.0, 2.0, 16, 4).to_indexed(true);
+        let texcoords = mesh.texcoords.as_ref().unwrap();
+        let mut first_texcoord = HashMap::new();
+        let mut seams = HashSet::new();
+        for (idx, &p) in mesh.positions.iter().enumerate() {
+            let t = position_key(texcoords[idx]);
+            if *first_texcoord.entry(position_key(p)).or_insert(t) != t {
+                seams.insert(position_key(p));
+            }
+        }
+        assert!(!seams.is_empty());
+
+        let simplified = mesh.simplify(&SimplifyOptions { target_triangles: 0, max_error: 1e-3, ..SimplifyOptions::default() });
+        assert!(simplified.triangle_count() < mesh.triangle_count());
+        let remaining: HashSet<[u32; 3]> = simplified.positions.iter().map(|&p| position_key(p)).collect();
+        assert!(seams.is_subset(&remaining));
+    }
+
+    #[test]
+    fn flat_normals_keep_hard_edges() {
+        let mesh = shapes::cube(2.0, 4).to_indexed(true);
+        let options = SimplifyOptions { target_triangles: 0, max_error: 1e-3, normals: NormalMode::Flat };
+        let simplified = mesh.simplify(&options);
+        assert!(simplified.triangle_count() < mesh.triangle_count());
+        for n in &simplified.normals {
+            let largest = n.x().abs().max(n.y().abs()).max(n.z().abs());
+            assert!((largest - 1.0).abs() < 1e-5, "{} {} {}", n.x(), n.y(), n.z());
+        }
+
+        // Smoothing rounds off the corners
+        let smoothed = mesh.simplify(&SimplifyOptions { normals: NormalMode::SmoothAngle, ..options });
+        assert!(smoothed.normals.iter().any(|n| n.x().abs() < 0.9 && n.y().abs() < 0.9 && n.z().abs() < 0.9));
+    }
+}
@@ -0,0 +1,336 @@
+use vector_math::{*};
+use std::collections::HashMap;
+use std::fs;
+use std::fs::File;
+use std::io;
+use std::io::{BufWriter, Write};
+use crate::{Mesh, ObjError, ObjErrorKind, ObjOptions, TriData};
+use crate::parse::{Corner, Face, LineTokens, ObjState, Statement, build_mesh};
+use crate::triangulate::polygon_normal;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum StlFormat {
+    Ascii,
+    Binary,
+}
+
+// Where normals come from: the facet normals in the file (computed from the triangle if they are zero),
+// or generated from the geometry according to ObjOptions::normals
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Default)]
+pub enum StlNormals {
+    #[default]
+    Facet,
+    Generate,
+}
+
+const BINARY_HEADER_SIZE: usize = 84;
+const BINARY_FACET_SIZE: usize = 50;
+
+// Collects triangles into an ObjState, with identical positions merged so that generated normals can be smooth
+struct StlBuilder {
+    state: ObjState,
+    faces: Vec<Face>,
+    known_positions: HashMap<[u32; 3], usize>,
+    normals: StlNormals,
+}
+
+impl StlBuilder {
+    fn new(normals: StlNormals) -> StlBuilder {
+        return StlBuilder {
+            state: ObjState::new(),
+            faces: Vec::new(),
+            known_positions: HashMap::new(),
+            normals: normals,
+        };
+    }
+
+    fn position_index(&mut self, p: Vec3) -> usize {
+        let key = [p.x().to_bits(), p.y().to_bits(), p.z().to_bits()];
+        if let Some(&idx) = self.known_positions.get(&key) {
+            return idx;
+        }
+        self.state.vertices.push(p);
+        self.state.colors.push(None);
+        self.known_positions.insert(key, self.state.vertices.len() - 1);
+        return self.state.vertices.len() - 1;
+    }
+
+    fn add_facet(&mut self, normal: Vec3, points: &[Vec3]) {
+        let use_facet_normal = self.normals == StlNormals::Facet && normal.length() > 0.0;
+        let n = if use_facet_normal {
+            self.state.normals.push(normal.normalized());
+            Some(self.state.normals.len() - 1)
+        }
+        else {
+            None
+        };
+        let corners = points.iter().map(|&p| Corner {
+            p: self.position_index(p),
+            t: None,
+            n: n,
+        }).collect();
+        self.state.smoothing_group = if self.normals == StlNormals::Generate { 1 } else { 0 };
+        let face = self.state.face(corners);
+        self.faces.push(face);
+    }
+}
+
+// Every "solid" becomes its own sub-mesh, named like an obj object. Facets with more than three vertices are
+// taken as polygons and get triangulated.
+fn parse_ascii(contents: &str, options: &ObjOptions, normals: StlNormals) -> Result<Mesh, ObjError> {
+    let mut builder = StlBuilder::new(normals);
+    let mut warnings = Vec::new();
+    let mut facet: Option<(Vec3, Vec<Vec3>, usize)> = None;
+    for (line_idx, line) in contents.lines().enumerate() {
+        let line_num = line_idx + 1;
+        let mut tokens = LineTokens::new(line_num, line);
+        let (column, keyword) = match tokens.next() {
+            Some(token) => token,
+            None => continue,
+        };
+        let result = match (keyword, facet.as_mut()) {
+            ("solid", None) => {
+                let name = tokens.rest().to_string();
+                builder.state.apply(line_num, Statement::Object(name)).map(|_| ())
+            },
+            ("endsolid", None) => Ok(()),
+            ("facet", None) => match tokens.next() {
+                Some((_, "normal")) => tokens.next_vec3().map(|normal| facet = Some((normal, Vec::new(), line_num))),
+                _ => Err(tokens.missing(ObjErrorKind::MissingIndex)),
+            },
+            ("outer", Some(_)) | ("endloop", Some(_)) => Ok(()),
+            ("vertex", Some((_, points, _))) => tokens.next_vec3().map(|p| points.push(p)),
+            ("endfacet", Some((normal, points, start_line))) => {
+                let result = if points.len() < 3 {
+                    Err(ObjError { line: *start_line, column: 1, text: "facet".to_string(), kind: ObjErrorKind::MissingIndex })
+                }
+                else {
+                    builder.add_facet(*normal, points);
+                    Ok(())
+                };
+                facet = None;
+                result
+            },
+            _ => Err(tokens.error(column, keyword, ObjErrorKind::UnsupportedDirective)),
+        };
+        if let Err(error) = result {
+            if !options.lenient {
+                return Err(error);
+            }
+            warnings.push(error);
+        }
+    }
+    if facet.is_some() {
+        let error = ObjError { line: contents.lines().count(), column: 1, text: "endfacet".to_string(), kind: ObjErrorKind::UnexpectedEnd };
+        if !options.lenient {
+            return Err(error);
+        }
+        warnings.push(error);
+    }
+    return Ok(build_mesh(builder.state, builder.faces, warnings, options));
+}
+
+fn read_vec3(bytes: &[u8]) -> Vec3 {
+    let value = |idx: usize| f32::from_le_bytes([bytes[idx * 4], bytes[idx * 4 + 1], bytes[idx * 4 + 2], bytes[idx * 4 + 3]]) as Scalar;
+    return Vec3::new(value(0), value(1), value(2));
+}
+
+// Size of a binary file with this many facets, if it fits in a usize
+fn binary_size(facet_count: usize) -> Option<usize> {
+    return facet_count.checked_mul(BINARY_FACET_SIZE)?.checked_add(BINARY_HEADER_SIZE);
+}
+
+// The facet count has to match the file size. In lenient mode, a file that is too short is read as far as
+// it goes, and trailing bytes are ignored, both with a warning.
+fn parse_binary(contents: &[u8], options: &ObjOptions, normals: StlNormals) -> Result<Mesh, ObjError> {
+    if contents.len() < BINARY_HEADER_SIZE {
+        return Err(ObjError { line: 0, column: 0, text: format!("{} bytes", contents.len()), kind: ObjErrorKind::UnexpectedEnd });
+    }
+    let facet_count = u32::from_le_bytes([contents[80], contents[81], contents[82], contents[83]]) as usize;
+    let available = (contents.len() - BINARY_HEADER_SIZE) / BINARY_FACET_SIZE;
+    let mut warnings = Vec::new();
+    if binary_size(facet_count) != Some(contents.len()) {
+        let error = ObjError {
+            line: 0,
+            column: 0,
+            text: format!("{} facets in a file of {} bytes", facet_count, contents.len()),
+            kind: if available < facet_count { ObjErrorKind::UnexpectedEnd } else { ObjErrorKind::BadHeader },
+        };
+        if !options.lenient {
+            return Err(error);
+        }
+        warnings.push(error);
+    }
+
+    let mut builder = StlBuilder::new(normals);
+    for facet_idx in 0..facet_count.min(available) {
+        let facet = &contents[BINARY_HEADER_SIZE + facet_idx * BINARY_FACET_SIZE..];
+        let points = [read_vec3(&facet[12..]), read_vec3(&facet[24..]), read_vec3(&facet[36..])];
+        builder.add_facet(read_vec3(facet), &points);
+    }
+    return Ok(build_mesh(builder.state, builder.faces, warnings, options));
+}
+
+// Binary files are recognized by their size matching the facet count, since plenty of exporters start
+// binary headers with "solid" as well
+fn is_binary(contents: &[u8]) -> bool {
+    if contents.len() >= BINARY_HEADER_SIZE {
+        let facet_count = u32::from_le_bytes([contents[80], contents[81], contents[82], contents[83]]) as usize;
+        if binary_size(facet_count) == Some(contents.len()) {
+            return true;
+        }
+    }
+    let start = contents.iter().position(|byte| !byte.is_ascii_whitespace()).unwrap_or(contents.len());
+    return !(contents[start..].starts_with(b"solid") && std::str::from_utf8(contents).is_ok());
+}
+
+// Reads an ascii or binary stl file into the same mesh as parse_obj would give
+pub fn parse_stl_with(contents: &[u8], options: &ObjOptions, normals: StlNormals) -> Result<Mesh, ObjError> {
+    if is_binary(contents) {
+        return parse_binary(contents, options, normals);
+    }
+    return parse_ascii(std::str::from_utf8(contents).unwrap(), options, normals);
+}
+
+pub fn parse_stl(contents: &[u8]) -> Result<Mesh, ObjError> {
+    return parse_stl_with(contents, &ObjOptions::default(), StlNormals::default());
+}
+
+pub fn read_stl_with(path: &str, options: &ObjOptions, normals: StlNormals) -> Result<Mesh, ObjError> {
+    let contents = fs::read(path).map_err(|error| ObjError {
+        line: 0,
+        column: 0,
+        text: format!("{}: {}", path, error),
+        kind: ObjErrorKind::FileRead,
+    })?;
+    return parse_stl_with(&contents, options, normals);
+}
+
+pub fn read_stl(path: &str) -> Result<Mesh, ObjError> {
+    return read_stl_with(path, &ObjOptions::default(), StlNormals::default());
+}
+
+// Writes triangles (of a Mesh, or IndexedMesh::to_triangles) as stl. Facet normals are computed from the
+// triangles, which get flipped back like write_obj_to does.
+pub fn write_stl_to<W: Write>(out: &mut W, triangles: &[TriData], name: &str, format: StlFormat) -> io::Result<()> {
+    let facets = triangles.iter().map(|tri| {
+        let points = [tri.p[2], tri.p[1], tri.p[0]];
+        let normal = polygon_normal(&points);
+        let normal = if normal.length() > 0.0 { normal.normalized() } else { normal };
+        return (normal, points);
+    });
+
+    if format == StlFormat::Ascii {
+        writeln!(out, "solid {}", name)?;
+        for (normal, points) in facets {
+            writeln!(out, "  facet normal {} {} {}", normal.x(), normal.y(), normal.z())?;
+            writeln!(out, "    outer loop")?;
+            for p in points {
+                writeln!(out, "      vertex {} {} {}", p.x(), p.y(), p.z())?;
+            }
+            writeln!(out, "    endloop")?;
+            writeln!(out, "  endfacet")?;
+        }
+        writeln!(out, "endsolid {}", name)?;
+        return Ok(());
+    }
+
+    // The header must not start with "solid", or some readers take the file for ascii
+    let mut header = [b' '; 80];
+    let title = format!("binary stl {}", name);
+    let title_len = title.len().min(80);
+    header[..title_len].copy_from_slice(&title.as_bytes()[..title_len]);
+    out.write_all(&header)?;
+    out.write_all(&(triangles.len() as u32).to_le_bytes())?;
+    for (normal, points) in facets {
+        for v in [normal, points[0], points[1], points[2]] {
+            for value in [v.x(), v.y(), v.z()] {
+                out.write_all(&(value as f32).to_le_bytes())?;
+            }
+        }
+        out.write_all(&[0, 0])?;
+    }
+    return Ok(());
+}
+
+pub fn write_stl(path: &str, triangles: &[TriData], format: StlFormat) -> io::Result<()> {
+    let name = std::path::Path::new(path).file_stem().map(|stem| stem.to_string_lossy().to_string()).unwrap_or_default();
+    let mut out = BufWriter::new(File::create(path)?);
+    write_stl_to(&mut out, triangles, &name, format)?;
+    return out.flush();
+}
+
+#[cfg(test)]
+mod tests {
+    use vector_math::{*};
+    use crate::{ObjErrorKind, icosphere};
+    use crate::process::triangle_normal;
+    use super::{StlFormat, parse_stl, write_stl_to};
+
+    fn xyz(v: Vec3) -> [Scalar; 3] {
+        return [v.x(), v.y(), v.z()];
+    }
+
+    #[test]
+    fn reads_tab_indented_ascii() {
+        let stl = "solid\tpart one\n\tfacet normal 0 0 1\n\t\touter loop\n\t\t\tvertex 0 0 0\n\t\t\tvertex 1\t0 0\n\t\t\tvertex 0 1 0\n\t\tendloop\n\tendfacet\nendsolid\tpart one\n";
+        let mesh = parse_stl(stl.as_bytes()).unwrap();
+        assert_eq!(mesh.triangles.len(), 1);
+        assert_eq!(mesh.sub_meshes[0].object, "part one");
+    }
+
+    #[test]
+    fn huge_facet_counts_are_rejected() {
+        let mut stl = vec![0u8; 80];
+        stl.extend_from_slice(&u32::MAX.to_le_bytes());
+        stl.extend_from_slice(&[0; 50]);
+        let error = parse_stl(&stl).err().unwrap();
+        assert!(error.kind == ObjErrorKind::UnexpectedEnd);
+    }
+
+    #[test]
+    fn binary_header_may_start_with_solid() {
+        // Made of bytes that are valid utf-8 as well, so only the size gives it away: zero for the normal,
+        // which then gets computed, and corners at 0 and 2 (0x40000000)
+        let mut stl = vec![b' '; 80];
+        stl[..11].copy_from_slice(b"solid thing");
+        stl.extend_from_slice(&1u32.to_le_bytes());
+        stl.extend_from_slice(&[0; 12]);
+        for corner in [[0.0f32, 0.0, 0.0], [2.0, 0.0, 0.0], [0.0, 2.0, 0.0]] {
+            for value in corner {
+                stl.extend_from_slice(&value.to_le_bytes());
+            }
+        }
+        stl.extend_from_slice(&[0, 0]);
+        assert!(std::str::from_utf8(&stl).is_ok());
+
+        let mesh = parse_stl(&stl).unwrap();
+        assert_eq!(mesh.triangles.len(), 1);
+        let tri = &mesh.triangles[0];
+        let mut corners = tri.p.map(xyz);
+        corners.sort_by(|a, b| a.partial_cmp(b).unwrap());
+        assert_eq!(corners, [[0.0, 0.0, 0.0], [0.0, 2.0, 0.0], [2.0, 0.0, 0.0]]);
+        assert_eq!(tri.n.map(xyz), [[0.0, 0.0, 1.0]; 3]);
+    }
+
+    #[test]
+    fn round_trips_through_parse_stl() {
+        let mesh = icosphere(1.5, 1);
+        for format in [StlFormat::Ascii, StlFormat::Binary] {
+            let mut out = Vec::new();
+            write_stl_to(&mut out, &mesh.triangles, "ball", format).unwrap();
+            assert_eq!(out.starts_with(b"solid ball\n"), format == StlFormat::Ascii);
+            let back = parse_stl(&out).unwrap();
+
+            // Same corners in the same order, so the winding and facing are the same too
+            assert_eq!(back.triangles.len(), mesh.triangles.len());
+            for (a, b) in mesh.triangles.iter().zip(back.triangles.iter()) {
+                assert_eq!(a.p.map(xyz), b.p.map(xyz));
+                let face_normal = triangle_normal(a.p).normalized();
+                for n in b.n {
+                    assert!((n & face_normal) > 0.999);
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,286 @@
+use std::collections::VecDeque;
+use std::io::BufRead;
+use crate::{Material, Mesh, ObjError, ObjErrorKind, ObjOptions, Point, Polyline, SubMesh, TriData};
+use crate::normals::{NormalMode, generate_normals};
+use crate::parse::{ObjState, Statement, continuation, obj_lines, parse_statement, build_mesh, polygon_triangles};
+
+// Incremental obj reader that yields triangles as it goes, holding on to the vertex data but not the file
+// or the triangles. Since smooth normals need the whole mesh, faces without normals always get flat ones,
+// and options asking for smooth ones get a warning saying so.
+// In lenient mode, bad lines end up in warnings(), otherwise the first error ends the stream.
+pub struct ObjStream<R: BufRead> {
+    reader: R,
+    line: String,
+    line_num: usize,
+    state: ObjState,
+    pending: VecDeque<TriData>,
+    warnings: Vec<ObjError>,
+    lenient: bool,
+    done: bool,
+}
+
+impl<R: BufRead> ObjStream<R> {
+    pub fn new(reader: R, options: &ObjOptions) -> ObjStream<R> {
+        let mut warnings = Vec::new();
+        if options.normals != NormalMode::Flat {
+            warnings.push(ObjError {
+                line: 0,
+                column: 0,
+                text: format!("normals: {:?}, streamed faces get flat normals", options.normals),
+                kind: ObjErrorKind::UnsupportedOption,
+            });
+        }
+        return ObjStream {
+            reader: reader,
+            line: String::new(),
+            line_num: 0,
+            state: ObjState::new(),
+            pending: VecDeque::new(),
+            warnings: warnings,
+            lenient: options.lenient,
+            done: false,
+        };
+    }
+
+    // Materials (names only, see load_materials), libraries and sub-meshes seen so far. Sub-mesh triangle
+    // ranges are not filled in, since streamed triangles are in file order.
+    pub fn materials(&self) -> &Vec<Material> {
+        return &self.state.materials;
+    }
+
+    pub fn material_libs(&self) -> &Vec<String> {
+        return &self.state.material_libs;
+    }
+
+    pub fn sub_meshes(&self) -> &Vec<SubMesh> {
+        return &self.state.sub_meshes;
+    }
+
+    // Lines and points seen so far
+    pub fn polylines(&self) -> &Vec<Polyline> {
+        return &self.state.polylines;
+    }
+
+    pub fn points(&self) -> &Vec<Point> {
+        return &self.state.points;
+    }
+
+    pub fn warnings(&self) -> &Vec<ObjError> {
+        return &self.warnings;
+    }
+
+    // Appends the next line to self.line, without its line ending. Returns false at the end of the file.
+    fn read_line(&mut self) -> Result<bool, ObjError> {
+        let read = self.reader.read_line(&mut self.line).map_err(|error| ObjError {
+            line: self.line_num + 1,
+            column: 0,
+            text: error.to_string(),
+            kind: ObjErrorKind::FileRead,
+        })?;
+        if read == 0 {
+            return Ok(false);
+        }
+        self.line_num += 1;
+        let line_end = self.line.trim_end_matches(&['\n', '\r'][..]).len();
+        self.line.truncate(line_end);
+        return Ok(true);
+    }
+
+    // Reads lines until one produces triangles, returns false at the end of the file
+    fn read_face(&mut self) -> Result<bool, ObjError> {
+        loop {
+            self.line.clear();
+            if !self.read_line()? {
+                return Ok(false);
+            }
+            let line_num = self.line_num;
+            while let Some(end) = continuation(&self.line) {
+                self.line.truncate(end);
+                self.line.push(' ');
+                if !self.read_line()? {
+                    break;
+                }
+            }
+
+            let result = parse_statement(line_num, &self.line).and_then(|statement| self.state.apply(line_num, statement));
+            match result {
+                Ok(Some(face)) => {
+                    let face_normals = generate_normals(&self.state.vertices, std::slice::from_ref(&face), NormalMode::Flat).pop().unwrap();
+                    let polygon = self.state.polygon(&face, face_normals);
+                    self.pending.extend(polygon_triangles(&polygon, face.material));
+                    return Ok(true);
+                },
+                Ok(None) => {},
+                Err(error) if self.lenient => self.warnings.push(error),
+                Err(error) => return Err(error),
+            }
+        }
+    }
+}
+
+impl<R: BufRead> Iterator for ObjStream<R> {
+    type Item = Result<TriData, ObjError>;
+
+    fn next(&mut self) -> Option<Result<TriData, ObjError>> {
+        while self.pending.is_empty() {
+            if self.done {
+                return None;
+            }
+            match self.read_face() {
+                Ok(true) => {},
+                Ok(false) => self.done = true,
+                Err(error) => {
+                    self.done = true;
+                    return Some(Err(error));
+                }
+            }
+        }
+        return self.pending.pop_front().map(Ok);
+    }
+}
+
+// Streams all triangles of an obj file to a callback, returning the warnings (see ObjStream)
+pub fn stream_obj<R: BufRead, F: FnMut(TriData)>(reader: R, options: &ObjOptions, mut callback: F) -> Result<Vec<ObjError>, ObjError> {
+    let mut stream = ObjStream::new(reader, options);
+    for tri in stream.by_ref() {
+        callback(tri?);
+    }
+    return Ok(stream.warnings);
+}
+
+// Whether the last line of some text (without its line ending) continues onto the next one
+fn continues(text: &str) -> bool {
+    let line_start = text.rfind('\n').map_or(0, |idx| idx + 1);
+    return continuation(&text[line_start..]).is_some();
+}
+
+// Parses chunks of lines on several threads, then applies them in order. Indices get resolved in that second
+// step, so relative indices and state like usemtl carry over between chunks just like they would in one pass.
+pub fn parse_obj_parallel(contents: &str, options: &ObjOptions) -> Result<Mesh, ObjError> {
+    // Split at line boundaries, but not after a line that continues onto the next one
+    let mut chunks = Vec::new();
+    let chunk_size = contents.len() / options.threads + 1;
+    let mut chunk_start = 0;
+    while chunk_start < contents.len() {
+        let mut chunk_end = (chunk_start + chunk_size).min(contents.len());
+        while chunk_end < contents.len() && (contents.as_bytes()[chunk_end - 1] != b'\n' || continues(&contents[..chunk_end - 1])) {
+            chunk_end += 1;
+        }
+        chunks.push(&contents[chunk_start..chunk_end]);
+        chunk_start = chunk_end;
+    }
+
+    // Parse lines, numbered from the start of each chunk for now
+    let mut parsed_chunks: Vec<Vec<(usize, Result<Statement, ObjError>)>> = Vec::new();
+    std::thread::scope(|scope| {
+        let handles: Vec<_> = chunks.iter().map(|chunk| scope.spawn(move || {
+            return obj_lines(chunk).map(|(line_num, line)| (line_num, parse_statement(line_num, &line))).collect::<Vec<_>>();
+        })).collect();
+        for handle in handles {
+            parsed_chunks.push(handle.join().expect("Threading issue"));
+        }
+    });
+
+    // Apply in order
+    let mut state = ObjState::new();
+    let mut faces = Vec::new();
+    let mut warnings = Vec::new();
+    let mut line_offset = 0;
+    for (chunk, parsed_chunk) in chunks.iter().zip(parsed_chunks) {
+        for (chunk_line_num, parsed) in parsed_chunk {
+            let line_num = line_offset + chunk_line_num;
+            let result = parsed.map_err(|mut error| {
+                error.line += line_offset;
+                error
+            }).and_then(|statement| state.apply(line_num, statement));
+            match result {
+                Ok(Some(face)) => faces.push(face),
+                Ok(None) => {},
+                Err(error) if options.lenient => warnings.push(error),
+                Err(error) => return Err(error),
+            }
+        }
+        line_offset += chunk.lines().count();
+    }
+    return Ok(build_mesh(state, faces, warnings, options));
+}
+
+#[cfg(test)]
+mod tests {
+    use vector_math::{*};
+    use std::fmt::Write;
+    use crate::{NormalMode, ObjErrorKind, ObjOptions, TriData, parse_obj_with};
+    use super::{ObjStream, parse_obj_parallel, stream_obj};
+
+    // Objects, groups and materials changing every few lines, relative and absolute indices, some faces with
+    // normals, and lines continued with \, so that chunk boundaries land between all of them
+    fn generate_obj() -> String {
+        let mut obj = String::new();
+        for idx in 0..300 {
+            let x = idx as Scalar * 0.5;
+            if idx % 7 == 0 {
+                writeln!(obj, "o part{}", idx / 7).unwrap();
+            }
+            if idx % 5 == 0 {
+                writeln!(obj, "g group{}", idx % 3).unwrap();
+            }
+            if idx % 3 == 0 {
+                writeln!(obj, "usemtl material{}", idx % 4).unwrap();
+            }
+            writeln!(obj, "v {} 0 0\nv {} 1 0\nv {} 0 1\nvt {} 0\nvt 0 {}\nvt 1 1", x, x, x + 0.5, x, x).unwrap();
+            match idx % 4 {
+                0 => writeln!(obj, "f -3/-3 -2/-2 -1/-1").unwrap(),
+                1 => writeln!(obj, "f -3/-3 \\\n  -2/-2 \\\n-1/-1").unwrap(),
+                2 => writeln!(obj, "vn 0 0 1\nf {0}/{0}/-1 {1}/{1}/-1 {2}/{2}/-1", 3 * idx + 1, 3 * idx + 2, 3 * idx + 3).unwrap(),
+                _ => writeln!(obj, "f -1 -2 -3 \\\n-6").unwrap(),
+            }
+        }
+        return obj;
+    }
+
+    fn key(tri: &TriData) -> Vec<u32> {
+        let mut key: Vec<u32> = Vec::new();
+        for v in tri.p.iter().chain(tri.n.iter()).chain(tri.t.iter().flatten()) {
+            key.extend([v.x().to_bits(), v.y().to_bits(), v.z().to_bits()]);
+        }
+        key.push(tri.material.map_or(u32::MAX, |material| material as u32));
+        return key;
+    }
+
+    #[test]
+    fn parallel_parsing_matches_sequential() {
+        let obj = generate_obj();
+        let sequential = parse_obj_with(&obj, &ObjOptions::default()).unwrap();
+        let expected: Vec<Vec<u32>> = sequential.triangles.iter().map(key).collect();
+        let sub_meshes = |mesh: &crate::Mesh| mesh.sub_meshes.iter().map(|s| (s.name.clone(), s.object.clone(), s.triangles.clone())).collect::<Vec<_>>();
+        for threads in [2, 3, 5, 8, 13] {
+            let parallel = parse_obj_parallel(&obj, &ObjOptions { threads: threads, ..ObjOptions::default() }).unwrap();
+            assert_eq!(parallel.triangles.iter().map(key).collect::<Vec<_>>(), expected, "{} threads", threads);
+            assert_eq!(sub_meshes(&parallel), sub_meshes(&sequential));
+            let names = |mesh: &crate::Mesh| mesh.materials.iter().map(|m| m.name.clone()).collect::<Vec<_>>();
+            assert_eq!(names(&parallel), names(&sequential));
+        }
+    }
+
+    #[test]
+    fn streaming_matches_parsing() {
+        // Streamed triangles come in file order, parsed ones by sub-mesh
+        let obj = generate_obj();
+        let flat = ObjOptions { normals: NormalMode::Flat, ..ObjOptions::default() };
+        let mut expected: Vec<Vec<u32>> = parse_obj_with(&obj, &flat).unwrap().triangles.iter().map(key).collect();
+        let mut streamed = Vec::new();
+        let warnings = stream_obj(obj.as_bytes(), &flat, |tri| streamed.push(key(&tri))).unwrap();
+        assert!(warnings.is_empty());
+        expected.sort();
+        streamed.sort();
+        assert_eq!(streamed, expected);
+    }
+
+    #[test]
+    fn streaming_warns_about_smooth_normals() {
+        let stream = ObjStream::new("v 0 0 0\nv 1 0 0\nv 0 1 0\nf 1 2 3\n".as_bytes(), &ObjOptions::default());
+        assert_eq!(stream.warnings().iter().map(|warning| warning.kind).collect::<Vec<_>>(), [ObjErrorKind::UnsupportedOption]);
+        let triangles: Vec<TriData> = stream.map(|tri| tri.unwrap()).collect();
+        assert_eq!(triangles[0].n.map(|n| [n.x(), n.y(), n.z()]), [[0.0, 0.0, 1.0]; 3]);
+    }
+}
@@ -0,0 +1,687 @@
+use vector_math::{*};
+use std::collections::{HashMap, HashSet};
+use std::f32::consts::PI;
+use crate::{Mesh, Polygon, TriData};
+use crate::parse::polygon_triangles;
+use crate::triangulate::polygon_normal;
+
+// What happens at the boundary of an open mesh. Boundary edges always stay sharp (they turn into b-spline
+// curves), the rules only differ in the corners.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Default)]
+pub enum BoundaryRule {
+    // Corners follow the boundary curve, so they get rounded off
+    #[default]
+    EdgeOnly,
+
+    // Boundary vertices with only one face (counting the triangles of a polygon as one) stay where they are,
+    // so the corners of a plane stay corners
+    EdgeAndCorner,
+}
+
+#[derive(Clone, Copy)]
+pub struct SubdivisionOptions {
+    // How often to subdivide. Every level turns each triangle into four (loop), or each n-gon into n quads
+    // (catmull-clark).
+    pub levels: usize,
+
+    pub boundary: BoundaryRule,
+
+    // Edges where the faces meet at more than this angle (in radians) stay sharp, like boundary edges
+    pub crease_angle: Option<Scalar>,
+}
+
+impl Default for SubdivisionOptions {
+    fn default() -> SubdivisionOptions {
+        return SubdivisionOptions {
+            levels: 1,
+            boundary: BoundaryRule::default(),
+            crease_angle: None,
+        };
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Scheme {
+    Loop,
+    CatmullClark,
+}
+
+fn edge_key(a: usize, b: usize) -> (usize, usize) {
+    return (a.min(b), a.max(b));
+}
+
+fn bits(v: Vec3) -> [u32; 3] {
+    return [v.x().to_bits(), v.y().to_bits(), v.z().to_bits()];
+}
+
+// One attribute (positions, normals, ...) of the faces, as shared values and per-face indices into them.
+// Attributes get subdivided separately, so that wherever they are split (uv seams, hard normals) they
+// behave like a boundary.
+// Corners are the values that BoundaryRule::EdgeAndCorner keeps in place if they end up on two boundary
+// edges: those that only belonged to one polygon of the source mesh.
+#[derive(Clone)]
+struct Channel {
+    values: Vec<Vec3>,
+    faces: Vec<Vec<usize>>,
+    corners: Vec<bool>,
+}
+
+impl Channel {
+    fn new() -> Channel {
+        return Channel { values: Vec::new(), faces: Vec::new(), corners: Vec::new() };
+    }
+
+    // Marks the corners, given the source polygon of every face
+    fn mark_corners(&mut self, face_sources: &[usize]) {
+        let mut sources: Vec<Option<usize>> = vec![None; self.values.len()];
+        self.corners = vec![true; self.values.len()];
+        for (face, &source) in self.faces.iter().zip(face_sources) {
+            for &idx in face {
+                if sources[idx].is_some_and(|other| other != source) {
+                    self.corners[idx] = false;
+                }
+                sources[idx] = Some(source);
+            }
+        }
+    }
+}
+
+// Builds a channel face by face, sharing values between faces only where they also share the position.
+// Corners without a value (faces without texture coordinates, say) only get shared with each other.
+struct ChannelBuilder {
+    channel: Channel,
+    known_values: HashMap<(usize, Option<[u32; 3]>), usize>,
+}
+
+impl ChannelBuilder {
+    fn new() -> ChannelBuilder {
+        return ChannelBuilder {
+            channel: Channel::new(),
+            known_values: HashMap::new(),
+        };
+    }
+
+    fn add_face(&mut self, positions: &[usize], values: Option<&Vec<Vec3>>) {
+        let face = positions.iter().enumerate().map(|(corner, &position)| {
+            let value = values.map(|values| values[corner]);
+            let key = (position, value.map(bits));
+            if let Some(&idx) = self.known_values.get(&key) {
+                return idx;
+            }
+            self.channel.values.push(value.unwrap_or(Vec3::new(0.0, 0.0, 0.0)));
+            self.known_values.insert(key, self.channel.values.len() - 1);
+            return self.channel.values.len() - 1;
+        }).collect();
+        self.channel.faces.push(face);
+    }
+}
+
+// Edges and their neighbourhoods for the faces of one channel. An edge is hard if it is a boundary, has
+// more than two faces, or was marked sharp.
+struct Topology {
+    edges: HashMap<(usize, usize), usize>,
+    edge_ends: Vec<[usize; 2]>,
+    edge_faces: Vec<Vec<(usize, usize)>>,
+    edge_sharp: Vec<bool>,
+    face_edges: Vec<Vec<usize>>,
+    vertex_edges: Vec<Vec<usize>>,
+    vertex_faces: Vec<Vec<usize>>,
+}
+
+// How a vertex moves: by the smooth rule, along a crease (or boundary) between the two given neighbours,
+// or not at all
+enum VertexRule {
+    Smooth,
+    Crease(usize, usize),
+    Corner,
+}
+
+impl Topology {
+    // face_sharp has a flag for every face edge, edge i going from corner i to corner i + 1
+    fn new(vertex_count: usize, faces: &[Vec<usize>], face_sharp: &[Vec<bool>]) -> Topology {
+        let mut topology = Topology {
+            edges: HashMap::new(),
+            edge_ends: Vec::new(),
+            edge_faces: Vec::new(),
+            edge_sharp: Vec::new(),
+            face_edges: Vec::with_capacity(faces.len()),
+            vertex_edges: vec![Vec::new(); vertex_count],
+            vertex_faces: vec![Vec::new(); vertex_count],
+        };
+        for (face_idx, face) in faces.iter().enumerate() {
+            let mut face_edges = Vec::with_capacity(face.len());
+            for corner in 0..face.len() {
+                let (a, b) = (face[corner], face[(corner + 1) % face.len()]);
+                let edge = match topology.edges.get(&edge_key(a, b)) {
+                    Some(&edge) => edge,
+                    None => {
+                        let edge = topology.edge_ends.len();
+                        topology.edges.insert(edge_key(a, b), edge);
+                        topology.edge_ends.push([a, b]);
+                        topology.edge_faces.push(Vec::new());
+                        topology.edge_sharp.push(false);
+                        topology.vertex_edges[a].push(edge);
+                        if b != a {
+                            topology.vertex_edges[b].push(edge);
+                        }
+                        edge
+                    }
+                };
+                topology.edge_faces[edge].push((face_idx, corner));
+                topology.edge_sharp[edge] |= face_sharp[face_idx][corner];
+                if !topology.vertex_faces[a].contains(&face_idx) {
+                    topology.vertex_faces[a].push(face_idx);
+                }
+                face_edges.push(edge);
+            }
+            topology.face_edges.push(face_edges);
+        }
+        return topology;
+    }
+
+    fn is_hard(&self, edge: usize) -> bool {
+        return self.edge_sharp[edge] || self.edge_faces[edge].len() != 2;
+    }
+
+    fn other_end(&self, edge: usize, vertex: usize) -> usize {
+        let [a, b] = self.edge_ends[edge];
+        return if a == vertex { b } else { a };
+    }
+
+    // Vertices on one hard edge (the tip of a crease) are smooth, on two they follow the crease, and
+    // where more hard edges meet they are corners. So are channel corners on two boundary edges, with
+    // the corner rule.
+    fn vertex_rule(&self, channel: &Channel, vertex: usize, boundary: BoundaryRule) -> VertexRule {
+        let hard: Vec<usize> = self.vertex_edges[vertex].iter().copied().filter(|&edge| self.is_hard(edge)).collect();
+        return match hard.len() {
+            0 | 1 => VertexRule::Smooth,
+            2 => {
+                let boundary_corner = channel.corners[vertex] && hard.iter().all(|&edge| self.edge_faces[edge].len() == 1);
+                if boundary == BoundaryRule::EdgeAndCorner && boundary_corner {
+                    VertexRule::Corner
+                }
+                else {
+                    VertexRule::Crease(self.other_end(hard[0], vertex), self.other_end(hard[1], vertex))
+                }
+            },
+            _ => VertexRule::Corner,
+        };
+    }
+}
+
+fn average(values: impl Iterator<Item = Vec3>) -> Vec3 {
+    let mut sum = Vec3::new(0.0, 0.0, 0.0);
+    let mut count = 0;
+    for value in values {
+        sum += value;
+        count += 1;
+    }
+    return if count == 0 { sum } else { sum / count as Scalar };
+}
+
+// One level of loop subdivision. New values are the old vertices, then one per edge, and every triangle
+// becomes three corner triangles and one in the middle.
+fn subdivide_loop(channel: &Channel, topology: &Topology, boundary: BoundaryRule) -> Channel {
+    let values = &channel.values;
+    let mut new_values = Vec::with_capacity(values.len() + topology.edge_ends.len());
+    for (vertex, &p) in values.iter().enumerate() {
+        let neighbours = &topology.vertex_edges[vertex];
+        new_values.push(match topology.vertex_rule(channel, vertex, boundary) {
+            VertexRule::Smooth if !neighbours.is_empty() => {
+                // Loops original weights
+                let n = neighbours.len() as Scalar;
+                let w = 0.375 + 0.25 * (2.0 * PI / n).cos();
+                let beta = (0.625 - w * w) / n;
+                let mut sum = Vec3::new(0.0, 0.0, 0.0);
+                for &edge in neighbours {
+                    sum += values[topology.other_end(edge, vertex)];
+                }
+                p * (1.0 - n * beta) + sum * beta
+            },
+            VertexRule::Crease(a, b) => p * 0.75 + (values[a] + values[b]) * 0.125,
+            _ => p,
+        });
+    }
+    for (edge, &[a, b]) in topology.edge_ends.iter().enumerate() {
+        new_values.push(if topology.is_hard(edge) {
+            (values[a] + values[b]) * 0.5
+        }
+        else {
+            let opposite = average(topology.edge_faces[edge].iter().map(|&(face, corner)| values[channel.faces[face][(corner + 2) % 3]]));
+            (values[a] + values[b]) * 0.375 + opposite * 0.25
+        });
+    }
+
+    let edge_point = |edge: usize| values.len() + edge;
+    let mut new_faces = Vec::with_capacity(channel.faces.len() * 4);
+    for (face, face_edges) in channel.faces.iter().zip(topology.face_edges.iter()) {
+        let [e0, e1, e2] = [edge_point(face_edges[0]), edge_point(face_edges[1]), edge_point(face_edges[2])];
+        new_faces.push(vec![face[0], e0, e2]);
+        new_faces.push(vec![face[1], e1, e0]);
+        new_faces.push(vec![face[2], e2, e1]);
+        new_faces.push(vec![e0, e1, e2]);
+    }
+    let mut corners = channel.corners.clone();
+    corners.resize(new_values.len(), false);
+    return Channel { values: new_values, faces: new_faces, corners: corners };
+}
+
+// One level of catmull-clark subdivision. New values are the old vertices, then one per edge, then one
+// per face, and every n-gon becomes n quads around its face point.
+fn subdivide_catmull_clark(channel: &Channel, topology: &Topology, boundary: BoundaryRule) -> Channel {
+    let values = &channel.values;
+    let face_points: Vec<Vec3> = channel.faces.iter().map(|face| average(face.iter().map(|&idx| values[idx]))).collect();
+    let mut new_values = Vec::with_capacity(values.len() + topology.edge_ends.len() + face_points.len());
+    for (vertex, &p) in values.iter().enumerate() {
+        let faces = &topology.vertex_faces[vertex];
+        let edges = &topology.vertex_edges[vertex];
+        new_values.push(match topology.vertex_rule(channel, vertex, boundary) {
+            VertexRule::Smooth if !faces.is_empty() => {
+                let n = edges.len() as Scalar;
+                let face_average = average(faces.iter().map(|&face| face_points[face]));
+                let edge_average = average(edges.iter().map(|&edge| (p + values[topology.other_end(edge, vertex)]) * 0.5));
+                (face_average + edge_average * 2.0 + p * (n - 3.0)) / n
+            },
+            VertexRule::Crease(a, b) => (p * 6.0 + values[a] + values[b]) / 8.0,
+            _ => p,
+        });
+    }
+    for (edge, &[a, b]) in topology.edge_ends.iter().enumerate() {
+        new_values.push(if topology.is_hard(edge) {
+            (values[a] + values[b]) * 0.5
+        }
+        else {
+            let face_average = average(topology.edge_faces[edge].iter().map(|&(face, _)| face_points[face]));
+            (values[a] + values[b] + face_average * 2.0) * 0.25
+        });
+    }
+    new_values.extend(face_points.iter().copied());
+
+    let edge_point = |edge: usize| values.len() + edge;
+    let face_point = |face: usize| values.len() + topology.edge_ends.len() + face;
+    let mut new_faces = Vec::new();
+    for (face_idx, (face, face_edges)) in channel.faces.iter().zip(topology.face_edges.iter()).enumerate() {
+        let count = face.len();
+        for corner in 0..count {
+            new_faces.push(vec![
+                face[corner],
+                edge_point(face_edges[corner]),
+                face_point(face_idx),
+                edge_point(face_edges[(corner + count - 1) % count]),
+            ]);
+        }
+    }
+    let mut corners = channel.corners.clone();
+    corners.resize(new_values.len(), false);
+    return Channel { values: new_values, faces: new_faces, corners: corners };
+}
+
+// Union-find lookup with path halving
+fn root(parents: &mut [usize], idx: usize) -> usize {
+    let mut idx = idx;
+    while parents[idx] != idx {
+        parents[idx] = parents[parents[idx]];
+        idx = parents[idx];
+    }
+    return idx;
+}
+
+// What a face keeps through subdivision
+#[derive(Clone, Copy)]
+struct FaceInfo {
+    material: Option<usize>,
+    sub_mesh: Option<usize>,
+    texcoords: bool,
+    colors: bool,
+}
+
+// The faces being subdivided, with sharp edges as pairs of position indices
+struct Cage {
+    faces: Vec<FaceInfo>,
+    positions: Channel,
+    normals: Channel,
+    texcoords: Option<Channel>,
+    colors: Option<Channel>,
+    sharp: HashSet<(usize, usize)>,
+}
+
+impl Cage {
+    // Faces are polygons in file winding order, with their material, sub-mesh and source polygon (the
+    // polygon a triangle came from, for loop subdivision), sorted by sub-mesh
+    fn new(faces: Vec<(Polygon, Option<usize>, Option<usize>, usize)>) -> Cage {
+        let mut known_positions: HashMap<[u32; 3], usize> = HashMap::new();
+        let mut positions = Channel::new();
+        let mut normals = ChannelBuilder::new();
+        let mut texcoords = ChannelBuilder::new();
+        let mut colors = ChannelBuilder::new();
+        let mut infos = Vec::with_capacity(faces.len());
+        for (polygon, material, sub_mesh, _) in faces.iter() {
+            let face: Vec<usize> = polygon.p.iter().map(|&p| *known_positions.entry(bits(p)).or_insert_with(|| {
+                positions.values.push(p);
+                return positions.values.len() - 1;
+            })).collect();
+            normals.add_face(&face, Some(&polygon.n));
+            texcoords.add_face(&face, polygon.t.as_ref());
+            colors.add_face(&face, polygon.c.as_ref());
+            positions.faces.push(face);
+            infos.push(FaceInfo {
+                material: *material,
+                sub_mesh: *sub_mesh,
+                texcoords: polygon.t.is_some(),
+                colors: polygon.c.is_some(),
+            });
+        }
+        let has_texcoords = infos.iter().any(|info| info.texcoords);
+        let has_colors = infos.iter().any(|info| info.colors);
+        let sources: Vec<usize> = faces.iter().map(|face| face.3).collect();
+        for channel in [&mut positions, &mut normals.channel, &mut texcoords.channel, &mut colors.channel] {
+            channel.mark_corners(&sources);
+        }
+        return Cage {
+            faces: infos,
+            positions: positions,
+            normals: normals.channel,
+            texcoords: if has_texcoords { Some(texcoords.channel) } else { None },
+            colors: if has_colors { Some(colors.channel) } else { None },
+            sharp: HashSet::new(),
+        };
+    }
+
+    fn face_sharp(&self) -> Vec<Vec<bool>> {
+        return self.positions.faces.iter().map(|face| (0..face.len()).map(|corner| {
+            return self.sharp.contains(&edge_key(face[corner], face[(corner + 1) % face.len()]));
+        }).collect()).collect();
+    }
+
+    // Marks edges between faces that meet at more than the crease angle as sharp
+    fn mark_creases(&mut self, crease_angle: Scalar) {
+        let face_sharp = self.face_sharp();
+        let topology = Topology::new(self.positions.values.len(), &self.positions.faces, &face_sharp);
+        let face_normals: Vec<Vec3> = self.positions.faces.iter().map(|face| {
+            let normal = polygon_normal(&face.iter().map(|&idx| self.positions.values[idx]).collect::<Vec<Vec3>>());
+            return if normal.length() > 0.0 { normal.normalized() } else { normal };
+        }).collect();
+        let min_cos = crease_angle.cos();
+        for (edge, edge_faces) in topology.edge_faces.iter().enumerate() {
+            if edge_faces.len() == 2 && (face_normals[edge_faces[0].0] & face_normals[edge_faces[1].0]) < min_cos {
+                let [a, b] = topology.edge_ends[edge];
+                self.sharp.insert(edge_key(a, b));
+            }
+        }
+    }
+
+    // Averages normals across every edge that isn't sharp, since the surface is going to be smooth there
+    // whatever the normals were. Hard normals stay only along creases and boundaries.
+    fn smooth_normals(&mut self) {
+        let face_sharp = self.face_sharp();
+        let topology = Topology::new(self.positions.values.len(), &self.positions.faces, &face_sharp);
+        let mut parents: Vec<usize> = (0..self.normals.values.len()).collect();
+        for (edge, edge_faces) in topology.edge_faces.iter().enumerate() {
+            if topology.is_hard(edge) {
+                continue;
+            }
+            let [(face_a, corner_a), (face_b, corner_b)] = [edge_faces[0], edge_faces[1]];
+            let (count_a, count_b) = (self.positions.faces[face_a].len(), self.positions.faces[face_b].len());
+            for corner in [corner_a, (corner_a + 1) % count_a] {
+                let position = self.positions.faces[face_a][corner];
+                let other = if self.positions.faces[face_b][corner_b] == position { corner_b } else { (corner_b + 1) % count_b };
+                let (a, b) = (root(&mut parents, self.normals.faces[face_a][corner]), root(&mut parents, self.normals.faces[face_b][other]));
+                parents[a] = b;
+            }
+        }
+
+        let mut sums: HashMap<usize, Vec3> = HashMap::new();
+        for idx in 0..parents.len() {
+            let sum = sums.entry(root(&mut parents, idx)).or_insert(Vec3::new(0.0, 0.0, 0.0));
+            *sum += self.normals.values[idx];
+        }
+        let mut new_indices: HashMap<usize, usize> = HashMap::new();
+        let mut new_values = Vec::new();
+        for face in self.normals.faces.iter_mut() {
+            for idx in face.iter_mut() {
+                let group = root(&mut parents, *idx);
+                *idx = *new_indices.entry(group).or_insert_with(|| {
+                    let sum = sums[&group];
+                    new_values.push(if sum.length() > 0.0 { sum.normalized() } else { sum });
+                    return new_values.len() - 1;
+                });
+            }
+        }
+        self.normals.values = new_values;
+    }
+
+    fn subdivide(&mut self, scheme: Scheme, boundary: BoundaryRule) {
+        let face_sharp = self.face_sharp();
+        let subdivide_channel = |channel: &Channel| {
+            let topology = Topology::new(channel.values.len(), &channel.faces, &face_sharp);
+            let subdivided = match scheme {
+                Scheme::Loop => subdivide_loop(channel, &topology, boundary),
+                Scheme::CatmullClark => subdivide_catmull_clark(channel, &topology, boundary),
+            };
+            return (subdivided, topology);
+        };
+
+        // Halves of sharp edges stay sharp
+        let (positions, topology) = subdivide_channel(&self.positions);
+        let vertex_count = self.positions.values.len();
+        self.sharp = self.sharp.iter().filter_map(|&(a, b)| topology.edges.get(&(a, b))).flat_map(|&edge| {
+            let [a, b] = topology.edge_ends[edge];
+            return [edge_key(a, vertex_count + edge), edge_key(vertex_count + edge, b)];
+        }).collect();
+        self.positions = positions;
+        self.normals = subdivide_channel(&self.normals).0;
+        self.texcoords = self.texcoords.as_ref().map(|channel| subdivide_channel(channel).0);
+        self.colors = self.colors.as_ref().map(|channel| subdivide_channel(channel).0);
+
+        self.faces = self.faces.iter().zip(face_sharp.iter()).flat_map(|(&info, edges)| {
+            let children = if scheme == Scheme::Loop { 4 } else { edges.len() };
+            return std::iter::repeat_n(info, children);
+        }).collect();
+    }
+
+    // Turns the faces back into polygons and triangles, with the sub-meshes and materials of the source mesh
+    fn to_mesh(&self, source: &Mesh) -> Mesh {
+        let mut mesh = Mesh {
+            triangles: Vec::new(),
+            polygons: Vec::new(),
+            polylines: source.polylines.clone(),
+            points: source.points.clone(),
+            sub_meshes: source.sub_meshes.iter().map(|sub_mesh| {
+                let mut sub_mesh = sub_mesh.clone();
+                sub_mesh.triangles = 0..0;
+                return sub_mesh;
+            }).collect(),
+            materials: source.materials.clone(),
+            material_libs: source.material_libs.clone(),
+            warnings: Vec::new(),
+        };
+        for (face_idx, info) in self.faces.iter().enumerate() {
+            let corners = |channel: &Channel| -> Vec<Vec3> {
+                return channel.faces[face_idx].iter().map(|&idx| channel.values[idx]).collect();
+            };
+            let mut polygon = Polygon {
+                p: corners(&self.positions),
+                n: corners(&self.normals).iter().map(|&n| if n.length() > 0.0 { n.normalized() } else { n }).collect(),
+                t: self.texcoords.as_ref().filter(|_| info.texcoords).map(corners),
+                c: self.colors.as_ref().filter(|_| info.colors).map(corners),
+                triangles: 0..0,
+            };
+            let first_triangle = mesh.triangles.len();
+            mesh.triangles.extend(polygon_triangles(&polygon, info.material));
+            polygon.triangles = first_triangle..mesh.triangles.len();
+            mesh.polygons.push(polygon);
+
+            if let Some(sub_mesh) = info.sub_mesh.map(|idx| &mut mesh.sub_meshes[idx]) {
+                if sub_mesh.triangles.is_empty() {
+                    sub_mesh.triangles.start = first_triangle;
+                }
+                sub_mesh.triangles.end = mesh.triangles.len();
+            }
+        }
+        return mesh;
+    }
+}
+
+// A triangle back in file winding order
+fn triangle_polygon(tri: &TriData) -> Polygon {
+    return Polygon {
+        p: vec![tri.p[2], tri.p[1], tri.p[0]],
+        n: vec![tri.n[2], tri.n[1], tri.n[0]],
+        t: tri.t.map(|t| vec![t[2], t[1], t[0]]),
+        c: tri.c.map(|c| vec![c[2], c[1], c[0]]),
+        triangles: 0..0,
+    };
+}
+
+impl Mesh {
+    fn sub_mesh_of_triangle(&self, triangle: usize) -> Option<usize> {
+        return self.sub_meshes.iter().position(|sub_mesh| sub_mesh.triangles.contains(&triangle));
+    }
+
+    fn subdivide(&self, faces: Vec<(Polygon, Option<usize>, Option<usize>, usize)>, scheme: Scheme, options: &SubdivisionOptions) -> Mesh {
+        let mut faces = faces;
+        faces.sort_by_key(|&(_, _, sub_mesh, _)| sub_mesh);
+        let mut cage = Cage::new(faces);
+        if let Some(crease_angle) = options.crease_angle {
+            cage.mark_creases(crease_angle);
+        }
+        cage.smooth_normals();
+        for _ in 0..options.levels {
+            cage.subdivide(scheme, options.boundary);
+        }
+        return cage.to_mesh(self);
+    }
+
+    // Loop subdivision of the triangles (polygons are subdivided as they were triangulated). Positions,
+    // normals, texture coordinates and colors all get smoothed, with seams in any of them kept as seams,
+    // except that normals are averaged first wherever the surface will be smooth.
+    pub fn subdivide_loop(&self, options: &SubdivisionOptions) -> Mesh {
+        // Triangles of one polygon count as one face for the corner rule, so the corners of a quad stay
+        // corners however it was triangulated
+        let mut sources: Vec<usize> = (self.polygons.len()..self.polygons.len() + self.triangles.len()).collect();
+        for (polygon_idx, polygon) in self.polygons.iter().enumerate() {
+            for triangle in polygon.triangles.clone() {
+                sources[triangle] = polygon_idx;
+            }
+        }
+        let faces = self.triangles.iter().enumerate().map(|(idx, tri)| {
+            return (triangle_polygon(tri), tri.material, self.sub_mesh_of_triangle(idx), sources[idx]);
+        }).collect();
+        return self.subdivide(faces, Scheme::Loop, options);
+    }
+
+    // Catmull-clark subdivision of the polygons, which gives a mesh of quads (meant for quad meshes, but
+    // triangles and n-gons work as well). Attributes are handled like in subdivide_loop.
+    pub fn subdivide_catmull_clark(&self, options: &SubdivisionOptions) -> Mesh {
+        let faces = self.polygons.iter().enumerate().filter(|(_, polygon)| !polygon.triangles.is_empty()).map(|(polygon_idx, polygon)| {
+            let first_triangle = polygon.triangles.start;
+            return (polygon.clone(), self.triangles[first_triangle].material, self.sub_mesh_of_triangle(first_triangle), polygon_idx);
+        }).collect();
+        return self.subdivide(faces, Scheme::CatmullClark, options);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use vector_math::{*};
+    use crate::{Mesh, parse_obj, shapes};
+    use super::{BoundaryRule, SubdivisionOptions};
+
+    fn options(levels: usize, boundary: BoundaryRule, crease_angle: Option<Scalar>) -> SubdivisionOptions {
+        return SubdivisionOptions { levels: levels, boundary: boundary, crease_angle: crease_angle };
+    }
+
+    fn has_point(mesh: &Mesh, p: Vec3) -> bool {
+        return mesh.triangles.iter().flat_map(|tri| tri.p.iter()).any(|&q| (q - p).length() < 1e-6);
+    }
+
+    #[test]
+    fn loop_quadruples_triangles() {
+        let mesh = shapes::icosphere(1.0, 0);
+        let count = mesh.triangles.len();
+        for levels in 1..=3 {
+            let subdivided = mesh.subdivide_loop(&options(levels, BoundaryRule::EdgeOnly, None));
+            assert_eq!(subdivided.triangles.len(), count << (2 * levels));
+            assert_eq!(subdivided.polygons.len(), subdivided.triangles.len());
+        }
+    }
+
+    #[test]
+    fn catmull_clark_makes_n_quads_per_n_gon() {
+        // A pentagon, a quad and a triangle
+        let obj = "v 0 0 0\nv 1 0 0\nv 1.5 1 0\nv 0.5 1.5 0\nv -0.5 1 0\nv 2 0 0\nv 2 1 0\nv 1 -1 0\n\
+            f 1 2 3 4 5\nf 2 6 7 3\nf 1 8 2\n";
+        let mesh = parse_obj(obj).unwrap();
+        let subdivided = mesh.subdivide_catmull_clark(&options(1, BoundaryRule::EdgeOnly, None));
+        assert_eq!(subdivided.polygons.len(), 5 + 4 + 3);
+        assert!(subdivided.polygons.iter().all(|polygon| polygon.p.len() == 4));
+        assert_eq!(subdivided.triangles.len(), 2 * 12);
+        let subdivided = mesh.subdivide_catmull_clark(&options(2, BoundaryRule::EdgeOnly, None));
+        assert_eq!(subdivided.polygons.len(), 4 * 12);
+    }
+
+    #[test]
+    fn plane_corners_stay_with_the_corner_rule() {
+        let plane = shapes::plane(2.0, 2.0, 2, 2);
+        let corners = [[-1.0, -1.0], [-1.0, 1.0], [1.0, -1.0], [1.0, 1.0]].map(|[x, z]| Vec3::new(x, 0.0, z));
+        for loop_scheme in [false, true] {
+            let subdivide = |boundary| {
+                let options = options(2, boundary, None);
+                return if loop_scheme { plane.subdivide_loop(&options) } else { plane.subdivide_catmull_clark(&options) };
+            };
+            let kept = subdivide(BoundaryRule::EdgeAndCorner);
+            assert!(corners.iter().all(|&corner| has_point(&kept, corner)));
+            // Everything stays in the plane
+            assert!(kept.triangles.iter().flat_map(|tri| tri.p.iter()).all(|p| p.y() == 0.0));
+            let rounded = subdivide(BoundaryRule::EdgeOnly);
+            assert!(!corners.iter().any(|&corner| has_point(&rounded, corner)));
+        }
+    }
+
+    #[test]
+    fn cube_creases_stay_sharp() {
+        let cube = shapes::cube(1.0, 1);
+        let on_surface = |mesh: &Mesh| mesh.triangles.iter().flat_map(|tri| tri.p.iter()).all(|p| {
+            return (p.x().abs().max(p.y().abs()).max(p.z().abs()) - 0.5).abs() < 1e-5;
+        });
+        let creased = cube.subdivide_catmull_clark(&options(2, BoundaryRule::EdgeOnly, Some(0.5)));
+        assert_eq!(creased.polygons.len(), 6 * 16);
+        assert!(on_surface(&creased));
+        // Normals stay those of the faces
+        for tri in &creased.triangles {
+            let [a, b, c] = tri.n;
+            assert!([a, b, c].iter().all(|&n| (n - a).length() < 1e-6));
+            assert!((a.x().abs().max(a.y().abs()).max(a.z().abs()) - 1.0).abs() < 1e-6);
+        }
+        assert!(on_surface(&cube.subdivide_loop(&options(1, BoundaryRule::EdgeOnly, Some(0.5)))));
+
+        // Without creases the cube turns into a blob
+        assert!(!on_surface(&cube.subdivide_catmull_clark(&options(2, BoundaryRule::EdgeOnly, None))));
+    }
+
+    #[test]
+    fn texcoords_and_colors_get_interpolated() {
+        let obj = "v 0 0 0 1 0 0\nv 1 0 0 0 1 0\nv 1 1 0 0 0 1\nv 0 1 0 1 1 1\n\
+            vt 0 0\nvt 1 0\nvt 1 1\nvt 0 1\nf 1/1 2/2 3/3 4/4\n";
+        let mesh = parse_obj(obj).unwrap();
+        let subdivided = mesh.subdivide_catmull_clark(&options(1, BoundaryRule::EdgeAndCorner, None));
+        assert_eq!(subdivided.polygons.len(), 4);
+        let mut center_found = false;
+        for polygon in &subdivided.polygons {
+            let (t, c) = (polygon.t.as_ref().unwrap(), polygon.c.as_ref().unwrap());
+            for (idx, p) in polygon.p.iter().enumerate() {
+                // Texture coordinates follow the positions of this flat, square quad
+                assert!((t[idx].x() - p.x()).abs() < 1e-6 && (t[idx].y() - p.y()).abs() < 1e-6);
+                if (p.x() - 0.5).abs() < 1e-6 && (p.y() - 0.5).abs() < 1e-6 {
+                    assert_eq!([c[idx].r(), c[idx].g(), c[idx].b()], [0.5, 0.5, 0.5]);
+                    center_found = true;
+                }
+            }
+        }
+        assert!(center_found);
+
+        let subdivided = mesh.subdivide_loop(&options(1, BoundaryRule::EdgeAndCorner, None));
+        assert_eq!(subdivided.triangles.len(), 8);
+        assert!(subdivided.triangles.iter().all(|tri| tri.t.is_some() && tri.c.is_some()));
+    }
+}
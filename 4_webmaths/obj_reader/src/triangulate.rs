@@ -0,0 +1,174 @@
+use vector_math::{*};
+
+// Normal of a (possibly non-planar) polygon via Newell's method, which gives the best-fit plane
+pub fn polygon_normal(points: &[Vec3]) -> Vec3 {
+    let mut normal = Vec3::new(0.0, 0.0, 0.0);
+    for idx in 0..points.len() {
+        let a = points[idx];
+        let b = points[(idx + 1) % points.len()];
+        normal += Vec3::new(
+            (a.y() - b.y()) * (a.z() + b.z()),
+            (a.z() - b.z()) * (a.x() + b.x()),
+            (a.x() - b.x()) * (a.y() + b.y()),
+        );
+    }
+    return normal;
+}
+
+// Projects points onto the plane with the given normal, such that the polygon winds counter-clockwise
+fn project(points: &[Vec3], normal: Vec3) -> Vec<Vec2> {
+    let helper = if normal.x().abs() < 0.9 { Vec3::new(1.0, 0.0, 0.0) } else { Vec3::new(0.0, 1.0, 0.0) };
+    let u = helper.cross(normal).normalized();
+    let v = normal.cross(u).normalized();
+    return points.iter().map(|p| Vec2::new(*p & u, *p & v)).collect();
+}
+
+#[inline(always)]
+fn cross_2d(a: Vec2, b: Vec2, c: Vec2) -> Scalar {
+    return (b.x() - a.x()) * (c.y() - a.y()) - (b.y() - a.y()) * (c.x() - a.x());
+}
+
+fn is_convex_planar(points: &[Vec3], points_2d: &[Vec2], normal: Vec3) -> bool {
+    // Planarity: every point close to the plane through the centroid
+    let mut centroid = Vec3::new(0.0, 0.0, 0.0);
+    let mut extent: Scalar = 0.0;
+    for idx in 0..points.len() {
+        centroid += points[idx];
+        extent = extent.max((points[(idx + 1) % points.len()] - points[idx]).length());
+    }
+    centroid /= points.len() as Scalar;
+    for p in points {
+        if ((*p - centroid) & normal).abs() > extent * 0.0001 {
+            return false;
+        }
+    }
+
+    // Convexity: every corner turns left
+    for idx in 0..points_2d.len() {
+        let a = points_2d[idx];
+        let b = points_2d[(idx + 1) % points_2d.len()];
+        let c = points_2d[(idx + 2) % points_2d.len()];
+        if cross_2d(a, b, c) <= 0.0 {
+            return false;
+        }
+    }
+    return true;
+}
+
+fn in_triangle(p: Vec2, a: Vec2, b: Vec2, c: Vec2) -> bool {
+    return cross_2d(a, b, p) >= 0.0 && cross_2d(b, c, p) >= 0.0 && cross_2d(c, a, p) >= 0.0;
+}
+
+// Ear clipping for concave polygons, O(n^2) but polygons in obj files are small
+fn ear_clip(points_2d: &[Vec2]) -> Vec<[usize; 3]> {
+    let mut remaining: Vec<usize> = (0..points_2d.len()).collect();
+    let mut triangles = Vec::new();
+    while remaining.len() > 3 {
+        let count = remaining.len();
+        let mut ear = None;
+        for idx in 0..count {
+            let prev = remaining[(idx + count - 1) % count];
+            let this = remaining[idx];
+            let next = remaining[(idx + 1) % count];
+            let (a, b, c) = (points_2d[prev], points_2d[this], points_2d[next]);
+            if cross_2d(a, b, c) <= 0.0 {
+                continue;
+            }
+            let blocked = remaining.iter().any(|&other|
+                other != prev && other != this && other != next && in_triangle(points_2d[other], a, b, c)
+            );
+            if !blocked {
+                ear = Some(idx);
+                break;
+            }
+        }
+
+        // Degenerate polygon (self-intersecting or collinear): clip anyway so we always terminate
+        let idx = ear.unwrap_or(0);
+        triangles.push([remaining[(idx + count - 1) % count], remaining[idx], remaining[(idx + 1) % count]]);
+        remaining.remove(idx);
+    }
+    triangles.push([remaining[0], remaining[1], remaining[2]]);
+    return triangles;
+}
+
+// Splits a polygon into triangles (as indices into points, in the polygons winding order).
+// Convex planar polygons get a fan, everything else is ear clipped on the best-fit plane.
+pub fn triangulate(points: &[Vec3]) -> Vec<[usize; 3]> {
+    if points.len() == 3 {
+        return vec![[0, 1, 2]];
+    }
+    let normal = polygon_normal(points);
+    if normal.length() == 0.0 {
+        return (1..points.len() - 1).map(|idx| [0, idx, idx + 1]).collect();
+    }
+    let normal = normal.normalized();
+    let points_2d = project(points, normal);
+    if is_convex_planar(points, &points_2d, normal) {
+        return (1..points.len() - 1).map(|idx| [0, idx, idx + 1]).collect();
+    }
+    return ear_clip(&points_2d);
+}
+
+#[cfg(test)]
+mod tests {
+    use vector_math::{*};
+    use crate::parse_obj;
+    use crate::process::triangle_normal;
+    use super::{polygon_normal, triangulate};
+
+    // Even-odd test in the xy plane
+    fn inside_xy(p: Vec3, polygon: &[Vec3]) -> bool {
+        let mut inside = false;
+        for idx in 0..polygon.len() {
+            let (a, b) = (polygon[idx], polygon[(idx + 1) % polygon.len()]);
+            if (a.y() > p.y()) != (b.y() > p.y()) && p.x() < a.x() + (p.y() - a.y()) / (b.y() - a.y()) * (b.x() - a.x()) {
+                inside = !inside;
+            }
+        }
+        return inside;
+    }
+
+    #[test]
+    fn concave_l_stays_inside() {
+        // Counter-clockwise seen from +z, with the reflex corner at (1, 1)
+        let obj = "v 0 0 0\nv 2 0 0\nv 2 1 0\nv 1 1 0\nv 1 2 0\nv 0 2 0\nf 1 2 3 4 5 6\n";
+        let mesh = parse_obj(obj).unwrap();
+        let polygon = &mesh.polygons[0].p;
+        assert_eq!(mesh.triangles.len(), 4);
+
+        let mut area = 0.0;
+        for tri in mesh.triangles.iter() {
+            // Triangles face the same way as the polygon
+            let normal = triangle_normal(tri.p);
+            assert!(normal.z() > 0.0);
+            area += normal.length() * 0.5;
+            let center = (tri.p[0] + tri.p[1] + tri.p[2]) * (1.0 / 3.0);
+            assert!(inside_xy(center, polygon));
+        }
+        assert!((area - 3.0).abs() < 1e-5);
+    }
+
+    #[test]
+    fn non_planar_quad_uses_every_corner() {
+        let points = [Vec3::new(0.0, 0.0, 0.0), Vec3::new(1.0, 0.0, 0.0), Vec3::new(1.0, 1.0, 0.3), Vec3::new(0.0, 1.0, 0.0)];
+        let triangles = triangulate(&points);
+        assert_eq!(triangles.len(), 2);
+        let mut corners: Vec<usize> = triangles.iter().flatten().copied().collect();
+        corners.sort();
+        corners.dedup();
+        assert_eq!(corners, [0, 1, 2, 3]);
+        // Both halves face roughly the way the whole quad does
+        let normal = polygon_normal(&points);
+        for [a, b, c] in triangles {
+            assert!(((points[b] - points[a]).cross(points[c] - points[a]) & normal) > 0.0);
+        }
+
+        let obj = "v 0 0 0\nv 1 0 0\nv 1 1 0.3\nv 0 1 0\nf 1 2 3 4\n";
+        let mesh = parse_obj(obj).unwrap();
+        assert_eq!(mesh.triangles.len(), 2);
+        for tri in mesh.triangles.iter() {
+            assert!((triangle_normal(tri.p) & normal) > 0.0);
+        }
+    }
+}
@@ -0,0 +1,466 @@
+use vector_math::{*};
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use crate::{Mesh, TriData};
+use crate::process::{position_key, triangle_normal};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum MeshIssueKind {
+    NonFinite,
+    DegenerateTriangle,
+    DuplicateTriangle,
+    NonManifoldEdge,
+    InconsistentWinding,
+    BoundaryEdge,
+    NonUnitNormal,
+    InwardNormal,
+}
+
+const ISSUE_KINDS: [MeshIssueKind; 8] = [
+    MeshIssueKind::NonFinite,
+    MeshIssueKind::DegenerateTriangle,
+    MeshIssueKind::DuplicateTriangle,
+    MeshIssueKind::NonManifoldEdge,
+    MeshIssueKind::InconsistentWinding,
+    MeshIssueKind::BoundaryEdge,
+    MeshIssueKind::NonUnitNormal,
+    MeshIssueKind::InwardNormal,
+];
+
+impl MeshIssueKind {
+    pub fn description(&self) -> &'static str {
+        return match self {
+            MeshIssueKind::NonFinite => "nan or infinite values",
+            MeshIssueKind::DegenerateTriangle => "degenerate triangles",
+            MeshIssueKind::DuplicateTriangle => "duplicate triangles",
+            MeshIssueKind::NonManifoldEdge => "non-manifold edges",
+            MeshIssueKind::InconsistentWinding => "inconsistently wound edges",
+            MeshIssueKind::BoundaryEdge => "boundary edges",
+            MeshIssueKind::NonUnitNormal => "normals that are not unit length",
+            MeshIssueKind::InwardNormal => "normals facing away from their triangle",
+        };
+    }
+
+    // Whether Mesh::repair fixes this kind of issue. Holes and non-manifold geometry need a human.
+    pub fn is_repairable(&self) -> bool {
+        return !matches!(self, MeshIssueKind::NonManifoldEdge | MeshIssueKind::BoundaryEdge);
+    }
+}
+
+// A problem with one triangle (an index into Mesh::triangles). Normal issues have the corner they are at,
+// edge issues the corner their edge starts at, and are reported once per edge.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct MeshIssue {
+    pub kind: MeshIssueKind,
+    pub triangle: usize,
+    pub corner: Option<usize>,
+}
+
+#[derive(Clone, Copy)]
+pub struct ValidationOptions {
+    // Triangles with at most this area count as degenerate
+    pub area_epsilon: Scalar,
+
+    // How far from 1 the length of a normal may be
+    pub normal_tolerance: Scalar,
+}
+
+impl Default for ValidationOptions {
+    fn default() -> ValidationOptions {
+        return ValidationOptions {
+            area_epsilon: 1e-12,
+            normal_tolerance: 1e-3,
+        };
+    }
+}
+
+// Everything validate found, ordered by triangle. Printing it gives a summary by kind.
+#[derive(Clone, Debug, Default)]
+pub struct ValidationReport {
+    pub triangle_count: usize,
+    pub issues: Vec<MeshIssue>,
+}
+
+impl ValidationReport {
+    pub fn is_clean(&self) -> bool {
+        return self.issues.is_empty();
+    }
+
+    pub fn count(&self, kind: MeshIssueKind) -> usize {
+        return self.issues.iter().filter(|issue| issue.kind == kind).count();
+    }
+
+    pub fn issues_of_kind(&self, kind: MeshIssueKind) -> impl Iterator<Item = &MeshIssue> {
+        return self.issues.iter().filter(move |issue| issue.kind == kind);
+    }
+
+    // Whether repair would leave the mesh clean
+    pub fn is_repairable(&self) -> bool {
+        return self.issues.iter().all(|issue| issue.kind.is_repairable());
+    }
+}
+
+// Lists up to this many places per kind of issue
+const LISTED_ISSUES: usize = 5;
+
+impl fmt::Display for ValidationReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.is_clean() {
+            return write!(f, "{} triangles, no issues", self.triangle_count);
+        }
+        write!(f, "{} triangles, {} issues", self.triangle_count, self.issues.len())?;
+        for kind in ISSUE_KINDS {
+            let count = self.count(kind);
+            if count == 0 {
+                continue;
+            }
+            let places: Vec<String> = self.issues_of_kind(kind).take(LISTED_ISSUES).map(|issue| match issue.corner {
+                Some(corner) => format!("{}:{}", issue.triangle, corner),
+                None => issue.triangle.to_string(),
+            }).collect();
+            let more = if count > LISTED_ISSUES { ", ..." } else { "" };
+            write!(f, "\n  {} {} (at {}{})", count, kind.description(), places.join(", "), more)?;
+        }
+        return Ok(());
+    }
+}
+
+fn is_finite(tri: &TriData) -> bool {
+    let finite = |v: &Vec3| v.x().is_finite() && v.y().is_finite() && v.z().is_finite();
+    return tri.p.iter().all(finite) && tri.n.iter().all(finite) &&
+        tri.t.iter().flatten().all(finite) && tri.c.iter().flatten().all(finite);
+}
+
+type EdgeKey = ([u32; 3], [u32; 3]);
+
+// Every edge of the given triangles by its sorted position keys, with the (triangle, corner) uses and
+// whether each goes from the smaller to the larger key. Edges of repeated points are left out.
+fn edge_uses(triangles: &[TriData], skip: &[bool]) -> HashMap<EdgeKey, Vec<(usize, usize, bool)>> {
+    let mut edges: HashMap<EdgeKey, Vec<(usize, usize, bool)>> = HashMap::new();
+    for (tri_idx, tri) in triangles.iter().enumerate().filter(|&(tri_idx, _)| !skip[tri_idx]) {
+        for corner in 0..3 {
+            let (a, b) = (position_key(tri.p[corner]), position_key(tri.p[(corner + 1) % 3]));
+            if a != b {
+                edges.entry((a.min(b), a.max(b))).or_default().push((tri_idx, corner, a < b));
+            }
+        }
+    }
+    return edges;
+}
+
+impl Mesh {
+    // Checks the triangles for problems. Edges connect triangles where their positions match exactly.
+    // Triangles with non-finite values, no area or a duplicate earlier in the mesh (with either winding)
+    // are left out of the edge and normal checks.
+    pub fn validate(&self, options: &ValidationOptions) -> ValidationReport {
+        let mut issues = Vec::new();
+        let mut skip = vec![false; self.triangles.len()];
+        let mut known_triangles: HashSet<[[u32; 3]; 3]> = HashSet::new();
+        for (tri_idx, tri) in self.triangles.iter().enumerate() {
+            let kind = if !is_finite(tri) {
+                Some(MeshIssueKind::NonFinite)
+            }
+            else if triangle_normal(tri.p).length() * 0.5 <= options.area_epsilon {
+                Some(MeshIssueKind::DegenerateTriangle)
+            }
+            else {
+                let mut key = tri.p.map(position_key);
+                key.sort();
+                if known_triangles.insert(key) { None } else { Some(MeshIssueKind::DuplicateTriangle) }
+            };
+            if let Some(kind) = kind {
+                issues.push(MeshIssue { kind: kind, triangle: tri_idx, corner: None });
+                skip[tri_idx] = true;
+            }
+        }
+
+        for uses in edge_uses(&self.triangles, &skip).values() {
+            let issue = match uses.len() {
+                1 => Some((MeshIssueKind::BoundaryEdge, uses[0])),
+                2 if uses[0].2 == uses[1].2 => Some((MeshIssueKind::InconsistentWinding, uses[1])),
+                2 => None,
+                _ => Some((MeshIssueKind::NonManifoldEdge, uses[0])),
+            };
+            if let Some((kind, (tri_idx, corner, _))) = issue {
+                issues.push(MeshIssue { kind: kind, triangle: tri_idx, corner: Some(corner) });
+            }
+        }
+
+        for (tri_idx, tri) in self.triangles.iter().enumerate().filter(|&(tri_idx, _)| !skip[tri_idx]) {
+            let face_normal = triangle_normal(tri.p);
+            for corner in 0..3 {
+                let n = tri.n[corner];
+                if (n.length() - 1.0).abs() > options.normal_tolerance {
+                    issues.push(MeshIssue { kind: MeshIssueKind::NonUnitNormal, triangle: tri_idx, corner: Some(corner) });
+                }
+                if (n & face_normal) < 0.0 {
+                    issues.push(MeshIssue { kind: MeshIssueKind::InwardNormal, triangle: tri_idx, corner: Some(corner) });
+                }
+            }
+        }
+
+        let kind_order = |kind: MeshIssueKind| ISSUE_KINDS.iter().position(|&other| other == kind);
+        issues.sort_by_key(|issue| (issue.triangle, issue.corner, kind_order(issue.kind)));
+        return ValidationReport {
+            triangle_count: self.triangles.len(),
+            issues: issues,
+        };
+    }
+
+    // Fixes what validate finds, as far as it can: drops broken and duplicate triangles, winds every
+    // connected part consistently (the way most of its normals face), then flips normals that still face
+    // the wrong way and normalizes the rest. Returns the report from before the repair, validate again to
+    // see what is left.
+    pub fn repair(&mut self, options: &ValidationOptions) -> ValidationReport {
+        let report = self.validate(options);
+        let mut keep = vec![true; self.triangles.len()];
+        for issue in report.issues.iter() {
+            if issue.corner.is_none() {
+                keep[issue.triangle] = false;
+            }
+        }
+        self.retain_triangles(&keep);
+        if report.count(MeshIssueKind::InconsistentWinding) + report.count(MeshIssueKind::InwardNormal) > 0 {
+            self.orient();
+        }
+        for tri in self.triangles.iter_mut() {
+            let face_normal = triangle_normal(tri.p).normalized();
+            for n in tri.n.iter_mut() {
+                if n.length() == 0.0 {
+                    *n = face_normal;
+                }
+                if (*n & face_normal) < 0.0 {
+                    *n *= -1.0;
+                }
+                if (n.length() - 1.0).abs() > options.normal_tolerance {
+                    *n = n.normalized();
+                }
+            }
+        }
+        return report;
+    }
+
+    // Drops triangles, keeping polygons and sub-meshes pointing at the right ones. Polygons that lose all
+    // their triangles are dropped as well.
+    fn retain_triangles(&mut self, keep: &[bool]) {
+        if keep.iter().all(|&kept| kept) {
+            return;
+        }
+        let mut new_index = Vec::with_capacity(keep.len() + 1);
+        let mut kept_count = 0;
+        for &kept in keep {
+            new_index.push(kept_count);
+            kept_count += kept as usize;
+        }
+        new_index.push(kept_count);
+
+        let mut tri_idx = 0;
+        self.triangles.retain(|_| {
+            tri_idx += 1;
+            return keep[tri_idx - 1];
+        });
+        for polygon in self.polygons.iter_mut() {
+            polygon.triangles = new_index[polygon.triangles.start]..new_index[polygon.triangles.end];
+        }
+        self.polygons.retain(|polygon| !polygon.triangles.is_empty());
+        for sub_mesh in self.sub_meshes.iter_mut() {
+            sub_mesh.triangles = new_index[sub_mesh.triangles.start]..new_index[sub_mesh.triangles.end];
+        }
+    }
+
+    // Flips triangles so that neighbours across manifold edges agree, then flips each connected part as a
+    // whole if most of its normals disagree with its winding. Normals themselves are left alone.
+    fn orient(&mut self) {
+        let edges = edge_uses(&self.triangles, &vec![false; self.triangles.len()]);
+        let mut neighbours: Vec<Vec<(usize, bool)>> = vec![Vec::new(); self.triangles.len()];
+        for uses in edges.values().filter(|uses| uses.len() == 2) {
+            let (a, b) = (uses[0], uses[1]);
+            neighbours[a.0].push((b.0, a.2 == b.2));
+            neighbours[b.0].push((a.0, a.2 == b.2));
+        }
+
+        let mut flip: Vec<Option<bool>> = vec![None; self.triangles.len()];
+        for seed in 0..self.triangles.len() {
+            if flip[seed].is_some() {
+                continue;
+            }
+            flip[seed] = Some(false);
+            let mut part = vec![seed];
+            let mut next = 0;
+            while next < part.len() {
+                let tri_idx = part[next];
+                next += 1;
+                for &(other, same_direction) in neighbours[tri_idx].iter() {
+                    if flip[other].is_none() {
+                        flip[other] = Some(flip[tri_idx].unwrap() != same_direction);
+                        part.push(other);
+                    }
+                }
+            }
+
+            let mut votes = 0;
+            for &tri_idx in part.iter() {
+                let tri = &self.triangles[tri_idx];
+                let sign = if flip[tri_idx].unwrap() { -1.0 } else { 1.0 };
+                let face_normal = triangle_normal(tri.p) * sign;
+                votes += tri.n.iter().map(|&n| ((n & face_normal) > 0.0) as i32 - ((n & face_normal) < 0.0) as i32).sum::<i32>();
+            }
+            if votes < 0 {
+                for &tri_idx in part.iter() {
+                    flip[tri_idx] = flip[tri_idx].map(|flipped| !flipped);
+                }
+            }
+        }
+
+        let flip: Vec<bool> = flip.iter().map(|flipped| flipped.unwrap()).collect();
+        for (tri, _) in self.triangles.iter_mut().zip(flip.iter()).filter(|&(_, &flipped)| flipped) {
+            tri.p.swap(1, 2);
+            tri.n.swap(1, 2);
+            if let Some(t) = tri.t.as_mut() {
+                t.swap(1, 2);
+            }
+            if let Some(c) = tri.c.as_mut() {
+                c.swap(1, 2);
+            }
+        }
+        for polygon in self.polygons.iter_mut().filter(|polygon| flip[polygon.triangles.start]) {
+            polygon.p.reverse();
+            polygon.n.reverse();
+            if let Some(t) = polygon.t.as_mut() {
+                t.reverse();
+            }
+            if let Some(c) = polygon.c.as_mut() {
+                c.reverse();
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use vector_math::{*};
+    use crate::{Mesh, TriData, cube, parse_obj};
+    use super::{MeshIssue, MeshIssueKind, ValidationOptions};
+
+    fn issues(mesh: &Mesh) -> Vec<MeshIssue> {
+        return mesh.validate(&ValidationOptions::default()).issues;
+    }
+
+    fn issue(kind: MeshIssueKind, triangle: usize, corner: Option<usize>) -> MeshIssue {
+        return MeshIssue { kind: kind, triangle: triangle, corner: corner };
+    }
+
+    // Repairs and checks that nothing is left
+    fn assert_repairs(mut mesh: Mesh) {
+        let report = mesh.repair(&ValidationOptions::default());
+        assert!(report.is_repairable());
+        let after = mesh.validate(&ValidationOptions::default());
+        assert!(after.is_clean(), "{}", after);
+    }
+
+    // Turns a triangle around, normals included, so it only disagrees with its neighbours
+    fn reversed(tri: TriData) -> TriData {
+        let mut tri = tri;
+        tri.p.swap(1, 2);
+        tri.n = [tri.n[0], tri.n[2], tri.n[1]].map(|n| n * -1.0);
+        return tri;
+    }
+
+    #[test]
+    fn cube_is_clean() {
+        assert!(cube(2.0, 2).validate(&ValidationOptions::default()).is_clean());
+    }
+
+    #[test]
+    fn finds_broken_and_duplicate_triangles() {
+        let mut mesh = cube(2.0, 1);
+        let mut nan = mesh.triangles[0];
+        nan.p[1] = Vec3::new(Scalar::NAN, 0.0, 0.0);
+        let mut flat = mesh.triangles[0];
+        flat.p[2] = (flat.p[0] + flat.p[1]) * 0.5;
+        let mut duplicate = mesh.triangles[3];
+        duplicate.p.swap(1, 2);
+        mesh.triangles.extend([nan, flat, duplicate]);
+
+        assert_eq!(issues(&mesh), [
+            issue(MeshIssueKind::NonFinite, 12, None),
+            issue(MeshIssueKind::DegenerateTriangle, 13, None),
+            issue(MeshIssueKind::DuplicateTriangle, 14, None),
+        ]);
+        assert_repairs(mesh);
+    }
+
+    #[test]
+    fn finds_a_flipped_triangle() {
+        // The last triangle, so it is the second use of all three of its edges
+        let mut mesh = cube(2.0, 1);
+        let last = mesh.triangles.pop().unwrap();
+        mesh.triangles.push(reversed(last));
+        assert_eq!(issues(&mesh), [0, 1, 2].map(|corner| issue(MeshIssueKind::InconsistentWinding, 11, Some(corner))));
+        assert_repairs(mesh);
+    }
+
+    #[test]
+    fn finds_normals_that_are_off() {
+        let mut mesh = cube(2.0, 1);
+        mesh.triangles[4].n[1] *= 2.0;
+        mesh.triangles[7].n[2] *= -1.0;
+        assert_eq!(issues(&mesh), [
+            issue(MeshIssueKind::NonUnitNormal, 4, Some(1)),
+            issue(MeshIssueKind::InwardNormal, 7, Some(2)),
+        ]);
+        assert_repairs(mesh);
+    }
+
+    #[test]
+    fn finds_holes_and_non_manifold_edges() {
+        // Every edge of the hole is at the corner of a neighbour where it runs the other way around
+        let mut open = cube(2.0, 1);
+        let removed = open.triangles.pop().unwrap();
+        let report = open.validate(&ValidationOptions::default());
+        assert_eq!(report.count(MeshIssueKind::BoundaryEdge), 3);
+        assert_eq!(report.issues.len(), 3);
+        let xyz = |v: Vec3| [v.x(), v.y(), v.z()];
+        for issue in report.issues.iter() {
+            let (p, corner) = (open.triangles[issue.triangle].p, issue.corner.unwrap());
+            let edge = (xyz(p[(corner + 1) % 3]), xyz(p[corner]));
+            assert!((0..3).any(|other| edge == (xyz(removed.p[other]), xyz(removed.p[(other + 1) % 3]))));
+        }
+        assert!(!report.is_repairable());
+
+        // A fin on one of the cube's edges
+        let mut fin = cube(2.0, 1);
+        let [a, b, _] = fin.triangles[0].p;
+        let mut extra = fin.triangles[0];
+        extra.p = [b, a, (a + b) * 0.5 + Vec3::new(5.0, 5.0, 5.0)];
+        fin.triangles.push(extra);
+        let report = fin.validate(&ValidationOptions::default());
+        let non_manifold: Vec<&MeshIssue> = report.issues_of_kind(MeshIssueKind::NonManifoldEdge).collect();
+        assert_eq!(non_manifold, [&issue(MeshIssueKind::NonManifoldEdge, 0, Some(0))]);
+        assert!(!report.is_repairable());
+    }
+
+    #[test]
+    fn retain_triangles_keeps_polygons_and_sub_meshes() {
+        let obj = "v 0 0 0\nv 1 0 0\nv 1 1 0\nv 0 1 0\nv 0 0 1\nv 1 0 1\nv 1 1 1\nv 0 1 1\n\
+            g a\nf 1 4 3 2\nf 5 6 7 8\nf 1 2 6 5\n\
+            g b\nf 3 4 8 7\nf 1 5 8 4\nf 2 3 7 6\n";
+        let mut mesh = parse_obj(obj).unwrap();
+        let sub_mesh_ranges = |mesh: &Mesh| mesh.sub_meshes.iter().map(|sub_mesh| sub_mesh.triangles.clone()).collect::<Vec<_>>();
+        assert_eq!(sub_mesh_ranges(&mesh), [0..6, 6..12]);
+        let expected_points: Vec<[Scalar; 3]> = [2, 3, 5, 8, 9, 10, 11].iter().map(|&tri: &usize| {
+            let p = mesh.triangles[tri].p[0];
+            return [p.x(), p.y(), p.z()];
+        }).collect();
+
+        // None of the first and fourth quad and half of the third
+        let keep: Vec<bool> = (0..12).map(|tri| ![0, 1, 4, 6, 7].contains(&tri)).collect();
+        mesh.retain_triangles(&keep);
+        assert_eq!(mesh.triangles.len(), 7);
+        let points: Vec<[Scalar; 3]> = mesh.triangles.iter().map(|tri| [tri.p[0].x(), tri.p[0].y(), tri.p[0].z()]).collect();
+        assert_eq!(points, expected_points);
+        assert_eq!(mesh.polygons.iter().map(|polygon| polygon.triangles.clone()).collect::<Vec<_>>(), [0..2, 2..3, 3..5, 5..7]);
+        assert_eq!(mesh.polygons[1].p.len(), 4);
+        assert_eq!(sub_mesh_ranges(&mesh), [0..3, 3..7]);
+    }
+}
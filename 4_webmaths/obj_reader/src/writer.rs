@@ -0,0 +1,371 @@
+use vector_math::{*};
+use std::collections::HashMap;
+use std::fs::File;
+use std::io;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+use crate::{IndexedMesh, Material, Mesh, Polyline, SubMesh, TriData};
+
+#[derive(Clone, Copy)]
+pub struct ObjWriteOptions {
+    pub normals: bool,
+    pub texcoords: bool,
+    pub colors: bool,
+
+    // Write o / g lines for sub-meshes and usemtl lines (plus a companion mtl file) for materials
+    pub groups: bool,
+    pub materials: bool,
+
+    // Merge positions, normals and texture coordinates that are written out identically
+    pub deduplicate: bool,
+
+    // Digits after the decimal point
+    pub precision: usize,
+}
+
+impl Default for ObjWriteOptions {
+    fn default() -> ObjWriteOptions {
+        return ObjWriteOptions {
+            normals: true,
+            texcoords: true,
+            colors: true,
+            groups: true,
+            materials: true,
+            deduplicate: true,
+            precision: 6,
+        };
+    }
+}
+
+// Formats numbers with fixed precision, without writing negative zeros
+fn format_scalars(values: &[Scalar], precision: usize) -> String {
+    let mut out = String::new();
+    for value in values {
+        let mut formatted = format!("{:.*}", precision, value);
+        if formatted.starts_with('-') && formatted[1..].chars().all(|c| c == '0' || c == '.') {
+            formatted.remove(0);
+        }
+        out.push(' ');
+        out.push_str(&formatted);
+    }
+    return out;
+}
+
+// Collects the lines for one kind of vertex attribute, handing out 1-based obj indices
+struct AttributeWriter {
+    lines: Vec<String>,
+    known: HashMap<String, usize>,
+    deduplicate: bool,
+}
+
+impl AttributeWriter {
+    fn new(deduplicate: bool) -> AttributeWriter {
+        return AttributeWriter {
+            lines: Vec::new(),
+            known: HashMap::new(),
+            deduplicate: deduplicate,
+        };
+    }
+
+    fn add(&mut self, line: String) -> usize {
+        if self.deduplicate {
+            if let Some(&idx) = self.known.get(&line) {
+                return idx;
+            }
+            self.known.insert(line.clone(), self.lines.len() + 1);
+        }
+        self.lines.push(line);
+        return self.lines.len();
+    }
+}
+
+// The o / g lines to get into a sub-mesh, keeping track of the current object and whether a group is open
+fn write_sub_mesh_start<W: Write>(out: &mut W, sub_mesh: &SubMesh, object: &mut String, grouped: &mut bool) -> io::Result<()> {
+    if sub_mesh.object != *object {
+        writeln!(out, "o {}", sub_mesh.object)?;
+        *object = sub_mesh.object.clone();
+        *grouped = false;
+    }
+    if !sub_mesh.groups.is_empty() {
+        writeln!(out, "g {}", sub_mesh.groups.join(" "))?;
+        *grouped = true;
+    }
+    else if *grouped {
+        // Otherwise the faces would stay in the groups before
+        writeln!(out, "g")?;
+        *grouped = false;
+    }
+    return Ok(());
+}
+
+// Writes an indexed mesh as obj. Sub-meshes become o / g lines, triangle materials usemtl lines, and
+// mtllib gets referenced if given. Triangles are written with the winding flipped back, so reading the
+// file gives the same triangles again.
+pub fn write_obj_to<W: Write>(out: &mut W, mesh: &IndexedMesh, sub_meshes: &[SubMesh], materials: &[Material], mtllib: Option<&str>, options: &ObjWriteOptions) -> io::Result<()> {
+    return write_obj_parts_to(out, mesh, sub_meshes, &[], materials, mtllib, options);
+}
+
+// write_obj_to plus polylines, which come after the faces as l lines in their sub-meshes
+fn write_obj_parts_to<W: Write>(out: &mut W, mesh: &IndexedMesh, sub_meshes: &[SubMesh], polylines: &[Polyline], materials: &[Material], mtllib: Option<&str>, options: &ObjWriteOptions) -> io::Result<()> {
+    let precision = options.precision;
+    let write_texcoords = options.texcoords && mesh.texcoords.is_some();
+    let write_colors = options.colors && mesh.colors.is_some();
+
+    let mut positions = AttributeWriter::new(options.deduplicate);
+    let mut texcoords = AttributeWriter::new(options.deduplicate);
+    let mut normals = AttributeWriter::new(options.deduplicate);
+    let mut vertex_refs = Vec::with_capacity(mesh.vertex_count());
+    for idx in 0..mesh.vertex_count() {
+        let p = mesh.positions[idx];
+        let mut line = format!("v{}", format_scalars(&[p.x(), p.y(), p.z()], precision));
+        if write_colors {
+            let c = mesh.colors.as_ref().unwrap()[idx];
+            line.push_str(&format_scalars(&[c.r(), c.g(), c.b()], precision));
+        }
+        let p_ref = positions.add(line);
+
+        let mut vertex_ref = p_ref.to_string();
+        if write_texcoords {
+            let t = mesh.texcoords.as_ref().unwrap()[idx];
+            let line = if t.z() == 0.0 {
+                format!("vt{}", format_scalars(&[t.x(), t.y()], precision))
+            }
+            else {
+                format!("vt{}", format_scalars(&[t.x(), t.y(), t.z()], precision))
+            };
+            vertex_ref.push_str(&format!("/{}", texcoords.add(line)));
+        }
+        if options.normals {
+            let n = mesh.normals[idx];
+            let n_ref = normals.add(format!("vn{}", format_scalars(&[n.x(), n.y(), n.z()], precision)));
+            if !write_texcoords {
+                vertex_ref.push('/');
+            }
+            vertex_ref.push_str(&format!("/{}", n_ref));
+        }
+        vertex_refs.push(vertex_ref);
+    }
+    let polyline_refs: Vec<Vec<String>> = polylines.iter().map(|polyline| {
+        return (0..polyline.p.len()).map(|idx| {
+            let p = polyline.p[idx];
+            let mut line = format!("v{}", format_scalars(&[p.x(), p.y(), p.z()], precision));
+            if let Some(c) = polyline.c.as_ref().filter(|_| options.colors) {
+                line.push_str(&format_scalars(&[c[idx].r(), c[idx].g(), c[idx].b()], precision));
+            }
+            let mut vertex_ref = positions.add(line).to_string();
+            if let Some(t) = polyline.t.as_ref().filter(|_| options.texcoords) {
+                let line = format!("vt{}", format_scalars(&[t[idx].x(), t[idx].y(), t[idx].z()], precision));
+                vertex_ref.push_str(&format!("/{}", texcoords.add(line)));
+            }
+            return vertex_ref;
+        }).collect();
+    }).collect();
+
+    if let Some(mtllib) = mtllib {
+        writeln!(out, "mtllib {}", mtllib)?;
+    }
+    for line in positions.lines.iter().chain(texcoords.lines.iter()).chain(normals.lines.iter()) {
+        writeln!(out, "{}", line)?;
+    }
+
+    let sub_mesh_starts: HashMap<usize, &SubMesh> = sub_meshes.iter()
+        .filter(|sub_mesh| !sub_mesh.triangles.is_empty())
+        .map(|sub_mesh| (sub_mesh.triangles.start, sub_mesh))
+        .collect();
+    let mut object = String::new();
+    let mut grouped = false;
+    let mut material = None;
+    let mut write_material = |out: &mut W, next: Option<usize>| -> io::Result<()> {
+        if options.materials && next != material {
+            // A bare usemtl goes back to no material
+            match next.and_then(|idx| materials.get(idx)) {
+                Some(next_material) => writeln!(out, "usemtl {}", next_material.name)?,
+                None => writeln!(out, "usemtl")?,
+            }
+            material = next;
+        }
+        return Ok(());
+    };
+    for (tri_idx, tri_indices) in mesh.indices.iter().enumerate() {
+        if options.groups {
+            if let Some(sub_mesh) = sub_mesh_starts.get(&tri_idx) {
+                write_sub_mesh_start(out, sub_mesh, &mut object, &mut grouped)?;
+            }
+        }
+        write_material(out, mesh.triangle_materials[tri_idx])?;
+        let [a, b, c] = tri_indices.map(|idx| idx as usize);
+        writeln!(out, "f {} {} {}", vertex_refs[c], vertex_refs[b], vertex_refs[a])?;
+    }
+    let mut sub_mesh_idx = None;
+    for (polyline, refs) in polylines.iter().zip(&polyline_refs) {
+        if options.groups && sub_mesh_idx != Some(polyline.sub_mesh) {
+            if let Some(sub_mesh) = sub_meshes.get(polyline.sub_mesh) {
+                write_sub_mesh_start(out, sub_mesh, &mut object, &mut grouped)?;
+            }
+            sub_mesh_idx = Some(polyline.sub_mesh);
+        }
+        write_material(out, polyline.material)?;
+        writeln!(out, "l {}", refs.join(" "))?;
+    }
+    return Ok(());
+}
+
+// Texture paths are written relative to the mtl file where possible
+fn relative_path(path: &Path, mtl_dir: &Path) -> String {
+    return path.strip_prefix(mtl_dir).unwrap_or(path).display().to_string();
+}
+
+pub fn write_mtl_to<W: Write>(out: &mut W, materials: &[Material], mtl_dir: &Path, precision: usize) -> io::Result<()> {
+    for material in materials {
+        writeln!(out, "newmtl {}", material.name)?;
+        writeln!(out, "Kd{}", format_scalars(&[material.diffuse.r(), material.diffuse.g(), material.diffuse.b()], precision))?;
+        writeln!(out, "Ks{}", format_scalars(&[material.specular.r(), material.specular.g(), material.specular.b()], precision))?;
+        writeln!(out, "Ke{}", format_scalars(&[material.emission.r(), material.emission.g(), material.emission.b()], precision))?;
+        writeln!(out, "Ns{}", format_scalars(&[material.specular_exponent], precision))?;
+        writeln!(out, "Ni{}", format_scalars(&[material.ior], precision))?;
+        writeln!(out, "d{}", format_scalars(&[material.dissolve], precision))?;
+        writeln!(out, "illum {}", material.illum)?;
+        for (statement, value) in [("Pr", material.roughness), ("Pm", material.metallic), ("Pc", material.clearcoat)] {
+            if let Some(value) = value {
+                writeln!(out, "{}{}", statement, format_scalars(&[value], precision))?;
+            }
+        }
+        if let Some(map) = &material.diffuse_map {
+            writeln!(out, "map_Kd {}", relative_path(map, mtl_dir))?;
+        }
+        if let Some(map) = &material.bump_map {
+            if material.bump_multiplier != 1.0 {
+                writeln!(out, "map_Bump -bm{} {}", format_scalars(&[material.bump_multiplier], precision), relative_path(map, mtl_dir))?;
+            }
+            else {
+                writeln!(out, "map_Bump {}", relative_path(map, mtl_dir))?;
+            }
+        }
+        writeln!(out)?;
+    }
+    return Ok(());
+}
+
+pub fn write_mtl(path: &str, materials: &[Material], precision: usize) -> io::Result<()> {
+    let mut out = BufWriter::new(File::create(path)?);
+    write_mtl_to(&mut out, materials, Path::new(path).parent().unwrap_or(Path::new("")), precision)?;
+    return out.flush();
+}
+
+// Writes an indexed mesh to a file, plus a companion mtl file next to it (same name, .mtl) if there are materials
+pub fn write_obj_indexed(path: &str, mesh: &IndexedMesh, sub_meshes: &[SubMesh], materials: &[Material], options: &ObjWriteOptions) -> io::Result<()> {
+    return write_obj_parts(path, mesh, sub_meshes, &[], materials, options);
+}
+
+fn write_obj_parts(path: &str, mesh: &IndexedMesh, sub_meshes: &[SubMesh], polylines: &[Polyline], materials: &[Material], options: &ObjWriteOptions) -> io::Result<()> {
+    let mut mtllib = None;
+    if options.materials && !materials.is_empty() {
+        let mtl_path = Path::new(path).with_extension("mtl");
+        write_mtl(&mtl_path.display().to_string(), materials, options.precision)?;
+        mtllib = mtl_path.file_name().map(|name| name.to_string_lossy().to_string());
+    }
+    let mut out = BufWriter::new(File::create(path)?);
+    write_obj_parts_to(&mut out, mesh, sub_meshes, polylines, materials, mtllib.as_deref(), options)?;
+    return out.flush();
+}
+
+pub fn write_obj_triangles(path: &str, triangles: &[TriData], materials: &[Material], options: &ObjWriteOptions) -> io::Result<()> {
+    return write_obj_indexed(path, &IndexedMesh::from_triangles(triangles, false), &[], materials, options);
+}
+
+// Writes a mesh as read by parse_obj / read_obj, including its sub-meshes, polylines and materials
+pub fn write_obj(path: &str, mesh: &Mesh, options: &ObjWriteOptions) -> io::Result<()> {
+    return write_obj_parts(path, &mesh.to_indexed(false), &mesh.sub_meshes, &mesh.polylines, &mesh.materials, options);
+}
+
+#[cfg(test)]
+mod tests {
+    use vector_math::{*};
+    use std::path::Path;
+    use crate::{Material, Mesh, ObjOptions, parse_mtl, parse_obj};
+    use super::{ObjWriteOptions, write_mtl_to, write_obj_parts_to};
+
+    fn xyz(v: Vec3) -> [Scalar; 3] {
+        return [v.x(), v.y(), v.z()];
+    }
+
+    fn round_trip(mesh: &Mesh) -> (String, Mesh) {
+        let mut out = Vec::new();
+        write_obj_parts_to(&mut out, &mesh.to_indexed(false), &mesh.sub_meshes, &mesh.polylines, &mesh.materials, Some("scene.mtl"), &ObjWriteOptions::default()).unwrap();
+        let text = String::from_utf8(out).unwrap();
+        let back = parse_obj(&text).unwrap();
+        return (text, back);
+    }
+
+    #[test]
+    fn round_trips_through_parse_obj() {
+        // The second o box goes back to the object without groups, after the lid group, and the bare
+        // usemtl goes back to no material
+        let obj = "mtllib scene.mtl\n\
+            v 0 0 0 1 0 0\nv 1 0 0 0 1 0\nv 1 1 0 0 0 1\nv 0 1 0 1 1 1\n\
+            vt 0 0\nvt 1 0\nvt 1 1\nvt 0 1\nvn 0 0 1\n\
+            o box\ng lid\nusemtl red\nf 1/1/1 2/2/1 3/3/1\n\
+            o box\nusemtl blue\nf 1/1/1 3/3/1 4/4/1\nl 1/1 2/2 3/3\n\
+            usemtl\nf 2/2/1 3/3/1 4/4/1\n\
+            g lid\nl 1 4\n";
+        let mesh = parse_obj(obj).unwrap();
+        let (text, back) = round_trip(&mesh);
+
+        assert_eq!(back.material_libs, ["scene.mtl"]);
+        let names = |mesh: &Mesh| mesh.materials.iter().map(|m| m.name.clone()).collect::<Vec<String>>();
+        assert_eq!(names(&back), ["red", "blue"]);
+
+        // The ungrouped faces end up in the default group, not in lid
+        assert!(text.contains("\ng\n"));
+        let sub_meshes: Vec<(&str, &str, usize)> = back.sub_meshes.iter().map(|s| (s.name.as_str(), s.object.as_str(), s.triangles.len())).collect();
+        assert_eq!(sub_meshes, [("lid", "box", 1), ("default", "box", 2)]);
+        assert!(text.contains("\nusemtl\n"));
+        let materials: Vec<Option<usize>> = back.triangles.iter().map(|tri| tri.material).collect();
+        assert_eq!(materials, [Some(0), Some(1), None]);
+        assert_eq!(back.polylines.iter().map(|polyline| polyline.material).collect::<Vec<_>>(), [Some(1), None]);
+
+        assert_eq!(back.triangles.len(), mesh.triangles.len());
+        for (a, b) in mesh.triangles.iter().zip(&back.triangles) {
+            assert_eq!(a.p.map(xyz), b.p.map(xyz));
+            assert_eq!(a.n.map(xyz), b.n.map(xyz));
+            assert_eq!(a.t.unwrap().map(xyz), b.t.unwrap().map(xyz));
+            assert_eq!(a.c.unwrap().map(xyz), b.c.unwrap().map(xyz));
+            assert_eq!(a.material, b.material);
+        }
+
+        assert_eq!(back.polylines.len(), 2);
+        for (a, b) in mesh.polylines.iter().zip(&back.polylines) {
+            assert_eq!(a.p.iter().map(|&p| xyz(p)).collect::<Vec<_>>(), b.p.iter().map(|&p| xyz(p)).collect::<Vec<_>>());
+            assert_eq!(a.t.as_ref().map(|t| t.iter().map(|&t| xyz(t)).collect::<Vec<_>>()), b.t.as_ref().map(|t| t.iter().map(|&t| xyz(t)).collect::<Vec<_>>()));
+            assert_eq!(a.c.as_ref().map(|c| c.iter().map(|&c| xyz(c)).collect::<Vec<_>>()), b.c.as_ref().map(|c| c.iter().map(|&c| xyz(c)).collect::<Vec<_>>()));
+            assert_eq!(a.material, b.material);
+            assert_eq!(a.sub_mesh, b.sub_mesh);
+        }
+    }
+
+    #[test]
+    fn materials_round_trip_through_parse_mtl() {
+        let mut material = Material::new("red");
+        material.diffuse = Vec3::new(1.0, 0.0, 0.0);
+        material.emission = Vec3::new(0.5, 0.25, 0.0);
+        material.specular_exponent = 20.0;
+        material.dissolve = 0.5;
+        material.roughness = Some(0.75);
+        material.diffuse_map = Some(Path::new("/textures/red.png").to_path_buf());
+        let mut out = Vec::new();
+        write_mtl_to(&mut out, &[material.clone(), Material::new("plain")], Path::new("/"), 6).unwrap();
+        let library = parse_mtl(&String::from_utf8(out).unwrap(), &ObjOptions::default()).unwrap();
+
+        assert_eq!(library.materials.len(), 2);
+        let back = &library.materials[0];
+        assert_eq!(back.name, "red");
+        assert_eq!(xyz(back.diffuse), xyz(material.diffuse));
+        assert_eq!(xyz(back.emission), xyz(material.emission));
+        assert_eq!(back.specular_exponent, 20.0);
+        assert_eq!(back.dissolve, 0.5);
+        assert_eq!(back.roughness, Some(0.75));
+        assert_eq!(back.metallic, None);
+        assert_eq!(back.diffuse_map.as_deref(), Some(Path::new("textures/red.png")));
+        assert_eq!(library.materials[1].name, "plain");
+    }
+}
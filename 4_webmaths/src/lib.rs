@@ -78,6 +78,23 @@ struct Hit {
     n: Option<Vec3>,
 }
 
+// Rough mapping from obj materials onto our BSDF parameters
+impl From<&Material> for BSDF {
+    fn from(material: &Material) -> BSDF {
+        let specular = material.specular.x().max(material.specular.y()).max(material.specular.z());
+        return BSDF {
+            albedo: material.diffuse,
+            emission: material.emission,
+            specularity: match material.roughness {
+                Some(roughness) => 2.0 / (roughness * roughness).max(0.001),
+                None => material.specular_exponent.sqrt() * 2.0,
+            },
+            reflectivity: material.metallic.unwrap_or(specular),
+            transmittance: 1.0 - material.dissolve,
+        };
+    }
+}
+
 impl BSDF {
     fn calc_refraction(d: Vec3, n: Vec3, r_inside: Scalar, r_outside: Scalar) -> Vec3 {
         // Figure out whether we're going in or out and flip things accordingly
@@ -233,7 +250,7 @@ impl Object for Triangle {
         bary_a /= triangle_den;
         bary_b /= triangle_den;
         bary_c /= triangle_den;
-        let n = (self.n1 * bary_a + self.n2 * bary_b + self.n3 * bary_c).normalized();
+        let n = fma!(self.n1 * bary_a + self.n2 * bary_b + self.n3 * bary_c).normalized();
 
         let hit = Hit {
             o: o,
@@ -304,7 +321,7 @@ fn pixel_col(pos: Vec2) -> Color {
     // Calculate ray
     let up = Vec3::new(0.0, 1.0, 0.0);
     let left = (origin - look_at).normalized().cross(up);
-    let screen_pixel = look_at + left * pos.x() + up * pos.y();
+    let screen_pixel = fma!(look_at + left * pos.x() + up * pos.y());
     let ray = (screen_pixel - origin).normalized();
 
     // Trace some rays
@@ -314,7 +331,7 @@ fn pixel_col(pos: Vec2) -> Color {
 // Load icosahedron object
 static ICOSAHEDRON_TEXT: &'static str = include_str!("../icosa.obj");
 lazy_static! {
-    static ref ICOSAHEDRON: Vec<TriData> = parse_obj(ICOSAHEDRON_TEXT);
+    static ref ICOSAHEDRON: Vec<TriData> = parse_obj(ICOSAHEDRON_TEXT).expect("Icosahedron parse error").triangles;
 }
 
 // Update scene stored in SCENE variable
@@ -360,7 +377,7 @@ fn set_scene(t: Scalar) {
                 reflectivity: 1.0 - emit_ramp * 0.8,
                 transmittance: 1.0,
             },
-            bvh_skip: 180,
+            bvh_skip: ICOSAHEDRON.len() as i32,
         }));
     }
 
@@ -1,5 +1,5 @@
 extern crate proc_macro;
-use proc_macro::{TokenStream};
+use proc_macro::{TokenStream, TokenTree};
 use itertools::Itertools;
 use std::format;
 
@@ -78,7 +78,18 @@ pub fn gen_swizz_assign(input: TokenStream) -> TokenStream {
     return out_src.parse().unwrap();
 }
 
-// Generate swizzled functions for Scalar, Vec2, Vec3 and Vec4
+// Generate single element accessors and setters
+#[proc_macro]
+pub fn gen_swizz_single(input: TokenStream) -> TokenStream {
+    let in_str = input.into_iter().next().unwrap().to_string();
+    let mut out_src = "".to_string();
+    for c in in_str.chars() {
+        out_src.push_str(&format!("gen_swizz!({0});\ngen_swizz_assign!({0});\n", c));
+    }
+    return out_src.parse().unwrap();
+}
+
+// Generate swizzled functions for Vec2, Vec3 and Vec4 (single elements are done by gen_swizz_single)
 #[proc_macro]
 pub fn gen_swizz_funcs(input: TokenStream) -> TokenStream {
     let in_str = input.into_iter().next().unwrap().to_string();
@@ -86,14 +97,6 @@ pub fn gen_swizz_funcs(input: TokenStream) -> TokenStream {
     let chars_initial = &chars_initial[1..];
     let mut chars: Vec<String> = chars_initial.iter().map(|x| x.to_string()).collect();
     let mut out_src = "".to_string();
-    for char_arr in &chars {
-        out_src.push_str(&"gen_swizz!(");
-        out_src.push_str(char_arr);
-        out_src.push_str(&");\n");
-        out_src.push_str(&"gen_swizz_assign!(");
-        out_src.push_str(char_arr);
-        out_src.push_str(&");\n");
-    }
     for vec_d in 0..3 {
         chars = chars.iter().cartesian_product(chars_initial.iter()).map(|x| format!("{}{}", x.0, x.1)).collect();
         for char_arr in &chars {
@@ -110,6 +113,24 @@ pub fn gen_swizz_funcs(input: TokenStream) -> TokenStream {
     return out_src.parse().unwrap();
 }
 
+// Implements SwizzleComponent for each element of a vector, which is what swz! reads and writes through
+#[proc_macro]
+pub fn gen_swizz_components(input: TokenStream) -> TokenStream {
+    let mut input_iter = input.into_iter();
+    let this_type = input_iter.next().unwrap().to_string();
+    let num_elements = input_iter.next().unwrap().to_string().parse::<i32>().unwrap();
+    let mut out_src = "".to_string();
+    for element in 0..num_elements {
+        out_src.push_str(&format!("
+            impl SwizzleComponent<{1}> for {0} {{
+                #[inline(always)] fn get(&self) -> Scalar {{ return self.0.v[{1}]; }}
+                #[inline(always)] fn set(&mut self, to: Scalar) {{ self.0.v[{1}] = to; }}
+            }}
+        ", this_type, element));
+    }
+    return out_src.parse().unwrap();
+}
+
 #[proc_macro]
 pub fn gen_constructor(input: TokenStream) -> TokenStream {
     let num_elements = input.into_iter().next().unwrap().to_string().parse::<i32>().unwrap();
@@ -242,6 +263,42 @@ pub fn gen_mat_access(input: TokenStream) -> TokenStream {
     ", num_cols).parse().unwrap();
 }
 
+#[proc_macro]
+pub fn gen_mat_rows(input: TokenStream) -> TokenStream {
+    let mut input_iter = input.into_iter();
+    let num_rows = input_iter.next().unwrap().to_string().parse::<i32>().unwrap();
+    let num_cols = input_iter.next().unwrap().to_string().parse::<i32>().unwrap();
+    let mut row_src = "".to_string();
+    let mut set_row_src = "".to_string();
+    for j in 0..num_cols {
+        row_src.push_str(&format!("self.m(row, {0}),", j));
+        set_row_src.push_str(&format!("self.set_m(row, {0}, to.m(0, {0}));", j));
+    }
+    let mut col_src = "".to_string();
+    let mut set_col_src = "".to_string();
+    for i in 0..num_rows {
+        col_src.push_str(&format!("self.m({0}, col),", i));
+        set_col_src.push_str(&format!("self.set_m({0}, col, to.m(0, {0}));", i));
+    }
+    return format!("
+        #[inline(always)] pub fn row(&self, row: usize) -> Vec{0} {{
+            return Vec{0}::new({2});
+        }}
+
+        #[inline(always)] pub fn set_row(&mut self, row: usize, to: Vec{0}) {{
+            {3}
+        }}
+
+        #[inline(always)] pub fn col(&self, col: usize) -> Vec{1} {{
+            return Vec{1}::new({4});
+        }}
+
+        #[inline(always)] pub fn set_col(&mut self, col: usize, to: Vec{1}) {{
+            {5}
+        }}
+    ", num_cols, num_rows, row_src, set_row_src, col_src, set_col_src).parse().unwrap();
+}
+
 #[proc_macro]
 pub fn gen_mat_utils(input: TokenStream) -> TokenStream {
     let mut input_iter = input.into_iter();
@@ -355,3 +412,237 @@ pub fn gen_dot_norm(input: TokenStream) -> TokenStream {
     ", this_type));
     return out_src.parse().unwrap();
 }
+
+
+#[proc_macro]
+pub fn gen_fma(input: TokenStream) -> TokenStream {
+    let mut input_iter = input.into_iter();
+    let this_type = input_iter.next().unwrap().to_string();
+    let num_elements = input_iter.next().unwrap().to_string().parse::<i32>().unwrap();
+    let mut lanes_scalar = "".to_string();
+    let mut lanes_vector = "".to_string();
+    let mut lanes_left = "".to_string();
+    for element in 0..num_elements {
+        lanes_scalar.push_str(&format!("self.0.v[{0}].mul_add(a, b.0.v[{0}]),", element));
+        lanes_vector.push_str(&format!("self.0.v[{0}].mul_add(a.0.v[{0}], b.0.v[{0}]),", element));
+        lanes_left.push_str(&format!("self.mul_add(a.0.v[{0}], b.0.v[{0}]),", element));
+    }
+    return format!("
+        impl Fma<Scalar, {0}> for {0} {{
+            type Output = {0};
+            #[inline(always)] fn mul_add(self, a: Scalar, b: {0}) -> {0} {{
+                return {0} {{ 0: VecN::<{1}> {{ v: [{2}] }} }};
+            }}
+        }}
+        impl Fma<{0}, {0}> for {0} {{
+            type Output = {0};
+            #[inline(always)] fn mul_add(self, a: {0}, b: {0}) -> {0} {{
+                return {0} {{ 0: VecN::<{1}> {{ v: [{3}] }} }};
+            }}
+        }}
+        impl Fma<{0}, {0}> for Scalar {{
+            type Output = {0};
+            #[inline(always)] fn mul_add(self, a: {0}, b: {0}) -> {0} {{
+                return {0} {{ 0: VecN::<{1}> {{ v: [{4}] }} }};
+            }}
+        }}
+    ", this_type, num_elements, lanes_scalar, lanes_vector, lanes_left).parse().unwrap();
+}
+
+// Rewrites a sum of products (e.g. "a + b * c - d * e") into a chain of fused multiply-adds,
+// accumulating from left to right. Anything that isn't a top level +, -, * or / is passed through
+// as part of a term, so operators that bind looser than + (like & and |) need to be parenthesized.
+// Fma is named by its full path, so callers don't need to import it.
+#[proc_macro]
+pub fn fma(input: TokenStream) -> TokenStream {
+    // Split into top level terms, remembering the sign of each
+    let mut terms: Vec<(bool, Vec<TokenTree>)> = Vec::new();
+    let mut current: Vec<TokenTree> = Vec::new();
+    let mut negate = false;
+    for token in input.into_iter() {
+        if let TokenTree::Punct(ref punct) = token {
+            let is_operator = match current.last() {
+                Some(TokenTree::Punct(last)) => !"+-*/".contains(last.as_char()),
+                Some(_) => true,
+                None => false,
+            };
+            if is_operator && (punct.as_char() == '+' || punct.as_char() == '-') {
+                terms.push((negate, current));
+                current = Vec::new();
+                negate = punct.as_char() == '-';
+                continue;
+            }
+            if !"+-*/.:!?".contains(punct.as_char()) {
+                panic!("fma! only supports sums of products, found '{}'", punct.as_char());
+            }
+        }
+        current.push(token);
+    }
+    if current.is_empty() {
+        panic!("fma! expression ends in an operator");
+    }
+    terms.push((negate, current));
+
+    // Split each term into a product "a * b" (at the last top level *) or leave it as is
+    let mut out_src = "{ let fma_acc = ".to_string();
+    let mut first = true;
+    for (negate, term) in terms {
+        let mut split_at = None;
+        let mut after_operand = false;
+        for (idx, token) in term.iter().enumerate() {
+            if let TokenTree::Punct(punct) = token {
+                if after_operand && punct.as_char() == '*' {
+                    split_at = Some(idx);
+                }
+                if after_operand && punct.as_char() == '/' {
+                    split_at = None;
+                }
+                after_operand = !"+-*/".contains(punct.as_char());
+            }
+            else {
+                after_operand = true;
+            }
+        }
+        let sign = if negate { "-" } else { "" };
+        match split_at {
+            Some(idx) => {
+                let lhs: TokenStream = term[..idx].iter().cloned().collect();
+                let rhs: TokenStream = term[idx + 1..].iter().cloned().collect();
+                if first {
+                    out_src.push_str(&format!("{0}({1}) * ({2});", sign, lhs, rhs));
+                }
+                else {
+                    out_src.push_str(&format!("let fma_acc = ::vector_math::Fma::mul_add({0}({1}), {2}, fma_acc);", sign, lhs, rhs));
+                }
+            }
+            None => {
+                let term: TokenStream = term.into_iter().collect();
+                if first {
+                    out_src.push_str(&format!("{0}({1});", sign, term));
+                }
+                else {
+                    out_src.push_str(&format!("let fma_acc = fma_acc {0} ({1});", if negate { "-" } else { "+" }, term));
+                }
+            }
+        }
+        first = false;
+    }
+    out_src.push_str(&"fma_acc }");
+    return out_src.parse().unwrap();
+}
+
+
+// Maps a swizzle component to its index, or None if it isn't one
+fn swizz_index(c: char) -> Option<(usize, &'static str)> {
+    match c {
+        'x' => Some((0, "xyzw")), 'y' => Some((1, "xyzw")), 'z' => Some((2, "xyzw")), 'w' => Some((3, "xyzw")),
+        'r' => Some((0, "rgba")), 'g' => Some((1, "rgba")), 'b' => Some((2, "rgba")), 'a' => Some((3, "rgba")),
+        'u' => Some((0, "uv")), 'v' => Some((1, "uv")),
+        _ => None,
+    }
+}
+
+// Reports a swz! misuse as a compile error at the macro call
+fn swz_error(message: String) -> TokenStream {
+    return format!("compile_error!({:?})", message).parse().unwrap();
+}
+
+// Resolves a swizzle like "v.zyx" (read) or "v.xz = other" (assign) at compile time. Elements are accessed
+// through ::vector_math::SwizzleComponent, so components the vector doesn't have (like .w on a Vec3) don't
+// compile. Reading works on any expression, e.g. "m.row(1).xz". Assigning works on places, and on matrix
+// rows / columns ("m.row(1).xz = other"), which get written back.
+#[proc_macro]
+pub fn swz(input: TokenStream) -> TokenStream {
+    let tokens: Vec<TokenTree> = input.into_iter().collect();
+
+    // Find the swizzle: the last top level ".ident" before an optional "="
+    let mut assign_at = None;
+    for (idx, token) in tokens.iter().enumerate() {
+        if let TokenTree::Punct(punct) = token {
+            if punct.as_char() == '=' && assign_at.is_none() {
+                assign_at = Some(idx);
+            }
+        }
+    }
+    let target_end = assign_at.unwrap_or(tokens.len());
+    if target_end < 3 {
+        return swz_error("swz! expects an expression followed by .swizzle".to_string());
+    }
+    let swizzle = match (&tokens[target_end - 2], &tokens[target_end - 1]) {
+        (TokenTree::Punct(dot), TokenTree::Ident(ident)) if dot.as_char() == '.' => ident.to_string(),
+        _ => return swz_error("swz! expects an expression followed by .swizzle".to_string()),
+    };
+    if swizzle.chars().count() > 4 {
+        return swz_error(format!("swz! swizzle '{}' is longer than 4 elements", swizzle));
+    }
+    let mut indices = Vec::new();
+    let mut swizzle_set = None;
+    for c in swizzle.chars() {
+        let (index, set) = match swizz_index(c) {
+            Some(component) => component,
+            None => return swz_error(format!("swz! '{}' is not a swizzle component", c)),
+        };
+        if swizzle_set.is_some() && swizzle_set != Some(set) {
+            return swz_error(format!("swz! swizzle '{}' mixes components from different sets", swizzle));
+        }
+        swizzle_set = Some(set);
+        indices.push(index);
+    }
+    let base: TokenStream = tokens[..target_end - 2].iter().cloned().collect();
+
+    // Read
+    if assign_at.is_none() {
+        if indices.len() == 1 {
+            return format!("::vector_math::SwizzleComponent::<{1}>::get(&({0}))", base, indices[0]).parse().unwrap();
+        }
+        let mut out_src = format!("{{ let swz_src = {0}; Vec{1}::new(", base, indices.len());
+        for index in &indices {
+            out_src.push_str(&format!("::vector_math::SwizzleComponent::<{0}>::get(&swz_src),", index));
+        }
+        out_src.push_str(&") }");
+        return out_src.parse().unwrap();
+    }
+
+    // Assign
+    for (i, index) in indices.iter().enumerate() {
+        if indices[..i].contains(index) {
+            return swz_error(format!("swz! cannot assign to swizzle '{}' with repeated components", swizzle));
+        }
+    }
+    let value: TokenStream = tokens[target_end + 1..].iter().cloned().collect();
+    let mut set_src = "".to_string();
+    if indices.len() == 1 {
+        set_src.push_str(&format!("::vector_math::SwizzleComponent::<{0}>::set(swz_dst, swz_val);", indices[0]));
+    }
+    else {
+        for (i, index) in indices.iter().enumerate() {
+            set_src.push_str(&format!(
+                "::vector_math::SwizzleComponent::<{0}>::set(swz_dst, ::vector_math::SwizzleComponent::<{1}>::get(&swz_val));",
+                index, i
+            ));
+        }
+    }
+
+    // Matrix rows and columns are copies, so write them back after assigning
+    let base_tokens: Vec<TokenTree> = base.clone().into_iter().collect();
+    let base_len = base_tokens.len();
+    if base_len >= 4 {
+        if let (TokenTree::Punct(dot), TokenTree::Ident(accessor), TokenTree::Group(arg)) =
+            (&base_tokens[base_len - 3], &base_tokens[base_len - 2], &base_tokens[base_len - 1]) {
+            let accessor = accessor.to_string();
+            if dot.as_char() == '.' && (accessor == "row" || accessor == "col") {
+                let mat: TokenStream = base_tokens[..base_len - 3].iter().cloned().collect();
+                return format!("{{
+                    let swz_val = {0};
+                    let swz_mat = &mut {1};
+                    let swz_idx = {2};
+                    let mut swz_line = swz_mat.{3}(swz_idx);
+                    let swz_dst = &mut swz_line;
+                    {4}
+                    swz_mat.set_{3}(swz_idx, swz_line);
+                }}", value, mat, arg, accessor, set_src).parse().unwrap();
+            }
+        }
+    }
+    return format!("{{ let swz_val = {0}; let swz_dst = &mut {1}; {2} }}", value, base, set_src).parse().unwrap();
+}
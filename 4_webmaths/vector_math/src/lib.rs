@@ -1,4 +1,8 @@
+// Lets fma! refer to ::vector_math paths from inside this crate too
+extern crate self as vector_math;
+
 use vector_macro::{*};
+pub use vector_macro::{fma, swz};
 use auto_ops::impl_op_ex;
 use auto_ops::impl_op_ex_commutative;
 use std::fmt;
@@ -22,31 +26,56 @@ pub struct Vec3(VecN<3>);
 #[derive(Copy, Clone)]
 pub struct Vec4(VecN<4>);
 
-// Constructors and swizzling + row matrix access for vectors
+// Constructors and swizzling + row matrix access for vectors.
+// Swizzles longer than one element are available through swz!, or as methods with the "swizzle_funcs" feature
 impl Vec2 {
     gen_constructor!(2);
-    gen_swizz_funcs!(xy);
-    gen_swizz_funcs!(uv);
+    gen_swizz_single!(xy);
+    gen_swizz_single!(uv);
+    #[cfg(feature = "swizzle_funcs")] gen_swizz_funcs!(xy);
+    #[cfg(feature = "swizzle_funcs")] gen_swizz_funcs!(uv);
     gen_mat_access!(1);
 }
 gen_display!(Vec2 1 2);
 
 impl Vec3 {
     gen_constructor!(3);
-    gen_swizz_funcs!(xyz);
-    gen_swizz_funcs!(rgb);
+    gen_swizz_single!(xyz);
+    gen_swizz_single!(rgb);
+    #[cfg(feature = "swizzle_funcs")] gen_swizz_funcs!(xyz);
+    #[cfg(feature = "swizzle_funcs")] gen_swizz_funcs!(rgb);
     gen_mat_access!(1);
 }
 gen_display!(Vec3 1 3);
 
 impl Vec4 {
     gen_constructor!(4);
-    gen_swizz_funcs!(xyzw);
-    gen_swizz_funcs!(rgba);
+    gen_swizz_single!(xyzw);
+    gen_swizz_single!(rgba);
+    #[cfg(feature = "swizzle_funcs")] gen_swizz_funcs!(xyzw);
+    #[cfg(feature = "swizzle_funcs")] gen_swizz_funcs!(rgba);
     gen_mat_access!(1);
 }
 gen_display!(Vec4 1 4);
 
+/// Element I of a vector, which swz! reads and writes through. Only implemented for the elements a vector has,
+/// so out of range swizzles are compile errors:
+///
+/// ```compile_fail
+/// use vector_math::{swz, Vec3};
+/// let v = Vec3::new(1.0, 2.0, 3.0);
+/// let w = swz!(v.w);
+/// ```
+#[diagnostic::on_unimplemented(message = "swz! component {I} is out of range for {Self}")]
+pub trait SwizzleComponent<const I: usize> {
+    fn get(&self) -> Scalar;
+    fn set(&mut self, to: Scalar);
+}
+
+gen_swizz_components!(Vec2 2);
+gen_swizz_components!(Vec3 3);
+gen_swizz_components!(Vec4 4);
+
 // Basic math ops for vectors
 gen_basic_ops!(Vec2 2);
 gen_basic_ops!(Vec3 3);
@@ -80,10 +109,11 @@ pub struct Mat4x3(VecN<12>);
 #[derive(Copy, Clone)]
 pub struct Mat4x4(VecN<16>);
 
-// Constructors, matrix accessors and transposition for matrices
+// Constructors, matrix accessors, row / column access and transposition for matrices
 impl Mat2x2 {
     gen_constructor!(4);
     gen_mat_access!(2);
+    gen_mat_rows!(2 2);
     gen_mat_utils!(Mat2x2 2 2);
 }
 gen_display!(Mat2x2 2 2);
@@ -91,6 +121,7 @@ gen_display!(Mat2x2 2 2);
 impl Mat2x3 {
     gen_constructor!(6);
     gen_mat_access!(3);
+    gen_mat_rows!(2 3);
     gen_mat_utils!(Mat3x2 2 3);
 }
 gen_display!(Mat2x3 2 3);
@@ -98,6 +129,7 @@ gen_display!(Mat2x3 2 3);
 impl Mat3x2 {
     gen_constructor!(6);
     gen_mat_access!(2);
+    gen_mat_rows!(3 2);
     gen_mat_utils!(Mat2x3 3 2);
 }
 gen_display!(Mat3x2 3 2);
@@ -105,6 +137,7 @@ gen_display!(Mat3x2 3 2);
 impl Mat3x3 {
     gen_constructor!(9);
     gen_mat_access!(3);
+    gen_mat_rows!(3 3);
     gen_mat_utils!(Mat3x3 3 3);
 }
 gen_display!(Mat3x3 3 3);
@@ -112,6 +145,7 @@ gen_display!(Mat3x3 3 3);
 impl Mat2x4 {
     gen_constructor!(8);
     gen_mat_access!(4);
+    gen_mat_rows!(2 4);
     gen_mat_utils!(Mat4x2 2 4);
 }
 gen_display!(Mat2x4 2 4);
@@ -119,6 +153,7 @@ gen_display!(Mat2x4 2 4);
 impl Mat4x2 {
     gen_constructor!(8);
     gen_mat_access!(2);
+    gen_mat_rows!(4 2);
     gen_mat_utils!(Mat2x4 4 2);
 }
 gen_display!(Mat4x2 4 2);
@@ -126,6 +161,7 @@ gen_display!(Mat4x2 4 2);
 impl Mat3x4 {
     gen_constructor!(12);
     gen_mat_access!(4);
+    gen_mat_rows!(3 4);
     gen_mat_utils!(Mat4x3 3 4);
 }
 gen_display!(Mat3x4 3 4);
@@ -133,6 +169,7 @@ gen_display!(Mat3x4 3 4);
 impl Mat4x3 {
     gen_constructor!(12);
     gen_mat_access!(3);
+    gen_mat_rows!(4 3);
     gen_mat_utils!(Mat3x4 4 3);
 }
 gen_display!(Mat4x3 4 3);
@@ -140,6 +177,7 @@ gen_display!(Mat4x3 4 3);
 impl Mat4x4 {
     gen_constructor!(16);
     gen_mat_access!(4);
+    gen_mat_rows!(4 4);
     gen_mat_utils!(Mat4x4 4 4);
 }
 gen_display!(Mat4x4 4 4);
@@ -235,6 +273,24 @@ gen_dot_norm!(Vec2 2);
 gen_dot_norm!(Vec3 3);
 gen_dot_norm!(Vec4 4);
 
+// Fused multiply-add, a.mul_add(b, c) = a * b + c with a single rounding per lane.
+// Note that targets without hardware FMA (like plain wasm32) fall back to a slower software fma.
+pub trait Fma<A, B> {
+    type Output;
+    fn mul_add(self, a: A, b: B) -> Self::Output;
+}
+
+impl Fma<Scalar, Scalar> for Scalar {
+    type Output = Scalar;
+    #[inline(always)] fn mul_add(self, a: Scalar, b: Scalar) -> Scalar {
+        return Scalar::mul_add(self, a, b);
+    }
+}
+
+gen_fma!(Vec2 2);
+gen_fma!(Vec3 3);
+gen_fma!(Vec4 4);
+
 // Cross product
 impl Vec3 {
     pub fn cross(&self, b: Vec3) -> Vec3 {
@@ -341,3 +397,128 @@ impl Mat4x4 {
         ) / det;
     }
 }
+
+#[cfg(test)]
+mod tests {
+    // Only what callers would import, to check that fma! doesn't need Fma in scope
+    use crate::{fma, swz, Scalar, Vec2, Vec3, Vec4, Mat3x3, Mat2x3};
+
+    fn naive(a: Scalar, b: Scalar, c: Scalar) -> Scalar {
+        return c + a * b;
+    }
+
+    #[test]
+    fn fma_matches_naive_on_exact_values() {
+        let (a, b, c) = (1.5 as Scalar, 2.25 as Scalar, -0.75 as Scalar);
+        assert_eq!(fma!(c + a * b), naive(a, b, c));
+        assert_eq!(fma!(c - a * b), c - a * b);
+        assert_eq!(fma!(a * b + c * c - a), a * b + c * c - a);
+    }
+
+    #[test]
+    fn fma_rounds_once() {
+        // a * a = 1 + 2^-11 + 2^-24, where the last bit gets lost when the product is rounded on its own
+        let a = 1.0 + (2.0 as Scalar).powi(-12);
+        let c = -(1.0 + (2.0 as Scalar).powi(-11));
+        assert_eq!(naive(a, a, c), 0.0);
+        assert_eq!(fma!(c + a * a), (2.0 as Scalar).powi(-24));
+        assert_eq!(fma!(c + a * a), a.mul_add(a, c));
+    }
+
+    #[test]
+    fn fma_is_close_to_naive() {
+        let mut seed = 12345u32;
+        let mut random = || {
+            seed = seed.wrapping_mul(1103515245).wrapping_add(12345);
+            return (seed >> 8) as Scalar / (1 << 24) as Scalar * 200.0 - 100.0;
+        };
+        for _ in 0..1000 {
+            let (a, b, c, d, e) = (random(), random(), random(), random(), random());
+            let fused = fma!(a + b * c - d * e);
+            let unfused = a + b * c - d * e;
+            let tolerance = (a.abs() + (b * c).abs() + (d * e).abs()) * Scalar::EPSILON * 4.0;
+            assert!((fused - unfused).abs() <= tolerance, "{} vs {}", fused, unfused);
+            assert_eq!(fused, (-d).mul_add(e, b.mul_add(c, a)));
+        }
+    }
+
+    #[test]
+    fn fma_on_vectors_rounds_per_lane() {
+        let a = 1.0 + (2.0 as Scalar).powi(-12);
+        let c = -(1.0 + (2.0 as Scalar).powi(-11));
+        let v = Vec3::new(a, 2.0 * a, 0.5);
+        let offset = Vec3::new(c, 4.0 * c, 1.0);
+        let fused = fma!(offset + v * v);
+        let unfused = offset + v * v;
+        assert_eq!([fused.x(), fused.y(), fused.z()], [(2.0 as Scalar).powi(-24), (2.0 as Scalar).powi(-22), 1.25]);
+        assert_eq!([unfused.x(), unfused.y(), unfused.z()], [0.0, 0.0, 1.25]);
+        let scaled = fma!(offset + v * a);
+        assert_eq!(scaled.x(), a.mul_add(a, c));
+    }
+
+    fn xyz(v: Vec3) -> [Scalar; 3] {
+        return [v.x(), v.y(), v.z()];
+    }
+
+    #[test]
+    fn swz_reads() {
+        let v = Vec4::new(1.0, 2.0, 3.0, 4.0);
+        assert_eq!(xyz(swz!(v.zyx)), [3.0, 2.0, 1.0]);
+        assert_eq!(xyz(swz!(v.bga)), [3.0, 2.0, 4.0]);
+        let wx = swz!(v.wx);
+        assert_eq!([wx.x(), wx.y()], [4.0, 1.0]);
+        let splat = swz!(v.yyyy);
+        assert_eq!([splat.x(), splat.y(), splat.z(), splat.w()], [2.0; 4]);
+        assert_eq!(swz!(v.w), 4.0);
+        let uv = Vec2::new(0.25, 0.75);
+        assert_eq!(xyz(swz!(uv.vuv)), [0.75, 0.25, 0.75]);
+        assert_eq!(xyz(swz!((v + v).xyz)), [2.0, 4.0, 6.0]);
+    }
+
+    #[test]
+    fn swz_assigns() {
+        let mut v = Vec3::new(1.0, 2.0, 3.0);
+        let o = Vec2::new(-1.0, -3.0);
+        swz!(v.xz = o);
+        assert_eq!(xyz(v), [-1.0, 2.0, -3.0]);
+        swz!(v.zyx = swz!(v.xyz));
+        assert_eq!(xyz(v), [-3.0, 2.0, -1.0]);
+        swz!(v.g = 5.0);
+        assert_eq!(xyz(v), [-3.0, 5.0, -1.0]);
+    }
+
+    #[test]
+    fn matrix_rows_and_columns() {
+        let mut m = Mat2x3::new(
+            1.0, 2.0, 3.0,
+            4.0, 5.0, 6.0,
+        );
+        assert_eq!(xyz(m.row(1)), [4.0, 5.0, 6.0]);
+        let col = m.col(2);
+        assert_eq!([col.x(), col.y()], [3.0, 6.0]);
+
+        m.set_row(0, Vec3::new(7.0, 8.0, 9.0));
+        m.set_col(1, Vec2::new(-1.0, -2.0));
+        assert_eq!(xyz(m.row(0)), [7.0, -1.0, 9.0]);
+        assert_eq!(xyz(m.row(1)), [4.0, -2.0, 6.0]);
+    }
+
+    #[test]
+    fn swz_writes_back_matrix_rows_and_columns() {
+        let mut m = Mat3x3::new(
+            1.0, 2.0, 3.0,
+            4.0, 5.0, 6.0,
+            7.0, 8.0, 9.0,
+        );
+        assert_eq!(xyz(swz!(m.row(1).zyx)), [6.0, 5.0, 4.0]);
+        assert_eq!(swz!(m.col(2).y), 6.0);
+
+        swz!(m.row(1).xz = Vec2::new(-4.0, -6.0));
+        assert_eq!(xyz(m.row(1)), [-4.0, 5.0, -6.0]);
+        swz!(m.col(0).zx = Vec2::new(70.0, 10.0));
+        assert_eq!(xyz(m.col(0)), [10.0, -4.0, 70.0]);
+        swz!(m.col(2).y = 0.0);
+        assert_eq!(xyz(m.row(1)), [-4.0, 5.0, 0.0]);
+        assert_eq!(xyz(m.row(2)), [70.0, 8.0, 9.0]);
+    }
+}